@@ -0,0 +1,1147 @@
+//! Contract ABI
+//!
+//! `transfer(address,uint256)`のようなシグネチャやJSONのABIを読み込み、
+//! 関数の引数と返り値をABIエンコード、デコードするモジュール
+//!
+//! ```
+//! use toyevm::abi::Function;
+//!
+//! let function = Function::parse("transfer(address,uint256)(bool)").unwrap();
+//! let data = function.encode_args(&["0x00000000000000000000000000000000000000aa", "5"]).unwrap();
+//! assert_eq!(data[..4], [0xa9, 0x05, 0x9c, 0xbb]);
+//! ```
+use super::util;
+use ethereum_types::{H160, H256, U256};
+use serde_json::Value;
+use std::fmt;
+
+/// ABIの型
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamType {
+    Address,
+    Bool,
+    Uint(usize), // ビット数
+    Int(usize),
+    FixedBytes(usize), // バイト数
+    Bytes,
+    String,
+    Array(Box<ParamType>),
+    FixedArray(Box<ParamType>, usize),
+    Tuple(Vec<ParamType>),
+}
+
+impl ParamType {
+    /// `uint256`、`(address,bytes)[]`のような型を読む `uint`と`int`は256ビットになる
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if let Some(inner) = s.strip_suffix(']') {
+            let open = inner.rfind('[').ok_or(format!("{} is invalid type", s))?;
+            let element = Box::new(Self::parse(&inner[..open])?);
+            let size = &inner[open + 1..];
+            if size.is_empty() {
+                return Ok(Self::Array(element));
+            }
+            let size = size.parse().map_err(|_| format!("{} is invalid type", s))?;
+            return Ok(Self::FixedArray(element, size));
+        }
+        let tuple = s.strip_prefix("tuple").unwrap_or(s);
+        if let Some(inner) = tuple.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+            // 要素には名前がついていてもよい
+            let types: Result<Vec<ParamType>, String> = split_top_level(inner)
+                .iter()
+                .map(|item| Ok(Param::parse(item)?.kind))
+                .collect();
+            return Ok(Self::Tuple(types?));
+        }
+        let bits = |digits: &str, default: usize| -> Option<usize> {
+            if digits.is_empty() {
+                return Some(default);
+            }
+            let bits: usize = digits.parse().ok()?;
+            return (bits > 0 && bits <= 256 && bits.is_multiple_of(8)).then_some(bits);
+        };
+        let kind = match s {
+            "address" => Some(Self::Address),
+            "bool" => Some(Self::Bool),
+            "string" => Some(Self::String),
+            "bytes" => Some(Self::Bytes),
+            "function" => Some(Self::FixedBytes(24)),
+            _ => {
+                if let Some(digits) = s.strip_prefix("uint") {
+                    bits(digits, 256).map(Self::Uint)
+                } else if let Some(digits) = s.strip_prefix("int") {
+                    bits(digits, 256).map(Self::Int)
+                } else if let Some(digits) = s.strip_prefix("bytes") {
+                    digits
+                        .parse()
+                        .ok()
+                        .filter(|size| (1..=32).contains(size))
+                        .map(Self::FixedBytes)
+                } else {
+                    None
+                }
+            }
+        };
+        return kind.ok_or(format!("{} is invalid type", s));
+    }
+
+    /// 値の長さが決まっていない型か
+    pub fn is_dynamic(&self) -> bool {
+        return match self {
+            Self::Bytes | Self::String | Self::Array(_) => true,
+            Self::FixedArray(element, _) => element.is_dynamic(),
+            Self::Tuple(types) => types.iter().any(|t| t.is_dynamic()),
+            _ => false,
+        };
+    }
+
+    /// エンコードしたときのhead部分の長さ 動的な型はオフセットの32byte
+    fn head_size(&self) -> usize {
+        if self.is_dynamic() {
+            return 32;
+        }
+        return match self {
+            Self::FixedArray(element, size) => element.head_size() * size,
+            Self::Tuple(types) => types.iter().map(|t| t.head_size()).sum(),
+            _ => 32,
+        };
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Address => write!(f, "address"),
+            Self::Bool => write!(f, "bool"),
+            Self::Uint(bits) => write!(f, "uint{}", bits),
+            Self::Int(bits) => write!(f, "int{}", bits),
+            Self::FixedBytes(size) => write!(f, "bytes{}", size),
+            Self::Bytes => write!(f, "bytes"),
+            Self::String => write!(f, "string"),
+            Self::Array(element) => write!(f, "{}[]", element),
+            Self::FixedArray(element, size) => write!(f, "{}[{}]", element, size),
+            Self::Tuple(types) => write!(f, "({})", join(types)),
+        }
+    }
+}
+
+/// ABIの値 intは2の補数で持つ 固定長の配列もArrayになる
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Address(H160),
+    Bool(bool),
+    Uint(U256),
+    Int(U256),
+    FixedBytes(Vec<u8>),
+    Bytes(Vec<u8>),
+    String(String),
+    Array(Vec<Token>),
+    Tuple(Vec<Token>),
+}
+
+impl Token {
+    /// 文字列をkindの値として読む<br/>
+    /// 数値は10進数か0xで始まる16進数、バイト列は16進数、配列は`[1,2]`、タプルは`(1,0xaa)`と書く
+    pub fn parse(kind: &ParamType, s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let invalid = || format!("{} is invalid {}", s, kind);
+        let token = match kind {
+            ParamType::Address => Self::Address(util::parse_h160(s)?),
+            ParamType::Bool => match s {
+                "true" => Self::Bool(true),
+                "false" => Self::Bool(false),
+                _ => return Err(invalid()),
+            },
+            ParamType::Uint(bits) => {
+                let value = util::parse_u256(s)?;
+                if !fits_uint(value, *bits) {
+                    return Err(invalid());
+                }
+                Self::Uint(value)
+            }
+            ParamType::Int(bits) => {
+                let value = match s.strip_prefix('-') {
+                    Some(abs) => negate(util::parse_u256(abs)?),
+                    None => util::parse_u256(s)?,
+                };
+                if !fits_int(value, *bits) || s.starts_with('-') != value.bit(255) {
+                    return Err(invalid());
+                }
+                Self::Int(value)
+            }
+            ParamType::FixedBytes(size) => {
+                let bytes = parse_hex(s)?;
+                if bytes.len() != *size {
+                    return Err(invalid());
+                }
+                Self::FixedBytes(bytes)
+            }
+            ParamType::Bytes => Self::Bytes(parse_hex(s)?),
+            ParamType::String => {
+                let unquoted = s.strip_prefix('"').and_then(|s| s.strip_suffix('"'));
+                Self::String(unquoted.unwrap_or(s).to_string())
+            }
+            ParamType::Array(element) | ParamType::FixedArray(element, _) => {
+                let inner = s.strip_prefix('[').and_then(|s| s.strip_suffix(']'));
+                let items = split_top_level(inner.ok_or_else(invalid)?);
+                if let ParamType::FixedArray(_, size) = kind {
+                    if items.len() != *size {
+                        return Err(invalid());
+                    }
+                }
+                let items: Result<Vec<Token>, String> = items
+                    .iter()
+                    .map(|item| Self::parse(element, item))
+                    .collect();
+                Self::Array(items?)
+            }
+            ParamType::Tuple(types) => {
+                let inner = s.strip_prefix('(').and_then(|s| s.strip_suffix(')'));
+                let items = split_top_level(inner.ok_or_else(invalid)?);
+                if items.len() != types.len() {
+                    return Err(invalid());
+                }
+                let items: Result<Vec<Token>, String> = types
+                    .iter()
+                    .zip(&items)
+                    .map(|(t, item)| Self::parse(t, item))
+                    .collect();
+                Self::Tuple(items?)
+            }
+        };
+        return Ok(token);
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Address(address) => write!(f, "0x{}", hex::encode(address)),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Uint(value) => write!(f, "{}", value),
+            Self::Int(value) if value.bit(255) => {
+                write!(f, "-{}", negate(*value))
+            }
+            Self::Int(value) => write!(f, "{}", value),
+            Self::FixedBytes(bytes) | Self::Bytes(bytes) => write!(f, "0x{}", hex::encode(bytes)),
+            Self::String(s) => write!(f, "{:?}", s),
+            Self::Array(items) => write!(f, "[{}]", join(items)),
+            Self::Tuple(items) => write!(f, "({})", join(items)),
+        }
+    }
+}
+
+/// 関数やイベントの引数
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub kind: ParamType,
+    pub indexed: bool, // イベントの引数がtopicに入るか
+}
+
+impl Param {
+    /// `uint256 amount`や`address indexed from`のような引数を読む 名前は省略できる
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        // タプルの型は空白を含むことがあるので対応する閉じ括弧までを型とする
+        let type_end = if s.starts_with('(') || s.starts_with("tuple(") {
+            let close = matching_paren(s).ok_or(format!("{} is invalid parameter", s))?;
+            close
+                + s[close..]
+                    .find(char::is_whitespace)
+                    .unwrap_or(s.len() - close)
+        } else {
+            s.find(char::is_whitespace).unwrap_or(s.len())
+        };
+        let kind = ParamType::parse(&s[..type_end])?;
+        let mut name = String::new();
+        let mut indexed = false;
+        for word in s[type_end..].split_whitespace() {
+            match word {
+                "indexed" => indexed = true,
+                "memory" | "calldata" | "storage" | "payable" => {}
+                word => name = word.to_string(),
+            }
+        }
+        return Ok(Self {
+            name,
+            kind,
+            indexed,
+        });
+    }
+
+    /// JSONのABIの引数 `tuple`は`components`から型を作る
+    pub fn from_json(json: &Value) -> Result<Self, String> {
+        let kind = json["type"]
+            .as_str()
+            .ok_or("type of parameter is missing")?;
+        let kind = match kind.strip_prefix("tuple") {
+            Some(suffix) => {
+                let components = json["components"]
+                    .as_array()
+                    .ok_or("components is missing")?;
+                let types: Result<Vec<String>, String> = components
+                    .iter()
+                    .map(|component| Ok(Self::from_json(component)?.kind.to_string()))
+                    .collect();
+                ParamType::parse(&format!("({}){}", types?.join(","), suffix))?
+            }
+            None => ParamType::parse(kind)?,
+        };
+        return Ok(Self {
+            name: json["name"].as_str().unwrap_or("").to_string(),
+            kind,
+            indexed: json["indexed"].as_bool().unwrap_or(false),
+        });
+    }
+}
+
+/// 関数 返り値の型がわかれば返り値をデコードできる
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub inputs: Vec<Param>,
+    pub outputs: Vec<Param>,
+}
+
+impl Function {
+    /// `name(types)`か、返り値の型をつけた`name(types)(types)`を読む
+    pub fn parse(signature: &str) -> Result<Self, String> {
+        let (name, inputs, rest) = parse_signature(signature)?;
+        let rest = rest.trim();
+        let rest = rest.strip_prefix("returns").unwrap_or(rest).trim();
+        let outputs = if rest.is_empty() {
+            vec![]
+        } else {
+            let (_, outputs, rest) = parse_signature(rest)?;
+            if !rest.trim().is_empty() {
+                return Err(format!("{} is invalid signature", signature));
+            }
+            outputs
+        };
+        return Ok(Self {
+            name,
+            inputs,
+            outputs,
+        });
+    }
+
+    fn from_json(json: &Value) -> Result<Self, String> {
+        return Ok(Self {
+            name: json["name"].as_str().unwrap_or("").to_string(),
+            inputs: params_from_json(&json["inputs"])?,
+            outputs: params_from_json(&json["outputs"])?,
+        });
+    }
+
+    /// 引数の型だけの正規化したシグネチャ
+    pub fn signature(&self) -> String {
+        return signature(&self.name, &self.inputs);
+    }
+
+    /// シグネチャのkeccak256の先頭4byte
+    pub fn selector(&self) -> [u8; 4] {
+        return selector(&self.signature());
+    }
+
+    /// セレクタとABIエンコードした引数を連結したcalldata
+    pub fn encode_input(&self, tokens: &[Token]) -> Result<Vec<u8>, String> {
+        let mut data = self.selector().to_vec();
+        data.extend(encode(&types(&self.inputs), tokens)?);
+        return Ok(data);
+    }
+
+    /// 文字列で書いた引数からcalldataを作る
+    pub fn encode_args(&self, args: &[&str]) -> Result<Vec<u8>, String> {
+        return self.encode_input(&parse_args(&self.inputs, args)?);
+    }
+
+    /// calldataの引数をデコードする
+    pub fn decode_input(&self, data: &[u8]) -> Result<Vec<Token>, String> {
+        if data.len() < 4 || data[..4] != self.selector() {
+            return Err(format!("calldata is not a call to {}", self.signature()));
+        }
+        return decode(&types(&self.inputs), &data[4..]);
+    }
+
+    /// 返り値をデコードする
+    pub fn decode_output(&self, data: &[u8]) -> Result<Vec<Token>, String> {
+        return decode(&types(&self.outputs), data);
+    }
+}
+
+/// イベント
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub name: String,
+    pub inputs: Vec<Param>,
+    pub anonymous: bool, // topic0にシグネチャのハッシュが入らない
+}
+
+impl Event {
+    /// `Transfer(address indexed from, address indexed to, uint256 value)`のようなシグネチャを読む
+    pub fn parse(signature: &str) -> Result<Self, String> {
+        let (name, inputs, _) = parse_signature(signature)?;
+        return Ok(Self {
+            name,
+            inputs,
+            anonymous: false,
+        });
+    }
+
+    pub fn signature(&self) -> String {
+        return signature(&self.name, &self.inputs);
+    }
+
+    /// topic0になるシグネチャのkeccak256
+    pub fn topic(&self) -> [u8; 32] {
+        return util::keccak256(self.signature().as_bytes());
+    }
+
+    /// ログをデコードする indexedな引数はtopicから、それ以外はdataから読む<br/>
+    /// indexedな動的な型の値はtopicにハッシュしか残らないので、ハッシュをbytes32として返す
+    pub fn decode_log(&self, topics: &[H256], data: &[u8]) -> Result<Decoded, String> {
+        let topics = if self.anonymous {
+            topics
+        } else {
+            match topics.split_first() {
+                Some((topic0, rest)) if topic0.0 == self.topic() => rest,
+                _ => return Err(format!("log is not {}", self.signature())),
+            }
+        };
+        let (indexed, unindexed): (Vec<&Param>, Vec<&Param>) =
+            self.inputs.iter().partition(|param| param.indexed);
+        if indexed.len() != topics.len() {
+            return Err(format!("log is not {}", self.signature()));
+        }
+        let unindexed_types: Vec<ParamType> =
+            unindexed.iter().map(|param| param.kind.clone()).collect();
+        let mut values = decode(&unindexed_types, data)?.into_iter();
+        let mut topics = topics.iter();
+        let mut params = vec![];
+        for param in &self.inputs {
+            let token = if !param.indexed {
+                values.next().unwrap()
+            } else if param.kind.is_dynamic() || matches!(param.kind, ParamType::Tuple(_)) {
+                Token::FixedBytes(topics.next().unwrap().as_bytes().to_vec())
+            } else {
+                decode_token(&param.kind, topics.next().unwrap().as_bytes(), 0)?
+            };
+            params.push((param.name.clone(), token));
+        }
+        return Ok(Decoded {
+            name: self.name.clone(),
+            params,
+        });
+    }
+}
+
+/// カスタムエラー
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomError {
+    pub name: String,
+    pub inputs: Vec<Param>,
+}
+
+impl CustomError {
+    pub fn parse(signature: &str) -> Result<Self, String> {
+        let (name, inputs, _) = parse_signature(signature)?;
+        return Ok(Self { name, inputs });
+    }
+
+    pub fn signature(&self) -> String {
+        return signature(&self.name, &self.inputs);
+    }
+
+    /// revertデータの先頭4byte
+    pub fn selector(&self) -> [u8; 4] {
+        return selector(&self.signature());
+    }
+
+    /// revertデータをデコードする
+    pub fn decode(&self, data: &[u8]) -> Result<Decoded, String> {
+        if data.len() < 4 || data[..4] != self.selector() {
+            return Err(format!("revert data is not {}", self.signature()));
+        }
+        let tokens = decode(&types(&self.inputs), &data[4..])?;
+        return Ok(Decoded {
+            name: self.name.clone(),
+            params: self
+                .inputs
+                .iter()
+                .map(|param| param.name.clone())
+                .zip(tokens)
+                .collect(),
+        });
+    }
+}
+
+/// デコードしたイベントかエラー `Name(name=value, ...)`と表示する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    pub name: String,
+    pub params: Vec<(String, Token)>, // 引数の名前と値 名前がなければ空
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(name, token)| {
+                if name.is_empty() {
+                    token.to_string()
+                } else {
+                    format!("{}={}", name, token)
+                }
+            })
+            .collect();
+        write!(f, "{}({})", self.name, params.join(", "))
+    }
+}
+
+/// `Error(string)`のセレクタ
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// `Panic(uint256)`のセレクタ
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Solidityのpanicのコードの意味
+pub fn panic_reason(code: &U256) -> &'static str {
+    if *code > U256::from(0xff) {
+        return "unknown panic code";
+    }
+    return match code.as_u32() {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array encoding",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized internal function",
+        _ => "unknown panic code",
+    };
+}
+
+/// JSONのABI
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Abi {
+    pub constructor: Option<Vec<Param>>,
+    pub functions: Vec<Function>,
+    pub events: Vec<Event>,
+    pub errors: Vec<CustomError>,
+}
+
+impl Abi {
+    /// solcが出力する`[{"type": "function", ...}, ...]`を読む
+    pub fn from_json(json: &Value) -> Result<Self, String> {
+        let items = json.as_array().ok_or("ABI must be an array")?;
+        let mut abi = Self::default();
+        for item in items {
+            match item["type"].as_str().unwrap_or("function") {
+                "function" => abi.functions.push(Function::from_json(item)?),
+                "constructor" => abi.constructor = Some(params_from_json(&item["inputs"])?),
+                "event" => abi.events.push(Event {
+                    name: item["name"].as_str().unwrap_or("").to_string(),
+                    inputs: params_from_json(&item["inputs"])?,
+                    anonymous: item["anonymous"].as_bool().unwrap_or(false),
+                }),
+                "error" => abi.errors.push(CustomError {
+                    name: item["name"].as_str().unwrap_or("").to_string(),
+                    inputs: params_from_json(&item["inputs"])?,
+                }),
+                _ => {} // fallback、receive
+            }
+        }
+        return Ok(abi);
+    }
+
+    /// JSONの文字列を読む
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let json: Value = serde_json::from_str(text).map_err(|e| format!("invalid ABI: {}", e))?;
+        return Self::from_json(&json);
+    }
+
+    /// 名前かシグネチャで関数を探す 名前がオーバーロードされている場合はシグネチャが必要
+    pub fn function(&self, name: &str) -> Result<&Function, String> {
+        if name.contains('(') {
+            let signature = Function::parse(name)?.signature();
+            return self
+                .functions
+                .iter()
+                .find(|function| function.signature() == signature)
+                .ok_or(format!("function {} is not found", name));
+        }
+        let found: Vec<&Function> = self
+            .functions
+            .iter()
+            .filter(|function| function.name == name)
+            .collect();
+        return match found[..] {
+            [function] => Ok(function),
+            [] => Err(format!("function {} is not found", name)),
+            _ => Err(format!(
+                "function {} is overloaded, please use signature",
+                name
+            )),
+        };
+    }
+
+    /// calldataのセレクタから関数を探す
+    pub fn function_by_selector(&self, data: &[u8]) -> Option<&Function> {
+        return self
+            .functions
+            .iter()
+            .find(|function| data.len() >= 4 && data[..4] == function.selector());
+    }
+
+    /// otherの関数、イベント、エラーを加える 同じシグネチャのものは加えない
+    pub fn merge(&mut self, other: Abi) {
+        for function in other.functions {
+            if !self.functions.contains(&function) {
+                self.functions.push(function);
+            }
+        }
+        for event in other.events {
+            if !self.events.contains(&event) {
+                self.events.push(event);
+            }
+        }
+        for error in other.errors {
+            if !self.errors.contains(&error) {
+                self.errors.push(error);
+            }
+        }
+        if self.constructor.is_none() {
+            self.constructor = other.constructor;
+        }
+    }
+
+    /// topic0が一致するイベントでログをデコードする<br/>
+    /// topic0が一致するイベントがなければanonymousなイベントを順に試す
+    pub fn decode_log(&self, topics: &[H256], data: &[u8]) -> Option<Decoded> {
+        let (anonymous, named): (Vec<&Event>, Vec<&Event>) =
+            self.events.iter().partition(|event| event.anonymous);
+        let matched: Vec<&Event> = named
+            .into_iter()
+            .filter(|event| topics.first().is_some_and(|topic| topic.0 == event.topic()))
+            .collect();
+        let candidates = if matched.is_empty() {
+            anonymous
+        } else {
+            matched
+        };
+        return candidates
+            .iter()
+            .find_map(|event| event.decode_log(topics, data).ok());
+    }
+
+    /// revertデータをデコードする `Error(string)`は文字列そのもの、`Panic(uint256)`はコードとその意味、
+    /// カスタムエラーは`Name(name=value, ...)`になる どれにも当てはまらなければNone
+    pub fn decode_revert(&self, data: &[u8]) -> Option<String> {
+        if data.len() < 4 {
+            return None;
+        }
+        if data[..4] == ERROR_SELECTOR {
+            return match decode(&[ParamType::String], &data[4..]).ok()?.pop()? {
+                Token::String(message) => Some(message),
+                _ => None,
+            };
+        }
+        if data[..4] == PANIC_SELECTOR {
+            return match decode(&[ParamType::Uint(256)], &data[4..]).ok()?.pop()? {
+                Token::Uint(code) => {
+                    Some(format!("Panic(0x{:02x}): {}", code, panic_reason(&code)))
+                }
+                _ => None,
+            };
+        }
+        return self
+            .errors
+            .iter()
+            .find_map(|error| error.decode(data).ok())
+            .map(|decoded| decoded.to_string());
+    }
+
+    /// コンストラクタの引数をエンコードする コンストラクタがなければ引数なし
+    pub fn encode_constructor(&self, args: &[&str]) -> Result<Vec<u8>, String> {
+        let inputs = self.constructor.clone().unwrap_or_default();
+        return encode(&types(&inputs), &parse_args(&inputs, args)?);
+    }
+}
+
+/// 文字列で書いた引数をparamsの型の値として読む
+pub fn parse_args(params: &[Param], args: &[&str]) -> Result<Vec<Token>, String> {
+    if params.len() != args.len() {
+        return Err(format!(
+            "expected {} argument(s), but {} given",
+            params.len(),
+            args.len()
+        ));
+    }
+    return params
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| Token::parse(&param.kind, arg))
+        .collect();
+}
+
+/// `name(params)rest`を名前、引数、残りに分ける
+fn parse_signature(signature: &str) -> Result<(String, Vec<Param>, &str), String> {
+    let invalid = || format!("{} is invalid signature", signature);
+    let open = signature.find('(').ok_or_else(invalid)?;
+    let close = open + matching_paren(&signature[open..]).ok_or_else(invalid)?;
+    let name = signature[..open].trim();
+    let name = name.rsplit(' ').next().unwrap_or(name).to_string(); // `function`などを除く
+    let params: Result<Vec<Param>, String> = split_top_level(&signature[open + 1..close - 1])
+        .iter()
+        .map(|param| Param::parse(param))
+        .collect();
+    return Ok((name, params?, &signature[close..]));
+}
+
+fn params_from_json(json: &Value) -> Result<Vec<Param>, String> {
+    return match json.as_array() {
+        Some(params) => params.iter().map(Param::from_json).collect(),
+        None => Ok(vec![]),
+    };
+}
+
+fn types(params: &[Param]) -> Vec<ParamType> {
+    return params.iter().map(|param| param.kind.clone()).collect();
+}
+
+fn signature(name: &str, params: &[Param]) -> String {
+    return format!("{}({})", name, join(&types(params)));
+}
+
+/// シグネチャのkeccak256の先頭4byte
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = util::keccak256(signature.as_bytes());
+    return [hash[0], hash[1], hash[2], hash[3]];
+}
+
+/// 先頭の開き括弧に対応する閉じ括弧の次の位置
+fn matching_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    return None;
+}
+
+/// 括弧と引用符の外側のカンマで区切る 空文字列なら要素なし
+fn split_top_level(s: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '[' if !quoted => depth += 1,
+            ')' | ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                items.push(item.trim().to_string());
+                item.clear();
+                continue;
+            }
+            _ => {}
+        }
+        item.push(c);
+    }
+    if !item.trim().is_empty() || !items.is_empty() {
+        items.push(item.trim().to_string());
+    }
+    return items;
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+    return items.join(",");
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    return hex::decode(hex).map_err(|_| format!("{} is invalid hex", s));
+}
+
+/// 2の補数の符号を反転する
+fn negate(value: U256) -> U256 {
+    return (!value).overflowing_add(U256::one()).0;
+}
+
+/// valueがbitsビットの符号なし整数に収まるか
+fn fits_uint(value: U256, bits: usize) -> bool {
+    return bits == 256 || value.bits() <= bits;
+}
+
+/// 2の補数のvalueがbitsビットの符号付き整数に収まるか
+fn fits_int(value: U256, bits: usize) -> bool {
+    let magnitude = if value.bit(255) { !value } else { value };
+    return magnitude.bits() < bits;
+}
+
+fn word(value: U256) -> [u8; 32] {
+    let mut word = [0; 32];
+    value.to_big_endian(&mut word);
+    return word;
+}
+
+/// 長さの32byteと32byteの倍数に右詰めしたバイト列
+fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = word(bytes.len().into()).to_vec();
+    encoded.extend_from_slice(bytes);
+    encoded.resize(32 + bytes.len().div_ceil(32) * 32, 0);
+    return encoded;
+}
+
+/// typesの値tokensをABIエンコードする
+pub fn encode(types: &[ParamType], tokens: &[Token]) -> Result<Vec<u8>, String> {
+    if types.len() != tokens.len() {
+        return Err(format!(
+            "expected {} value(s), but {} given",
+            types.len(),
+            tokens.len()
+        ));
+    }
+    let head_size: usize = types.iter().map(|t| t.head_size()).sum();
+    let mut head = vec![];
+    let mut tail = vec![];
+    for (kind, token) in types.iter().zip(tokens) {
+        let encoded = encode_token(kind, token)?;
+        if kind.is_dynamic() {
+            head.extend_from_slice(&word((head_size + tail.len()).into()));
+            tail.extend(encoded);
+        } else {
+            head.extend(encoded);
+        }
+    }
+    head.extend(tail);
+    return Ok(head);
+}
+
+fn encode_token(kind: &ParamType, token: &Token) -> Result<Vec<u8>, String> {
+    let mismatch = || format!("{} is not {}", token, kind);
+    let encoded = match (kind, token) {
+        (ParamType::Address, Token::Address(address)) => word(util::h160_to_u256(address)).to_vec(),
+        (ParamType::Bool, Token::Bool(b)) => word((*b as u8).into()).to_vec(),
+        (ParamType::Uint(bits), Token::Uint(value)) if fits_uint(*value, *bits) => {
+            word(*value).to_vec()
+        }
+        (ParamType::Int(bits), Token::Int(value)) if fits_int(*value, *bits) => {
+            word(*value).to_vec()
+        }
+        (ParamType::FixedBytes(size), Token::FixedBytes(bytes)) if bytes.len() == *size => {
+            let mut encoded = bytes.clone();
+            encoded.resize(32, 0);
+            encoded
+        }
+        (ParamType::Bytes, Token::Bytes(bytes)) => encode_bytes(bytes),
+        (ParamType::String, Token::String(s)) => encode_bytes(s.as_bytes()),
+        (ParamType::Array(element), Token::Array(items)) => {
+            let mut encoded = word(items.len().into()).to_vec();
+            encoded.extend(encode(&vec![*element.clone(); items.len()], items)?);
+            encoded
+        }
+        (ParamType::FixedArray(element, size), Token::Array(items)) if items.len() == *size => {
+            encode(&vec![*element.clone(); *size], items)?
+        }
+        (ParamType::Tuple(types), Token::Tuple(items)) => encode(types, items)?,
+        _ => return Err(mismatch()),
+    };
+    return Ok(encoded);
+}
+
+/// ABIエンコードされたdataをtypesの値としてデコードする
+pub fn decode(types: &[ParamType], data: &[u8]) -> Result<Vec<Token>, String> {
+    return decode_at(types, data, 0);
+}
+
+/// baseから始まるtypesの値をデコードする 動的な型のオフセットはbaseからの位置
+fn decode_at(types: &[ParamType], data: &[u8], base: usize) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut position = base;
+    for kind in types {
+        let token = if kind.is_dynamic() {
+            let offset = read_usize(data, position)?;
+            decode_token(kind, data, base + offset)?
+        } else {
+            decode_token(kind, data, position)?
+        };
+        tokens.push(token);
+        position += kind.head_size();
+    }
+    return Ok(tokens);
+}
+
+fn decode_token(kind: &ParamType, data: &[u8], at: usize) -> Result<Token, String> {
+    let token = match kind {
+        ParamType::Address => Token::Address(util::u256_to_h160(&read_word(data, at)?)),
+        ParamType::Bool => match read_word(data, at)? {
+            value if value.is_zero() => Token::Bool(false),
+            value if value == U256::one() => Token::Bool(true),
+            _ => return Err(format!("invalid bool at {}", at)),
+        },
+        ParamType::Uint(bits) => {
+            let value = read_word(data, at)?;
+            if !fits_uint(value, *bits) {
+                return Err(format!("invalid {} at {}", kind, at));
+            }
+            Token::Uint(value)
+        }
+        ParamType::Int(bits) => {
+            let value = read_word(data, at)?;
+            if !fits_int(value, *bits) {
+                return Err(format!("invalid {} at {}", kind, at));
+            }
+            Token::Int(value)
+        }
+        ParamType::FixedBytes(size) => Token::FixedBytes(read_bytes(data, at, *size)?),
+        ParamType::Bytes => Token::Bytes(read_bytes(data, at + 32, read_usize(data, at)?)?),
+        ParamType::String => {
+            let bytes = read_bytes(data, at + 32, read_usize(data, at)?)?;
+            Token::String(String::from_utf8(bytes).map_err(|_| "invalid utf-8 string")?)
+        }
+        ParamType::Array(element) => {
+            let length = read_usize(data, at)?;
+            Token::Array(decode_at(&vec![*element.clone(); length], data, at + 32)?)
+        }
+        ParamType::FixedArray(element, size) => {
+            Token::Array(decode_at(&vec![*element.clone(); *size], data, at)?)
+        }
+        ParamType::Tuple(types) => Token::Tuple(decode_at(types, data, at)?),
+    };
+    return Ok(token);
+}
+
+fn read_word(data: &[u8], at: usize) -> Result<U256, String> {
+    return Ok(U256::from_big_endian(&read_bytes(data, at, 32)?));
+}
+
+/// 長さやオフセット データの長さを超える値は不正とする
+fn read_usize(data: &[u8], at: usize) -> Result<usize, String> {
+    let value = read_word(data, at)?;
+    if value > data.len().into() {
+        return Err(format!("invalid length or offset at {}", at));
+    }
+    return Ok(value.as_usize());
+}
+
+fn read_bytes(data: &[u8], at: usize, length: usize) -> Result<Vec<u8>, String> {
+    return data
+        .get(at..at + length)
+        .map(|bytes| bytes.to_vec())
+        .ok_or(format!(
+            "data is too short to read {} bytes at {}",
+            length, at
+        ));
+}
+
+#[test]
+fn test_parse_type() {
+    assert_eq!(ParamType::parse("uint").unwrap(), ParamType::Uint(256));
+    assert_eq!(
+        ParamType::parse("(address,bytes32)[2][]")
+            .unwrap()
+            .to_string(),
+        "(address,bytes32)[2][]"
+    );
+    assert!(ParamType::parse("uint7").is_err());
+    assert!(ParamType::parse("bytes33").is_err());
+
+    let function = Function::parse(
+        "function swap((address to, uint amount)[] calldata orders) returns (bool)",
+    )
+    .unwrap();
+    assert_eq!(function.signature(), "swap((address,uint256)[])");
+    assert_eq!(function.inputs[0].name, "orders");
+    assert_eq!(function.outputs[0].kind, ParamType::Bool);
+
+    let event =
+        Event::parse("Transfer(address indexed from, address indexed to, uint256 value)").unwrap();
+    assert!(event.inputs[1].indexed && !event.inputs[2].indexed);
+    assert_eq!(
+        hex::encode(event.topic()),
+        "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+    );
+}
+
+#[test]
+fn test_encode_static() {
+    let function = Function::parse("transfer(address,uint256)").unwrap();
+    assert_eq!(hex::encode(function.selector()), "a9059cbb");
+    let data = function
+        .encode_args(&["0x00000000000000000000000000000000000000aa", "0x10"])
+        .unwrap();
+    assert_eq!(
+        hex::encode(&data[4..]),
+        format!("{:0>64}{:0>64}", "aa", "10")
+    );
+    assert_eq!(
+        function.decode_input(&data).unwrap()[1],
+        Token::Uint(16.into())
+    );
+
+    let int = Token::parse(&ParamType::Int(8), "-1").unwrap();
+    assert_eq!(int.to_string(), "-1");
+    assert_eq!(encode(&[ParamType::Int(8)], &[int]).unwrap(), [0xff; 32]);
+    assert!(Token::parse(&ParamType::Int(8), "128").is_err());
+    assert!(Token::parse(&ParamType::Uint(8), "256").is_err());
+}
+
+#[test]
+fn test_encode_dynamic() {
+    // Solidityのドキュメントの例 f(uint256,uint32[],bytes10,bytes)
+    let function = Function::parse("f(uint256,uint32[],bytes10,bytes)").unwrap();
+    let data = function
+        .encode_args(&[
+            "0x123",
+            "[0x456,0x789]",
+            "0x31323334353637383930",
+            "0x48656c6c6f2c20776f726c6421",
+        ])
+        .unwrap();
+    let expected = "8be65246\
+        0000000000000000000000000000000000000000000000000000000000000123\
+        0000000000000000000000000000000000000000000000000000000000000080\
+        3132333435363738393000000000000000000000000000000000000000000000\
+        00000000000000000000000000000000000000000000000000000000000000e0\
+        0000000000000000000000000000000000000000000000000000000000000002\
+        0000000000000000000000000000000000000000000000000000000000000456\
+        0000000000000000000000000000000000000000000000000000000000000789\
+        000000000000000000000000000000000000000000000000000000000000000d\
+        48656c6c6f2c20776f726c642100000000000000000000000000000000000000";
+    assert_eq!(hex::encode(&data), expected);
+    let tokens = function.decode_input(&data).unwrap();
+    assert_eq!(tokens[1].to_string(), "[1110,1929]");
+
+    // 動的な型を含むタプルの配列と文字列
+    let types = vec![
+        ParamType::parse("(string,uint8)[]").unwrap(),
+        ParamType::String,
+    ];
+    let tokens = vec![
+        Token::parse(&types[0], r#"[("a,b",1),("",2)]"#).unwrap(),
+        Token::parse(&types[1], "hello").unwrap(),
+    ];
+    let data = encode(&types, &tokens).unwrap();
+    assert_eq!(decode(&types, &data).unwrap(), tokens);
+    assert_eq!(tokens[0].to_string(), r#"[("a,b",1),("",2)]"#);
+    assert!(decode(&types, &data[..data.len() - 32]).is_err());
+
+    // boolは0か1でなければならない 大きな値もErrになる
+    let word = |byte: usize| {
+        let mut data = vec![0; 32];
+        data[byte] = 1;
+        data
+    };
+    assert_eq!(
+        decode(&[ParamType::Bool], &word(31)),
+        Ok(vec![Token::Bool(true)])
+    );
+    assert!(decode(&[ParamType::Bool], &word(30)).is_err());
+    assert!(decode(&[ParamType::Bool], &word(0)).is_err());
+}
+
+#[test]
+fn test_json_abi() {
+    let abi = Abi::parse(
+        r#"[
+        {"type": "constructor", "inputs": [{"name": "owner", "type": "address"}]},
+        {"type": "function", "name": "get", "inputs": [],
+         "outputs": [{"name": "", "type": "tuple[]", "components": [
+             {"name": "a", "type": "uint256"}, {"name": "b", "type": "bytes"}]}]},
+        {"type": "event", "name": "Set", "anonymous": false,
+         "inputs": [{"name": "value", "type": "uint256", "indexed": true}]},
+        {"type": "error", "name": "Unauthorized", "inputs": []}
+    ]"#,
+    )
+    .unwrap();
+    let get = abi.function("get").unwrap();
+    assert_eq!(get.outputs[0].kind.to_string(), "(uint256,bytes)[]");
+    assert_eq!(abi.function_by_selector(&get.selector()), Some(get));
+    assert!(abi.function("set").is_err());
+    assert!(abi.events[0].inputs[0].indexed);
+    assert_eq!(hex::encode(abi.errors[0].selector()), "82b42900");
+    assert_eq!(
+        abi.encode_constructor(&["0x00000000000000000000000000000000000000aa"])
+            .unwrap()
+            .len(),
+        32
+    );
+}
+
+#[test]
+fn test_decode_log_and_revert() {
+    let mut abi = Abi::default();
+    abi.events.push(
+        Event::parse("Transfer(address indexed from, address indexed to, uint256 value)").unwrap(),
+    );
+    let mut note = Event::parse("Note(string indexed tag, bytes data)").unwrap();
+    note.anonymous = true;
+    abi.events.push(note);
+    abi.errors.push(
+        CustomError::parse("InsufficientBalance(uint256 available, uint256 required)").unwrap(),
+    );
+
+    let topics = vec![
+        H256::from(abi.events[0].topic()),
+        H256::from_low_u64_be(0xaa),
+        H256::from_low_u64_be(0xbb),
+    ];
+    let data = encode(&[ParamType::Uint(256)], &[Token::Uint(5.into())]).unwrap();
+    assert_eq!(
+        abi.decode_log(&topics, &data).unwrap().to_string(),
+        format!(
+            "Transfer(from=0x{:0>40}, to=0x{:0>40}, value=5)",
+            "aa", "bb"
+        )
+    );
+    // anonymousなイベントはtopic0がない indexedな文字列はハッシュになる
+    let hash = H256::from(util::keccak256(b"tag"));
+    let data = encode(&[ParamType::Bytes], &[Token::Bytes(vec![1, 2])]).unwrap();
+    let decoded = abi.decode_log(&[hash], &data).unwrap();
+    assert_eq!(decoded.name, "Note");
+    assert_eq!(decoded.params[1].1, Token::Bytes(vec![1, 2]));
+    assert!(abi.decode_log(&[], &[]).is_none());
+    // topic0が一致するイベントがあればanonymousなイベントは試さない
+    let topics = vec![H256::from(abi.events[0].topic())];
+    assert!(abi.decode_log(&topics, &data).is_none());
+
+    let function = Function::parse("Error(string)").unwrap();
+    let error = function.encode_args(&["not owner"]).unwrap();
+    assert_eq!(abi.decode_revert(&error).unwrap(), "not owner");
+    let panic = Function::parse("Panic(uint256)").unwrap();
+    let panic = panic.encode_args(&["0x11"]).unwrap();
+    assert_eq!(
+        abi.decode_revert(&panic).unwrap(),
+        "Panic(0x11): arithmetic overflow or underflow"
+    );
+    let custom = abi.errors[0].selector().to_vec();
+    let custom = [
+        custom,
+        encode(
+            &[ParamType::Uint(256), ParamType::Uint(256)],
+            &[Token::Uint(1.into()), Token::Uint(2.into())],
+        )
+        .unwrap(),
+    ]
+    .concat();
+    assert_eq!(
+        abi.decode_revert(&custom).unwrap(),
+        "InsufficientBalance(available=1, required=2)"
+    );
+    assert!(abi.decode_revert(&[0xde, 0xad, 0xbe, 0xef]).is_none());
+}
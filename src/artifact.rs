@@ -0,0 +1,340 @@
+//! Contract artifact
+//!
+//! solcのstandard JSONの出力、Hardhatのbuild-info、Foundry、Hardhat、Truffleのビルド結果から
+//! コントラクトのABIとバイトコードを読み込むモジュール<br/>
+//! ライブラリのリンクのプレースホルダはデプロイ時にアドレスで置き換える
+use super::abi::Abi;
+use super::util;
+use ethereum_types::H160;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// バイトコード中のライブラリのアドレスを埋める位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkReference {
+    pub file: String, // ライブラリのソースファイル
+    pub name: String, // ライブラリの名前
+    pub offsets: Vec<usize>,
+}
+
+/// 1つのコントラクトのビルド結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub name: String,
+    pub source: String, // ソースファイルのパス わからなければ空
+    pub abi: Abi,
+    pub bytecode: String, // 初期化コードの16進文字列 リンクのプレースホルダを含むことがある
+    pub deployed_bytecode: String,
+    pub link_references: Vec<LinkReference>,
+}
+
+impl Artifact {
+    /// `ソースファイル:名前`
+    pub fn id(&self) -> String {
+        return format!("{}:{}", self.source, self.name);
+    }
+
+    /// librariesのアドレスでリンクした初期化コード<br/>
+    /// librariesのキーはライブラリの名前か`ソースファイル:名前`
+    pub fn link(&self, libraries: &[(String, H160)]) -> Result<Vec<u8>, String> {
+        if self.bytecode.is_empty() {
+            return Err(format!(
+                "{} has no bytecode (abstract contract or interface)",
+                self.name
+            ));
+        }
+        let mut hex = self.bytecode.clone();
+        for reference in &self.link_references {
+            let address = find_library(libraries, &reference.file, &reference.name)
+                .ok_or(format!("library {} is not linked", reference.name))?;
+            for offset in &reference.offsets {
+                let range = offset * 2..offset * 2 + 40;
+                if hex.get(range.clone()).is_none() {
+                    return Err(format!("invalid link reference of {}", reference.name));
+                }
+                hex.replace_range(range, &hex::encode(address));
+            }
+        }
+        // linkReferencesのないTruffleやsolcの古い形式のプレースホルダ
+        while let Some(start) = hex.find("__") {
+            let placeholder = hex.get(start..start + 40).unwrap_or(&hex[start..]);
+            let name = placeholder
+                .trim_matches(|c| c == '_' || c == '$')
+                .to_string();
+            let address = find_library(libraries, "", &name)
+                .ok_or(format!("library {} is not linked", name))?;
+            hex.replace_range(start..start + placeholder.len(), &hex::encode(address));
+        }
+        return hex::decode(&hex).map_err(|e| format!("invalid bytecode of {}: {}", self.name, e));
+    }
+}
+
+/// ライブラリを名前、`ソースファイル:名前`、solc 0.5以降のプレースホルダのハッシュで探す
+fn find_library(libraries: &[(String, H160)], file: &str, name: &str) -> Option<H160> {
+    return libraries.iter().find_map(|(key, address)| {
+        let id = match key.rsplit_once(':') {
+            Some((key_file, key_name)) => {
+                if key_name == name && (file.is_empty() || key_file == file) {
+                    return Some(*address);
+                }
+                key.clone()
+            }
+            None if key == name => return Some(*address),
+            None => format!("{}:{}", file, key),
+        };
+        // プレースホルダは`ソースファイル:名前`のkeccak256の先頭17byte
+        let hash = hex::encode(&util::keccak256(id.as_bytes())[..17]);
+        return (hash == name).then_some(*address);
+    });
+}
+
+/// 読み込んだコントラクトの一覧
+#[derive(Debug, Clone, Default)]
+pub struct Artifacts {
+    artifacts: Vec<Artifact>,
+}
+
+impl Artifacts {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Artifact> {
+        return self.artifacts.iter();
+    }
+
+    /// ファイルかディレクトリを読み込む<br/>
+    /// ディレクトリはFoundryのout、Hardhatのartifacts、Truffleのbuild/contractsのように
+    /// 下にあるJSONファイルをすべて読み、artifactとして読めないファイルは無視する
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let path = Path::new(path);
+        if path.is_dir() {
+            return self.load_dir(path);
+        }
+        let json = read_json(path)?;
+        return self.load_json(&json, &file_stem(path));
+    }
+
+    fn load_dir(&mut self, dir: &Path) -> Result<(), String> {
+        let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let name = path.file_name().unwrap().to_string_lossy();
+            if path.is_dir() {
+                // Hardhatのbuild-infoは同じコントラクトを含むので読まない
+                if name != "build-info" {
+                    self.load_dir(&path)?;
+                }
+            } else if name.ends_with(".json") && !name.ends_with(".dbg.json") {
+                if let Ok(json) = read_json(&path) {
+                    let _ = self.load_json(&json, &file_stem(&path));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// JSONの形式を判定して読み込む stemはFoundryのartifactのファイル名(コントラクトの名前)
+    pub fn load_json(&mut self, json: &Value, stem: &str) -> Result<(), String> {
+        if json["output"].is_object() {
+            return self.load_standard_json(&json["output"]);
+        }
+        if json["contracts"].is_object() {
+            return self.load_standard_json(json);
+        }
+        if json["abi"].is_array() {
+            let artifact = if json["bytecode"].is_object() {
+                foundry(json, stem)?
+            } else {
+                // HardhatとTruffleはbytecodeが文字列
+                Artifact {
+                    name: json["contractName"]
+                        .as_str()
+                        .ok_or("contractName is missing")?
+                        .to_string(),
+                    source: json["sourceName"]
+                        .as_str()
+                        .or(json["sourcePath"].as_str())
+                        .unwrap_or("")
+                        .to_string(),
+                    abi: Abi::from_json(&json["abi"])?,
+                    bytecode: strip_hex(&json["bytecode"]),
+                    deployed_bytecode: strip_hex(&json["deployedBytecode"]),
+                    link_references: link_references(&json["linkReferences"])?,
+                }
+            };
+            self.artifacts.push(artifact);
+            return Ok(());
+        }
+        return Err("unknown format: expected standard JSON output or artifact".to_string());
+    }
+
+    fn load_standard_json(&mut self, output: &Value) -> Result<(), String> {
+        let contracts = output["contracts"]
+            .as_object()
+            .ok_or("contracts is missing")?;
+        for (source, contracts) in contracts {
+            for (name, contract) in contracts.as_object().into_iter().flatten() {
+                let evm = &contract["evm"];
+                self.artifacts.push(Artifact {
+                    name: name.clone(),
+                    source: source.clone(),
+                    abi: Abi::from_json(&contract["abi"])?,
+                    bytecode: strip_hex(&evm["bytecode"]["object"]),
+                    deployed_bytecode: strip_hex(&evm["deployedBytecode"]["object"]),
+                    link_references: link_references(&evm["bytecode"]["linkReferences"])?,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// 名前か`ソースファイル:名前`でコントラクトを探す
+    pub fn find(&self, name: &str) -> Result<&Artifact, String> {
+        let found: Vec<&Artifact> = self
+            .artifacts
+            .iter()
+            .filter(|artifact| artifact.name == name || artifact.id() == name)
+            .collect();
+        return match found[..] {
+            [artifact] => Ok(artifact),
+            [] => Err(format!("contract {} is not found", name)),
+            _ => Err(format!(
+                "contract {} is ambiguous, please use <source>:<name>",
+                name
+            )),
+        };
+    }
+}
+
+/// Foundryのartifact コントラクトの名前はmetadataのcompilationTarget、なければファイル名
+fn foundry(json: &Value, stem: &str) -> Result<Artifact, String> {
+    let target = json["metadata"]["settings"]["compilationTarget"]
+        .as_object()
+        .and_then(|target| target.iter().next());
+    let (source, name) = match target {
+        Some((source, name)) => (source.clone(), name.as_str().unwrap_or(stem).to_string()),
+        None => (
+            json["ast"]["absolutePath"]
+                .as_str()
+                .unwrap_or("")
+                .to_string(),
+            stem.to_string(),
+        ),
+    };
+    return Ok(Artifact {
+        name,
+        source,
+        abi: Abi::from_json(&json["abi"])?,
+        bytecode: strip_hex(&json["bytecode"]["object"]),
+        deployed_bytecode: strip_hex(&json["deployedBytecode"]["object"]),
+        link_references: link_references(&json["bytecode"]["linkReferences"])?,
+    });
+}
+
+/// `{"ファイル": {"名前": [{"start": 0, "length": 20}]}}`
+fn link_references(json: &Value) -> Result<Vec<LinkReference>, String> {
+    let mut references = vec![];
+    for (file, libraries) in json.as_object().into_iter().flatten() {
+        for (name, offsets) in libraries.as_object().into_iter().flatten() {
+            let offsets: Option<Vec<usize>> = offsets
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|offset| Some(offset["start"].as_u64()? as usize))
+                .collect();
+            references.push(LinkReference {
+                file: file.clone(),
+                name: name.clone(),
+                offsets: offsets.ok_or(format!("invalid link reference of {}", name))?,
+            });
+        }
+    }
+    return Ok(references);
+}
+
+fn strip_hex(value: &Value) -> String {
+    let hex = value.as_str().unwrap_or("");
+    return hex.strip_prefix("0x").unwrap_or(hex).to_string();
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+fn file_stem(path: &Path) -> String {
+    return path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+}
+
+#[test]
+fn test_load_artifacts() {
+    let library = H160::from_low_u64_be(0x1234);
+    let placeholder = format!(
+        "__${}$__",
+        hex::encode(&util::keccak256(b"src/Math.sol:Math")[..17])
+    );
+    let mut artifacts = Artifacts::new();
+    // solcのstandard JSONの出力
+    let standard = serde_json::json!({
+        "contracts": {"src/C.sol": {"C": {
+            "abi": [{"type": "constructor", "inputs": [{"name": "x", "type": "uint256"}]}],
+            "evm": {
+                "bytecode": {
+                    "object": format!("73{}00", placeholder),
+                    "linkReferences": {"src/Math.sol": {"Math": [{"start": 1, "length": 20}]}}
+                },
+                "deployedBytecode": {"object": "00"}
+            }
+        }}}
+    });
+    artifacts.load_json(&standard, "").unwrap();
+    // Hardhatのartifact
+    let hardhat = serde_json::json!({
+        "_format": "hh-sol-artifact-1",
+        "contractName": "C",
+        "sourceName": "contracts/C.sol",
+        "abi": [],
+        "bytecode": "0x6000",
+        "deployedBytecode": "0x",
+        "linkReferences": {}
+    });
+    artifacts.load_json(&hardhat, "C").unwrap();
+    // Truffleのartifact リンクのプレースホルダは名前
+    let truffle = serde_json::json!({
+        "contractName": "T",
+        "abi": [],
+        "bytecode": format!("0x73{:_<40}00", "__Math"),
+        "deployedBytecode": "0x00",
+        "sourcePath": "/project/contracts/T.sol"
+    });
+    artifacts.load_json(&truffle, "T").unwrap();
+
+    assert!(artifacts.find("C").is_err());
+    let c = artifacts.find("src/C.sol:C").unwrap();
+    assert!(c.abi.constructor.is_some());
+    assert_eq!(c.link(&[]), Err("library Math is not linked".to_string()));
+    let linked = c.link(&[("Math".to_string(), library)]).unwrap();
+    assert_eq!(linked[1..21], library[..]);
+    // ハッシュのプレースホルダは`ソースファイル:名前`で解決できる
+    let mut unresolved = c.clone();
+    unresolved.link_references.clear();
+    let linked = unresolved
+        .link(&[("src/Math.sol:Math".to_string(), library)])
+        .unwrap();
+    assert_eq!(linked[1..21], library[..]);
+
+    let t = artifacts.find("T").unwrap();
+    assert_eq!(t.link(&[("Math".to_string(), library)]).unwrap().len(), 22);
+    assert_eq!(
+        artifacts.find("contracts/C.sol:C").unwrap().link(&[]),
+        Ok(vec![0x60, 0x00])
+    );
+}
@@ -0,0 +1,343 @@
+//! Assembler
+//!
+//! ニーモニックで書かれたソースをEVMバイトコードに変換するモジュール
+//!
+//! ```text
+//! #define SIZE 0x20      ; 定数の定義
+//! start:                 ; ラベルはJUMPDESTになる
+//!     PUSH1 0x05         ; 幅を指定したPUSH
+//!     PUSH SIZE          ; 値に合わせて幅を自動で選ぶPUSH
+//!     PUSH @start        ; ラベルの位置をPUSHする
+//!     JUMP               // コメントは;か//で始める
+//! #bytes 0xfe            ; 任意のバイト列をそのまま埋め込む
+//! ```
+use super::opcode;
+use ethereum_types::U256;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// ソースのうちPUSHされる値
+#[derive(Debug, Clone)]
+enum Operand {
+    Value(U256),
+    Label(String),
+}
+
+/// ソースの1要素
+#[derive(Debug, Clone)]
+enum Item {
+    Op(u8),
+    // widthがNoneなら値に合わせて幅を選ぶ
+    Push {
+        width: Option<usize>,
+        operand: Operand,
+        line: usize,
+    },
+    Label(String),
+    Bytes(Vec<u8>),
+}
+
+/// ソースをバイトコードに変換する<br/>
+/// エラーの場合は行番号つきのメッセージを返す
+pub fn assemble(src: &str) -> Result<Vec<u8>, String> {
+    let items = parse(src)?;
+    let widths = layout(&items);
+    let labels = label_offsets(&items, &widths);
+
+    let mut code = Vec::new();
+    for (item, width) in items.iter().zip(&widths) {
+        match item {
+            Item::Op(opcode) => code.push(*opcode),
+            Item::Push { operand, line, .. } => {
+                let value = resolve(operand, &labels, *line)?;
+                if *width == 0 {
+                    code.push(0x5f);
+                    continue;
+                }
+                if byte_length(&value) > *width {
+                    return Err(format!(
+                        "line {}: 0x{:x} does not fit in PUSH{}",
+                        line, value, width
+                    ));
+                }
+                let mut bytes = [0; 32];
+                value.to_big_endian(&mut bytes);
+                code.push(0x5f + *width as u8);
+                code.extend_from_slice(&bytes[32 - width..]);
+            }
+            Item::Label(_) => code.push(0x5b),
+            Item::Bytes(bytes) => code.extend_from_slice(bytes),
+        }
+    }
+    return Ok(code);
+}
+
+/// ソースを行ごとに読んで要素の列にする
+fn parse(src: &str) -> Result<Vec<Item>, String> {
+    let mut items = Vec::new();
+    let mut defines: HashMap<String, U256> = HashMap::new();
+    let mut labels: Vec<String> = Vec::new();
+
+    for (i, line) in src.lines().enumerate() {
+        let line_number = i + 1;
+        let mut rest = strip_comment(line).trim();
+
+        if rest.starts_with('#') {
+            let mut tokens = rest.split_whitespace();
+            match (tokens.next(), tokens.next(), tokens.next(), tokens.next()) {
+                (Some("#define"), Some(name), Some(value), None) => {
+                    let value = parse_value(value, &defines)
+                        .ok_or_else(|| format!("line {}: invalid value {}", line_number, value))?;
+                    defines.insert(name.to_string(), value);
+                }
+                (Some("#bytes"), Some(value), None, None) => {
+                    let hex = value.strip_prefix("0x").unwrap_or(value);
+                    let bytes = hex::decode(hex)
+                        .map_err(|_| format!("line {}: invalid bytes {}", line_number, value))?;
+                    items.push(Item::Bytes(bytes));
+                }
+                _ => return Err(format!("line {}: invalid directive {}", line_number, rest)),
+            }
+            continue;
+        }
+
+        // label: の後に命令を続けて書くこともできる
+        if let Some(pos) = rest.find(':') {
+            let label = rest[..pos].trim();
+            if !is_identifier(label) {
+                return Err(format!("line {}: invalid label {}", line_number, label));
+            }
+            if labels.iter().any(|l| l == label) {
+                return Err(format!(
+                    "line {}: label {} is already defined",
+                    line_number, label
+                ));
+            }
+            labels.push(label.to_string());
+            items.push(Item::Label(label.to_string()));
+            rest = rest[pos + 1..].trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let mut tokens = rest.split_whitespace();
+        let mnemonic = tokens.next().unwrap().to_uppercase();
+        let argument = tokens.next();
+        if tokens.next().is_some() {
+            return Err(format!("line {}: too many operands", line_number));
+        }
+
+        let width = match &mnemonic[..] {
+            "PUSH" => None,
+            "PUSH0" => Some(0),
+            m => match m.strip_prefix("PUSH").map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if (1..=32).contains(&n) => Some(n),
+                Some(_) => return Err(format!("line {}: unknown opcode {}", line_number, m)),
+                None => {
+                    let opcode = opcode::from_name(m)
+                        .ok_or_else(|| format!("line {}: unknown opcode {}", line_number, m))?;
+                    if argument.is_some() {
+                        return Err(format!("line {}: {} takes no operand", line_number, m));
+                    }
+                    items.push(Item::Op(opcode));
+                    continue;
+                }
+            },
+        };
+
+        let operand = match (width, argument) {
+            (Some(0), None) => Operand::Value(U256::zero()),
+            (Some(0), Some(_)) => {
+                return Err(format!("line {}: PUSH0 takes no operand", line_number))
+            }
+            (_, None) => return Err(format!("line {}: {} needs operand", line_number, mnemonic)),
+            (_, Some(arg)) => match arg.strip_prefix('@') {
+                Some(label) => Operand::Label(label.to_string()),
+                None => Operand::Value(
+                    parse_value(arg, &defines)
+                        .ok_or_else(|| format!("line {}: invalid value {}", line_number, arg))?,
+                ),
+            },
+        };
+        items.push(Item::Push {
+            width,
+            operand,
+            line: line_number,
+        });
+    }
+
+    // 未定義のラベルを参照していないか確認する
+    for item in &items {
+        if let Item::Push {
+            operand: Operand::Label(label),
+            line,
+            ..
+        } = item
+        {
+            if !labels.contains(label) {
+                return Err(format!("line {}: label {} is not defined", line, label));
+            }
+        }
+    }
+    return Ok(items);
+}
+
+/// 各要素の即値の幅を決める<br/>
+/// ラベルのPUSHの幅はラベルの位置に依存するので、幅が変わらなくなるまで繰り返す
+fn layout(items: &[Item]) -> Vec<usize> {
+    let mut widths: Vec<usize> = items
+        .iter()
+        .map(|item| match item {
+            Item::Push {
+                width: Some(width), ..
+            } => *width,
+            Item::Push {
+                width: None,
+                operand: Operand::Value(value),
+                ..
+            } => byte_length(value),
+            // 最初は1byteで足りると仮定する
+            Item::Push { width: None, .. } => 1,
+            _ => 0,
+        })
+        .collect();
+
+    loop {
+        let labels = label_offsets(items, &widths);
+        let mut changed = false;
+        for (item, width) in items.iter().zip(widths.iter_mut()) {
+            if let Item::Push {
+                width: None,
+                operand: Operand::Label(label),
+                ..
+            } = item
+            {
+                let needed = byte_length(&U256::from(labels[label]));
+                // ラベルの位置は後ろにずれる一方なので幅は広げるだけでよい
+                if needed > *width {
+                    *width = needed;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return widths;
+        }
+    }
+}
+
+/// ラベルの名前とコード中の位置の対応
+fn label_offsets(items: &[Item], widths: &[usize]) -> HashMap<String, usize> {
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    for (item, width) in items.iter().zip(widths) {
+        offset += match item {
+            Item::Op(_) => 1,
+            Item::Push { .. } => 1 + width,
+            Item::Label(label) => {
+                labels.insert(label.clone(), offset);
+                1
+            }
+            Item::Bytes(bytes) => bytes.len(),
+        };
+    }
+    return labels;
+}
+
+fn resolve(
+    operand: &Operand,
+    labels: &HashMap<String, usize>,
+    line: usize,
+) -> Result<U256, String> {
+    return match operand {
+        Operand::Value(value) => Ok(*value),
+        Operand::Label(label) => labels
+            .get(label)
+            .map(|&offset| U256::from(offset))
+            .ok_or_else(|| format!("line {}: label {} is not defined", line, label)),
+    };
+}
+
+/// 16進数(0x..)、10進数、#defineで定義した定数のいずれかを値として読む
+fn parse_value(s: &str, defines: &HashMap<String, U256>) -> Option<U256> {
+    if let Some(value) = defines.get(s) {
+        return Some(*value);
+    }
+    if let Some(hex) = s.strip_prefix("0x") {
+        if hex.is_empty() || hex.len() > 64 {
+            return None;
+        }
+        return U256::from_str(hex).ok();
+    }
+    return U256::from_dec_str(s).ok();
+}
+
+/// 値を表すのに必要な最小のバイト数 0も1byteで表す
+fn byte_length(value: &U256) -> usize {
+    return value.bits().div_ceil(8).max(1);
+}
+
+fn strip_comment(line: &str) -> &str {
+    let end = [line.find(';'), line.find("//")]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+        .unwrap_or(line.len());
+    return &line[..end];
+}
+
+fn is_identifier(s: &str) -> bool {
+    return !s.is_empty()
+        && !s.starts_with(|c: char| c.is_ascii_digit())
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+#[test]
+fn test_assemble() {
+    let code = assemble("PUSH1 0x05\nPUSH1 0x04\nADD").unwrap();
+    assert_eq!(hex::encode(code), "6005600401");
+}
+
+#[test]
+fn test_assemble_label_and_define() {
+    let src = "
+        #define SIZE 0x20       ; 定数
+        PUSH @end               // 前方参照
+        JUMP
+        PUSH SIZE
+        PUSH 256
+    end:
+        PUSH0
+        keccak256
+        STOP
+    ";
+    let code = assemble(src).unwrap();
+    assert_eq!(hex::encode(code), "60085660206101005b5f2000");
+}
+
+#[test]
+fn test_assemble_label_width() {
+    // ラベルが255より後ろにあるとPUSHの幅が2byteに広がる
+    let src = format!("PUSH @end\nJUMP\n#bytes 0x{}\nend:", "00".repeat(300));
+    let code = assemble(&src).unwrap();
+    assert_eq!(&code[..4], &[0x61, 0x01, 0x30, 0x56]);
+    assert_eq!(code[0x130], 0x5b);
+}
+
+#[test]
+fn test_assemble_error() {
+    assert_eq!(
+        assemble("PUSH1 0x0100").unwrap_err(),
+        "line 1: 0x100 does not fit in PUSH1"
+    );
+    assert_eq!(
+        assemble("ADD\nFOO").unwrap_err(),
+        "line 2: unknown opcode FOO"
+    );
+    assert_eq!(
+        assemble("PUSH @nowhere").unwrap_err(),
+        "line 1: label nowhere is not defined"
+    );
+}
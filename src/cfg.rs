@@ -0,0 +1,297 @@
+//! Control flow graph
+//!
+//! バイトコードを基本ブロックに分割し、制御フローグラフを作るモジュール
+use super::disasm::{self, Instruction};
+use ethereum_types::U256;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// ブロック末尾のジャンプ命令の飛び先
+#[derive(Debug, Clone, PartialEq)]
+pub enum JumpTarget {
+    Static(usize), // 定数として求まったJUMPDESTの位置
+    Invalid(U256), // 定数として求まったがJUMPDESTではない位置
+    Dynamic,       // 静的には求まらない
+}
+
+/// 基本ブロック 途中に飛び込んだり途中から抜けたりしない命令列
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize, // 先頭の命令の位置
+    pub end: usize,   // 末尾の命令の次の位置
+    pub instructions: Vec<Instruction>,
+    pub jump: Option<JumpTarget>, // JUMP, JUMPIで終わるブロックの飛び先
+}
+
+impl BasicBlock {
+    fn last(&self) -> &Instruction {
+        return self.instructions.last().unwrap();
+    }
+
+    /// 次のブロックに実行が続くことがあるか
+    fn falls_through(&self) -> bool {
+        return !matches!(self.last().opcode, 0x56 | 0x00 | 0xf3 | 0xfd | 0xfe | 0xff)
+            && self.last().info.is_some();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Jump,         // JUMPによる移動
+    JumpTaken,    // JUMPIの条件が真の場合
+    JumpNotTaken, // JUMPIの条件が偽の場合
+    Fallthrough,  // 次のJUMPDESTにそのまま進む場合
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Jump => "jump",
+            EdgeKind::JumpTaken => "true",
+            EdgeKind::JumpNotTaken => "false",
+            EdgeKind::Fallthrough => "fallthrough",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub from: usize, // ブロックの先頭の位置
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// 制御フローグラフ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+}
+
+/// コードを基本ブロックに分割し、ブロック間の遷移を求める<br/>
+/// 飛び先はブロック内でPUSHされた定数だけを追跡して求める
+pub fn build(code: &[u8]) -> Cfg {
+    let instructions = disasm::disassemble(code).instructions;
+    let jumpdests: HashSet<usize> = instructions
+        .iter()
+        .filter(|i| i.is_jumpdest())
+        .map(|i| i.offset)
+        .collect();
+
+    // JUMPDESTの手前と、ジャンプ命令・停止命令の後ろでブロックを区切る
+    let mut blocks: Vec<BasicBlock> = Vec::new();
+    let mut current: Vec<Instruction> = Vec::new();
+    for instruction in instructions {
+        if instruction.is_jumpdest() && !current.is_empty() {
+            blocks.push(new_block(std::mem::take(&mut current), &jumpdests));
+        }
+        let terminates = instruction.info.is_none()
+            || matches!(
+                instruction.opcode,
+                0x56 | 0x57 | 0x00 | 0xf3 | 0xfd | 0xfe | 0xff
+            );
+        current.push(instruction);
+        if terminates {
+            blocks.push(new_block(std::mem::take(&mut current), &jumpdests));
+        }
+    }
+    if !current.is_empty() {
+        blocks.push(new_block(current, &jumpdests));
+    }
+
+    let mut edges = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let taken = match block.last().opcode {
+            0x57 => EdgeKind::JumpTaken,
+            _ => EdgeKind::Jump,
+        };
+        if let Some(JumpTarget::Static(to)) = block.jump {
+            edges.push(Edge {
+                from: block.start,
+                to,
+                kind: taken,
+            });
+        }
+        if let Some(next) = blocks.get(i + 1) {
+            if block.falls_through() {
+                let kind = match block.last().opcode {
+                    0x57 => EdgeKind::JumpNotTaken,
+                    _ => EdgeKind::Fallthrough,
+                };
+                edges.push(Edge {
+                    from: block.start,
+                    to: next.start,
+                    kind,
+                });
+            }
+        }
+    }
+
+    return Cfg { blocks, edges };
+}
+
+fn new_block(instructions: Vec<Instruction>, jumpdests: &HashSet<usize>) -> BasicBlock {
+    let first = &instructions[0];
+    let last = instructions.last().unwrap();
+    let jump = match last.opcode {
+        0x56 | 0x57 => Some(match jump_target(&instructions) {
+            Some(target)
+                if target <= U256::from(usize::MAX) && jumpdests.contains(&target.as_usize()) =>
+            {
+                JumpTarget::Static(target.as_usize())
+            }
+            Some(target) => JumpTarget::Invalid(target),
+            None => JumpTarget::Dynamic,
+        }),
+        _ => None,
+    };
+    return BasicBlock {
+        start: first.offset,
+        end: last.offset + last.size(),
+        instructions,
+        jump,
+    };
+}
+
+/// ブロック内でスタックの値を追跡し、末尾のジャンプ命令の飛び先を求める<br/>
+/// ブロックに入る時点のスタックの値や、演算の結果は不明(None)として扱う
+fn jump_target(instructions: &[Instruction]) -> Option<U256> {
+    let mut stack: Vec<Option<U256>> = Vec::new();
+    let (last, body) = instructions.split_last().unwrap();
+    for instruction in body {
+        let info = instruction.info?;
+        // 足りない分はブロックに入る前から積まれていた不明な値
+        let depth = info.inputs.max(info.outputs);
+        while stack.len() < depth {
+            stack.insert(0, None);
+        }
+        match instruction.opcode {
+            0x5f..=0x7f => stack.push(Some(U256::from_big_endian(&instruction.immediate))),
+            0x58 => stack.push(Some(U256::from(instruction.offset))),
+            0x80..=0x8f => {
+                let value = stack[stack.len() - info.inputs];
+                stack.push(value);
+            }
+            0x90..=0x9f => {
+                let top = stack.len() - 1;
+                stack.swap(top, top - (info.inputs - 1));
+            }
+            _ => {
+                stack.truncate(stack.len() - info.inputs);
+                stack.extend(std::iter::repeat_n(None, info.outputs));
+            }
+        }
+    }
+    debug_assert!(matches!(last.opcode, 0x56 | 0x57));
+    return stack.pop().flatten();
+}
+
+impl Cfg {
+    /// Graphvizのdot形式で出力する
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box fontname=\"monospace\"];").unwrap();
+        for block in &self.blocks {
+            let mut label = String::new();
+            for instruction in &block.instructions {
+                write!(label, "{}\\l", instruction).unwrap();
+            }
+            match &block.jump {
+                Some(JumpTarget::Dynamic) => label += "(dynamic jump)\\l",
+                Some(JumpTarget::Invalid(target)) => {
+                    write!(label, "(invalid jump to 0x{:x})\\l", target).unwrap()
+                }
+                _ => {}
+            }
+            writeln!(dot, "    b{:04x} [label=\"{}\"];", block.start, label).unwrap();
+        }
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "    b{:04x} -> b{:04x} [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                edge.kind.name()
+            )
+            .unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        return dot;
+    }
+
+    /// JSON形式で出力する
+    pub fn to_json(&self) -> serde_json::Value {
+        let blocks: Vec<serde_json::Value> = self
+            .blocks
+            .iter()
+            .map(|block| {
+                let jump = match &block.jump {
+                    Some(JumpTarget::Static(target)) => json!(target),
+                    Some(JumpTarget::Invalid(target)) => json!(format!("invalid 0x{:x}", target)),
+                    Some(JumpTarget::Dynamic) => json!("dynamic"),
+                    None => serde_json::Value::Null,
+                };
+                json!({
+                    "start": block.start,
+                    "end": block.end,
+                    "instructions": block.instructions.iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+                    "jump": jump,
+                })
+            })
+            .collect();
+        let edges: Vec<serde_json::Value> = self
+            .edges
+            .iter()
+            .map(|edge| json!({"from": edge.from, "to": edge.to, "kind": edge.kind.name()}))
+            .collect();
+        return json!({ "blocks": blocks, "edges": edges });
+    }
+}
+
+#[test]
+fn test_build_cfg() {
+    // 0000: PUSH1 0x01, PUSH1 0x08, JUMPI
+    // 0005: PUSH1 0x00, STOP
+    // 0008: JUMPDEST, PUSH1 0x01, PUSH1 0x10, SWAP1, POP, JUMP (SWAPを挟んでも追跡できる)
+    // 0010: JUMPDEST, STOP
+    let code = hex::decode("60016008576000005b600160109050565b00").unwrap();
+    let cfg = build(&code);
+    let starts: Vec<usize> = cfg.blocks.iter().map(|b| b.start).collect();
+    assert_eq!(starts, vec![0x00, 0x05, 0x08, 0x10]);
+    assert_eq!(cfg.blocks[0].jump, Some(JumpTarget::Static(0x08)));
+    assert_eq!(cfg.blocks[2].jump, Some(JumpTarget::Static(0x10)));
+    assert_eq!(
+        cfg.edges,
+        vec![
+            Edge {
+                from: 0x00,
+                to: 0x08,
+                kind: EdgeKind::JumpTaken
+            },
+            Edge {
+                from: 0x00,
+                to: 0x05,
+                kind: EdgeKind::JumpNotTaken
+            },
+            Edge {
+                from: 0x08,
+                to: 0x10,
+                kind: EdgeKind::Jump
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_build_cfg_dynamic_jump() {
+    // 飛び先が演算の結果なら求まらない 定数でもJUMPDESTでなければ不正なジャンプ
+    let code = hex::decode("6001600201565b600056").unwrap();
+    let cfg = build(&code);
+    assert_eq!(cfg.blocks[0].jump, Some(JumpTarget::Dynamic));
+    assert_eq!(cfg.blocks[1].jump, Some(JumpTarget::Invalid(U256::zero())));
+    assert!(cfg.edges.is_empty());
+    assert!(cfg.to_dot().contains("(dynamic jump)"));
+    assert_eq!(cfg.to_json()["blocks"][1]["jump"], "invalid 0x0");
+}
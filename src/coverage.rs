@@ -0,0 +1,399 @@
+//! Code coverage
+//!
+//! 実行した命令とJUMPIの分岐をコードのハッシュごとに記録するモジュール<br/>
+//! source mapがあればソースの行ごとに集計してLCOVの形式で出力できる<br/>
+//! 命令ごとの実行回数はテキストとHTMLの表で出力できる
+use super::disasm;
+use super::inspector::Inspector;
+use super::srcmap::Sources;
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::WorldState;
+use super::util;
+use super::vm::VM;
+use ethereum_types::H256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// JUMPIの分岐した回数と分岐しなかった回数
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Branch {
+    pub taken: usize,
+    pub not_taken: usize,
+}
+
+/// 1つのコードのカバレッジ
+#[derive(Debug, Clone, Default)]
+pub struct CodeCoverage {
+    pub code: Vec<u8>,
+    pub hits: BTreeMap<usize, usize>,      // pcごとの実行回数
+    pub branches: BTreeMap<usize, Branch>, // JUMPIのpcごとの分岐
+}
+
+/// ソースファイルごとの集計
+#[derive(Debug, Default)]
+struct FileCoverage {
+    lines: BTreeMap<usize, usize>, // 行ごとの実行回数 行の命令の実行回数の最大値
+    branches: Vec<(usize, Option<Branch>)>, // JUMPIの行と分岐 実行されていなければNone
+}
+
+/// 1つのコードの命令ごとの集計 テキストとHTMLの表で共有する
+struct Summary<'a> {
+    name: &'a str, // コントラクトの名前 source mapがなければunknown
+    instructions: Vec<disasm::Instruction>,
+    covered: usize,                     // 実行された命令の数
+    branches: usize,                    // JUMPIの分岐先の数(JUMPIの数の2倍)
+    branches_hit: usize,                // 実行された分岐先の数
+    locations: BTreeMap<usize, String>, // pcごとのソースの位置
+}
+
+impl<'a> Summary<'a> {
+    fn new(coverage: &CodeCoverage, sources: &'a Sources) -> Self {
+        let instructions = disasm::disassemble(&coverage.code).instructions;
+        let covered = instructions
+            .iter()
+            .filter(|instruction| coverage.hits.contains_key(&instruction.offset))
+            .count();
+        let jumpis = instructions
+            .iter()
+            .filter(|instruction| instruction.opcode == 0x57)
+            .count();
+        let branches_hit = coverage
+            .branches
+            .values()
+            .map(|branch| (branch.taken > 0) as usize + (branch.not_taken > 0) as usize)
+            .sum();
+        let locations = sources
+            .locate_all(&coverage.code)
+            .into_iter()
+            .map(|(pc, location)| (pc, location.to_string()))
+            .collect();
+        return Self {
+            name: sources.contract_name(&coverage.code).unwrap_or("unknown"),
+            instructions,
+            covered,
+            branches: jumpis * 2,
+            branches_hit,
+            locations,
+        };
+    }
+
+    /// `code 0x.. (name): a/b instructions, c/d branches`
+    fn title(&self, hash: &H256) -> String {
+        return format!(
+            "code 0x{} ({}): {}/{} instructions, {}/{} branches",
+            hex::encode(hash),
+            self.name,
+            self.covered,
+            self.instructions.len(),
+            self.branches_hit,
+            self.branches
+        );
+    }
+}
+
+/// 実行した命令を記録するInspector<br/>
+/// 複数のトランザクションに渡して実行すると、すべての実行をまとめて集計する
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    codes: BTreeMap<H256, CodeCoverage>, // コードのハッシュごとのカバレッジ
+    current: H256,                       // 直前の命令を実行したコードのハッシュ
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// 実行したコードのハッシュごとのカバレッジ
+    pub fn codes(&self) -> &BTreeMap<H256, CodeCoverage> {
+        &self.codes
+    }
+
+    /// source mapで対応づけたソースの行ごとのカバレッジをLCOVの形式で返す<br/>
+    /// sourcesのコントラクトのうち実行されなかったものは、ランタイムコードをすべて未実行として含める
+    pub fn lcov(&self, sources: &Sources) -> String {
+        let mut executed = BTreeSet::new();
+        let mut codes: Vec<&CodeCoverage> = vec![];
+        for coverage in self.codes.values() {
+            if let Some(name) = sources.contract_name(&coverage.code) {
+                executed.insert(name);
+            }
+            codes.push(coverage);
+        }
+        let unexecuted: Vec<CodeCoverage> = sources
+            .runtime_codes()
+            .into_iter()
+            .filter(|code| {
+                sources
+                    .contract_name(code)
+                    .is_some_and(|name| !executed.contains(name))
+            })
+            .map(|code| CodeCoverage {
+                code: code.to_vec(),
+                ..CodeCoverage::default()
+            })
+            .collect();
+        codes.extend(unexecuted.iter());
+
+        let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+        for coverage in codes {
+            for (pc, location) in sources.locate_all(&coverage.code) {
+                let file = files.entry(location.path).or_default();
+                let hits = coverage.hits.get(&pc).copied().unwrap_or(0);
+                let line = file.lines.entry(location.line).or_default();
+                *line = (*line).max(hits);
+                if coverage.code[pc] == 0x57 {
+                    let branch = coverage.branches.get(&pc).copied();
+                    file.branches.push((location.line, branch));
+                }
+            }
+        }
+
+        let mut lcov = String::new();
+        for (path, file) in files {
+            writeln!(lcov, "TN:").unwrap();
+            writeln!(lcov, "SF:{}", path).unwrap();
+            let mut branches_hit = 0;
+            for (block, (line, branch)) in file.branches.iter().enumerate() {
+                let counts = match branch {
+                    Some(branch) => [branch.taken, branch.not_taken],
+                    None => [0, 0],
+                };
+                for (i, count) in counts.iter().enumerate() {
+                    if branch.is_some() {
+                        writeln!(lcov, "BRDA:{},{},{},{}", line, block, i, count).unwrap();
+                    } else {
+                        writeln!(lcov, "BRDA:{},{},{},-", line, block, i).unwrap();
+                    }
+                    if *count > 0 {
+                        branches_hit += 1;
+                    }
+                }
+            }
+            writeln!(lcov, "BRF:{}", file.branches.len() * 2).unwrap();
+            writeln!(lcov, "BRH:{}", branches_hit).unwrap();
+            for (line, hits) in &file.lines {
+                writeln!(lcov, "DA:{},{}", line, hits).unwrap();
+            }
+            writeln!(lcov, "LF:{}", file.lines.len()).unwrap();
+            let lines_hit = file.lines.values().filter(|hits| **hits > 0).count();
+            writeln!(lcov, "LH:{}", lines_hit).unwrap();
+            writeln!(lcov, "end_of_record").unwrap();
+        }
+        return lcov;
+    }
+
+    /// 実行したコードごとに、すべての命令の実行回数とJUMPIの分岐を並べた表<br/>
+    /// source mapがあればソースの位置も表示する
+    pub fn report(&self, sources: &Sources) -> String {
+        let mut report = String::new();
+        for (hash, coverage) in &self.codes {
+            let summary = Summary::new(coverage, sources);
+            writeln!(report, "{}", summary.title(hash)).unwrap();
+            for instruction in &summary.instructions {
+                let hits = coverage.hits.get(&instruction.offset).copied();
+                let mut line = match hits {
+                    Some(hits) => format!("{:>8}  {}", hits, instruction),
+                    None => format!("{:>8}  {}", "-", instruction),
+                };
+                if let Some(branch) = coverage.branches.get(&instruction.offset) {
+                    write!(
+                        line,
+                        "  [taken {}, not taken {}]",
+                        branch.taken, branch.not_taken
+                    )
+                    .unwrap();
+                }
+                if let Some(location) = summary.locations.get(&instruction.offset) {
+                    write!(line, "  {}", location).unwrap();
+                }
+                writeln!(report, "{}", line).unwrap();
+            }
+            writeln!(report).unwrap();
+        }
+        return report;
+    }
+
+    /// reportと同じ表を1つのHTMLのページにする<br/>
+    /// 実行されなかった命令と、片方にしか分岐しなかったJUMPIは色を変えて表示する
+    pub fn html(&self, sources: &Sources) -> String {
+        let mut html = String::new();
+        writeln!(html, "<!DOCTYPE html>").unwrap();
+        writeln!(html, "<html>").unwrap();
+        writeln!(html, "<head>").unwrap();
+        writeln!(html, "<meta charset=\"utf-8\">").unwrap();
+        writeln!(html, "<title>ToyEVM coverage</title>").unwrap();
+        writeln!(html, "<style>").unwrap();
+        writeln!(html, "body {{ font-family: sans-serif; }}").unwrap();
+        writeln!(html, "table {{ border-collapse: collapse; }}").unwrap();
+        writeln!(html, "td, th {{ padding: 0 8px; text-align: left; }}").unwrap();
+        writeln!(html, "td {{ font-family: monospace; white-space: pre; }}").unwrap();
+        writeln!(html, "td.hits {{ text-align: right; }}").unwrap();
+        writeln!(html, "tr.hit {{ background: #dfd; }}").unwrap();
+        writeln!(html, "tr.miss {{ background: #fdd; }}").unwrap();
+        writeln!(html, "tr.partial {{ background: #ffd; }}").unwrap();
+        writeln!(html, "</style>").unwrap();
+        writeln!(html, "</head>").unwrap();
+        writeln!(html, "<body>").unwrap();
+        writeln!(html, "<h1>ToyEVM coverage</h1>").unwrap();
+        for (hash, coverage) in &self.codes {
+            let summary = Summary::new(coverage, sources);
+            writeln!(html, "<h2>{}</h2>", escape(&summary.title(hash))).unwrap();
+            writeln!(html, "<table>").unwrap();
+            writeln!(
+                html,
+                "<tr><th>hits</th><th>instruction</th><th>branch</th><th>source</th></tr>"
+            )
+            .unwrap();
+            for instruction in &summary.instructions {
+                let hits = coverage.hits.get(&instruction.offset).copied();
+                let branch = coverage.branches.get(&instruction.offset);
+                let class = match (hits, branch) {
+                    (None, _) => "miss",
+                    (Some(_), Some(branch)) if branch.taken == 0 || branch.not_taken == 0 => {
+                        "partial"
+                    }
+                    (Some(_), _) => "hit",
+                };
+                let branch = branch
+                    .map(|branch| format!("taken {}, not taken {}", branch.taken, branch.not_taken))
+                    .unwrap_or_default();
+                let location = summary
+                    .locations
+                    .get(&instruction.offset)
+                    .map(String::as_str)
+                    .unwrap_or("");
+                writeln!(
+                    html,
+                    "<tr class=\"{}\"><td class=\"hits\">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    class,
+                    hits.map_or("-".to_string(), |hits| hits.to_string()),
+                    escape(&instruction.to_string()),
+                    branch,
+                    escape(location)
+                )
+                .unwrap();
+            }
+            writeln!(html, "</table>").unwrap();
+        }
+        writeln!(html, "</body>").unwrap();
+        writeln!(html, "</html>").unwrap();
+        return html;
+    }
+}
+
+/// HTMLの特殊文字をエスケープする
+fn escape(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+impl Inspector for Coverage {
+    fn step(&mut self, vm: &VM, _ws: &WorldState) {
+        let code = vm.code();
+        let same = self
+            .codes
+            .get(&self.current)
+            .is_some_and(|coverage| coverage.code == code);
+        if !same {
+            self.current = H256::from(util::keccak256(code));
+        }
+        let coverage = self
+            .codes
+            .entry(self.current)
+            .or_insert_with(|| CodeCoverage {
+                code: code.to_vec(),
+                ..CodeCoverage::default()
+            });
+        let pc = vm.pc();
+        *coverage.hits.entry(pc).or_default() += 1;
+
+        // JUMPIはスタックの2番目が0でなければ分岐する
+        let stack = vm.stack();
+        if vm.opcode() == Some(0x57) && stack.len() >= 2 {
+            let branch = coverage.branches.entry(pc).or_default();
+            if stack[stack.len() - 2].is_zero() {
+                branch.not_taken += 1;
+            } else {
+                branch.taken += 1;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_coverage() {
+    use super::srcmap::SourceMap;
+    use super::transaction::{self, Transaction};
+    use ethereum_types::H160;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // if (calldatasize != 0) { storage[0] = 1 } STOP
+    // 0: CALLDATASIZE, 1: PUSH1 0x07, 3: JUMPI, 4: STOP, 5: INVALID, 6: INVALID,
+    // 7: JUMPDEST, 8: PUSH1 0x01, a: PUSH1 0x00, c: SSTORE, d: STOP
+    // calldataなしで2回実行するので分岐は一度もしない
+    let code = hex::decode("3660075700fefe5b600160005500").unwrap();
+    let source = "contract C {\n    fallback() external {\n        if (msg.data.length != 0) {\n            x = 1;\n        }\n    }\n}\n";
+    let condition = source.find("if").unwrap();
+    let assignment = source.find("x = 1").unwrap();
+    let map = format!(
+        "0:80:0;{c}:50;;0:80;;;;{a}:5;;;0:80",
+        c = condition,
+        a = assignment
+    );
+    let mut sources = Sources::new();
+    sources.add_source(0, "C.sol", source);
+    sources.add_contract(
+        "C.sol:C",
+        None,
+        Some((code.clone(), SourceMap::parse(&map).unwrap())),
+    );
+
+    let mut ws = test_world_state(&[(0xbb, &hex::encode(&code))]);
+    let coverage = Rc::new(RefCell::new(Coverage::new()));
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    for _ in 0..2 {
+        transaction::execute_with_inspector(&mut ws, &tx, Some(coverage.clone())).unwrap();
+    }
+
+    let coverage = coverage.take();
+    let hash = H256::from(util::keccak256(&code));
+    let code_coverage = &coverage.codes()[&hash];
+    assert_eq!(code_coverage.hits[&0], 2);
+    assert_eq!(code_coverage.hits.get(&8), None);
+    assert_eq!(
+        code_coverage.branches[&3],
+        Branch {
+            taken: 0,
+            not_taken: 2
+        }
+    );
+
+    let lcov = coverage.lcov(&sources);
+    assert!(lcov.starts_with("TN:\nSF:C.sol\n"));
+    assert!(lcov.contains("BRDA:3,0,0,0\nBRDA:3,0,1,2\nBRF:2\nBRH:1\n"));
+    assert!(lcov.contains("DA:3,2\nDA:4,0\n"));
+    assert!(lcov.contains("LF:3\nLH:2\nend_of_record\n"));
+
+    let report = coverage.report(&sources);
+    assert!(report.contains("(C.sol:C): 4/11 instructions, 1/2 branches"));
+    assert!(report.contains("[taken 0, not taken 2]  C.sol:3:9"));
+
+    let html = coverage.html(&sources);
+    assert!(html.contains("<h2>code 0x"));
+    assert!(html.contains("(C.sol:C): 4/11 instructions, 1/2 branches</h2>"));
+    // 片方にしか分岐しなかったJUMPIと実行されなかった命令
+    assert!(html.contains(
+        "<tr class=\"partial\"><td class=\"hits\">2</td><td>0003: 57   JUMPI</td>\
+         <td>taken 0, not taken 2</td><td>C.sol:3:9</td></tr>"
+    ));
+    assert!(
+        html.contains("<tr class=\"miss\"><td class=\"hits\">-</td><td>0008: 60   PUSH1 0x01</td>")
+    );
+    assert_eq!(escape("a<b>&\"c\""), "a&lt;b&gt;&amp;&quot;c&quot;");
+}
@@ -0,0 +1,245 @@
+//! Debugger
+//!
+//! VMを1命令ずつ止めながら実行するための、ブレークポイントと停止条件を扱うモジュール
+use super::opcode;
+#[cfg(test)]
+use super::state::test_world_state;
+use super::util;
+use super::vm::VM;
+use ethereum_types::U256;
+use std::fmt::{self, Write};
+
+/// 実行を止める条件
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    Pc(usize),                  // pcがこの位置の命令
+    Opcode(u8),                 // このopcodeの命令
+    StorageWrite(Option<U256>), // SSTORE Someならそのslotへの書き込みのみ
+}
+
+impl Breakpoint {
+    /// `pc <n>`、`op <name>`、`sstore [slot]`の形式から作る
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        return match words {
+            ["pc", pc] => Ok(Breakpoint::Pc(util::parse_usize(pc)?)),
+            ["op", name] => match opcode::from_name(name) {
+                Some(opcode) => Ok(Breakpoint::Opcode(opcode)),
+                None => Err(format!("{} is unknown opcode", name)),
+            },
+            ["sstore"] => Ok(Breakpoint::StorageWrite(None)),
+            ["sstore", slot] => Ok(Breakpoint::StorageWrite(Some(util::parse_u256(slot)?))),
+            _ => Err("breakpoint must be pc <n>, op <name> or sstore [slot]".to_string()),
+        };
+    }
+
+    /// vmが次に実行する命令で止まるか
+    pub fn hit(&self, vm: &VM) -> bool {
+        return match self {
+            Breakpoint::Pc(pc) => vm.pc() == *pc,
+            Breakpoint::Opcode(opcode) => vm.opcode() == Some(*opcode),
+            Breakpoint::StorageWrite(slot) => {
+                // SSTOREはスタックのトップがslot
+                vm.opcode() == Some(0x55)
+                    && slot.is_none_or(|slot| vm.stack().last() == Some(&slot))
+            }
+        };
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Breakpoint::Pc(pc) => write!(f, "pc 0x{:04x}", pc),
+            Breakpoint::Opcode(opcode) => {
+                let name = opcode::info(*opcode)
+                    .map(|info| info.name)
+                    .unwrap_or("UNKNOWN");
+                write!(f, "op {}", name)
+            }
+            Breakpoint::StorageWrite(None) => write!(f, "sstore"),
+            Breakpoint::StorageWrite(Some(slot)) => write!(f, "sstore 0x{:x}", slot),
+        };
+    }
+}
+
+/// 止まった後に実行を再開する方法
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    StepInto, // 次の命令で止まる(呼び出し先のコードにも入る)
+    StepOver, // 同じ深さかそれより浅い次の命令で止まる
+    StepOut,  // 呼び出し元に戻ったところで止まる
+    Continue, // ブレークポイントまで止まらない
+}
+
+/// 次に止まる条件
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Into,
+    Over(usize), // この深さ以下で止まる
+    Out(usize),  // この深さ未満で止まる
+    Continue,
+}
+
+/// ブレークポイントと再開方法から、命令ごとに止まるかを判定する
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Debugger {
+    /// 最初の命令で止まるデバッガ
+    pub fn new() -> Self {
+        return Self {
+            breakpoints: vec![],
+            mode: Mode::Into,
+        };
+    }
+
+    /// vmが次に実行する命令の前で止まるか
+    pub fn should_stop(&self, vm: &VM) -> bool {
+        let stop = match self.mode {
+            Mode::Into => true,
+            Mode::Over(depth) => vm.depth() <= depth,
+            Mode::Out(depth) => vm.depth() < depth,
+            Mode::Continue => false,
+        };
+        return stop || self.breakpoints.iter().any(|breakpoint| breakpoint.hit(vm));
+    }
+
+    /// 深さdepthで止まっている状態から実行を再開する
+    pub fn resume(&mut self, resume: Resume, depth: usize) {
+        self.mode = match resume {
+            Resume::StepInto => Mode::Into,
+            Resume::StepOver => Mode::Over(depth),
+            Resume::StepOut => Mode::Out(depth),
+            Resume::Continue => Mode::Continue,
+        };
+    }
+}
+
+/// 16byteごとにアドレス、16進、ASCIIを並べて表示する
+pub fn hexdump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(dump, "{:04x}: {:<47}  |{}|", i * 16, bytes.join(" "), ascii).unwrap();
+    }
+    return dump;
+}
+
+/// 0xbbのコントラクトから0xccのコントラクトをCALLし、止まった位置(深さ, pc)を記録する<br/>
+/// on_stopは止まるたびに呼ばれ、再開方法を返す
+#[cfg(test)]
+fn debug_stops(
+    debugger: Debugger,
+    mut on_stop: impl FnMut(&VM) -> Resume + 'static,
+) -> Vec<(usize, usize)> {
+    use super::inspector::StepFn;
+    use super::state::WorldState;
+    use super::transaction::{self, Transaction};
+    use ethereum_types::H160;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // 0xcc: storage[0] = 1
+    let callee = "600160005500";
+    // 0xbb: CALL(0xffff, 0xcc, 0, 0, 0, 0, 0) STOP
+    let caller = "6000600060006000600060cc61fffff100";
+    let mut ws = test_world_state(&[(0xbb, caller), (0xcc, callee)]);
+
+    let stops = Rc::new(RefCell::new(vec![]));
+    let recorded = Rc::clone(&stops);
+    let mut debugger = debugger;
+    let inspector = Rc::new(RefCell::new(StepFn(move |vm: &VM, _: &WorldState| {
+        if debugger.should_stop(vm) {
+            recorded.borrow_mut().push((vm.depth(), vm.pc()));
+            debugger.resume(on_stop(vm), vm.depth());
+        }
+    })));
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let receipt = transaction::execute_with_inspector(&mut ws, &tx, Some(inspector)).unwrap();
+    assert!(receipt.status.is_success());
+    return stops.take();
+}
+
+#[test]
+fn test_step_over() {
+    // 呼び出し先のコードには止まらない
+    let stops = debug_stops(Debugger::new(), |_| Resume::StepOver);
+    let pcs: Vec<(usize, usize)> = [0, 2, 4, 6, 8, 10, 12, 15, 16]
+        .iter()
+        .map(|&pc| (0, pc))
+        .collect();
+    assert_eq!(stops, pcs);
+}
+
+#[test]
+fn test_step_into_and_out() {
+    // CALLで呼び出し先に入り、呼び出し先の最初の命令から呼び出し元に戻る
+    let stops = debug_stops(Debugger::new(), |vm| {
+        if vm.depth() == 1 {
+            Resume::StepOut
+        } else {
+            Resume::StepInto
+        }
+    });
+    assert_eq!(
+        stops,
+        vec![
+            (0, 0),
+            (0, 2),
+            (0, 4),
+            (0, 6),
+            (0, 8),
+            (0, 10),
+            (0, 12),
+            (0, 15),
+            (1, 0),
+            (0, 16)
+        ]
+    );
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut debugger = Debugger::new();
+    debugger.resume(Resume::Continue, 0);
+    debugger.breakpoints = vec![
+        Breakpoint::parse(&["sstore", "0"]).unwrap(),
+        Breakpoint::parse(&["op", "stop"]).unwrap(),
+        Breakpoint::parse(&["pc", "0x0c"]).unwrap(),
+    ];
+    let stops = debug_stops(debugger, |_| Resume::Continue);
+    // pc 0x0c、呼び出し先のSSTOREとSTOP、呼び出し元のSTOP
+    assert_eq!(stops, vec![(0, 12), (1, 4), (1, 5), (0, 16)]);
+
+    // 範囲外のpcはパニックせずにErrになる
+    assert!(Breakpoint::parse(&["pc", "0x1000000000000000000"]).is_err());
+}
+
+#[test]
+fn test_hexdump() {
+    let dump = hexdump(b"0123456789abcdef\x00\xff");
+    assert_eq!(
+        dump,
+        "0000: 30 31 32 33 34 35 36 37 38 39 61 62 63 64 65 66  |0123456789abcdef|\n\
+         0010: 00 ff                                            |..|\n"
+    );
+}
@@ -0,0 +1,289 @@
+//! Step debugger
+//!
+//! debugサブコマンドの対話的なデバッガ 止まるたびにスタック、メモリ、storageの変化を表示する<br/>
+//! 実行した命令はすべて記録しているので、過去の命令に戻って状態を見ることもできる
+use super::print_receipt;
+use ethereum_types::{H160, U256};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::cell::RefCell;
+use std::process::exit;
+use std::rc::Rc;
+use toyevm::abi::Abi;
+use toyevm::debug::{self, Breakpoint, Debugger, Resume};
+use toyevm::inspector::{CallInputs, CallOutcome, CreateInputs, Inspector};
+use toyevm::record::{Recording, Snapshot};
+use toyevm::srcmap::Sources;
+use toyevm::{disasm, state, transaction, util, vm};
+
+/// デバッガのコマンドと説明
+const COMMANDS: [&str; 14] = [
+    "step (s): execute one instruction, entering calls",
+    "next (n): execute one instruction, stepping over calls",
+    "out (o): run until the current call returns",
+    "continue (c): run until a breakpoint",
+    "back (r) [n]: go back n recorded instructions",
+    "forward (f) [n]: go forward n recorded instructions",
+    "goto (g) <step>: go to the step-th instruction",
+    "where mem <offset> | storage <slot> [addr]: find the last instruction that changed it",
+    "break (b) pc <n> | op <name> | sstore [slot]: add breakpoint",
+    "breakpoints (bl): list breakpoints",
+    "delete (d) <index>: delete breakpoint",
+    "info (i): show current state again",
+    "quit (q): abort debugging",
+    "an empty line repeats the last step command",
+];
+
+struct Session {
+    debugger: Debugger,
+    editor: DefaultEditor,
+    recording: Recording,
+    last: Resume,            // 空行で繰り返す再開方法
+    view: usize,             // 表示している命令の番号 実行中の命令より前なら過去の状態を見ている
+    target: Option<usize>,   // gotoで指定したまだ実行していない命令の番号
+    shown: Option<Snapshot>, // 前回表示した状態 storageの変化を求めるのに使う
+    sources: Sources,        // 命令のソースの位置を表示するためのsource map
+}
+
+/// トランザクションを最初の命令で止めた状態から対話的に実行し、結果をdecodingのABIでデコードして表示する
+pub fn run(
+    ws: &mut state::WorldState,
+    tx: &transaction::Transaction,
+    sources: Sources,
+    decoding: &Abi,
+) -> i32 {
+    let editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    let session = Session {
+        debugger: Debugger::new(),
+        editor,
+        recording: Recording::new(),
+        last: Resume::StepInto,
+        view: 0,
+        target: None,
+        shown: None,
+        sources,
+    };
+    let session = Rc::new(RefCell::new(session));
+
+    println!("type help to list commands");
+    return match transaction::execute_with_inspector(ws, tx, Some(session)) {
+        Ok(receipt) => print_receipt(&receipt, decoding),
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    };
+}
+
+impl Inspector for Session {
+    /// 各命令の実行前に呼ばれ、止まる場合は再開のコマンドが入力されるまで待つ
+    fn step(&mut self, vm: &vm::VM, ws: &state::WorldState) {
+        self.recording.record(vm, ws);
+        let live = self.recording.len() - 1;
+        if !self.debugger.should_stop(vm) && self.target != Some(live) {
+            return;
+        }
+        self.target = None;
+        self.view = live;
+        self.show();
+
+        loop {
+            let line = match self.editor.readline("debug> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => exit(1),
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.is_empty() {
+                let _ = self.editor.add_history_entry(line.as_str());
+            }
+            match self.command(&words, live) {
+                Ok(Some(resume)) => {
+                    self.last = resume;
+                    self.debugger.resume(resume, vm.depth());
+                    return;
+                }
+                Ok(None) => {}
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    // storageの差分はRecordingが書き込みと呼び出しの結果の通知から作る
+    fn call(&mut self, inputs: &CallInputs) {
+        self.recording.call(inputs);
+    }
+
+    fn call_end(&mut self, inputs: &CallInputs, outcome: &CallOutcome) {
+        self.recording.call_end(inputs, outcome);
+    }
+
+    fn create(&mut self, inputs: &CreateInputs) {
+        self.recording.create(inputs);
+    }
+
+    fn create_end(&mut self, inputs: &CreateInputs, outcome: &CallOutcome) {
+        self.recording.create_end(inputs, outcome);
+    }
+
+    fn storage_write(&mut self, address: &H160, slot: &U256, old: &U256, new: &U256) {
+        self.recording.storage_write(address, slot, old, new);
+    }
+}
+
+impl Session {
+    /// 入力されたコマンドを実行する 実行を再開する場合はその方法を返す<br/>
+    /// liveは実行中の命令の番号 過去の状態を見ていても、再開は実行中の命令から行う
+    fn command(&mut self, words: &[&str], live: usize) -> Result<Option<Resume>, String> {
+        let command = match words.first() {
+            Some(command) => *command,
+            None => return Ok(Some(self.last)),
+        };
+        let count = || -> Result<usize, String> {
+            return match words.get(1) {
+                Some(n) => n.parse().map_err(|_| format!("{} is invalid number", n)),
+                None => Ok(1),
+            };
+        };
+        match command {
+            "step" | "s" => return Ok(Some(Resume::StepInto)),
+            "next" | "n" => return Ok(Some(Resume::StepOver)),
+            "out" | "o" => return Ok(Some(Resume::StepOut)),
+            "continue" | "c" => return Ok(Some(Resume::Continue)),
+            "back" | "r" => {
+                self.view = self.view.saturating_sub(count()?);
+                self.show();
+            }
+            "forward" | "f" => {
+                self.view = (self.view + count()?).min(live);
+                self.show();
+            }
+            "goto" | "g" => {
+                let step = words.get(1).ok_or("please input step")?;
+                let step: usize = step
+                    .parse()
+                    .map_err(|_| format!("{} is invalid step", step))?;
+                if step > live {
+                    // まだ実行していない命令まではブレークポイントを除いて止まらずに進む
+                    self.target = Some(step);
+                    return Ok(Some(Resume::Continue));
+                }
+                self.view = step;
+                self.show();
+            }
+            "where" | "w" => self.find_write(&words[1..])?,
+            "break" | "b" => {
+                let breakpoint = Breakpoint::parse(&words[1..])?;
+                println!(
+                    "breakpoint {}: {}",
+                    self.debugger.breakpoints.len(),
+                    breakpoint
+                );
+                self.debugger.breakpoints.push(breakpoint);
+            }
+            "breakpoints" | "bl" => {
+                for (i, breakpoint) in self.debugger.breakpoints.iter().enumerate() {
+                    println!("{}: {}", i, breakpoint);
+                }
+            }
+            "delete" | "d" => {
+                let index = words.get(1).ok_or("please input index of breakpoint")?;
+                let index: usize = index
+                    .parse()
+                    .map_err(|_| format!("{} is invalid index", index))?;
+                if index >= self.debugger.breakpoints.len() {
+                    return Err(format!("breakpoint {} is not found", index));
+                }
+                self.debugger.breakpoints.remove(index);
+            }
+            "info" | "i" => self.show(),
+            "quit" | "q" => exit(1),
+            "help" | "h" => {
+                for description in COMMANDS.iter() {
+                    println!("{}", description);
+                }
+            }
+            command => return Err(format!("{} is invalid command", command)),
+        }
+        return Ok(None);
+    }
+
+    /// 表示している時点でメモリやstorageを最後に変えた命令を表示する
+    fn find_write(&self, words: &[&str]) -> Result<(), String> {
+        let step = match words {
+            ["mem", offset] => {
+                let offset = util::parse_usize(offset)?;
+                self.recording.last_memory_write(self.view, offset)
+            }
+            ["storage", slot] | ["storage", slot, _] => {
+                let address = match words.get(2) {
+                    Some(address) => util::parse_h160(address)?,
+                    None => self.recording.step(self.view).unwrap().address,
+                };
+                let slot = util::parse_u256(slot)?;
+                self.recording
+                    .last_storage_write(self.view, &address, &slot)
+            }
+            _ => return Err("usage: where mem <offset> | storage <slot> [addr]".to_string()),
+        };
+        match step.and_then(|step| self.recording.snapshot(step)) {
+            Some(snapshot) => {
+                let code = self.recording.code(snapshot.frame);
+                println!(
+                    "step {} [depth {}] {}",
+                    snapshot.step,
+                    snapshot.depth,
+                    disasm::instruction_at(code, snapshot.pc)
+                );
+            }
+            None => println!("not changed during this transaction"),
+        }
+        return Ok(());
+    }
+
+    /// 表示している命令、gas、深さ、スタック、メモリ、storageの変化を表示する
+    fn show(&mut self) {
+        let snapshot = self.recording.snapshot(self.view).unwrap();
+        let live = self.recording.len() - 1;
+        println!(
+            "step {}{} [depth {}] 0x{}  gas: {}",
+            snapshot.step,
+            if snapshot.step < live {
+                " (history)"
+            } else {
+                ""
+            },
+            snapshot.depth,
+            hex::encode(snapshot.address),
+            snapshot.gas
+        );
+        let code = self.recording.code(snapshot.frame);
+        println!("{}", disasm::instruction_at(code, snapshot.pc));
+        if let Some(location) = self.sources.locate(code, snapshot.pc) {
+            println!("at {}: {}", location, location.text);
+        }
+
+        println!("stack:");
+        for (i, value) in snapshot.stack.iter().rev().enumerate() {
+            println!("  {:>4}: 0x{:x}", i, value);
+        }
+        if snapshot.memory.is_empty() {
+            println!("memory: (empty)");
+        } else {
+            println!("memory:");
+            print!("{}", debug::hexdump(&snapshot.memory));
+        }
+        if let Some(shown) = &self.shown {
+            for (slot, old, new) in snapshot.storage_changes(shown) {
+                println!("storage[0x{:x}]: 0x{:x} -> 0x{:x}", slot, old, new);
+            }
+        }
+        self.shown = Some(snapshot);
+    }
+}
@@ -0,0 +1,389 @@
+//! Static disassembler
+//!
+//! EVMバイトコードを実行せずに先頭から順に逆アセンブルするモジュール
+#[cfg(test)]
+use super::asm;
+use super::opcode;
+use std::fmt;
+
+/// 逆アセンブルした1命令
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub offset: usize,                // コード中の位置
+    pub opcode: u8,                   // オペコード
+    pub info: Option<opcode::OpInfo>, // 未定義のオペコードならNone
+    pub immediate: Vec<u8>,           // PUSH命令の即値
+    pub truncated: bool,              // 即値がコードの終端で途切れているか
+}
+
+impl Instruction {
+    /// ニーモニック 未定義のオペコードはUNKNOWN
+    pub fn mnemonic(&self) -> &'static str {
+        match self.info {
+            Some(info) => info.name,
+            None => "UNKNOWN",
+        }
+    }
+
+    pub fn is_jumpdest(&self) -> bool {
+        self.opcode == 0x5b
+    }
+
+    /// 命令の長さ(即値を含む)
+    pub fn size(&self) -> usize {
+        1 + self.immediate.len()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // JUMPDESTはジャンプ先として印をつける
+        let mark = if self.is_jumpdest() { ">" } else { " " };
+        write!(
+            f,
+            "{:04x}: {:02x} {} {}",
+            self.offset,
+            self.opcode,
+            mark,
+            self.mnemonic()
+        )?;
+        if self.info.is_none() {
+            write!(f, " 0x{:02x}", self.opcode)?;
+        }
+        if !self.immediate.is_empty() || self.truncated {
+            write!(f, " 0x{}", hex::encode(&self.immediate))?;
+        }
+        if self.truncated {
+            write!(f, " (truncated)")?;
+        }
+        return Ok(());
+    }
+}
+
+/// solcがコードの末尾に付与するCBORエンコードされたメタデータ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub offset: usize,                  // メタデータの開始位置
+    pub raw: Vec<u8>,                   // 末尾の長さ2byteを含むメタデータ
+    pub entries: Vec<(String, String)>, // キーと値
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}: metadata ({} bytes)",
+            self.offset,
+            self.raw.len()
+        )?;
+        for (key, value) in &self.entries {
+            write!(f, "\n      {}: {}", key, value)?;
+        }
+        return Ok(());
+    }
+}
+
+/// 逆アセンブルの結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disassembly {
+    pub instructions: Vec<Instruction>,
+    pub metadata: Option<Metadata>,
+}
+
+impl Disassembly {
+    /// asmモジュールでアセンブルできるソースに変換する<br/>
+    /// 未定義のバイトや途切れたPUSH、メタデータは#bytesでそのまま埋め込むので、元のコードに戻せる
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        for instruction in &self.instructions {
+            if instruction.info.is_none() || instruction.truncated {
+                let mut bytes = vec![instruction.opcode];
+                bytes.extend_from_slice(&instruction.immediate);
+                source += &format!("#bytes 0x{}\n", hex::encode(bytes));
+            } else if instruction.immediate.is_empty() {
+                source += &format!("{}\n", instruction.mnemonic());
+            } else {
+                source += &format!(
+                    "{} 0x{}\n",
+                    instruction.mnemonic(),
+                    hex::encode(&instruction.immediate)
+                );
+            }
+        }
+        if let Some(metadata) = &self.metadata {
+            source += &format!("#bytes 0x{}\n", hex::encode(&metadata.raw));
+        }
+        return source;
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for instruction in &self.instructions {
+            writeln!(f, "{}", instruction)?;
+        }
+        if let Some(metadata) = &self.metadata {
+            writeln!(f, "{}", metadata)?;
+        }
+        return Ok(());
+    }
+}
+
+/// コードを先頭から順に逆アセンブルする<br/>
+/// 末尾にメタデータがあればその手前までを命令として扱う
+pub fn disassemble(code: &[u8]) -> Disassembly {
+    let metadata = find_metadata(code);
+    let end = match &metadata {
+        Some(metadata) => metadata.offset,
+        None => code.len(),
+    };
+
+    let mut instructions = Vec::new();
+    let mut pc = 0;
+    while pc < end {
+        let instruction = decode(code, pc, end);
+        pc += instruction.size();
+        instructions.push(instruction);
+    }
+
+    return Disassembly {
+        instructions,
+        metadata,
+    };
+}
+
+/// pcの位置の1命令を逆アセンブルする pcはコードの範囲内でなければならない
+pub fn instruction_at(code: &[u8], pc: usize) -> Instruction {
+    return decode(code, pc, code.len());
+}
+
+/// endより後ろは即値として読まない
+fn decode(code: &[u8], pc: usize, end: usize) -> Instruction {
+    let opcode = code[pc];
+    let info = opcode::info(opcode);
+    let length = info.map(|info| info.immediate).unwrap_or(0);
+    let immediate_end = end.min(pc + 1 + length);
+    return Instruction {
+        offset: pc,
+        opcode,
+        info,
+        immediate: code[pc + 1..immediate_end].to_vec(),
+        truncated: pc + 1 + length > end,
+    };
+}
+
+/// solcがメタデータに含めるキー
+const METADATA_KEYS: [&str; 4] = ["ipfs", "bzzr0", "bzzr1", "solc"];
+
+/// コードの末尾からsolcのメタデータを探す<br/>
+/// 末尾2byteがCBORの長さを表し、その直前がsolcの既知のキーを含むCBORのマップとして読めればメタデータとみなす
+pub fn find_metadata(code: &[u8]) -> Option<Metadata> {
+    if code.len() < 2 {
+        return None;
+    }
+    let length = u16::from_be_bytes([code[code.len() - 2], code[code.len() - 1]]) as usize;
+    if length == 0 || length + 2 > code.len() {
+        return None;
+    }
+    let offset = code.len() - 2 - length;
+    let cbor = &code[offset..code.len() - 2];
+
+    let mut reader = CborReader { data: cbor, pos: 0 };
+    let entries = reader.read_map()?;
+    if reader.pos != cbor.len() {
+        return None;
+    }
+    // 空のマップや既知のキーがないマップは偶然読めたただのバイト列とみなす
+    if !entries
+        .iter()
+        .any(|(key, _)| METADATA_KEYS.contains(&key.as_str()))
+    {
+        return None;
+    }
+    return Some(Metadata {
+        offset,
+        raw: code[offset..].to_vec(),
+        entries,
+    });
+}
+
+/// メタデータを読むための最小限のCBORデコーダ
+struct CborReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// CBORの値
+enum CborValue {
+    Bytes(Vec<u8>),
+    Text(String),
+    Bool(bool),
+    Uint(u64),
+}
+
+impl CborReader<'_> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let b = *self.data.get(self.pos)?;
+        self.pos += 1;
+        return Some(b);
+    }
+
+    /// 先頭のバイトの下位5bitから続く長さ(または値)を読む
+    fn read_length(&mut self, additional: u8) -> Option<u64> {
+        let size = match additional {
+            0..=23 => return Some(additional as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return None,
+        };
+        let mut value = 0u64;
+        for _ in 0..size {
+            value = (value << 8) | self.read_byte()? as u64;
+        }
+        return Some(value);
+    }
+
+    fn read_slice(&mut self, length: u64) -> Option<&[u8]> {
+        let end = self.pos.checked_add(length as usize)?;
+        let slice = self.data.get(self.pos..end)?;
+        self.pos = end;
+        return Some(slice);
+    }
+
+    fn read_value(&mut self) -> Option<CborValue> {
+        let head = self.read_byte()?;
+        let (major, additional) = (head >> 5, head & 0x1f);
+        return match major {
+            0 => Some(CborValue::Uint(self.read_length(additional)?)),
+            2 => {
+                let length = self.read_length(additional)?;
+                Some(CborValue::Bytes(self.read_slice(length)?.to_vec()))
+            }
+            3 => {
+                let length = self.read_length(additional)?;
+                let text = std::str::from_utf8(self.read_slice(length)?).ok()?;
+                Some(CborValue::Text(text.to_string()))
+            }
+            7 => match additional {
+                20 => Some(CborValue::Bool(false)),
+                21 => Some(CborValue::Bool(true)),
+                _ => None,
+            },
+            _ => None,
+        };
+    }
+
+    /// キーが文字列のマップを読む
+    fn read_map(&mut self) -> Option<Vec<(String, String)>> {
+        let head = self.read_byte()?;
+        if head >> 5 != 5 {
+            return None;
+        }
+        let count = self.read_length(head & 0x1f)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let key = match self.read_value()? {
+                CborValue::Text(key) => key,
+                _ => return None,
+            };
+            let value = match self.read_value()? {
+                // solcのバージョンは3byteで表される
+                CborValue::Bytes(bytes) if key == "solc" && bytes.len() == 3 => {
+                    format!("{}.{}.{}", bytes[0], bytes[1], bytes[2])
+                }
+                CborValue::Bytes(bytes) => format!("0x{}", hex::encode(bytes)),
+                CborValue::Text(text) => text,
+                CborValue::Bool(b) => b.to_string(),
+                CborValue::Uint(n) => n.to_string(),
+            };
+            entries.push((key, value));
+        }
+        return Some(entries);
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    let code = hex::decode("6005600401").unwrap();
+    let disassembly = disassemble(&code);
+    assert_eq!(disassembly.instructions.len(), 3);
+    assert_eq!(disassembly.metadata, None);
+    assert_eq!(
+        disassembly.to_string(),
+        "0000: 60   PUSH1 0x05\n0002: 60   PUSH1 0x04\n0004: 01   ADD\n"
+    );
+}
+
+#[test]
+fn test_disassemble_unreachable() {
+    // 実行されないコードや未定義のオペコード、途中で途切れたPUSHも逆アセンブルする
+    let code = hex::decode("600556005b0c61ff").unwrap();
+    let disassembly = disassemble(&code);
+    let lines: Vec<String> = disassembly
+        .instructions
+        .iter()
+        .map(|i| i.to_string())
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            "0000: 60   PUSH1 0x05",
+            "0002: 56   JUMP",
+            "0003: 00   STOP",
+            "0004: 5b > JUMPDEST",
+            "0005: 0c   UNKNOWN 0x0c",
+            "0006: 61   PUSH2 0xff (truncated)",
+        ]
+    );
+}
+
+#[test]
+fn test_disassemble_metadata() {
+    // {"ipfs": 0x1220.., "solc": 0.8.19} + 長さ(0x0033)
+    let metadata = "a264697066735822\
+                    1220aabbccddeeff00112233445566778899aabbccddeeff00112233445566778899\
+                    64736f6c63430008130033";
+    let code = hex::decode(format!("6080604052fe{}", metadata)).unwrap();
+    let disassembly = disassemble(&code);
+    assert_eq!(disassembly.instructions.len(), 4);
+    assert_eq!(disassembly.instructions[3].mnemonic(), "INVALID");
+
+    let metadata = disassembly.metadata.unwrap();
+    assert_eq!(metadata.offset, 6);
+    assert_eq!(metadata.raw.len(), 0x35);
+    assert_eq!(metadata.entries[0].0, "ipfs");
+    assert_eq!(
+        metadata.entries[1],
+        ("solc".to_string(), "0.8.19".to_string())
+    );
+
+    // 空のマップ(a0)や既知のキーがないマップ({"a": "b"})はメタデータとみなさない
+    for code in ["6001a00001", "6001a1616161620005"] {
+        let code = hex::decode(code).unwrap();
+        let disassembly = disassemble(&code);
+        assert_eq!(disassembly.metadata, None);
+        // 末尾まで命令として逆アセンブルされる
+        let last = disassembly.instructions.last().unwrap();
+        assert_eq!(last.offset + last.size(), code.len());
+    }
+}
+
+#[test]
+fn test_disassemble_round_trip() {
+    // 逆アセンブルした結果をアセンブルすると元のコードに戻る
+    let codes = [
+        "6005600401",
+        "600556005b0c61ff",
+        "7f00000000000000000000000000000000000000000000000000000000000000015f00",
+        "6080604052fea264697066735822\
+         1220aabbccddeeff00112233445566778899aabbccddeeff00112233445566778899\
+         64736f6c63430008130033",
+    ];
+    for code in codes.iter() {
+        let code = hex::decode(code).unwrap();
+        let source = disassemble(&code).to_source();
+        assert_eq!(asm::assemble(&source).unwrap(), code);
+    }
+}
@@ -0,0 +1,91 @@
+//! Hard fork
+//!
+//! EVMの挙動を切り替えるハードフォークを表現するモジュール
+use std::fmt;
+use std::str::FromStr;
+
+/// メインネットのハードフォーク 後のフォークほど大きい値になる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Fork {
+    Frontier,
+    Homestead,
+    TangerineWhistle, // EIP-150
+    SpuriousDragon,   // EIP-161
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    Berlin,
+    London, // EIP-3529
+    Merge,
+    Shanghai,
+    #[default]
+    Cancun, // EIP-6780
+}
+
+impl Fork {
+    pub const ALL: [Fork; 13] = [
+        Fork::Frontier,
+        Fork::Homestead,
+        Fork::TangerineWhistle,
+        Fork::SpuriousDragon,
+        Fork::Byzantium,
+        Fork::Constantinople,
+        Fork::Petersburg,
+        Fork::Istanbul,
+        Fork::Berlin,
+        Fork::London,
+        Fork::Merge,
+        Fork::Shanghai,
+        Fork::Cancun,
+    ];
+
+    /// 設定ファイルやCLIで使う名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fork::Frontier => "frontier",
+            Fork::Homestead => "homestead",
+            Fork::TangerineWhistle => "tangerine",
+            Fork::SpuriousDragon => "spurious",
+            Fork::Byzantium => "byzantium",
+            Fork::Constantinople => "constantinople",
+            Fork::Petersburg => "petersburg",
+            Fork::Istanbul => "istanbul",
+            Fork::Berlin => "berlin",
+            Fork::London => "london",
+            Fork::Merge => "merge",
+            Fork::Shanghai => "shanghai",
+            Fork::Cancun => "cancun",
+        }
+    }
+
+    /// トランザクション終了時に返還されるgasの上限(使用gasに対する除数)<br/>
+    /// EIP-3529でgas_used / 2からgas_used / 5に引き下げられた
+    pub fn max_refund_quotient(&self) -> usize {
+        if *self >= Fork::London {
+            5
+        } else {
+            2
+        }
+    }
+}
+
+impl fmt::Display for Fork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Fork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase();
+        for fork in Fork::ALL.iter() {
+            if fork.name() == name {
+                return Ok(*fork);
+            }
+        }
+        return Err(format!("{} is unknown fork", s));
+    }
+}
@@ -0,0 +1,169 @@
+//! Inspector
+//!
+//! 実行中のVMから命令、呼び出し、ログ、storageの読み書き、SELFDESTRUCTを通知してもらうためのトレイト<br/>
+//! トレーサ、デバッガ、カバレッジなどはインタプリタに手を入れずにこのトレイトを実装して作る
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::{Log, WorldState};
+use super::vm::{ExitStatus, VM};
+use ethereum_types::{H160, U256};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// 実行中のVMとCALL系命令で作られる呼び出し先のVMで共有するInspector
+pub type InspectorRef = Rc<RefCell<dyn Inspector>>;
+
+/// メッセージコールの種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Call,
+    CallCode,
+    DelegateCall,
+    StaticCall,
+}
+
+/// 呼び出しの開始時に渡される情報 トランザクションによる呼び出しは深さ0のCall
+#[derive(Debug, Clone)]
+pub struct CallInputs {
+    pub kind: CallKind,
+    pub caller: H160,
+    pub to: H160, // 呼び出したアカウント DELEGATECALL、CALLCODEでは実行するコードの持ち主
+    pub value: U256,
+    pub input: Vec<u8>,
+    pub gas: usize, // 呼び出し先に渡したgas
+    pub depth: usize,
+}
+
+/// コントラクトの作成の開始時に渡される情報
+#[derive(Debug, Clone)]
+pub struct CreateInputs {
+    pub caller: H160,
+    pub address: H160, // 作成されるコントラクトのアドレス
+    pub value: U256,
+    pub init_code: Vec<u8>,
+    pub gas: usize,
+    pub depth: usize,
+}
+
+/// 呼び出しやコントラクトの作成の結果
+#[derive(Debug, Clone)]
+pub struct CallOutcome {
+    pub status: ExitStatus,
+    pub output: Vec<u8>, // RETURN、REVERTで返されたデータ
+    pub gas_used: usize,
+}
+
+/// 実行中に呼ばれるコールバック 必要なものだけを実装すればよい<br/>
+/// stepとstep_endは命令ごとに、それ以外は該当する命令の中から呼ばれる
+#[allow(unused_variables)]
+pub trait Inspector {
+    /// 命令の実行前
+    fn step(&mut self, vm: &VM, ws: &WorldState) {}
+
+    /// 命令の実行後 例外停止した命令では呼ばれない
+    fn step_end(&mut self, vm: &VM, ws: &WorldState) {}
+
+    /// 命令が例外停止したとき step_endの代わりに呼ばれる gasはすべて消費されている
+    fn step_halt(&mut self, vm: &VM, ws: &WorldState, reason: &str) {}
+
+    /// CALL系命令で呼び出し先のコードを実行する前
+    fn call(&mut self, inputs: &CallInputs) {}
+
+    /// callに対応する呼び出しの終了後
+    fn call_end(&mut self, inputs: &CallInputs, outcome: &CallOutcome) {}
+
+    /// 初期化コードを実行する前
+    fn create(&mut self, inputs: &CreateInputs) {}
+
+    /// createに対応するコントラクトの作成の終了後
+    fn create_end(&mut self, inputs: &CreateInputs, outcome: &CallOutcome) {}
+
+    /// LOG0-LOG4でログが追加されたとき
+    fn log(&mut self, log: &Log) {}
+
+    /// SLOADでstorageを読んだとき
+    fn storage_read(&mut self, address: &H160, slot: &U256, value: &U256) {}
+
+    /// SSTOREでstorageに書き込んだとき oldは書き込み前の値
+    fn storage_write(&mut self, address: &H160, slot: &U256, old: &U256, new: &U256) {}
+
+    /// SELFDESTRUCTでbalanceを受取人に送金したとき
+    fn selfdestruct(&mut self, address: &H160, beneficiary: &H160, balance: &U256) {}
+}
+
+/// 命令の実行前に関数を呼ぶだけのInspector
+pub struct StepFn<F>(pub F);
+
+impl<F: FnMut(&VM, &WorldState)> Inspector for StepFn<F> {
+    fn step(&mut self, vm: &VM, ws: &WorldState) {
+        (self.0)(vm, ws);
+    }
+}
+
+/// 通知を文字列にして記録する
+#[cfg(test)]
+#[derive(Default)]
+struct EventLog(Vec<String>);
+
+#[cfg(test)]
+impl Inspector for EventLog {
+    fn call(&mut self, inputs: &CallInputs) {
+        self.0
+            .push(format!("call {} {:?}", inputs.depth, inputs.to));
+    }
+
+    fn call_end(&mut self, inputs: &CallInputs, outcome: &CallOutcome) {
+        self.0
+            .push(format!("call_end {} {}", inputs.depth, outcome.status));
+    }
+
+    fn log(&mut self, log: &Log) {
+        self.0.push(format!("log {:?}", log.address));
+    }
+
+    fn storage_read(&mut self, _: &H160, slot: &U256, value: &U256) {
+        self.0.push(format!("sload {} {}", slot, value));
+    }
+
+    fn storage_write(&mut self, _: &H160, slot: &U256, old: &U256, new: &U256) {
+        self.0.push(format!("sstore {} {} {}", slot, old, new));
+    }
+
+    fn selfdestruct(&mut self, _: &H160, beneficiary: &H160, _: &U256) {
+        self.0.push(format!("selfdestruct {:?}", beneficiary));
+    }
+}
+
+#[test]
+fn test_inspector_events() {
+    use super::transaction::{self, Transaction};
+
+    // SSTORE, SLOAD, LOG0, CALL(0xcc), SELFDESTRUCT(0xdd)
+    let code = "600260015560015450600060006000a0\
+                6000600060006000600060cc61fffff150\
+                60ddff";
+    let mut ws = test_world_state(&[(0xbb, code), (0xcc, "00")]);
+
+    let events = Rc::new(RefCell::new(EventLog::default()));
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let receipt = transaction::execute_with_inspector(&mut ws, &tx, Some(events.clone())).unwrap();
+    assert!(receipt.status.is_success());
+    let (bb, cc, dd) = (
+        H160::from_low_u64_be(0xbb),
+        H160::from_low_u64_be(0xcc),
+        H160::from_low_u64_be(0xdd),
+    );
+    assert_eq!(
+        events.take().0,
+        vec![
+            format!("call 0 {:?}", bb),
+            "sstore 1 0 2".to_string(),
+            "sload 1 2".to_string(),
+            format!("log {:?}", bb),
+            format!("call 1 {:?}", cc),
+            "call_end 1 success".to_string(),
+            format!("selfdestruct {:?}", dd),
+            "call_end 0 success".to_string(),
+        ]
+    );
+}
@@ -0,0 +1,29 @@
+//! ToyEVM
+//!
+//! 簡易的なEVM(Ethereum Virtual Machine)の実装
+
+// 明示的なreturnを使うスタイルで統一している
+#![allow(clippy::needless_return)]
+
+pub mod abi;
+pub mod artifact;
+pub mod asm;
+pub mod cfg;
+pub mod coverage;
+pub mod debug;
+pub mod disasm;
+pub mod fork;
+pub mod inspector;
+pub mod node;
+pub mod opcode;
+pub mod precompile;
+pub mod profile;
+pub mod record;
+pub mod rlp;
+pub mod script;
+pub mod srcmap;
+pub mod state;
+pub mod tracer;
+pub mod transaction;
+pub mod util;
+pub mod vm;
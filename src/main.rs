@@ -1,17 +1,23 @@
+// 明示的なreturnを使うスタイルで統一している
+#![allow(clippy::needless_return)]
+
 extern crate ethereum_types;
-extern crate python_input;
-extern crate serde;
-extern crate serde_json;
-extern crate sha3;
 
-use python_input::input;
+mod debugger;
+mod repl;
 
-mod state;
-mod util;
-mod vm;
-use ethereum_types::H160;
+use std::cell::RefCell;
 use std::env;
+use std::fs;
 use std::process::exit;
+use std::rc::Rc;
+use toyevm::{
+    abi, artifact, asm, cfg, coverage, disasm, fork, node, profile, script, srcmap, state, tracer,
+    transaction, util,
+};
+
+/// --stateを指定しなかった場合に読み込むワールドステート
+const DEFAULT_STATE: &str = "./config/config.json";
 
 /// init
 fn main() {
@@ -29,16 +35,75 @@ fn run() -> i32 {
                     println!("please input EVMbytecode");
                     return 1;
                 }
-                let code = &args[2][..];
-                vm::VM::disassemble(code);
+                match util::read_bytecode(&args[2]) {
+                    Ok(code) => print!("{}", disasm::disassemble(&code)),
+                    Err(e) => {
+                        println!("{}", e);
+                        return 1;
+                    }
+                }
+                return 0;
+            }
+            "asm" => {
+                if args.len() < 3 {
+                    println!("please input assembly source");
+                    return 1;
+                }
+                // ファイルが存在すればその中身を、なければ引数をそのままソースとみなす
+                let source = match fs::read_to_string(&args[2]) {
+                    Ok(source) => source,
+                    Err(_) => args[2].clone(),
+                };
+                match asm::assemble(&source) {
+                    Ok(code) => println!("{}", hex::encode(code)),
+                    Err(e) => {
+                        println!("{}", e);
+                        return 1;
+                    }
+                }
+                return 0;
+            }
+            "cfg" => {
+                if args.len() < 3 {
+                    println!("please input EVMbytecode");
+                    return 1;
+                }
+                let code = match util::read_bytecode(&args[2]) {
+                    Ok(code) => code,
+                    Err(e) => {
+                        println!("{}", e);
+                        return 1;
+                    }
+                };
+                let graph = cfg::build(&code);
+                // --format dot|json で出力形式を選ぶ(デフォルトはdot)
+                let format = match args.iter().position(|arg| arg == "--format") {
+                    Some(i) => args.get(i + 1).map(|f| &f[..]).unwrap_or(""),
+                    None => "dot",
+                };
+                match format {
+                    "dot" => print!("{}", graph.to_dot()),
+                    "json" => println!("{:#}", graph.to_json()),
+                    f => {
+                        println!("{} is unknown format", f);
+                        return 1;
+                    }
+                }
                 return 0;
             }
+            "abi" => return abi_command(&args),
+            "call" => return call(&args),
             "help" => {
                 help();
                 return 0;
             }
+            "deploy" => return deploy(&args),
+            "debug" => return debug(&args),
+            "estimate" => return estimate(&args),
+            "node" => return run_node(&args),
+            "profile" => return profile(&args),
+            "script" => return run_script(&args),
             "run" => {}
-            "deploy" => {}
             _ => {
                 println!("subcommand is needed");
                 return 1;
@@ -46,83 +111,703 @@ fn run() -> i32 {
         }
     }
 
-    let mut ws = state::WorldState::new("./config/config.json");
-    // ステートの初期化
-    ws.update_state();
-    println!("world state: {}", ws.get_hash());
+    // --fork <name> で適用するハードフォークを指定できる
+    let fork = match parse_fork(&args) {
+        Ok(fork) => fork,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+
+    let loaded = load_state(&args, DEFAULT_STATE)
+        .and_then(|(ws, output)| Ok((ws, output, decoding_abi(&args)?)));
+    let (ws, output, decoding) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    return repl::run(ws, output, fork, decoding);
+}
+
+/// --stateで指定したファイル(指定がなければdefault)からワールドステートを読み込む<br/>
+/// 保存先は--outで指定したファイル、なければ--stateで指定したファイル
+/// どちらも指定がなければ保存しない
+fn load_state(
+    args: &[String],
+    default: &str,
+) -> Result<(state::WorldState, Option<String>), String> {
+    let state = flag(args, "--state")?;
+    let output = flag(args, "--out")?.or(state).map(|path| path.to_string());
+    let ws = state::WorldState::load(state.unwrap_or(default))?;
+    return Ok((ws, output));
+}
+
+fn save_state(ws: &state::WorldState, path: &str) {
+    match ws.save(path) {
+        Ok(()) => println!("world state is saved to {}", path),
+        Err(e) => println!("{}", e),
+    }
+}
+
+/// --name に続く値 --nameがなければNone
+fn flag<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>, String> {
+    return match args.iter().position(|arg| arg == name) {
+        Some(i) => match args.get(i + 1) {
+            Some(value) => Ok(Some(value)),
+            None => Err(format!("please input value of {}", name)),
+        },
+        None => Ok(None),
+    };
+}
+
+/// --fork で指定されたハードフォーク 指定がなければ最新のフォーク
+fn parse_fork(args: &[String]) -> Result<fork::Fork, String> {
+    return match flag(args, "--fork")? {
+        Some(name) => name.parse(),
+        None => Ok(fork::Fork::default()),
+    };
+}
+
+/// --sources で指定したsolcの出力やartifactのsource map 指定がなければ空
+fn load_sources(args: &[String]) -> Result<srcmap::Sources, String> {
+    let mut sources = srcmap::Sources::new();
+    if let Some(path) = flag(args, "--sources")? {
+        sources.load(path)?;
+    }
+    return Ok(sources);
+}
+
+/// --argsに続く--で始まらない引数
+fn arg_values(args: &[String]) -> Vec<&str> {
+    return match args.iter().position(|arg| arg == "--args") {
+        Some(i) => args[i + 1..]
+            .iter()
+            .take_while(|arg| !arg.starts_with("--"))
+            .map(|arg| &arg[..])
+            .collect(),
+        None => vec![],
+    };
+}
+
+/// --artifactsを指定しなかった場合に探すFoundry、Hardhat、Truffleのビルド結果のディレクトリ
+const ARTIFACT_DIRS: [&str; 3] = ["out", "artifacts", "build/contracts"];
+
+/// --contractで指定したコントラクトを--artifactsのファイルかディレクトリから探す
+fn load_artifact(args: &[String]) -> Result<Option<artifact::Artifact>, String> {
+    let name = match flag(args, "--contract")? {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    return load_artifacts(args)?.find(name).cloned().map(Some);
+}
+
+/// --artifactsのファイルかディレクトリ、なければARTIFACT_DIRSのディレクトリのartifactをすべて読み込む
+fn load_artifacts(args: &[String]) -> Result<artifact::Artifacts, String> {
+    let mut artifacts = artifact::Artifacts::new();
+    match flag(args, "--artifacts")? {
+        Some(path) => artifacts.load(path)?,
+        None => {
+            let dir = ARTIFACT_DIRS
+                .iter()
+                .find(|dir| std::path::Path::new(dir).is_dir())
+                .ok_or("please input --artifacts")?;
+            artifacts.load(dir)?;
+        }
+    }
+    return Ok(artifacts);
+}
+
+/// --libraries `Name=0x...,path:Name=0x...`で指定したライブラリのアドレス
+fn parse_libraries(args: &[String]) -> Result<Vec<(String, ethereum_types::H160)>, String> {
+    let libraries = match flag(args, "--libraries")? {
+        Some(libraries) => libraries,
+        None => return Ok(vec![]),
+    };
+    return libraries
+        .split(',')
+        .map(|library| {
+            let (name, address) = library
+                .split_once('=')
+                .ok_or(format!("{} is invalid library (<name>=<address>)", library))?;
+            Ok((name.to_string(), util::parse_h160(address)?))
+        })
+        .collect();
+}
+
+/// --abiで指定したJSONのABI ABIだけのファイルか、abiを含むartifactを読める<br/>
+/// --abiがなければ--contractで指定したコントラクトのABI
+fn load_abi(args: &[String]) -> Result<Option<abi::Abi>, String> {
+    let path = match flag(args, "--abi")? {
+        Some(path) => path,
+        None => return Ok(load_artifact(args)?.map(|artifact| artifact.abi)),
+    };
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let json: serde_json::Value =
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let json = if json["abi"].is_array() {
+        &json["abi"]
+    } else {
+        &json
+    };
+    return abi::Abi::from_json(json).map(Some);
+}
+
+/// ログとrevertデータのデコードに使うABI<br/>
+/// --abiのABIに、--artifactsか--contractがあれば読み込んだすべてのartifactのABIを合わせる
+fn decoding_abi(args: &[String]) -> Result<abi::Abi, String> {
+    let mut decoding = abi::Abi::default();
+    if flag(args, "--abi")?.is_some() {
+        decoding.merge(load_abi(args)?.unwrap_or_default());
+    }
+    if flag(args, "--artifacts")?.is_some() || flag(args, "--contract")?.is_some() {
+        for artifact in load_artifacts(args)?.iter() {
+            decoding.merge(artifact.abi.clone());
+        }
+    }
+    return Ok(decoding);
+}
 
-    loop {
-        let command = input("select next action: transaction(1) or deploy(2) => ")
-            .trim_end()
-            .to_string();
+/// --sigで指定した関数 --abiがあれば名前かシグネチャでABIから探す
+fn parse_function(args: &[String]) -> Result<Option<abi::Function>, String> {
+    let sig = match flag(args, "--sig")? {
+        Some(sig) => sig,
+        None => return Ok(None),
+    };
+    return match load_abi(args)? {
+        Some(abi) => abi.function(sig).cloned().map(Some),
+        None => abi::Function::parse(sig).map(Some),
+    };
+}
 
+/// abiサブコマンド: selector <sig>、encode <sig> [values]、decode <sig> <hex><br/>
+/// decodeはシグネチャに返り値の型があれば返り値として、なければcalldataとしてデコードする<br/>
+/// 名前のない`(uint256,bool)`のようなシグネチャはセレクタをつけずにエンコード、デコードする
+fn abi_command(args: &[String]) -> i32 {
+    let result = (|| -> Result<String, String> {
+        let usage = "usage: toyevm abi selector <sig> | encode <sig> [values] | decode <sig> <hex>";
+        let command = args.get(2).ok_or(usage)?;
+        let function = abi::Function::parse(args.get(3).ok_or(usage)?)?;
+        let types: Vec<abi::ParamType> = function
+            .inputs
+            .iter()
+            .map(|param| param.kind.clone())
+            .collect();
         match &command[..] {
-            "transaction" | "1" => {
-                let code_owner = input("contract address    > ").trim_end().to_string();
-                let sender = input("sender address      > ").trim_end().to_string();
-                transaction(
-                    &mut ws,
-                    util::to_h160(&code_owner),
-                    util::to_h160(&sender),
-                    1_000_000_000,
-                    100_000_000_000_000_000,
-                );
-                println!();
+            "selector" => return Ok(format!("0x{}", hex::encode(function.selector()))),
+            "encode" => {
+                let values: Vec<&str> = args[4..].iter().map(|arg| &arg[..]).collect();
+                let data = if function.name.is_empty() {
+                    abi::encode(&types, &abi::parse_args(&function.inputs, &values)?)?
+                } else {
+                    function.encode_args(&values)?
+                };
+                return Ok(format!("0x{}", hex::encode(data)));
             }
-            "deploy" | "2" => {
-                let code = &input("contract code      > ").trim_end().to_string();
-                deploy(&mut ws, code);
-                println!();
+            "decode" => {
+                let data = args.get(4).ok_or(usage)?;
+                let data = hex::decode(data.strip_prefix("0x").unwrap_or(data))
+                    .map_err(|e| format!("invalid data: {}", e))?;
+                let tokens = if !function.outputs.is_empty() {
+                    function.decode_output(&data)?
+                } else if function.name.is_empty() {
+                    abi::decode(&types, &data)?
+                } else {
+                    function.decode_input(&data)?
+                };
+                let lines: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+                return Ok(lines.join("\n"));
             }
-            "exit" | "quit" => {
-                return 0;
+            _ => return Err(usage.to_string()),
+        }
+    })();
+    return match result {
+        Ok(output) => {
+            println!("{}", output);
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    };
+}
+
+/// callサブコマンド: フラグで指定したトランザクションを1回だけ実行して結果を表示する
+fn call(args: &[String]) -> i32 {
+    return execute_with_state(args, call_transaction(args));
+}
+
+/// --to --data と共通のフラグからコントラクトを呼び出すトランザクションを作る<br/>
+/// --dataの代わりに--sigと--argsで関数と引数を指定するとcalldataをABIエンコードする
+fn call_transaction(args: &[String]) -> Result<transaction::Transaction, String> {
+    return parse_transaction(args).and_then(|mut tx| {
+        let to = flag(args, "--to")?.ok_or("please input --to")?;
+        tx.to = Some(util::parse_h160(to)?);
+        if let Some(function) = parse_function(args)? {
+            if flag(args, "--data")?.is_some() {
+                return Err("--sig and --data cannot be used together".to_string());
             }
-            "help" => {
-                println!("help");
-                println!("transaction or 1: execute transaction");
-                println!("deploy or 2: deploy contract");
-                println!("exit or quit: quit EVM");
-                println!("help: print help");
+            tx.data = function.encode_args(&arg_values(args))?;
+        } else if let Some(data) = flag(args, "--data")? {
+            let hex = data.strip_prefix("0x").unwrap_or(data);
+            tx.data = hex::decode(hex).map_err(|e| format!("invalid data: {}", e))?;
+        }
+        Ok(tx)
+    });
+}
+
+/// deployサブコマンド: 初期化コードを実行してコントラクトを作成する
+fn deploy(args: &[String]) -> i32 {
+    return execute_with_state(args, deploy_transaction(args));
+}
+
+/// --code --args と共通のフラグからコントラクトを作成するトランザクションを作る<br/>
+/// --argsで渡したABIエンコード済みのコンストラクタの引数は初期化コードの後ろに連結される<br/>
+/// --sig `constructor(types)`か--abiがあれば、--argsの値をコンストラクタの引数としてエンコードする<br/>
+/// --codeの代わりに--contractで指定したコントラクトを--librariesでリンクしてデプロイすることもできる
+fn deploy_transaction(args: &[String]) -> Result<transaction::Transaction, String> {
+    return parse_transaction(args).and_then(|mut tx| {
+        tx.data = match (flag(args, "--code")?, load_artifact(args)?) {
+            (Some(code), _) => util::read_bytecode(code)?,
+            (None, Some(artifact)) => artifact.link(&parse_libraries(args)?)?,
+            (None, None) => return Err("please input --code or --contract".to_string()),
+        };
+        let constructor = match (flag(args, "--sig")?, load_abi(args)?) {
+            (Some(sig), _) => Some(abi::Function::parse(sig)?.inputs),
+            (None, Some(abi)) => Some(abi.constructor.unwrap_or_default()),
+            (None, None) => None,
+        };
+        if let Some(inputs) = constructor {
+            let tokens = abi::parse_args(&inputs, &arg_values(args))?;
+            let types: Vec<abi::ParamType> = inputs.into_iter().map(|param| param.kind).collect();
+            tx.data.extend(abi::encode(&types, &tokens)?);
+        } else if let Some(arguments) = flag(args, "--args")? {
+            let hex = arguments.strip_prefix("0x").unwrap_or(arguments);
+            let arguments = hex::decode(hex).map_err(|e| format!("invalid args: {}", e))?;
+            tx.data.extend_from_slice(&arguments);
+        }
+        Ok(tx)
+    });
+}
+
+/// --toがあれば呼び出し、なければデプロイのトランザクションを作る
+fn call_or_deploy_transaction(args: &[String]) -> Result<transaction::Transaction, String> {
+    return match flag(args, "--to")? {
+        Some(_) => call_transaction(args),
+        None => deploy_transaction(args),
+    };
+}
+
+/// debugサブコマンド: --toがあれば呼び出し、なければデプロイを1命令ずつ実行する
+fn debug(args: &[String]) -> i32 {
+    let tx = call_or_deploy_transaction(args);
+    let loaded = tx.and_then(|tx| {
+        let (ws, output) = load_state(args, DEFAULT_STATE)?;
+        Ok((tx, ws, output, load_sources(args)?, decoding_abi(args)?))
+    });
+    let (tx, mut ws, output, sources, decoding) = match loaded {
+        Ok(result) => result,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    let code = debugger::run(&mut ws, &tx, sources, &decoding);
+    if let Some(path) = output {
+        ws.update_state();
+        save_state(&ws, &path);
+    }
+    return code;
+}
+
+/// estimateサブコマンド: 呼び出しかデプロイが成功する最小のgasを二分探索で求める<br/>
+/// --gasを探索の上限にする ワールドステートは変更しない
+fn estimate(args: &[String]) -> i32 {
+    let result = call_or_deploy_transaction(args).and_then(|tx| {
+        let (ws, _) = load_state(args, DEFAULT_STATE)?;
+        transaction::estimate_gas(&ws, &tx, &decoding_abi(args)?)
+    });
+    return match result {
+        Ok(gas) => {
+            println!("estimated gas: {}", gas);
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    };
+}
+
+/// profileサブコマンド: 呼び出しかデプロイを実行し、opcodeごと、pcごと、--sourcesがあれば関数ごとのgasを表示する<br/>
+/// --foldedで指定したファイルにflamegraph用のfolded形式で書き出す
+fn profile(args: &[String]) -> i32 {
+    let result = call_or_deploy_transaction(args).and_then(|tx| {
+        let top = match flag(args, "--top")? {
+            Some(top) => top
+                .parse()
+                .map_err(|_| format!("{} is invalid number", top))?,
+            None => 20,
+        };
+        let decoding = decoding_abi(args)?;
+        let sources = load_sources(args)?;
+        let (mut ws, output) = load_state(args, DEFAULT_STATE)?;
+        let (receipt, profiler) = profile::profile(&mut ws, &tx, sources)?;
+        if let Some(path) = flag(args, "--folded")? {
+            fs::write(path, profiler.folded()).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        print!("{}", profiler.report(top));
+        println!();
+        let code = print_receipt(&receipt, &decoding);
+        if let Some(path) = output {
+            ws.update_state();
+            save_state(&ws, &path);
+        }
+        Ok(code)
+    });
+    return result.unwrap_or_else(|e| {
+        println!("{}", e);
+        1
+    });
+}
+
+/// nodeサブコマンド: ワールドステートをJSON-RPCで操作できるHTTPサーバを起動する<br/>
+/// ワールドステートが変わるたびに--outか--stateで指定したファイルに保存する
+fn run_node(args: &[String]) -> i32 {
+    let result = parse_fork(args).and_then(|fork| {
+        let port: u16 = match flag(args, "--port")? {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("{} is invalid port", port))?,
+            None => 8545,
+        };
+        let chain_id = match flag(args, "--chain-id")? {
+            Some(id) => id
+                .parse()
+                .map_err(|_| format!("{} is invalid chain id", id))?,
+            None => node::DEFAULT_CHAIN_ID,
+        };
+        let (ws, output) = load_state(args, DEFAULT_STATE)?;
+        let mut node = node::Node::new(ws, fork);
+        node.set_chain_id(chain_id);
+        node.set_abi(decoding_abi(args)?);
+        let server = tiny_http::Server::http(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+        println!(
+            "listening on http://127.0.0.1:{} (chain id {})",
+            port, chain_id
+        );
+
+        for mut request in server.incoming_requests() {
+            // ブラウザから呼び出せるようにCORSを許可する
+            let headers = [
+                "Content-Type: application/json",
+                "Access-Control-Allow-Origin: *",
+                "Access-Control-Allow-Headers: *",
+            ];
+            let mut body = String::new();
+            let response = if *request.method() == tiny_http::Method::Options {
+                String::new()
+            } else if let Err(e) = request.as_reader().read_to_string(&mut body) {
+                node::RpcError::new(-32700, &e.to_string())
+                    .response(serde_json::Value::Null)
+                    .to_string()
+            } else {
+                let hash = node.world_state().get_hash();
+                let response = match serde_json::from_str(&body) {
+                    Ok(json) => node.handle(&json),
+                    Err(e) => node::RpcError::new(-32700, &e.to_string())
+                        .response(serde_json::Value::Null),
+                };
+                if let Some(path) = output
+                    .as_ref()
+                    .filter(|_| node.world_state().get_hash() != hash)
+                {
+                    save_state(node.world_state(), path);
+                }
+                response.to_string()
+            };
+            let mut response = tiny_http::Response::from_string(response);
+            for header in headers {
+                response.add_header(header.parse::<tiny_http::Header>().unwrap());
+            }
+            if let Err(e) = request.respond(response) {
+                println!("{}", e);
+            }
+        }
+        Ok(0)
+    });
+    return result.unwrap_or_else(|e| {
+        println!("{}", e);
+        1
+    });
+}
+
+/// scriptサブコマンド: シナリオを実行し、assertが1つでも失敗すれば1を返す<br/>
+/// 初期状態は--state、シナリオのstate、./config/config.jsonの順に探す<br/>
+/// --coverageで命令ごとの実行回数を表示し、--lcovで指定したファイルにLCOVの形式で書き出す<br/>
+/// --htmlで指定したファイルには命令ごとの実行回数をHTMLの表で書き出す
+fn run_script(args: &[String]) -> i32 {
+    let result = args
+        .get(2)
+        .ok_or_else(|| "please input scenario file".to_string())
+        .and_then(|path| fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e)))
+        .and_then(|text| script::parse(&text))
+        .and_then(|scenario| {
+            let default = scenario.state.as_deref().unwrap_or(DEFAULT_STATE);
+            let (mut ws, output) = load_state(args, default)?;
+            let sources = load_sources(args)?;
+            let decoding = decoding_abi(args)?;
+            let lcov = flag(args, "--lcov")?;
+            let html = flag(args, "--html")?;
+            let report = args.iter().any(|arg| arg == "--coverage");
+            let coverage = Rc::new(RefCell::new(coverage::Coverage::new()));
+            let inspector = if report || lcov.is_some() || html.is_some() {
+                Some(coverage.clone() as _)
+            } else {
+                None
+            };
+            let result = script::run_with_inspector(&scenario, &mut ws, inspector, &decoding);
+            for (i, result) in result.steps.iter().enumerate() {
+                match result {
+                    Ok(message) => println!("[{}] {}", i + 1, message),
+                    Err(message) => println!("[{}] FAILED: {}", i + 1, message),
+                }
+            }
+            if let Some(e) = result.error {
+                return Err(e);
+            }
+            let failures = result.failures();
+            if report {
                 println!();
+                print!("{}", coverage.borrow().report(&sources));
             }
-            c => {
-                println!("{} is invalid command", c);
-                continue;
+            if let Some(path) = lcov {
+                fs::write(path, coverage.borrow().lcov(&sources))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                println!("coverage is saved to {}", path);
             }
+            if let Some(path) = html {
+                fs::write(path, coverage.borrow().html(&sources))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                println!("coverage report is saved to {}", path);
+            }
+            if let Some(path) = output {
+                ws.update_state();
+                save_state(&ws, &path);
+            }
+            Ok(failures)
+        });
+
+    match result {
+        Ok(0) => {
+            println!("all assertions passed");
+            return 0;
+        }
+        Ok(failures) => {
+            println!("{} assertion(s) failed", failures);
         }
+        Err(e) => println!("{}", e),
+    }
+    return 1;
+}
 
-        // ステートを更新
+/// --from --value --gas --fork から宛先のないトランザクションを作る
+fn parse_transaction(args: &[String]) -> Result<transaction::Transaction, String> {
+    let from = flag(args, "--from")?.ok_or("please input --from")?;
+    let mut tx = transaction::Transaction::create(util::parse_h160(from)?, vec![]);
+    if let Some(value) = flag(args, "--value")? {
+        tx.value = util::parse_u256(value)?;
+    }
+    if let Some(gas) = flag(args, "--gas")? {
+        tx.gas = gas.parse().map_err(|_| format!("{} is invalid gas", gas))?;
+    }
+    tx.fork = parse_fork(args)?;
+    return Ok(tx);
+}
+
+/// --stateで指定したワールドステート上でトランザクションを実行し、実行後のステートを保存する
+fn execute_with_state(args: &[String], tx: Result<transaction::Transaction, String>) -> i32 {
+    let loaded = tx.and_then(|tx| {
+        let (ws, output) = load_state(args, DEFAULT_STATE)?;
+        Ok((tx, ws, output, load_sources(args)?, decoding_abi(args)?))
+    });
+    let (tx, mut ws, output, sources, decoding) = match loaded {
+        Ok(result) => result,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    let code = match flag(args, "--tracer") {
+        Ok(Some(tracer)) => {
+            let diff = args.iter().any(|arg| arg == "--diff");
+            execute_traced(&mut ws, &tx, tracer, diff, decoding)
+        }
+        Ok(None) if args.iter().any(|arg| arg == "--json") => execute_json(&mut ws, &tx, sources),
+        Ok(None) => {
+            // 呼び出した関数の返り値の型がわかればデコードして表示する
+            let function = match flag(args, "--to") {
+                Ok(Some(_)) => parse_function(args).unwrap_or(None),
+                _ => None,
+            };
+            execute(&mut ws, &tx, sources, function.as_ref(), &decoding)
+        }
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    if let Some(path) = output {
         ws.update_state();
-        println!("world state: {}", ws.get_hash());
+        save_state(&ws, &path);
     }
-    return 0;
+    return code;
+}
+
+/// トランザクションを実行してEIP-3155のトレースだけを標準出力に出す 成功しなければ1を返す
+fn execute_json(
+    ws: &mut state::WorldState,
+    tx: &transaction::Transaction,
+    sources: srcmap::Sources,
+) -> i32 {
+    return match tracer::trace(ws, tx, sources) {
+        Ok((receipt, lines)) => {
+            for line in lines {
+                println!("{}", line);
+            }
+            if receipt.status.is_success() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    };
+}
+
+/// トランザクションを実行してgethのcallTracerかprestateTracerの形式の出力だけを表示する<br/>
+/// prestateTracerはdiffがtrueなら実行前後の差分を出力する 成功しなければ1を返す<br/>
+/// callTracerのログとrevertの理由はdecodingのABIでデコードする
+fn execute_traced(
+    ws: &mut state::WorldState,
+    tx: &transaction::Transaction,
+    tracer: &str,
+    diff: bool,
+    decoding: abi::Abi,
+) -> i32 {
+    let result = match tracer {
+        "callTracer" => tracer::call_trace(ws, tx, decoding),
+        "prestateTracer" => tracer::prestate_trace(ws, tx, diff),
+        _ => Err(format!(
+            "{} is unknown tracer (callTracer or prestateTracer)",
+            tracer
+        )),
+    };
+    return match result {
+        Ok((receipt, trace)) => {
+            println!("{:#}", trace);
+            if receipt.status.is_success() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    };
 }
 
-/// execute transaction
-fn transaction(
+/// トランザクションを実行して結果を表示する 成功しなければ1を返す<br/>
+/// 失敗した場合はsourcesから求めた失敗した命令のソースの位置も表示する<br/>
+/// ログとrevertの理由はdecodingのABIでデコードする
+fn execute(
     ws: &mut state::WorldState,
-    code_owner: H160,
-    sender: H160,
-    gas_price: usize,
-    value: usize,
-) {
-    let mut env = vm::Environment::new(code_owner, sender, gas_price, value);
-    let mut contract = ws.get_account_state(&code_owner);
-    env.set_code(util::str_to_bytes(&contract.get_code()));
-    let mut vm = vm::VM::new(env);
-    vm.exec_transaction(&mut contract);
-
-    let mut sender_account = ws.get_account_state(&sender);
-    sender_account.increment_nonce();
-}
-
-/// deploy contract
-fn deploy(ws: &mut state::WorldState, code: &str) {
-    // TODO: 正確にする
-    let account_state = state::AccountState::new(code.to_string());
-    let address = H160::random();
-    ws.push_account_state(address, account_state);
-    println!("{} is deployed!", hex::encode(address));
+    tx: &transaction::Transaction,
+    sources: srcmap::Sources,
+    function: Option<&abi::Function>,
+    decoding: &abi::Abi,
+) -> i32 {
+    let last = Rc::new(RefCell::new(srcmap::LastStep::new(sources)));
+    return match transaction::execute_with_inspector(ws, tx, Some(last.clone())) {
+        Ok(receipt) => {
+            let code = print_receipt(&receipt, decoding);
+            if let Some(function) = function.filter(|_| receipt.status.is_success()) {
+                print_decoded(function, &receipt.output);
+            }
+            if !receipt.status.is_success() {
+                if let Some(location) = last.borrow().location() {
+                    println!("{} at {}", receipt.status, location);
+                    println!("    {}", location.text);
+                }
+            }
+            code
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    };
+}
+
+/// 返り値をfunctionの返り値の型でデコードして表示する 型がわからなければ何もしない
+fn print_decoded(function: &abi::Function, output: &[u8]) {
+    if function.outputs.is_empty() {
+        return;
+    }
+    match function.decode_output(output) {
+        Ok(tokens) => {
+            for (param, token) in function.outputs.iter().zip(tokens) {
+                if param.name.is_empty() {
+                    println!("  {}: {}", param.kind, token);
+                } else {
+                    println!("  {} {}: {}", param.kind, param.name, token);
+                }
+            }
+        }
+        Err(e) => println!("  cannot decode return value: {}", e),
+    }
+}
+
+/// 実行結果を表示する 成功しなければ1を返す<br/>
+/// ログとrevertの理由はdecodingのイベントとカスタムエラーでデコードできれば表示する<br/>
+/// Error(string)とPanic(uint256)はABIがなくてもデコードする
+fn print_receipt(receipt: &transaction::Receipt, decoding: &abi::Abi) -> i32 {
+    println!("status: {}", receipt.status);
+    if let Some(address) = receipt.contract_address {
+        println!("contract address: 0x{}", hex::encode(address));
+    } else {
+        println!("return: 0x{}", hex::encode(&receipt.output));
+    }
+    if !receipt.status.is_success() {
+        if let Some(reason) = decoding.decode_revert(&receipt.output) {
+            println!("revert reason: {}", reason);
+        }
+    }
+    println!(
+        "gas used: {} (refund: {})",
+        receipt.gas_used, receipt.gas_refund
+    );
+    println!("logs: {}", receipt.logs.len());
+    for (i, log) in receipt.logs.iter().enumerate() {
+        println!("  [{}] address: 0x{}", i, hex::encode(log.address));
+        for topic in &log.topics {
+            println!("      topic: 0x{}", hex::encode(topic));
+        }
+        println!("      data: 0x{}", hex::encode(&log.data));
+        if let Some(event) = decoding.decode_log(&log.topics, &log.data) {
+            println!("      event: {}", event);
+        }
+    }
+
+    return if receipt.status.is_success() { 0 } else { 1 };
 }
 
 /// print help
@@ -130,6 +815,53 @@ fn help() {
     println!("usage: toyevm <command> [<args>] ");
     println!();
     println!("run       start EVM");
-    println!("disasm    disassemble EVM bytecode");
+    println!("          [--state <file>] [--out <file>] [--fork <name>]");
+    println!("disasm    disassemble EVM bytecode (hex string or file)");
+    println!("asm       assemble mnemonics into EVM bytecode (source or file)");
+    println!("cfg       print control flow graph (--format dot|json)");
+    println!("abi       selector <sig> | encode <sig> [values] | decode <sig> <hex>");
+    println!("call      execute a transaction once");
+    println!("          --to <addr> --from <addr> [--data <hex>] [--value <wei>] [--gas <n>]");
+    println!("          [--sig <sig|name> [--abi <file>] --args <values>] instead of --data");
+    println!("          [--state <file>] [--out <file>] [--fork <name>] [--json]");
+    println!("          [--tracer callTracer|prestateTracer [--diff]] [--sources <file>]");
+    println!("deploy    deploy a contract by executing its init code");
+    println!(
+        "          --from <addr> --code <hex|file> [--args <hex>] [--value <wei>] [--gas <n>]"
+    );
+    println!("          [--sig 'constructor(<types>)' | --abi <file>] --args <values>");
+    println!(
+        "          --contract <name> [--artifacts <file|dir>] [--libraries <name>=<addr>,...]"
+    );
+    println!("          deploys a compiled contract (solc, Foundry, Hardhat or Truffle) instead");
+    println!("          of --code, and call uses its ABI for --sig");
+    println!("          [--state <file>] [--out <file>] [--fork <name>] [--json]");
+    println!("          [--tracer callTracer|prestateTracer [--diff]] [--sources <file>]");
+    println!("          --json prints an EIP-3155 trace, --tracer a geth-style trace");
+    println!("          instead of the receipt");
+    println!("          --sources <file> maps pcs to Solidity source lines with the source maps");
+    println!("          in a solc standard JSON output, Hardhat build-info or Foundry artifact");
+    println!("debug     execute a call (--to) or deploy (--code) one instruction at a time");
+    println!("          with the same flags as call or deploy");
+    println!(
+        "estimate  find the minimum gas limit for a call (--to) or deploy (--code) to succeed"
+    );
+    println!("          with the same flags as call or deploy, searching up to --gas");
+    println!("          the state is not changed; prints the revert reason if it always fails");
+    println!("profile   report gas by opcode and pc for a call (--to) or deploy (--code)");
+    println!("          with the same flags as call or deploy, [--top <n>] [--folded <file>]");
+    println!("          [--sources <file>] also reports gas by Solidity function");
+    println!("node      serve the world state over JSON-RPC (eth_* methods)");
+    println!(
+        "          [--port <n>] [--chain-id <n>] [--state <file>] [--out <file>] [--fork <name>]"
+    );
+    println!("          every transaction is mined into its own block and the state is saved");
+    println!("          to --out; evm_*, anvil_* and eth_call state overrides are supported");
+    println!("script    run a scenario file (json or yaml)");
+    println!("          <file> [--state <file>] [--out <file>]");
+    println!("          [--coverage] [--lcov <file>] [--html <file>] [--sources <file>]");
+    println!("          --coverage prints hits per instruction, --html writes them as an html");
+    println!("          table, --lcov writes source line coverage mapped with the source maps");
+    println!("          of --sources");
     println!("help      print help message");
 }
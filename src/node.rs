@@ -0,0 +1,1458 @@
+//! JSON-RPC node
+//!
+//! ワールドステートをEthereumのJSON-RPC(`eth_*`)で操作できるようにするモジュール<br/>
+//! 送信されたトランザクションはすぐに実行し、1つのトランザクションだけを含むブロックにする<br/>
+//! 過去のブロックのステートは保持しないので、ステートを参照するメソッドは最新のブロックだけを受け付ける
+use super::abi::Abi;
+use super::fork::Fork;
+use super::precompile;
+use super::rlp::{self, Rlp};
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::{AccountOverride, Log, WorldState};
+use super::transaction::{self, Receipt, Transaction};
+use super::util;
+use super::vm::{BlockEnv, ExitStatus};
+use ethereum_types::{H160, H256, U256};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// --chain-idを指定しなかった場合のチェーンID(Hardhat、Anvilと同じ)
+pub const DEFAULT_CHAIN_ID: u64 = 31337;
+
+/// ブロックのgasの上限 ブロックの情報として返すだけで、トランザクションのgasは制限しない
+const BLOCK_GAS_LIMIT: usize = 30_000_000;
+
+/// JSON-RPCのエラー
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>, // revertの返り値など
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> Self {
+        return Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        };
+    }
+
+    /// 引数が不正
+    fn invalid_params(message: &str) -> Self {
+        return Self::new(-32602, message);
+    }
+
+    /// idのリクエストに対するエラーのレスポンス
+    pub fn response(&self, id: Value) -> Value {
+        let mut error = json!({ "code": self.code, "message": self.message });
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        return json!({ "jsonrpc": "2.0", "id": id, "error": error });
+    }
+}
+
+/// トランザクションの実行に失敗したなどのエラー
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        return Self::new(-32000, &message);
+    }
+}
+
+/// 署名済みトランザクションをデコードしたもの
+#[derive(Debug, Clone)]
+pub struct SignedTransaction {
+    pub tx: Transaction,
+    pub nonce: usize,
+    pub chain_id: Option<u64>, // EIP-155より前の署名ならNone
+    pub kind: u8,              // トランザクションの種類 0: legacy, 1: EIP-2930, 2: EIP-1559
+    pub hash: H256,
+}
+
+/// ブロック
+#[derive(Debug, Clone)]
+struct Block {
+    number: u64,
+    hash: H256,
+    parent_hash: H256,
+    timestamp: u64,
+    transactions: Vec<H256>,
+    gas_used: usize,
+}
+
+/// 実行したトランザクションとその結果
+#[derive(Debug, Clone)]
+struct MinedTransaction {
+    tx: Transaction,
+    nonce: usize,
+    kind: u8,
+    block_number: u64,
+    receipt: Receipt,
+}
+
+/// evm_snapshotで保存したチェーンの状態
+#[derive(Debug, Clone)]
+struct Snapshot {
+    id: u64,
+    ws: WorldState,
+    blocks: Vec<Block>,
+    transactions: HashMap<H256, MinedTransaction>,
+    time_offset: u64,
+}
+
+/// JSON-RPCのリクエストを処理するノード
+#[derive(Debug, Clone)]
+pub struct Node {
+    ws: WorldState,
+    fork: Fork,
+    chain_id: u64,
+    abi: Abi,                    // revertデータのデコードに使うABI
+    accounts: Vec<H160>,         // eth_sendTransactionで署名なしに送信できるアカウント
+    impersonated: HashSet<H160>, // 署名なしに送信できるようにしたアカウント
+    blocks: Vec<Block>,          // 0番目はgenesisブロック
+    transactions: HashMap<H256, MinedTransaction>,
+    time_offset: u64,            // increase_timeで進めた秒数の合計
+    next_timestamp: Option<u64>, // 次のブロックのタイムスタンプの指定
+    snapshots: Vec<Snapshot>,    // idの昇順
+    next_snapshot_id: u64,
+}
+
+impl Node {
+    /// wsをgenesisブロックのステートとするノード<br/>
+    /// コードを持たないアカウントはeth_sendTransactionで署名なしに使える
+    pub fn new(ws: WorldState, fork: Fork) -> Self {
+        let accounts = ws
+            .addresses()
+            .into_iter()
+            .filter(|address| ws.account(address).unwrap().get_code().is_empty())
+            .collect();
+        let mut node = Self {
+            ws,
+            fork,
+            chain_id: DEFAULT_CHAIN_ID,
+            abi: Abi::default(),
+            accounts,
+            impersonated: HashSet::new(),
+            blocks: vec![],
+            transactions: HashMap::new(),
+            time_offset: 0,
+            next_timestamp: None,
+            snapshots: vec![],
+            next_snapshot_id: 1,
+        };
+        node.new_block(vec![], 0);
+        return node;
+    }
+
+    /// チェーンIDをセットする
+    pub fn set_chain_id(&mut self, chain_id: u64) {
+        self.chain_id = chain_id;
+    }
+
+    /// eth_callとeth_estimateGasのrevertデータのデコードに使うABIをセットする
+    pub fn set_abi(&mut self, abi: Abi) {
+        self.abi = abi;
+    }
+
+    /// getter for world state
+    pub fn world_state(&self) -> &WorldState {
+        &self.ws
+    }
+
+    /// 最新のブロックの番号
+    pub fn block_number(&self) -> u64 {
+        return self.blocks.len() as u64 - 1;
+    }
+
+    /// 最新のブロックの番号とタイムスタンプ
+    pub fn latest_block(&self) -> BlockEnv {
+        let latest = self.blocks.last().unwrap();
+        return BlockEnv {
+            number: latest.number,
+            timestamp: latest.timestamp,
+        };
+    }
+
+    /// 次に作るブロックの番号とタイムスタンプ<br/>
+    /// タイムスタンプは指定がなければ現在時刻にincrease_timeの秒数を加えたもので、親より前にはならない
+    pub fn pending_block(&self) -> BlockEnv {
+        let (number, parent_timestamp) = match self.blocks.last() {
+            Some(parent) => (parent.number + 1, parent.timestamp),
+            None => (0, 0),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let timestamp = match self.next_timestamp {
+            Some(timestamp) => timestamp,
+            None => (now + self.time_offset).max(parent_timestamp),
+        };
+        return BlockEnv { number, timestamp };
+    }
+
+    /// 現在のワールドステートとブロックを保存し、revertで戻るためのidを返す
+    pub fn snapshot(&mut self) -> u64 {
+        let id = self.next_snapshot_id;
+        self.next_snapshot_id += 1;
+        self.snapshots.push(Snapshot {
+            id,
+            ws: self.ws.clone(),
+            blocks: self.blocks.clone(),
+            transactions: self.transactions.clone(),
+            time_offset: self.time_offset,
+        });
+        return id;
+    }
+
+    /// idのsnapshotの状態に戻す 戻ったsnapshotとそれより後のsnapshotは使えなくなる<br/>
+    /// idのsnapshotがなければfalseを返す
+    pub fn revert(&mut self, id: u64) -> bool {
+        let index = match self.snapshots.iter().position(|snapshot| snapshot.id == id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let snapshot = self.snapshots.drain(index..).next().unwrap();
+        self.ws = snapshot.ws;
+        self.blocks = snapshot.blocks;
+        self.transactions = snapshot.transactions;
+        self.time_offset = snapshot.time_offset;
+        self.next_timestamp = None;
+        return true;
+    }
+
+    /// トランザクションを含まないブロックをblocks個作る
+    pub fn mine(&mut self, blocks: u64) {
+        for _ in 0..blocks {
+            self.new_block(vec![], 0);
+        }
+    }
+
+    /// 以降のブロックのタイムスタンプをseconds秒進め、進めた秒数の合計を返す
+    pub fn increase_time(&mut self, seconds: u64) -> u64 {
+        self.time_offset += seconds;
+        return self.time_offset;
+    }
+
+    /// 次のブロックのタイムスタンプを指定する 最新のブロックより前にはできない
+    pub fn set_next_block_timestamp(&mut self, timestamp: u64) -> Result<(), String> {
+        let latest = self.latest_block().timestamp;
+        if timestamp < latest {
+            return Err(format!(
+                "timestamp {} is before the latest block ({})",
+                timestamp, latest
+            ));
+        }
+        self.next_timestamp = Some(timestamp);
+        return Ok(());
+    }
+
+    /// アカウントの残高を書き換える
+    pub fn set_balance(&mut self, address: &H160, balance: U256) {
+        self.override_account(
+            address,
+            &AccountOverride {
+                balance: Some(balance),
+                ..AccountOverride::default()
+            },
+        );
+    }
+
+    /// アカウントのコードを書き換える
+    pub fn set_code(&mut self, address: &H160, code: &[u8]) {
+        self.override_account(
+            address,
+            &AccountOverride {
+                code: Some(code.to_vec()),
+                ..AccountOverride::default()
+            },
+        );
+    }
+
+    /// アカウントのstorageのslotの値を書き換える
+    pub fn set_storage_at(&mut self, address: &H160, slot: U256, value: U256) {
+        self.override_account(
+            address,
+            &AccountOverride {
+                state_diff: vec![(slot, value)],
+                ..AccountOverride::default()
+            },
+        );
+    }
+
+    /// アカウントを書き換える 存在しなければ作成する
+    pub fn override_account(&mut self, address: &H160, account_override: &AccountOverride) {
+        self.ws.override_account(address, account_override);
+        self.ws.update_state();
+    }
+
+    /// addressから署名なしにトランザクションを送れるようにする
+    pub fn impersonate_account(&mut self, address: H160) {
+        self.impersonated.insert(address);
+    }
+
+    /// impersonate_accountをやめる
+    pub fn stop_impersonating_account(&mut self, address: &H160) {
+        self.impersonated.remove(address);
+    }
+
+    /// 署名なしのトランザクションを実行し、そのトランザクションだけを含むブロックを作る<br/>
+    /// 送信者はeth_accountsのアカウントかimpersonate_accountしたアカウントでなければならない
+    pub fn send_transaction(&mut self, tx: Transaction) -> Result<H256, String> {
+        if self.impersonated.contains(&tx.from) {
+            self.ws.get_or_create_account_state(&tx.from);
+        } else if !self.accounts.contains(&tx.from) {
+            return Err(format!("sender 0x{} is not unlocked", hex::encode(tx.from)));
+        }
+        // 署名がないので、nonceと送信者を含むフィールドのRLPからハッシュを計算する
+        let nonce = self.nonce(&tx.from);
+        let hash = H256::from(util::keccak256(&rlp::encode(&Rlp::List(vec![
+            Rlp::uint(nonce.into()),
+            Rlp::uint(tx.gas_price.into()),
+            Rlp::uint(tx.gas.into()),
+            Rlp::Bytes(tx.to.map(|to| to.as_bytes().to_vec()).unwrap_or_default()),
+            Rlp::uint(tx.value),
+            Rlp::Bytes(tx.data.clone()),
+            Rlp::Bytes(tx.from.as_bytes().to_vec()),
+        ]))));
+        return self.mine_transaction(hash, tx, 0);
+    }
+
+    /// ステートを変更せずに最新のブロックでトランザクションを実行する<br/>
+    /// overridesでアカウントを書き換えた状態で実行し、送信者が存在しなければ作成する
+    pub fn simulate(
+        &self,
+        tx: &Transaction,
+        overrides: &[(H160, AccountOverride)],
+    ) -> Result<Receipt, String> {
+        let mut ws = self.ws.clone();
+        for (address, account_override) in overrides {
+            ws.override_account(address, account_override);
+        }
+        ws.get_or_create_account_state(&tx.from);
+        let mut tx = tx.clone();
+        tx.block = self.latest_block();
+        return transaction::execute(&mut ws, &tx);
+    }
+
+    /// 最新のブロックの上でトランザクションが成功する最小のgasの上限を二分探索で求める
+    pub fn estimate_gas(&self, tx: &Transaction) -> Result<usize, String> {
+        let mut ws = self.ws.clone();
+        ws.get_or_create_account_state(&tx.from);
+        let mut tx = tx.clone();
+        tx.block = self.latest_block();
+        return transaction::estimate_gas(&ws, &tx, &self.abi);
+    }
+
+    /// JSON-RPCのリクエスト(バッチを含む)を処理してレスポンスを返す
+    pub fn handle(&mut self, request: &Value) -> Value {
+        return match request.as_array() {
+            Some(batch) => Value::Array(batch.iter().map(|r| self.handle_one(r)).collect()),
+            None => self.handle_one(request),
+        };
+    }
+
+    /// 1つのリクエストを処理する
+    fn handle_one(&mut self, request: &Value) -> Value {
+        let id = request["id"].clone();
+        let method = match request["method"].as_str() {
+            Some(method) => method,
+            None => return RpcError::new(-32600, "invalid request").response(id),
+        };
+        let params = match &request["params"] {
+            Value::Array(params) => params.clone(),
+            Value::Null => vec![],
+            _ => return RpcError::invalid_params("params must be array").response(id),
+        };
+        return match self.call(method, &params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => e.response(id),
+        };
+    }
+
+    /// メソッドを呼び出して結果を返す
+    pub fn call(&mut self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        let param = |index: usize| params.get(index).unwrap_or(&Value::Null);
+        return match method {
+            "web3_clientVersion" => Ok(json!(format!("toyevm/{}", env!("CARGO_PKG_VERSION")))),
+            "net_version" => Ok(json!(self.chain_id.to_string())),
+            "eth_chainId" => Ok(quantity(self.chain_id)),
+            "eth_accounts" => Ok(json!(self
+                .accounts
+                .iter()
+                .map(hex_address)
+                .collect::<Vec<String>>())),
+            "eth_blockNumber" => Ok(quantity(self.block_number())),
+            "eth_gasPrice" => Ok(quantity(1)),
+            "eth_getBalance" => {
+                let address = parse_address(param(0))?;
+                self.check_latest(param(1))?;
+                let balance = self.ws.account(&address).map(|a| a.get_balance());
+                Ok(quantity(balance.unwrap_or_default()))
+            }
+            "eth_getCode" => {
+                let address = parse_address(param(0))?;
+                self.check_latest(param(1))?;
+                let code = self.ws.account(&address).map(|a| a.get_code());
+                Ok(json!(format!("0x{}", code.unwrap_or_default())))
+            }
+            "eth_getStorageAt" => {
+                let address = parse_address(param(0))?;
+                let slot = parse_quantity(param(1))?;
+                self.check_latest(param(2))?;
+                let value = self.ws.account(&address).map(|a| a.get_storage(&slot));
+                let mut word = [0; 32];
+                value.unwrap_or_default().to_big_endian(&mut word);
+                Ok(hex_data(&word))
+            }
+            "eth_getTransactionCount" => {
+                let address = parse_address(param(0))?;
+                self.check_latest(param(1))?;
+                let nonce = self.ws.account(&address).map(|a| a.get_nonce());
+                Ok(quantity(nonce.unwrap_or(0)))
+            }
+            "eth_call" => {
+                let tx = self.parse_call(param(0))?;
+                self.check_latest(param(1))?;
+                let overrides = parse_overrides(param(2))?;
+                let receipt = self.simulate(&tx, &overrides)?;
+                check_status(&receipt, &self.abi)?;
+                Ok(hex_data(&receipt.output))
+            }
+            "eth_estimateGas" => {
+                let tx = self.parse_call(param(0))?;
+                self.check_latest(param(1))?;
+                // 上限のgasで失敗すればeth_callと同じエラーを返す
+                check_status(&self.simulate(&tx, &[])?, &self.abi)?;
+                Ok(quantity(self.estimate_gas(&tx)?))
+            }
+            "eth_sendTransaction" => {
+                let tx = self.parse_call(param(0))?;
+                let nonce = self.nonce(&tx.from);
+                if !param(0)["nonce"].is_null()
+                    && parse_quantity(&param(0)["nonce"])? != nonce.into()
+                {
+                    return Err(RpcError::from(format!("nonce must be {}", nonce)));
+                }
+                Ok(hex_hash(&self.send_transaction(tx)?))
+            }
+            "eth_sendRawTransaction" => {
+                let raw = parse_bytes(param(0))?;
+                let signed = decode_raw_transaction(&raw, self.fork)?;
+                if signed.chain_id.is_some_and(|id| id != self.chain_id) {
+                    return Err(RpcError::from(format!(
+                        "chain id must be {}",
+                        self.chain_id
+                    )));
+                }
+                let nonce = self.nonce(&signed.tx.from);
+                if signed.nonce != nonce {
+                    return Err(RpcError::from(format!("nonce must be {}", nonce)));
+                }
+                Ok(hex_hash(&self.mine_transaction(
+                    signed.hash,
+                    signed.tx,
+                    signed.kind,
+                )?))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash = parse_hash(param(0))?;
+                Ok(match self.transactions.get(&hash) {
+                    Some(mined) => self.receipt_json(&hash, mined),
+                    None => Value::Null,
+                })
+            }
+            "eth_getTransactionByHash" => {
+                let hash = parse_hash(param(0))?;
+                Ok(match self.transactions.get(&hash) {
+                    Some(mined) => self.transaction_json(&hash, mined),
+                    None => Value::Null,
+                })
+            }
+            "eth_getBlockByNumber" => {
+                let number = self.parse_block_number(param(0))?;
+                let full = param(1).as_bool().unwrap_or(false);
+                Ok(match self.blocks.get(number as usize) {
+                    Some(block) => self.block_json(block, full),
+                    None => Value::Null,
+                })
+            }
+            "eth_getBlockByHash" => {
+                let hash = parse_hash(param(0))?;
+                let full = param(1).as_bool().unwrap_or(false);
+                Ok(match self.blocks.iter().find(|block| block.hash == hash) {
+                    Some(block) => self.block_json(block, full),
+                    None => Value::Null,
+                })
+            }
+            "eth_getLogs" => self.get_logs(param(0)),
+            "evm_snapshot" => Ok(quantity(self.snapshot())),
+            "evm_revert" => Ok(json!(self.revert(parse_number(param(0))?))),
+            "evm_mine" => {
+                // [timestamp]か[{ timestamp, blocks }]
+                let options = param(0);
+                let timestamp = if options.is_object() {
+                    &options["timestamp"]
+                } else {
+                    options
+                };
+                if !timestamp.is_null() {
+                    self.set_next_block_timestamp(parse_number(timestamp)?)?;
+                }
+                let blocks = match options.get("blocks") {
+                    Some(blocks) => parse_number(blocks)?,
+                    None => 1,
+                };
+                self.mine(blocks);
+                Ok(json!("0x0"))
+            }
+            "evm_increaseTime" => Ok(json!(self.increase_time(parse_number(param(0))?))),
+            "evm_setNextBlockTimestamp" => {
+                self.set_next_block_timestamp(parse_number(param(0))?)?;
+                Ok(Value::Null)
+            }
+            "anvil_setBalance" | "hardhat_setBalance" => {
+                self.set_balance(&parse_address(param(0))?, parse_quantity(param(1))?);
+                Ok(Value::Null)
+            }
+            "anvil_setCode" | "hardhat_setCode" => {
+                self.set_code(&parse_address(param(0))?, &parse_bytes(param(1))?);
+                Ok(Value::Null)
+            }
+            "anvil_setStorageAt" | "hardhat_setStorageAt" => {
+                let address = parse_address(param(0))?;
+                self.set_storage_at(
+                    &address,
+                    parse_quantity(param(1))?,
+                    parse_quantity(param(2))?,
+                );
+                Ok(json!(true))
+            }
+            "anvil_impersonateAccount" | "hardhat_impersonateAccount" => {
+                self.impersonate_account(parse_address(param(0))?);
+                Ok(Value::Null)
+            }
+            "anvil_stopImpersonatingAccount" | "hardhat_stopImpersonatingAccount" => {
+                self.stop_impersonating_account(&parse_address(param(0))?);
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                -32601,
+                &format!("method {} is not supported", method),
+            )),
+        };
+    }
+
+    /// アカウントのnonce 存在しなければ0
+    fn nonce(&self, address: &H160) -> usize {
+        return self.ws.account(address).map_or(0, |a| a.get_nonce());
+    }
+
+    /// トランザクションを次のブロックで実行してブロックにする 実行できなければブロックは作らない
+    fn mine_transaction(
+        &mut self,
+        hash: H256,
+        mut tx: Transaction,
+        kind: u8,
+    ) -> Result<H256, String> {
+        let nonce = self.nonce(&tx.from);
+        tx.block = self.pending_block();
+        let receipt = transaction::execute(&mut self.ws, &tx)?;
+        self.ws.update_state();
+        self.new_block(vec![hash], receipt.gas_used);
+        let mined = MinedTransaction {
+            tx,
+            nonce,
+            kind,
+            block_number: self.block_number(),
+            receipt,
+        };
+        self.transactions.insert(hash, mined);
+        return Ok(hash);
+    }
+
+    /// transactionsを含むブロックをpending_blockの番号とタイムスタンプで最新のブロックの後ろに追加する<br/>
+    /// ブロックのハッシュは親のハッシュ、番号、タイムスタンプ、トランザクションのハッシュから計算する
+    fn new_block(&mut self, transactions: Vec<H256>, gas_used: usize) {
+        let parent_hash = self
+            .blocks
+            .last()
+            .map_or(H256::zero(), |parent| parent.hash);
+        let BlockEnv { number, timestamp } = self.pending_block();
+        self.next_timestamp = None;
+        let mut preimage = parent_hash.as_bytes().to_vec();
+        preimage.extend_from_slice(&number.to_be_bytes());
+        preimage.extend_from_slice(&timestamp.to_be_bytes());
+        for hash in &transactions {
+            preimage.extend_from_slice(hash.as_bytes());
+        }
+        self.blocks.push(Block {
+            number,
+            hash: H256::from(util::keccak256(&preimage)),
+            parent_hash,
+            timestamp,
+            transactions,
+            gas_used,
+        });
+    }
+
+    /// eth_callなどのトランザクションのオブジェクトを読む<br/>
+    /// fromを省略するとゼロアドレス、gasを省略するとTransactionのデフォルトになる
+    fn parse_call(&self, value: &Value) -> Result<Transaction, RpcError> {
+        if !value.is_object() {
+            return Err(RpcError::invalid_params("transaction must be object"));
+        }
+        let from = match &value["from"] {
+            Value::Null => H160::zero(),
+            from => parse_address(from)?,
+        };
+        let mut tx = match &value["to"] {
+            Value::Null => Transaction::create(from, vec![]),
+            to => Transaction::new(from, parse_address(to)?),
+        };
+        tx.fork = self.fork;
+        if !value["gas"].is_null() {
+            tx.gas = parse_usize(&value["gas"])?;
+        }
+        for key in ["gasPrice", "maxFeePerGas"] {
+            if !value[key].is_null() {
+                tx.gas_price = parse_usize(&value[key])?;
+            }
+        }
+        if !value["value"].is_null() {
+            tx.value = parse_quantity(&value["value"])?;
+        }
+        for key in ["data", "input"] {
+            if !value[key].is_null() {
+                tx.data = parse_bytes(&value[key])?;
+            }
+        }
+        return Ok(tx);
+    }
+
+    /// ブロックの番号かタグ(latest, pending, safe, finalized, earliest)を読む
+    fn parse_block_number(&self, value: &Value) -> Result<u64, RpcError> {
+        return match value.as_str() {
+            None | Some("latest") | Some("pending") | Some("safe") | Some("finalized") => {
+                Ok(self.block_number())
+            }
+            Some("earliest") => Ok(0),
+            Some(_) => {
+                let number = parse_quantity(value)?;
+                if number > U256::from(u64::MAX) {
+                    return Err(RpcError::invalid_params("block number is too large"));
+                }
+                Ok(number.as_u64())
+            }
+        };
+    }
+
+    /// ステートを参照するメソッドのブロックの指定が最新のブロックか確かめる<br/>
+    /// EIP-1898の`{ blockNumber }`、`{ blockHash }`の形も受け付ける
+    fn check_latest(&self, value: &Value) -> Result<(), RpcError> {
+        let latest = self.blocks.last().unwrap();
+        let number = if let Some(hash) = value.get("blockHash") {
+            let hash = parse_hash(hash)?;
+            match self.blocks.iter().find(|block| block.hash == hash) {
+                Some(block) => block.number,
+                None => {
+                    return Err(RpcError::from(format!(
+                        "block 0x{} is not found",
+                        hex::encode(hash)
+                    )))
+                }
+            }
+        } else if let Some(number) = value.get("blockNumber") {
+            self.parse_block_number(number)?
+        } else {
+            self.parse_block_number(value)?
+        };
+        if number > latest.number {
+            return Err(RpcError::from(format!("block {} is not found", number)));
+        }
+        if number < latest.number {
+            return Err(RpcError::from(format!(
+                "state of block {} is not available (only latest)",
+                number
+            )));
+        }
+        return Ok(());
+    }
+
+    /// eth_getLogsのフィルタに一致するログ
+    fn get_logs(&self, filter: &Value) -> Result<Value, RpcError> {
+        let (from, to) = match &filter["blockHash"] {
+            Value::Null => (
+                self.parse_block_number(&filter["fromBlock"])?,
+                self.parse_block_number(&filter["toBlock"])?,
+            ),
+            hash => {
+                let hash = parse_hash(hash)?;
+                match self.blocks.iter().find(|block| block.hash == hash) {
+                    Some(block) => (block.number, block.number),
+                    None => {
+                        return Err(RpcError::from(format!(
+                            "block 0x{} is not found",
+                            hex::encode(hash)
+                        )))
+                    }
+                }
+            }
+        };
+        let addresses = match &filter["address"] {
+            Value::Null => vec![],
+            Value::Array(addresses) => addresses
+                .iter()
+                .map(parse_address)
+                .collect::<Result<Vec<H160>, RpcError>>()?,
+            address => vec![parse_address(address)?],
+        };
+        // topicsの各位置はnull(なんでもよい)、ハッシュ、ハッシュのどれか
+        let topics = match &filter["topics"] {
+            Value::Null => vec![],
+            Value::Array(topics) => topics
+                .iter()
+                .map(|topic| match topic {
+                    Value::Null => Ok(None),
+                    Value::Array(any) => any
+                        .iter()
+                        .map(parse_hash)
+                        .collect::<Result<Vec<H256>, RpcError>>()
+                        .map(Some),
+                    topic => Ok(Some(vec![parse_hash(topic)?])),
+                })
+                .collect::<Result<Vec<Option<Vec<H256>>>, RpcError>>()?,
+            _ => return Err(RpcError::invalid_params("topics must be array")),
+        };
+
+        let mut logs = vec![];
+        let blocks = self
+            .blocks
+            .iter()
+            .skip(from as usize)
+            .take_while(|b| b.number <= to);
+        for block in blocks {
+            for hash in &block.transactions {
+                let mined = &self.transactions[hash];
+                for (index, log) in mined.receipt.logs.iter().enumerate() {
+                    let address_matches = addresses.is_empty() || addresses.contains(&log.address);
+                    let topics_match = topics.iter().enumerate().all(|(i, topic)| match topic {
+                        Some(any) => log.topics.get(i).is_some_and(|t| any.contains(t)),
+                        None => true,
+                    });
+                    if address_matches && topics_match {
+                        logs.push(self.log_json(hash, mined, index, log));
+                    }
+                }
+            }
+        }
+        return Ok(Value::Array(logs));
+    }
+
+    /// ブロックのJSON fullならトランザクションのオブジェクト、でなければハッシュを並べる
+    fn block_json(&self, block: &Block, full: bool) -> Value {
+        let transactions: Vec<Value> = block
+            .transactions
+            .iter()
+            .map(|hash| {
+                if full {
+                    self.transaction_json(hash, &self.transactions[hash])
+                } else {
+                    json!(format!("0x{}", hex::encode(hash)))
+                }
+            })
+            .collect();
+        let logs: Vec<Log> = block
+            .transactions
+            .iter()
+            .flat_map(|hash| self.transactions[hash].receipt.logs.clone())
+            .collect();
+        let mut json = json!({
+            "number": quantity(block.number),
+            "hash": format!("0x{}", hex::encode(block.hash)),
+            "parentHash": format!("0x{}", hex::encode(block.parent_hash)),
+            "nonce": "0x0000000000000000",
+            "mixHash": format!("0x{}", hex::encode(H256::zero())),
+            // 空のリストのRLPのkeccak256
+            "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "logsBloom": hex_data(&logs_bloom(&logs)),
+            "transactionsRoot": format!("0x{}", hex::encode(H256::zero())),
+            "stateRoot": format!("0x{}", hex::encode(H256::zero())),
+            "receiptsRoot": format!("0x{}", hex::encode(H256::zero())),
+            "miner": hex_address(&H160::zero()),
+            "difficulty": "0x0",
+            "totalDifficulty": "0x0",
+            "extraData": "0x",
+            "size": "0x0",
+            "gasLimit": quantity(BLOCK_GAS_LIMIT),
+            "gasUsed": quantity(block.gas_used),
+            "timestamp": quantity(block.timestamp),
+            "transactions": transactions,
+            "uncles": [],
+        });
+        // EIP-1559
+        if self.fork >= Fork::London {
+            json["baseFeePerGas"] = quantity(0);
+        }
+        return json;
+    }
+
+    /// トランザクションのJSON
+    fn transaction_json(&self, hash: &H256, mined: &MinedTransaction) -> Value {
+        let block = &self.blocks[mined.block_number as usize];
+        let tx = &mined.tx;
+        return json!({
+            "hash": format!("0x{}", hex::encode(hash)),
+            "type": quantity(mined.kind),
+            "nonce": quantity(mined.nonce),
+            "blockHash": format!("0x{}", hex::encode(block.hash)),
+            "blockNumber": quantity(block.number),
+            "transactionIndex": "0x0",
+            "from": hex_address(&tx.from),
+            "to": tx.to.as_ref().map(hex_address),
+            "value": quantity(tx.value),
+            "gas": quantity(tx.gas),
+            "gasPrice": quantity(tx.gas_price),
+            "input": hex_data(&tx.data),
+            "chainId": quantity(self.chain_id),
+        });
+    }
+
+    /// レシートのJSON
+    fn receipt_json(&self, hash: &H256, mined: &MinedTransaction) -> Value {
+        let block = &self.blocks[mined.block_number as usize];
+        let receipt = &mined.receipt;
+        let logs: Vec<Value> = receipt
+            .logs
+            .iter()
+            .enumerate()
+            .map(|(index, log)| self.log_json(hash, mined, index, log))
+            .collect();
+        let status = if receipt.status.is_success() { 1 } else { 0 };
+        return json!({
+            "transactionHash": format!("0x{}", hex::encode(hash)),
+            "transactionIndex": "0x0",
+            "type": quantity(mined.kind),
+            "blockHash": format!("0x{}", hex::encode(block.hash)),
+            "blockNumber": quantity(block.number),
+            "from": hex_address(&mined.tx.from),
+            "to": mined.tx.to.as_ref().map(hex_address),
+            "cumulativeGasUsed": quantity(receipt.gas_used),
+            "gasUsed": quantity(receipt.gas_used),
+            "effectiveGasPrice": quantity(mined.tx.gas_price),
+            "contractAddress": receipt.contract_address.as_ref().map(hex_address),
+            "logs": logs,
+            "logsBloom": hex_data(&logs_bloom(&receipt.logs)),
+            "status": quantity(status),
+        });
+    }
+
+    /// ログのJSON ブロックにはトランザクションが1つしかないので、logIndexはトランザクションの中の番号と同じ
+    fn log_json(&self, hash: &H256, mined: &MinedTransaction, index: usize, log: &Log) -> Value {
+        let block = &self.blocks[mined.block_number as usize];
+        return json!({
+            "address": hex_address(&log.address),
+            "topics": log
+                .topics
+                .iter()
+                .map(|topic| format!("0x{}", hex::encode(topic)))
+                .collect::<Vec<String>>(),
+            "data": hex_data(&log.data),
+            "blockNumber": quantity(block.number),
+            "blockHash": format!("0x{}", hex::encode(block.hash)),
+            "transactionHash": format!("0x{}", hex::encode(hash)),
+            "transactionIndex": "0x0",
+            "logIndex": quantity(index),
+            "removed": false,
+        });
+    }
+}
+
+/// 署名済みトランザクション(legacy、EIP-2930、EIP-1559)をデコードし、署名から送信者を復元する<br/>
+/// アクセスリストは読み飛ばすので、アクセスリストのgasはかからない
+pub fn decode_raw_transaction(raw: &[u8], fork: Fork) -> Result<SignedTransaction, String> {
+    let kind = match raw.first() {
+        Some(kind @ (1 | 2)) => *kind,
+        Some(0xc0..=0xff) => 0,
+        _ => return Err("unsupported transaction type".to_string()),
+    };
+    let payload = if kind == 0 { raw } else { &raw[1..] };
+    let items = rlp::decode(payload)?;
+    let items = items.as_list()?;
+    // 各種類のフィールドの数と、nonce、gasPrice、gasの位置
+    let (length, nonce, gas_price, gas) = match kind {
+        0 => (9, 0, 1, 2),
+        1 => (11, 1, 2, 3),
+        _ => (12, 1, 3, 4),
+    };
+    if items.len() != length {
+        return Err(format!(
+            "transaction of type {} must have {} fields",
+            kind, length
+        ));
+    }
+    let to = items[gas + 1].as_address()?;
+    let value = items[gas + 2].as_u256()?;
+    let data = items[gas + 3].as_bytes()?.to_vec();
+    let v = items[length - 3].as_u256()?;
+    let r = items[length - 2].as_u256()?;
+    let s = items[length - 1].as_u256()?;
+
+    // 署名されたデータ legacyはEIP-155ならchain id, 0, 0を加え、それ以外は種類を先頭につける
+    let mut unsigned = items[..length - 3].to_vec();
+    let (chain_id, recovery_id, preimage) = if kind == 0 {
+        if v == 27.into() || v == 28.into() {
+            (None, v.as_u64() - 27, rlp::encode(&Rlp::List(unsigned)))
+        } else if v >= 35.into() && v <= U256::from(u64::MAX) {
+            let chain_id = (v.as_u64() - 35) / 2;
+            unsigned.extend([
+                Rlp::uint(chain_id.into()),
+                Rlp::uint(0.into()),
+                Rlp::uint(0.into()),
+            ]);
+            (
+                Some(chain_id),
+                (v.as_u64() - 35) % 2,
+                rlp::encode(&Rlp::List(unsigned)),
+            )
+        } else {
+            return Err(format!("invalid signature v {}", v));
+        }
+    } else {
+        let chain_id = items[0].as_u256()?;
+        if chain_id > U256::from(u64::MAX) || v > 1.into() {
+            return Err("invalid chain id or signature".to_string());
+        }
+        let mut preimage = vec![kind];
+        preimage.extend(rlp::encode(&Rlp::List(unsigned)));
+        (Some(chain_id.as_u64()), v.as_u64(), preimage)
+    };
+
+    // r、sは1以上n未満 EIP-2でHomestead以降はsがn/2以下の署名だけを受け付ける
+    // ecrecoverはsが大きい署名も受け付けるので、同じトランザクションが2つのハッシュを持たないように先に確かめる
+    let order = U256::from_big_endian(&SECP256K1_ORDER);
+    if r.is_zero() || s.is_zero() || r >= order || s >= order {
+        return Err("invalid signature".to_string());
+    }
+    if fork >= Fork::Homestead && s > order / 2 {
+        return Err("invalid signature s".to_string());
+    }
+
+    // ecrecoverのプリコンパイル済みコントラクトで送信者を復元する
+    let mut input = util::keccak256(&preimage).to_vec();
+    let mut word = [0; 32];
+    for value in [U256::from(27 + recovery_id), r, s] {
+        value.to_big_endian(&mut word);
+        input.extend_from_slice(&word);
+    }
+    let (output, _) = precompile::execute(&H160::from_low_u64_be(1), &input, 3000, fork)?;
+    if output.is_empty() {
+        return Err("invalid signature".to_string());
+    }
+    let from = H160::from_slice(&output[12..]);
+
+    let mut tx = match to {
+        Some(to) => Transaction::new(from, to),
+        None => Transaction::create(from, vec![]),
+    };
+    tx.value = value;
+    tx.data = data;
+    tx.gas = to_usize(items[gas].as_u256()?)?;
+    tx.gas_price = to_usize(items[gas_price].as_u256()?)?;
+    tx.fork = fork;
+    return Ok(SignedTransaction {
+        tx,
+        nonce: to_usize(items[nonce].as_u256()?)?,
+        chain_id,
+        kind,
+        hash: H256::from(util::keccak256(raw)),
+    });
+}
+
+/// secp256k1の位数n
+const SECP256K1_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// 実行が失敗していればエラーにする revertは返り値をdataに入れ、decodingで理由がわかればメッセージにつける
+fn check_status(receipt: &Receipt, decoding: &Abi) -> Result<(), RpcError> {
+    return match &receipt.status {
+        ExitStatus::Success => Ok(()),
+        ExitStatus::Revert => {
+            let message = match decoding.decode_revert(&receipt.output) {
+                Some(reason) => format!("execution reverted: {}", reason),
+                None => "execution reverted".to_string(),
+            };
+            let mut error = RpcError::new(3, &message);
+            error.data = Some(hex_data(&receipt.output));
+            Err(error)
+        }
+        ExitStatus::Halt(reason) => Err(RpcError::from(reason.clone())),
+    };
+}
+
+/// ログのアドレスとトピックから計算する2048bitのbloom filter
+fn logs_bloom(logs: &[Log]) -> Vec<u8> {
+    let mut bloom = vec![0; 256];
+    for log in logs {
+        let topics = log.topics.iter().map(|topic| topic.as_bytes());
+        for item in std::iter::once(log.address.as_bytes()).chain(topics) {
+            // keccak256の先頭3組の2byteの下位11bitの位置のbitを立てる
+            let hash = util::keccak256(item);
+            for i in [0, 2, 4] {
+                let bit = ((hash[i] as usize) << 8 | hash[i + 1] as usize) & 2047;
+                bloom[255 - bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+    return bloom;
+}
+
+/// 先頭の0を除いた16進数の数値
+fn quantity<T: Into<U256>>(value: T) -> Value {
+    return json!(format!("0x{:x}", value.into()));
+}
+
+/// 0xで始まる16進数のバイト列
+fn hex_data(data: &[u8]) -> Value {
+    return json!(format!("0x{}", hex::encode(data)));
+}
+
+fn hex_hash(hash: &H256) -> Value {
+    return json!(format!("0x{}", hex::encode(hash)));
+}
+
+fn hex_address(address: &H160) -> String {
+    return format!("0x{}", hex::encode(address));
+}
+
+fn parse_address(value: &Value) -> Result<H160, RpcError> {
+    let s = value
+        .as_str()
+        .ok_or(RpcError::invalid_params("address must be string"))?;
+    return util::parse_h160(s).map_err(|e| RpcError::invalid_params(&e));
+}
+
+fn parse_hash(value: &Value) -> Result<H256, RpcError> {
+    let bytes = parse_bytes(value)?;
+    if bytes.len() != 32 {
+        return Err(RpcError::invalid_params("hash must be 32 bytes"));
+    }
+    return Ok(H256::from_slice(&bytes));
+}
+
+fn parse_bytes(value: &Value) -> Result<Vec<u8>, RpcError> {
+    let s = value
+        .as_str()
+        .ok_or(RpcError::invalid_params("data must be string"))?;
+    let hex = s
+        .strip_prefix("0x")
+        .ok_or(RpcError::invalid_params("data must start with 0x"))?;
+    return hex::decode(hex).map_err(|e| RpcError::invalid_params(&format!("{}: {}", s, e)));
+}
+
+/// 0xで始まる16進数の数値
+fn parse_quantity(value: &Value) -> Result<U256, RpcError> {
+    return match value.as_str() {
+        Some(s) if s.starts_with("0x") => {
+            util::parse_u256(s).map_err(|e| RpcError::invalid_params(&e))
+        }
+        _ => Err(RpcError::invalid_params(&format!(
+            "{} is invalid quantity",
+            value
+        ))),
+    };
+}
+
+/// JSONの数値か0xで始まる16進数の数値
+fn parse_number(value: &Value) -> Result<u64, RpcError> {
+    if let Some(number) = value.as_u64() {
+        return Ok(number);
+    }
+    let number = parse_quantity(value)?;
+    if number > U256::from(u64::MAX) {
+        return Err(RpcError::invalid_params(&format!(
+            "{} is too large",
+            number
+        )));
+    }
+    return Ok(number.as_u64());
+}
+
+/// eth_callの3番目の引数のstate override `{ address: { balance, nonce, code, state, stateDiff } }`
+fn parse_overrides(value: &Value) -> Result<Vec<(H160, AccountOverride)>, RpcError> {
+    let overrides = match value {
+        Value::Null => return Ok(vec![]),
+        Value::Object(overrides) => overrides,
+        _ => return Err(RpcError::invalid_params("state override must be object")),
+    };
+    // storageのslotと値の組 slotはオブジェクトのキーなのでJSONの文字列にしてから読む
+    let storage = |value: &Value| -> Result<Vec<(U256, U256)>, RpcError> {
+        let entries = value
+            .as_object()
+            .ok_or(RpcError::invalid_params("state must be object"))?;
+        return entries
+            .iter()
+            .map(|(slot, value)| Ok((parse_quantity(&json!(slot))?, parse_quantity(value)?)))
+            .collect();
+    };
+    let mut parsed = vec![];
+    for (address, fields) in overrides {
+        let mut account_override = AccountOverride::default();
+        if !fields["balance"].is_null() {
+            account_override.balance = Some(parse_quantity(&fields["balance"])?);
+        }
+        if !fields["nonce"].is_null() {
+            account_override.nonce = Some(parse_usize(&fields["nonce"])?);
+        }
+        if !fields["code"].is_null() {
+            account_override.code = Some(parse_bytes(&fields["code"])?);
+        }
+        match (&fields["state"], &fields["stateDiff"]) {
+            (Value::Null, Value::Null) => {}
+            (state, Value::Null) => account_override.state = Some(storage(state)?),
+            (Value::Null, diff) => account_override.state_diff = storage(diff)?,
+            _ => {
+                return Err(RpcError::invalid_params(
+                    "state and stateDiff cannot be used together",
+                ))
+            }
+        }
+        parsed.push((parse_address(&json!(address))?, account_override));
+    }
+    return Ok(parsed);
+}
+
+fn parse_usize(value: &Value) -> Result<usize, RpcError> {
+    return Ok(to_usize(parse_quantity(value)?)?);
+}
+
+fn to_usize(value: U256) -> Result<usize, String> {
+    if value > U256::from(usize::MAX) {
+        return Err(format!("{} is too large", value));
+    }
+    return Ok(value.as_usize());
+}
+
+#[cfg(test)]
+fn rpc(node: &mut Node, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    let response = node.handle(&request);
+    assert_eq!(response["id"], 1);
+    assert!(response.get("error").is_none(), "{}", response);
+    return response["result"].clone();
+}
+
+#[test]
+fn test_node_rpc() {
+    let mut node = Node::new(test_world_state(&[]), Fork::default());
+    let sender = hex_address(&H160::from_low_u64_be(0xaa));
+    assert_eq!(rpc(&mut node, "eth_accounts", json!([])), json!([sender]));
+    assert_eq!(rpc(&mut node, "eth_chainId", json!([])), "0x7a69");
+    assert_eq!(rpc(&mut node, "eth_blockNumber", json!([])), "0x0");
+
+    // calldataの先頭32byteをslot 0に保存し、それをtopicとしてLOG1するコントラクト
+    // 初期化コードはランタイムコード(6000358060005560006000a100)をそのまま返す
+    let code = "0x600d600c600039600d6000f36000358060005560006000a100";
+    let hash = rpc(
+        &mut node,
+        "eth_sendTransaction",
+        json!([{ "from": sender, "data": code }]),
+    );
+    let receipt = rpc(&mut node, "eth_getTransactionReceipt", json!([hash]));
+    assert_eq!(receipt["status"], "0x1");
+    assert_eq!(receipt["blockNumber"], "0x1");
+    let contract = receipt["contractAddress"].clone();
+    assert_eq!(
+        rpc(&mut node, "eth_getCode", json!([contract, "latest"])),
+        "0x6000358060005560006000a100"
+    );
+    assert_eq!(
+        rpc(
+            &mut node,
+            "eth_getTransactionCount",
+            json!([sender, "latest"])
+        ),
+        "0x1"
+    );
+
+    let word = hex_data(&[[0; 31].as_slice(), &[5]].concat());
+    let call = json!({ "from": sender, "to": contract, "data": word });
+    assert_eq!(rpc(&mut node, "eth_call", json!([call, "latest"])), "0x");
+    let hash = rpc(&mut node, "eth_sendTransaction", json!([call]));
+    assert_eq!(
+        rpc(
+            &mut node,
+            "eth_getStorageAt",
+            json!([contract, "0x0", "latest"])
+        ),
+        word
+    );
+    let logs = rpc(
+        &mut node,
+        "eth_getLogs",
+        json!([{ "fromBlock": "0x0", "topics": [word] }]),
+    );
+    assert_eq!(logs.as_array().unwrap().len(), 1);
+    assert_eq!(logs[0]["address"], contract);
+    assert_eq!(logs[0]["transactionHash"], hash);
+    assert_eq!(logs[0]["blockNumber"], "0x2");
+    let logs = rpc(&mut node, "eth_getLogs", json!([{ "address": sender }]));
+    assert_eq!(logs, json!([]));
+    let block = rpc(&mut node, "eth_getBlockByNumber", json!(["latest", false]));
+    assert_eq!(block["transactions"], json!([hash]));
+    assert_ne!(block["logsBloom"], hex_data(&[0; 256]));
+
+    // 過去のブロックのステートは参照できない
+    let response =
+        node.handle(&json!({ "id": 2, "method": "eth_getBalance", "params": [sender, "0x1"] }));
+    assert_eq!(response["error"]["code"], -32000);
+    let response = node.handle(&json!({ "id": 3, "method": "eth_foo" }));
+    assert_eq!(response["error"]["code"], -32601);
+}
+
+#[test]
+fn test_eth_call_revert() {
+    // 0xcc: REVERT(Error("no"))
+    let code = "7f08c379a000000000000000000000000000000000000000000000000000000000600052\
+                60206004526002602452\
+                7f6e6f000000000000000000000000000000000000000000000000000000000000604452\
+                60646000fd";
+    let mut ws = test_world_state(&[]);
+    ws.push_account_state(
+        H160::from_low_u64_be(0xcc),
+        super::state::AccountState::new(code.to_string()),
+    );
+    let mut node = Node::new(ws, Fork::default());
+    let call = json!({ "to": hex_address(&H160::from_low_u64_be(0xcc)) });
+    let response = node.handle(&json!({ "id": 1, "method": "eth_call", "params": [call] }));
+    assert_eq!(response["error"]["code"], 3);
+    assert_eq!(response["error"]["message"], "execution reverted: no");
+    assert!(response["error"]["data"]
+        .as_str()
+        .unwrap()
+        .starts_with("0x08c379a0"));
+
+    // セットしたABIのカスタムエラーはeth_estimateGasでもデコードする
+    let mut abi = Abi::default();
+    abi.errors
+        .push(super::abi::CustomError::parse("Unauthorized(uint256 id)").unwrap());
+    // 0xdd: REVERT(Unauthorized(7))
+    let code = format!(
+        "7f{}{}600052600760045260246000fd",
+        hex::encode(abi.errors[0].selector()),
+        "00".repeat(28)
+    );
+    node.ws.push_account_state(
+        H160::from_low_u64_be(0xdd),
+        super::state::AccountState::new(code),
+    );
+    node.set_abi(abi);
+    let call = json!({ "to": hex_address(&H160::from_low_u64_be(0xdd)) });
+    for method in ["eth_call", "eth_estimateGas"] {
+        let response = node.handle(&json!({ "id": 2, "method": method, "params": [call] }));
+        assert_eq!(
+            response["error"]["message"],
+            "execution reverted: Unauthorized(id=7)"
+        );
+    }
+}
+
+#[test]
+fn test_send_raw_transaction() {
+    use super::state::AccountState;
+    use k256::ecdsa::SigningKey;
+
+    let key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+    let public_key = key.verifying_key().to_encoded_point(false);
+    let from = H160::from_slice(&util::keccak256(&public_key.as_bytes()[1..])[12..]);
+    let mut ws = WorldState::default();
+    let mut sender = AccountState::new("".to_string());
+    sender.set_balance(1000.into());
+    ws.push_account_state(from, sender);
+    let mut node = Node::new(ws, Fork::default());
+    let to = H160::from_low_u64_be(0xbb);
+
+    // EIP-155のlegacyトランザクションとEIP-1559のトランザクションでそれぞれ100weiを送る
+    for kind in [0u8, 2] {
+        let nonce = Rlp::uint(node.nonce(&from).into());
+        let chain_id = Rlp::uint(DEFAULT_CHAIN_ID.into());
+        let common = vec![
+            Rlp::uint(21000.into()),
+            Rlp::Bytes(to.as_bytes().to_vec()),
+            Rlp::uint(100.into()),
+            Rlp::Bytes(vec![]),
+        ];
+        let mut fields = match kind {
+            0 => vec![nonce, Rlp::uint(1.into())],
+            _ => vec![
+                chain_id.clone(),
+                nonce,
+                Rlp::uint(0.into()),
+                Rlp::uint(1.into()),
+            ],
+        };
+        fields.extend(common);
+        let preimage = match kind {
+            0 => {
+                let mut unsigned = fields.clone();
+                unsigned.extend([chain_id, Rlp::uint(0.into()), Rlp::uint(0.into())]);
+                rlp::encode(&Rlp::List(unsigned))
+            }
+            _ => {
+                fields.push(Rlp::List(vec![]));
+                let mut preimage = vec![kind];
+                preimage.extend(rlp::encode(&Rlp::List(fields.clone())));
+                preimage
+            }
+        };
+        let (signature, recovery_id) = key
+            .sign_prehash_recoverable(&util::keccak256(&preimage))
+            .unwrap();
+        let v = |recovery_id: u8| match kind {
+            0 => DEFAULT_CHAIN_ID * 2 + 35 + recovery_id as u64,
+            _ => recovery_id as u64,
+        };
+        let signed = |v: u64, r: U256, s: U256| {
+            let mut fields = fields.clone();
+            fields.extend([Rlp::uint(v.into()), Rlp::uint(r), Rlp::uint(s)]);
+            let mut raw = if kind == 0 { vec![] } else { vec![kind] };
+            raw.extend(rlp::encode(&Rlp::List(fields)));
+            raw
+        };
+        let signature = signature.to_bytes();
+        let r = U256::from_big_endian(&signature[..32]);
+        let s = U256::from_big_endian(&signature[32..]);
+        let raw = signed(v(recovery_id.to_byte()), r, s);
+
+        // sをn - sにして復元IDを反転した署名も同じ送信者を復元するが、EIP-2で受け付けない
+        let order = U256::from_big_endian(&SECP256K1_ORDER);
+        let malleable = signed(v(recovery_id.to_byte() ^ 1), r, order - s);
+        assert_eq!(
+            decode_raw_transaction(&malleable, Fork::default()).err(),
+            Some("invalid signature s".to_string())
+        );
+        let frontier = decode_raw_transaction(&malleable, Fork::Frontier).unwrap();
+        assert_eq!(frontier.tx.from, from);
+        assert!(decode_raw_transaction(&signed(v(0), U256::zero(), s), Fork::default()).is_err());
+
+        let hash = rpc(&mut node, "eth_sendRawTransaction", json!([hex_data(&raw)]));
+        let tx = rpc(&mut node, "eth_getTransactionByHash", json!([hash]));
+        assert_eq!(tx["from"], hex_address(&from));
+        assert_eq!(tx["type"], quantity(kind));
+        // 同じトランザクションはnonceが合わないので送れない
+        let response = node.handle(
+            &json!({ "id": 1, "method": "eth_sendRawTransaction", "params": [hex_data(&raw)] }),
+        );
+        assert_eq!(
+            response["error"]["message"],
+            format!("nonce must be {}", node.nonce(&from))
+        );
+    }
+    assert_eq!(
+        rpc(
+            &mut node,
+            "eth_getBalance",
+            json!([hex_address(&to), "latest"])
+        ),
+        "0xc8"
+    );
+}
+
+#[test]
+fn test_dev_methods() {
+    let mut node = Node::new(test_world_state(&[]), Fork::default());
+    let sender = hex_address(&H160::from_low_u64_be(0xaa));
+    let contract = hex_address(&H160::from_low_u64_be(0xcc));
+    // TIMESTAMP, NUMBER をslot 0, 1に保存する
+    let code = hex_data(&hex::decode("42600055436001550000").unwrap());
+    rpc(&mut node, "anvil_setCode", json!([contract, code]));
+    assert_eq!(
+        rpc(&mut node, "eth_getCode", json!([contract, "latest"])),
+        code
+    );
+
+    let snapshot = rpc(&mut node, "evm_snapshot", json!([]));
+    let timestamp = node.latest_block().timestamp;
+    rpc(&mut node, "evm_mine", json!([{ "blocks": "0x3" }]));
+    assert_eq!(rpc(&mut node, "eth_blockNumber", json!([])), "0x3");
+    assert_eq!(rpc(&mut node, "evm_increaseTime", json!([3600])), 3600);
+    rpc(
+        &mut node,
+        "eth_sendTransaction",
+        json!([{ "from": sender, "to": contract }]),
+    );
+    let slot = |node: &mut Node, slot: &str| {
+        let value = rpc(node, "eth_getStorageAt", json!([contract, slot, "latest"]));
+        util::parse_u256(value.as_str().unwrap()).unwrap()
+    };
+    assert!(slot(&mut node, "0x0") >= U256::from(timestamp + 3600));
+    assert_eq!(slot(&mut node, "0x1"), 4.into());
+
+    // 指定したタイムスタンプでブロックを作る
+    let next = node.latest_block().timestamp + 100;
+    rpc(&mut node, "evm_mine", json!([next]));
+    assert_eq!(node.latest_block().timestamp, next);
+
+    // snapshotに戻るとブロックもstorageも戻り、同じsnapshotには2度戻れない
+    assert_eq!(rpc(&mut node, "evm_revert", json!([snapshot])), true);
+    assert_eq!(rpc(&mut node, "eth_blockNumber", json!([])), "0x0");
+    assert_eq!(slot(&mut node, "0x1"), 0.into());
+    assert_eq!(rpc(&mut node, "evm_revert", json!([snapshot])), false);
+
+    rpc(
+        &mut node,
+        "anvil_setStorageAt",
+        json!([contract, "0x1", "0x2a"]),
+    );
+    assert_eq!(slot(&mut node, "0x1"), 42.into());
+    rpc(&mut node, "anvil_setBalance", json!([contract, "0x64"]));
+    assert_eq!(
+        rpc(&mut node, "eth_getBalance", json!([contract, "latest"])),
+        "0x64"
+    );
+
+    // impersonateしたアカウントは署名なしに送れる 存在しなければ作成される
+    let whale = hex_address(&H160::from_low_u64_be(0xee));
+    let tx = json!([{ "from": whale, "to": contract }]);
+    let response = node.handle(&json!({ "id": 1, "method": "eth_sendTransaction", "params": tx }));
+    assert_eq!(
+        response["error"]["message"],
+        format!("sender {} is not unlocked", whale)
+    );
+    rpc(&mut node, "anvil_impersonateAccount", json!([whale]));
+    let hash = rpc(&mut node, "eth_sendTransaction", tx.clone());
+    let receipt = rpc(&mut node, "eth_getTransactionReceipt", json!([hash]));
+    assert_eq!(receipt["from"], whale);
+    rpc(&mut node, "anvil_stopImpersonatingAccount", json!([whale]));
+    let response = node.handle(&json!({ "id": 1, "method": "eth_sendTransaction", "params": tx }));
+    assert!(response.get("error").is_some());
+}
+
+#[test]
+fn test_eth_call_state_override() {
+    let mut node = Node::new(test_world_state(&[]), Fork::default());
+    let contract = hex_address(&H160::from_low_u64_be(0xcc));
+    // SLOAD(0)を返す
+    let code = "0x60005460005260206000f3";
+    let call = json!({ "to": contract });
+    let slot = format!("0x{}", "00".repeat(32));
+    let overrides =
+        json!({ contract.clone(): { "code": code, "stateDiff": { slot.clone(): "0x07" } } });
+    let output = rpc(&mut node, "eth_call", json!([call, "latest", overrides]));
+    assert_eq!(output, hex_data(&[[0; 31].as_slice(), &[7]].concat()));
+    // overrideはノードのステートを変えない
+    assert_eq!(
+        rpc(&mut node, "eth_getCode", json!([contract, "latest"])),
+        "0x"
+    );
+
+    let overrides = json!({ contract: { "state": {}, "stateDiff": {} } });
+    let response = node
+        .handle(&json!({ "id": 1, "method": "eth_call", "params": [call, "latest", overrides] }));
+    assert_eq!(response["error"]["code"], -32602);
+}
@@ -0,0 +1,679 @@
+//! Precompiled contracts
+//!
+//! アドレス0x01-0x0aに配置されるプリコンパイル済みコントラクトを実装するモジュール
+use super::fork::Fork;
+use ethereum_types::{H160, U256};
+use num_bigint::BigUint;
+use sha3::{Digest, Keccak256};
+
+/// プリコンパイル済みコントラクトの実行結果 (出力, 消費したgas)<br/>
+/// 失敗した場合は呼び出し元に渡されたgasがすべて消費される
+pub type PrecompileResult = Result<(Vec<u8>, usize), String>;
+
+/// addressがforkで有効なプリコンパイル済みコントラクトか
+pub fn is_precompile(address: &H160, fork: Fork) -> bool {
+    match precompile_index(address) {
+        Some(1..=4) => true,
+        Some(5..=8) => fork >= Fork::Byzantium,
+        Some(9) => fork >= Fork::Istanbul,
+        Some(10) => fork >= Fork::Cancun,
+        _ => false,
+    }
+}
+
+/// アドレスの下位1byte以外が0ならその値を返す
+fn precompile_index(address: &H160) -> Option<u8> {
+    let bytes = address.as_bytes();
+    if bytes[..19].iter().all(|b| *b == 0) {
+        return Some(bytes[19]);
+    }
+    return None;
+}
+
+/// プリコンパイル済みコントラクトをgasの範囲内で実行する<br/>
+/// 先にgasを計算し、足りなければ何も計算せずに失敗する
+pub fn execute(address: &H160, input: &[u8], gas: usize, fork: Fork) -> PrecompileResult {
+    let index = match precompile_index(address) {
+        Some(index @ 1..=10) => index,
+        _ => return Err(format!("{:?} is not precompiled contract", address)),
+    };
+    let cost = gas_cost(index, input, fork)?;
+    if cost > gas {
+        return Err("out of gas".to_string());
+    }
+    let output = match index {
+        1 => ecrecover(input)?,
+        2 => sha256(input),
+        3 => ripemd160(input),
+        4 => input.to_vec(),
+        5 => modexp(input)?,
+        6 => bn128_add(input)?,
+        7 => bn128_mul(input)?,
+        8 => bn128_pairing(input)?,
+        9 => blake2f(input)?,
+        _ => point_evaluation(input)?,
+    };
+    return Ok((output, cost));
+}
+
+/// 入力の長さや内容から必要なgasを計算する 重い計算はしない
+fn gas_cost(index: u8, input: &[u8], fork: Fork) -> Result<usize, String> {
+    let cost = match index {
+        // 0x01: ecrecover
+        1 => 3000,
+        // 0x02: SHA-256
+        2 => 60 + 12 * words(input.len()),
+        // 0x03: RIPEMD-160
+        3 => 600 + 120 * words(input.len()),
+        // 0x04: identity
+        4 => 15 + 3 * words(input.len()),
+        5 => modexp_cost(input, fork)?,
+        // 0x06, 0x07: EIP-1108でIstanbul以降は安くなった
+        6 if fork >= Fork::Istanbul => 150,
+        6 => 500,
+        7 if fork >= Fork::Istanbul => 6000,
+        7 => 40000,
+        8 => {
+            if !input.len().is_multiple_of(192) {
+                return Err("invalid input length".to_string());
+            }
+            let pairs = input.len() / 192;
+            if fork >= Fork::Istanbul {
+                34000 * pairs + 45000
+            } else {
+                80000 * pairs + 100000
+            }
+        }
+        // 0x09: 1ラウンドあたり1gas
+        9 => {
+            if input.len() != 213 {
+                return Err("invalid input length".to_string());
+            }
+            u32::from_be_bytes([input[0], input[1], input[2], input[3]]) as usize
+        }
+        // 0x0a: point evaluation
+        _ => 50000,
+    };
+    return Ok(cost);
+}
+
+/// 32byteのワード数
+fn words(length: usize) -> usize {
+    length.div_ceil(32)
+}
+
+/// inputのoffsetからlengthバイトを取り出す 足りない部分は0で埋める
+fn get_data(input: &[u8], offset: usize, length: usize) -> Vec<u8> {
+    let mut data = vec![0; length];
+    if offset < input.len() {
+        let end = input.len().min(offset + length);
+        data[..end - offset].copy_from_slice(&input[offset..end]);
+    }
+    return data;
+}
+
+/// 0x01: 署名から署名者のアドレスを復元する
+fn ecrecover(input: &[u8]) -> Result<Vec<u8>, String> {
+    use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+    let input = get_data(input, 0, 128);
+    let hash = &input[0..32];
+    let v = U256::from_big_endian(&input[32..64]);
+
+    // 不正な署名は失敗ではなく空の出力を返す
+    if v != U256::from(27) && v != U256::from(28) {
+        return Ok(vec![]);
+    }
+    let signature = match Signature::from_slice(&input[64..128]) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(vec![]),
+    };
+    let mut recovery_id = v.as_u32() as u8 - 27;
+    // k256はsが大きい署名を受け付けないので正規化する 署名者は変わらない
+    let signature = match signature.normalize_s() {
+        Some(normalized) => {
+            recovery_id ^= 1;
+            normalized
+        }
+        None => signature,
+    };
+    let recovery_id = RecoveryId::from_byte(recovery_id).unwrap();
+    let key = match VerifyingKey::recover_from_prehash(hash, &signature, recovery_id) {
+        Ok(key) => key,
+        Err(_) => return Ok(vec![]),
+    };
+
+    // アドレスは公開鍵のkeccak256の下位20byte
+    let public_key = key.to_encoded_point(false);
+    let mut hasher = Keccak256::new();
+    hasher.input(&public_key.as_bytes()[1..]);
+    let mut output = vec![0; 12];
+    output.extend_from_slice(&hasher.result()[12..]);
+    return Ok(output);
+}
+
+/// 0x02: SHA-256
+fn sha256(input: &[u8]) -> Vec<u8> {
+    let mut hasher = sha2::Sha256::new();
+    hasher.input(input);
+    return hasher.result().to_vec();
+}
+
+/// 0x03: RIPEMD-160 出力は32byteに左詰めされる
+fn ripemd160(input: &[u8]) -> Vec<u8> {
+    let mut hasher = ripemd160::Ripemd160::new();
+    hasher.input(input);
+    let mut output = vec![0; 12];
+    output.extend_from_slice(&hasher.result());
+    return output;
+}
+
+/// modexpの入力の先頭にあるbase、exp、modの長さ
+fn modexp_lengths(input: &[u8]) -> Result<(usize, usize, usize), String> {
+    let base_len = U256::from_big_endian(&get_data(input, 0, 32));
+    let exp_len = U256::from_big_endian(&get_data(input, 32, 32));
+    let mod_len = U256::from_big_endian(&get_data(input, 64, 32));
+    // 巨大な長さはgasが足りなくなるので計算しない
+    let limit = U256::from(u32::MAX);
+    if base_len > limit || exp_len > limit || mod_len > limit {
+        return Err("out of gas".to_string());
+    }
+    return Ok((base_len.as_usize(), exp_len.as_usize(), mod_len.as_usize()));
+}
+
+/// 0x05のgas 指数の長さとbase、modの長い方から計算する(EIP-198, EIP-2565)
+fn modexp_cost(input: &[u8], fork: Fork) -> Result<usize, String> {
+    let (base_len, exp_len, mod_len) = modexp_lengths(input)?;
+
+    // 指数の先頭32byteのビット長から反復回数を見積もる
+    let exp_head = U256::from_big_endian(&get_data(input, 96 + base_len, exp_len.min(32)));
+    let head_bits = if exp_head.is_zero() {
+        0
+    } else {
+        exp_head.bits() as u128 - 1
+    };
+    let iteration = if exp_len <= 32 {
+        head_bits
+    } else {
+        8 * (exp_len as u128 - 32) + head_bits
+    };
+    let max_len = base_len.max(mod_len) as u128;
+
+    let cost = if fork >= Fork::Berlin {
+        // EIP-2565
+        let complexity = max_len.div_ceil(8).pow(2);
+        (complexity * iteration.max(1) / 3).max(200)
+    } else {
+        let complexity = if max_len <= 64 {
+            max_len * max_len
+        } else if max_len <= 1024 {
+            max_len * max_len / 4 + 96 * max_len - 3072
+        } else {
+            max_len * max_len / 16 + 480 * max_len - 199680
+        };
+        complexity * iteration.max(1) / 20
+    };
+    if cost > usize::MAX as u128 {
+        return Err("out of gas".to_string());
+    }
+    return Ok(cost as usize);
+}
+
+/// 0x05: base ** exp % modを計算する(EIP-198)
+fn modexp(input: &[u8]) -> Result<Vec<u8>, String> {
+    let (base_len, exp_len, mod_len) = modexp_lengths(input)?;
+    if mod_len == 0 {
+        return Ok(vec![]);
+    }
+    let base = BigUint::from_bytes_be(&get_data(input, 96, base_len));
+    let exp = BigUint::from_bytes_be(&get_data(input, 96 + base_len, exp_len));
+    let modulus = BigUint::from_bytes_be(&get_data(input, 96 + base_len + exp_len, mod_len));
+    let result = if modulus == BigUint::from(0u32) {
+        vec![]
+    } else {
+        base.modpow(&exp, &modulus).to_bytes_be()
+    };
+
+    // 結果はmod_lenバイトに右詰めする
+    let mut output = vec![0; mod_len];
+    output[mod_len - result.len()..].copy_from_slice(&result);
+    return Ok(output);
+}
+
+/// alt_bn128上の点を読み込む (0, 0)は無限遠点
+fn read_g1(input: &[u8]) -> Result<bn::G1, String> {
+    use bn::{AffineG1, Fq, Group, G1};
+
+    let x = Fq::from_slice(&input[0..32]).map_err(|_| "invalid field element")?;
+    let y = Fq::from_slice(&input[32..64]).map_err(|_| "invalid field element")?;
+    if x.is_zero() && y.is_zero() {
+        return Ok(G1::zero());
+    }
+    let point = AffineG1::new(x, y).map_err(|_| "point is not on curve")?;
+    return Ok(point.into());
+}
+
+/// alt_bn128上の点を64byteで書き出す
+fn write_g1(point: bn::G1) -> Vec<u8> {
+    let mut output = vec![0; 64];
+    if let Some(point) = bn::AffineG1::from_jacobian(point) {
+        point.x().to_big_endian(&mut output[0..32]).unwrap();
+        point.y().to_big_endian(&mut output[32..64]).unwrap();
+    }
+    return output;
+}
+
+/// 0x06: alt_bn128上の点の加算(EIP-196)
+fn bn128_add(input: &[u8]) -> Result<Vec<u8>, String> {
+    let input = get_data(input, 0, 128);
+    let p1 = read_g1(&input[0..64])?;
+    let p2 = read_g1(&input[64..128])?;
+    return Ok(write_g1(p1 + p2));
+}
+
+/// 0x07: alt_bn128上の点のスカラー倍(EIP-196)
+fn bn128_mul(input: &[u8]) -> Result<Vec<u8>, String> {
+    let input = get_data(input, 0, 96);
+    let p = read_g1(&input[0..64])?;
+    let scalar = bn::Fr::from_slice(&input[64..96]).map_err(|_| "invalid scalar")?;
+    return Ok(write_g1(p * scalar));
+}
+
+/// 0x08: alt_bn128上のペアリングの積が1になるか(EIP-197)
+fn bn128_pairing(input: &[u8]) -> Result<Vec<u8>, String> {
+    use bn::{AffineG2, Fq, Fq2, Group, Gt, G2};
+
+    // 長さはgasの計算で192byteの倍数であることを確認している
+    let mut points = Vec::with_capacity(input.len() / 192);
+    for chunk in input.chunks(192) {
+        let a = read_g1(&chunk[0..64])?;
+        // G2の座標は虚部、実部の順に並んでいる
+        let mut fq = [Fq::zero(); 4];
+        for (i, element) in fq.iter_mut().enumerate() {
+            let offset = 64 + i * 32;
+            *element =
+                Fq::from_slice(&chunk[offset..offset + 32]).map_err(|_| "invalid field element")?;
+        }
+        let x = Fq2::new(fq[1], fq[0]);
+        let y = Fq2::new(fq[3], fq[2]);
+        let b = if x.is_zero() && y.is_zero() {
+            G2::zero()
+        } else {
+            AffineG2::new(x, y)
+                .map_err(|_| "point is not on curve")?
+                .into()
+        };
+        points.push((a, b));
+    }
+
+    let mut output = vec![0; 32];
+    if bn::pairing_batch(&points) == Gt::one() {
+        output[31] = 1;
+    }
+    return Ok(output);
+}
+
+/// BLAKE2bの初期化ベクトル
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// BLAKE2bのメッセージの並び替え
+const BLAKE2B_SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// BLAKE2bの混合関数G
+fn blake2b_g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+/// 0x09: BLAKE2bの圧縮関数F(EIP-152)
+/// 長さはgasの計算で213byteであることを確認している
+fn blake2f(input: &[u8]) -> Result<Vec<u8>, String> {
+    let rounds = u32::from_be_bytes([input[0], input[1], input[2], input[3]]) as usize;
+    let word = |offset: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&input[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    };
+    let mut h = [0u64; 8];
+    for (i, h) in h.iter_mut().enumerate() {
+        *h = word(4 + i * 8);
+    }
+    let mut m = [0u64; 16];
+    for (i, m) in m.iter_mut().enumerate() {
+        *m = word(68 + i * 8);
+    }
+    let t = [word(196), word(204)];
+    let last_block = match input[212] {
+        0 => false,
+        1 => true,
+        _ => return Err("invalid final block indicator".to_string()),
+    };
+
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(&h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= t[0];
+    v[13] ^= t[1];
+    if last_block {
+        v[14] = !v[14];
+    }
+    for round in 0..rounds {
+        let s = &BLAKE2B_SIGMA[round % 10];
+        blake2b_g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+        blake2b_g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+        blake2b_g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+        blake2b_g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+        blake2b_g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+        blake2b_g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+        blake2b_g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+        blake2b_g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+    }
+
+    let mut output = Vec::with_capacity(64);
+    for i in 0..8 {
+        output.extend_from_slice(&(h[i] ^ v[i] ^ v[i + 8]).to_le_bytes());
+    }
+    return Ok(output);
+}
+
+/// 0x0a: blobのKZGコミットメントのzにおける値がyであることを検証する(EIP-4844)
+fn point_evaluation(input: &[u8]) -> Result<Vec<u8>, String> {
+    use c_kzg::{Bytes32, Bytes48};
+
+    if input.len() != 192 {
+        return Err("invalid input length".to_string());
+    }
+    let versioned_hash = &input[0..32];
+    let commitment = &input[96..144];
+
+    // versioned hashはコミットメントのSHA-256の先頭1byteをバージョン(0x01)にしたもの
+    let mut hasher = sha2::Sha256::new();
+    hasher.input(commitment);
+    let mut hash = hasher.result().to_vec();
+    hash[0] = 0x01;
+    if versioned_hash != &hash[..] {
+        return Err("mismatched versioned hash".to_string());
+    }
+
+    let to_err = |_| "invalid input".to_string();
+    let z = Bytes32::from_bytes(&input[32..64]).map_err(to_err)?;
+    let y = Bytes32::from_bytes(&input[64..96]).map_err(to_err)?;
+    let commitment = Bytes48::from_bytes(commitment).map_err(to_err)?;
+    let proof = Bytes48::from_bytes(&input[144..192]).map_err(to_err)?;
+    let settings = c_kzg::ethereum_kzg_settings(0);
+    match settings.verify_kzg_proof(&commitment, &z, &y, &proof) {
+        Ok(true) => {}
+        _ => return Err("invalid proof".to_string()),
+    }
+
+    // FIELD_ELEMENTS_PER_BLOBとBLS_MODULUSを返す
+    let mut output = vec![0; 64];
+    U256::from(4096).to_big_endian(&mut output[0..32]);
+    output[32..64].copy_from_slice(&BLS_MODULUS);
+    return Ok(output);
+}
+
+/// BLS12-381のスカラー体の位数
+const BLS_MODULUS: [u8; 32] = [
+    0x73, 0xed, 0xa7, 0x53, 0x29, 0x9d, 0x7d, 0x48, 0x33, 0x39, 0xd8, 0x08, 0x09, 0xa1, 0xd8, 0x05,
+    0x53, 0xbd, 0xa4, 0x02, 0xff, 0xfe, 0x5b, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01,
+];
+
+#[cfg(test)]
+fn precompile_address(index: u64) -> H160 {
+    H160::from_low_u64_be(index)
+}
+
+#[test]
+fn test_is_precompile() {
+    assert!(is_precompile(&precompile_address(1), Fork::Frontier));
+    assert!(!is_precompile(&precompile_address(5), Fork::Homestead));
+    assert!(is_precompile(&precompile_address(8), Fork::Byzantium));
+    assert!(!is_precompile(&precompile_address(9), Fork::Petersburg));
+    assert!(!is_precompile(&precompile_address(10), Fork::Shanghai));
+    assert!(is_precompile(&precompile_address(10), Fork::Cancun));
+    assert!(!is_precompile(&precompile_address(11), Fork::Cancun));
+    assert!(!is_precompile(&H160::from_low_u64_be(0x0101), Fork::Cancun));
+}
+
+#[test]
+fn test_ecrecover() {
+    let input = hex::decode(
+        "18c547e4f7b0f325ad1e56f57e26c745b09a3e503d86e00e5255ff7f715d3d1c\
+         000000000000000000000000000000000000000000000000000000000000001c\
+         73b1693892219d736caba55bdb67216e485557ea6b6af75f37096c9aa6a5a75f\
+         eeb940b1d03b21e36b0e47e79769f095fe2ab855bd91e3a38756b7d75a9c4549",
+    )
+    .unwrap();
+    let (output, gas) = execute(&precompile_address(1), &input, 3000, Fork::Cancun).unwrap();
+    assert_eq!(
+        hex::encode(output),
+        "000000000000000000000000a94f5374fce5edbc8e2a8697c15331677e6ebf0b"
+    );
+    assert_eq!(gas, 3000);
+
+    // vが27, 28以外なら空の出力を返す
+    let mut invalid = input.clone();
+    invalid[63] = 0x1d;
+    let (output, _) = execute(&precompile_address(1), &invalid, 3000, Fork::Cancun).unwrap();
+    assert!(output.is_empty());
+
+    // gasが足りなければ失敗する
+    assert!(execute(&precompile_address(1), &input, 2999, Fork::Cancun).is_err());
+}
+
+#[test]
+fn test_hash_precompiles() {
+    let (output, gas) = execute(&precompile_address(2), &[], 100, Fork::Cancun).unwrap();
+    assert_eq!(
+        hex::encode(output),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(gas, 60);
+
+    let (output, gas) = execute(&precompile_address(3), &[], 1000, Fork::Cancun).unwrap();
+    assert_eq!(
+        hex::encode(output),
+        "0000000000000000000000009c1185a5c5e9fc54612808977ee8f548b2258d31"
+    );
+    assert_eq!(gas, 600);
+
+    let (output, gas) = execute(&precompile_address(4), &[1, 2, 3], 100, Fork::Cancun).unwrap();
+    assert_eq!(output, vec![1, 2, 3]);
+    assert_eq!(gas, 18);
+}
+
+#[test]
+fn test_modexp() {
+    // 3 ** (p - 1) % p = 1 (EIP-198の例)
+    let input = hex::decode(
+        "0000000000000000000000000000000000000000000000000000000000000001\
+         0000000000000000000000000000000000000000000000000000000000000020\
+         0000000000000000000000000000000000000000000000000000000000000020\
+         03\
+         fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2e\
+         fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f",
+    )
+    .unwrap();
+    let (output, gas) = execute(&precompile_address(5), &input, 100000, Fork::Byzantium).unwrap();
+    assert_eq!(output, util_word(1));
+    assert_eq!(gas, 13056);
+
+    // EIP-2565
+    let (_, gas) = execute(&precompile_address(5), &input, 100000, Fork::Berlin).unwrap();
+    assert_eq!(gas, 1360);
+
+    // modが0なら結果も0
+    let input = hex::decode(
+        "0000000000000000000000000000000000000000000000000000000000000001\
+         0000000000000000000000000000000000000000000000000000000000000001\
+         0000000000000000000000000000000000000000000000000000000000000002\
+         0302",
+    )
+    .unwrap();
+    let (output, gas) = execute(&precompile_address(5), &input, 100000, Fork::Berlin).unwrap();
+    assert_eq!(output, vec![0, 0]);
+    assert_eq!(gas, 200);
+
+    // gasが足りなければ巨大なバッファを確保する前に失敗する
+    let input = hex::decode(
+        "00000000000000000000000000000000000000000000000000000000ffffffff\
+         00000000000000000000000000000000000000000000000000000000ffffffff\
+         00000000000000000000000000000000000000000000000000000000ffffffff",
+    )
+    .unwrap();
+    assert_eq!(
+        execute(&precompile_address(5), &input, 100000, Fork::Berlin),
+        Err("out of gas".to_string())
+    );
+}
+
+#[cfg(test)]
+fn util_word(value: u64) -> Vec<u8> {
+    let mut word = vec![0; 32];
+    U256::from(value).to_big_endian(&mut word);
+    return word;
+}
+
+#[test]
+fn test_bn128() {
+    // (1, 2)は生成元
+    let g1 = "0000000000000000000000000000000000000000000000000000000000000001\
+              0000000000000000000000000000000000000000000000000000000000000002";
+    let doubled = "030644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd3\
+                   15ed738c0e0a7c92e7845f96b2ae9c0a68a6a449e3538fc7ff3ebf7a5a18a2c4";
+
+    let input = hex::decode(format!("{}{}", g1, g1)).unwrap();
+    let (output, gas) = execute(&precompile_address(6), &input, 150, Fork::Istanbul).unwrap();
+    assert_eq!(hex::encode(output), doubled);
+    assert_eq!(gas, 150);
+
+    let input = hex::decode(format!("{}{}", g1, hex::encode(util_word(2)))).unwrap();
+    let (output, gas) = execute(&precompile_address(7), &input, 40000, Fork::Byzantium).unwrap();
+    assert_eq!(hex::encode(output), doubled);
+    assert_eq!(gas, 40000);
+
+    // 曲線上にない点は失敗する
+    let input = hex::decode(format!("{}{}", doubled, hex::encode(util_word(1)))).unwrap();
+    assert!(execute(&precompile_address(6), &input[..96], 150, Fork::Istanbul).is_err());
+}
+
+#[test]
+fn test_bn128_pairing() {
+    // 空の入力は1を返す
+    let (output, gas) = execute(&precompile_address(8), &[], 45000, Fork::Istanbul).unwrap();
+    assert_eq!(output, util_word(1));
+    assert_eq!(gas, 45000);
+
+    // e(P, Q) * e(-P, Q) = 1
+    let g2 = "198e9393920d483a7260bfb731fb5d25f1aa493335a9e71297e485b7aef312c2\
+              1800deef121f1e76426a00665e5c4479674322d4f75edadd46debd5cd992f6ed\
+              090689d0585ff075ec9e99ad690c3395bc4b313370b38ef355acdadcd122975b\
+              12c85ea5db8c6deb4aab71808dcb408fe3d1e7690c43d37b4ce6cc0166fa7daa";
+    let p = "0000000000000000000000000000000000000000000000000000000000000001\
+             0000000000000000000000000000000000000000000000000000000000000002";
+    let neg_p = "0000000000000000000000000000000000000000000000000000000000000001\
+                 30644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd45";
+    let input = hex::decode(format!("{}{}{}{}", p, g2, neg_p, g2)).unwrap();
+    let (output, gas) = execute(&precompile_address(8), &input, 300000, Fork::Byzantium).unwrap();
+    assert_eq!(output, util_word(1));
+    assert_eq!(gas, 260000);
+
+    // e(P, Q) * e(P, Q) != 1
+    let input = hex::decode(format!("{}{}{}{}", p, g2, p, g2)).unwrap();
+    let (output, _) = execute(&precompile_address(8), &input, 200000, Fork::Istanbul).unwrap();
+    assert_eq!(output, util_word(0));
+
+    // 192byteの倍数でなければ失敗する
+    assert!(execute(&precompile_address(8), &input[1..], 200000, Fork::Istanbul).is_err());
+}
+
+#[test]
+fn test_blake2f() {
+    // EIP-152のテストベクタ5
+    let input = hex::decode(
+        "0000000c48c9bdf267e6096a3ba7ca8485ae67bb2bf894fe72f36e3cf1361d5f3af54fa5d182e6ad7f520e511f6c3e2b8c68059b6bbd41fbabd9831f79217e1319cde05b61626300000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000300000000000000000000000000000001",
+    )
+    .unwrap();
+    let (output, gas) = execute(&precompile_address(9), &input, 100, Fork::Istanbul).unwrap();
+    assert_eq!(
+        hex::encode(output),
+        "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+    );
+    assert_eq!(gas, 12);
+
+    // ラウンド数のgasが足りなければ計算する前に失敗する
+    let mut many_rounds = input.clone();
+    many_rounds[..4].copy_from_slice(&[0xff; 4]);
+    assert_eq!(
+        execute(&precompile_address(9), &many_rounds, 100, Fork::Istanbul),
+        Err("out of gas".to_string())
+    );
+
+    // 最後のbyteは0か1でなければならない
+    let mut invalid = input.clone();
+    invalid[212] = 2;
+    assert!(execute(&precompile_address(9), &invalid, 100, Fork::Istanbul).is_err());
+}
+
+#[test]
+fn test_point_evaluation() {
+    use c_kzg::{Blob, Bytes32};
+
+    let settings = c_kzg::ethereum_kzg_settings(0);
+    let mut blob = Blob::new([0; c_kzg::BYTES_PER_BLOB]);
+    for (i, element) in blob.chunks_mut(32).enumerate() {
+        element[31] = i as u8;
+    }
+    let commitment = settings.blob_to_kzg_commitment(&blob).unwrap();
+    let mut z = [0; 32];
+    z[31] = 5;
+    let z = Bytes32::new(z);
+    let (proof, y) = settings.compute_kzg_proof(&blob, &z).unwrap();
+
+    let mut hasher = sha2::Sha256::new();
+    hasher.input(&commitment[..]);
+    let mut versioned_hash = hasher.result().to_vec();
+    versioned_hash[0] = 0x01;
+
+    let mut input = versioned_hash.clone();
+    input.extend_from_slice(&z[..]);
+    input.extend_from_slice(&y[..]);
+    input.extend_from_slice(&commitment[..]);
+    input.extend_from_slice(&proof[..]);
+    let (output, gas) = execute(&precompile_address(10), &input, 50000, Fork::Cancun).unwrap();
+    assert_eq!(output[..32], util_word(4096)[..]);
+    assert_eq!(output[32..], BLS_MODULUS[..]);
+    assert_eq!(gas, 50000);
+
+    // 値が違えば失敗する
+    let mut invalid = input.clone();
+    invalid[95] ^= 1;
+    assert!(execute(&precompile_address(10), &invalid, 50000, Fork::Cancun).is_err());
+}
@@ -0,0 +1,458 @@
+//! Gas profiler
+//!
+//! 消費したgasと実行回数をopcodeごと、pcごと、(source mapがあれば)関数ごとに集計するモジュール<br/>
+//! 呼び出しのスタックごとの集計はflamegraphのfolded形式で出力できる
+use super::inspector::{CallInputs, CallOutcome, CreateInputs, Inspector};
+use super::opcode;
+use super::srcmap::{Jump, Location, Sources};
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::WorldState;
+use super::transaction::{self, Receipt, Transaction};
+use super::vm::VM;
+use ethereum_types::H160;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// 実行回数と消費したgas
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stat {
+    pub count: usize,
+    pub gas: usize,
+}
+
+impl Stat {
+    fn add(&mut self, gas: usize) {
+        self.count += 1;
+        self.gas += gas;
+    }
+}
+
+/// 実行中の命令
+#[derive(Debug, Clone)]
+struct Pending {
+    depth: usize,
+    address: H160,
+    pc: usize,
+    opcode: u8,
+    gas: usize,               // 実行前の残りのgas
+    child_gas: usize,         // CALL系命令で呼び出し先が消費したgas
+    stack: String,            // folded形式の呼び出しのスタック
+    function: Option<String>, // 実行中の関数
+}
+
+/// 実行中の呼び出し
+#[derive(Debug, Clone)]
+struct Frame {
+    address: H160,
+    functions: Vec<String>, // source mapのジャンプから求めた実行中の関数 末尾が最も内側
+    entering: Option<usize>, // 直前の命令が関数に入るジャンプならその飛び先
+}
+
+impl Frame {
+    fn new(address: H160) -> Self {
+        return Self {
+            address,
+            functions: vec![],
+            entering: None,
+        };
+    }
+}
+
+/// 命令ごとのgasを集計する<br/>
+/// CALL系命令のgasには呼び出し先で消費した分を含めない(呼び出し先の命令に計上される)<br/>
+/// source mapがあれば、関数に入るジャンプ(`i`)と関数から戻るジャンプ(`o`)で関数ごとにも集計する
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    opcodes: BTreeMap<u8, Stat>,
+    pcs: BTreeMap<(H160, usize), (u8, Stat)>,
+    functions: BTreeMap<String, Stat>, // countは関数に入った回数、gasは関数自身の命令のgas
+    folded: BTreeMap<String, usize>,
+    frames: Vec<Frame>, // 呼び出しのスタック 末尾が実行中の呼び出し
+    pending: Vec<Pending>,
+    sources: Sources,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// sourcesのsource mapで関数ごとにも集計する
+    pub fn with_sources(sources: Sources) -> Self {
+        return Self {
+            sources,
+            ..Self::default()
+        };
+    }
+
+    /// opcodeごとの集計
+    pub fn opcodes(&self) -> &BTreeMap<u8, Stat> {
+        &self.opcodes
+    }
+
+    /// (アドレス, pc)ごとの集計とopcode
+    pub fn pcs(&self) -> &BTreeMap<(H160, usize), (u8, Stat)> {
+        &self.pcs
+    }
+
+    /// `コントラクト.関数`ごとの集計 呼び出した関数のgasは含めない
+    pub fn functions(&self) -> &BTreeMap<String, Stat> {
+        &self.functions
+    }
+
+    /// 命令の実行を計上する
+    fn record(&mut self, step: Pending, gas: usize) {
+        self.opcodes.entry(step.opcode).or_default().add(gas);
+        self.pcs
+            .entry((step.address, step.pc))
+            .or_insert((step.opcode, Stat::default()))
+            .1
+            .add(gas);
+        if let Some(function) = step.function {
+            self.functions.entry(function).or_default().gas += gas;
+        }
+        *self.folded.entry(step.stack).or_default() += gas;
+    }
+
+    /// depth以上の深さで実行中の命令を例外停止したものとして、残りのgasをすべて計上する
+    fn halt_from(&mut self, depth: usize) {
+        while self.pending.last().is_some_and(|step| step.depth >= depth) {
+            let step = self.pending.pop().unwrap();
+            let gas = step.gas.saturating_sub(step.child_gas);
+            self.record(step, gas);
+        }
+    }
+
+    /// 呼び出しの終了 呼び出し先の実行中の命令を片付け、呼び出し元のCALL系命令に呼び出し先のgasを記録する
+    fn exit(&mut self, depth: usize, outcome: &CallOutcome) {
+        self.halt_from(depth);
+        self.frames.pop();
+        if depth > 0 {
+            if let Some(step) = self.pending.last_mut() {
+                step.child_gas += outcome.gas_used;
+            }
+        }
+    }
+
+    /// flamegraphのfolded形式 `呼び出し;...;opcode gas`の行
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (stack, gas) in &self.folded {
+            writeln!(folded, "{} {}", stack, gas).unwrap();
+        }
+        return folded;
+    }
+
+    /// opcodeごとと、pcごとの上位limit件のgasの多い順の表
+    pub fn report(&self, limit: usize) -> String {
+        let total: usize = self.opcodes.values().map(|stat| stat.gas).sum();
+        let percent = |gas: usize| -> f64 {
+            if total == 0 {
+                0.0
+            } else {
+                gas as f64 * 100.0 / total as f64
+            }
+        };
+        let mut report = String::new();
+        writeln!(report, "total execution gas: {}", total).unwrap();
+
+        let mut opcodes: Vec<(&u8, &Stat)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.gas.cmp(&a.1.gas).then(a.0.cmp(b.0)));
+        writeln!(report, "\nby opcode:").unwrap();
+        writeln!(
+            report,
+            "  {:<14} {:>8} {:>10} {:>7}",
+            "opcode", "count", "gas", "%"
+        )
+        .unwrap();
+        for (op, stat) in opcodes {
+            writeln!(
+                report,
+                "  {:<14} {:>8} {:>10} {:>6.2}%",
+                name(*op),
+                stat.count,
+                stat.gas,
+                percent(stat.gas)
+            )
+            .unwrap();
+        }
+
+        let mut pcs: Vec<_> = self.pcs.iter().collect();
+        pcs.sort_by(|a, b| b.1 .1.gas.cmp(&a.1 .1.gas).then(a.0.cmp(b.0)));
+        writeln!(report, "\nby pc (top {}):", limit).unwrap();
+        writeln!(
+            report,
+            "  {:<42} {:>6} {:<14} {:>8} {:>10} {:>7}",
+            "address", "pc", "opcode", "count", "gas", "%"
+        )
+        .unwrap();
+        for ((address, pc), (op, stat)) in pcs.into_iter().take(limit) {
+            writeln!(
+                report,
+                "  0x{:<40} {:>6} {:<14} {:>8} {:>10} {:>6.2}%",
+                hex::encode(address),
+                format!("0x{:04x}", pc),
+                name(*op),
+                stat.count,
+                stat.gas,
+                percent(stat.gas)
+            )
+            .unwrap();
+        }
+
+        if !self.functions.is_empty() {
+            let mut functions: Vec<_> = self.functions.iter().collect();
+            functions.sort_by(|a, b| b.1.gas.cmp(&a.1.gas).then(a.0.cmp(b.0)));
+            writeln!(report, "\nby function (top {}):", limit).unwrap();
+            writeln!(
+                report,
+                "  {:<40} {:>8} {:>10} {:>7}",
+                "function", "calls", "gas", "%"
+            )
+            .unwrap();
+            for (function, stat) in functions.into_iter().take(limit) {
+                writeln!(
+                    report,
+                    "  {:<40} {:>8} {:>10} {:>6.2}%",
+                    function,
+                    stat.count,
+                    stat.gas,
+                    percent(stat.gas)
+                )
+                .unwrap();
+            }
+        }
+        return report;
+    }
+}
+
+impl Inspector for Profiler {
+    fn step(&mut self, vm: &VM, _ws: &WorldState) {
+        let opcode = vm.opcode().unwrap_or(0);
+        let code_map = self.sources.code_map(vm.code_hash(), vm.code());
+        let contract = code_map.as_ref().map_or_else(
+            || format!("0x{}", hex::encode(vm.address())),
+            |code_map| code_map.name().to_string(),
+        );
+        if let Some(frame) = self.frames.last_mut() {
+            // 関数に入るジャンプの飛び先のJUMPDESTが関数の先頭
+            if let Some(destination) = frame.entering.take() {
+                let sources = &self.sources;
+                let location = code_map
+                    .as_ref()
+                    .and_then(|code_map| sources.locate_in(code_map, destination));
+                let function = match location {
+                    Some(location) => function_name(&location),
+                    None => format!("0x{:04x}", destination),
+                };
+                let key = format!("{}.{}", contract, function);
+                self.functions.entry(key).or_default().count += 1;
+                frame.functions.push(function);
+            }
+        }
+
+        let mut stack = String::new();
+        for frame in &self.frames {
+            write!(stack, "0x{};", hex::encode(frame.address)).unwrap();
+            for function in &frame.functions {
+                write!(stack, "{};", function).unwrap();
+            }
+        }
+        stack.push_str(name(opcode));
+        let function = self
+            .frames
+            .last()
+            .and_then(|frame| frame.functions.last())
+            .map(|function| format!("{}.{}", contract, function));
+        self.pending.push(Pending {
+            depth: vm.depth(),
+            address: vm.address(),
+            pc: vm.pc(),
+            opcode,
+            gas: vm.gas(),
+            child_gas: 0,
+            stack,
+            function,
+        });
+
+        // JUMPのジャンプの種類で関数の出入りを追う 戻るジャンプは関数の命令として計上した後で抜ける
+        if opcode == 0x56 {
+            let jump = code_map
+                .as_ref()
+                .and_then(|code_map| code_map.entry(vm.pc()))
+                .map(|entry| entry.jump);
+            if let Some(frame) = self.frames.last_mut() {
+                match jump {
+                    Some(Jump::In) => {
+                        let destination = vm.stack().last().map_or(0, |dest| dest.low_u64());
+                        frame.entering = Some(destination as usize);
+                    }
+                    Some(Jump::Out) => {
+                        frame.functions.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn step_end(&mut self, vm: &VM, _ws: &WorldState) {
+        self.halt_from(vm.depth() + 1);
+        if let Some(step) = self.pending.pop() {
+            let gas = (step.gas - vm.gas()).saturating_sub(step.child_gas);
+            self.record(step, gas);
+        }
+    }
+
+    fn call(&mut self, inputs: &CallInputs) {
+        self.frames.push(Frame::new(inputs.to));
+    }
+
+    fn call_end(&mut self, inputs: &CallInputs, outcome: &CallOutcome) {
+        self.exit(inputs.depth, outcome);
+    }
+
+    fn create(&mut self, inputs: &CreateInputs) {
+        self.frames.push(Frame::new(inputs.address));
+    }
+
+    fn create_end(&mut self, inputs: &CreateInputs, outcome: &CallOutcome) {
+        self.exit(inputs.depth, outcome);
+    }
+}
+
+fn name(op: u8) -> &'static str {
+    return opcode::info(op).map(|info| info.name).unwrap_or("INVALID");
+}
+
+/// 関数の定義の行から関数名を取り出す 見つからなければソースの位置
+fn function_name(location: &Location) -> String {
+    let text = &location.text;
+    for keyword in ["function ", "modifier "] {
+        if let Some(start) = text.find(keyword) {
+            let name: String = text[start + keyword.len()..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                .collect();
+            if !name.is_empty() {
+                return name;
+            }
+        }
+    }
+    for keyword in ["constructor", "fallback", "receive"] {
+        if text.starts_with(keyword) {
+            return keyword.to_string();
+        }
+    }
+    return location.to_string();
+}
+
+/// トランザクションを実行してgasを集計する sourcesのsource mapで関数ごとにも集計する
+pub fn profile(
+    ws: &mut WorldState,
+    tx: &Transaction,
+    sources: Sources,
+) -> Result<(Receipt, Profiler), String> {
+    let profiler = Rc::new(RefCell::new(Profiler::with_sources(sources)));
+    let receipt = transaction::execute_with_inspector(ws, tx, Some(profiler.clone()))?;
+    return Ok((receipt, profiler.take()));
+}
+
+#[test]
+fn test_profile() {
+    // 0xcc: storage[0] = 1
+    let callee = "600160005500";
+    // 0xbb: CALL(0xffff, 0xcc, 0, 0, 0, 0, 0) STOP
+    let caller = "6000600060006000600060cc61fffff100";
+    let mut ws = test_world_state(&[(0xbb, caller), (0xcc, callee)]);
+
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let (receipt, profiler) = profile(&mut ws, &tx, Sources::new()).unwrap();
+    assert!(receipt.status.is_success());
+    let opcodes = profiler.opcodes();
+    // 呼び出し元の6つと呼び出し先の2つ
+    assert_eq!(opcodes[&0x60], Stat { count: 8, gas: 24 });
+    assert_eq!(
+        opcodes[&0x55],
+        Stat {
+            count: 1,
+            gas: 22100
+        }
+    );
+    // 呼び出し先で消費したgasを含まない 呼び出し先は初めてのアクセスなので2600
+    assert_eq!(
+        opcodes[&0xf1],
+        Stat {
+            count: 1,
+            gas: 2600
+        }
+    );
+    assert_eq!(
+        profiler.pcs()[&(H160::from_low_u64_be(0xcc), 4)],
+        (
+            0x55,
+            Stat {
+                count: 1,
+                gas: 22100
+            }
+        )
+    );
+    assert_eq!(
+        receipt.gas_used - 21000,
+        opcodes.values().map(|stat| stat.gas).sum::<usize>()
+    );
+
+    let bb = format!("0x{}", hex::encode(H160::from_low_u64_be(0xbb)));
+    let cc = format!("0x{}", hex::encode(H160::from_low_u64_be(0xcc)));
+    let folded = profiler.folded();
+    assert!(folded.contains(&format!("{};{};SSTORE 22100\n", bb, cc)));
+    assert!(folded.contains(&format!("{};CALL 2600\n", bb)));
+    assert!(profiler
+        .report(10)
+        .starts_with("total execution gas: 24727\n"));
+}
+
+#[test]
+fn test_profile_functions() {
+    use super::srcmap::SourceMap;
+
+    // PUSH1 0x07, PUSH1 0x09, JUMP(i), STOP, STOP, JUMPDEST, STOP,
+    // f: JUMPDEST, PUSH1 0x01, PUSH1 0x00, SSTORE, JUMP(o)
+    let code = "600760095600005b005b600160005556";
+    let source = "contract C {\n    function f() internal {\n        x = 1;\n    }\n}\n";
+    let f = source.find("function f").unwrap();
+    let x = source.find("x = 1").unwrap();
+    let map = format!("0:60:0;;:::i;:::-;;;;{}:40;{}:5;;;{}:40:0:o", f, x, f);
+    let mut sources = Sources::new();
+    sources.add_source(0, "C.sol", source);
+    sources.add_contract(
+        "C.sol:C",
+        None,
+        Some((hex::decode(code).unwrap(), SourceMap::parse(&map).unwrap())),
+    );
+
+    let mut ws = test_world_state(&[(0xbb, code)]);
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let (receipt, profiler) = profile(&mut ws, &tx, sources).unwrap();
+    assert!(receipt.status.is_success());
+
+    // PUSH1, PUSH1, SSTORE(cold), 戻るJUMPが関数fのgas (ジャンプ先のJUMPDESTは実行されない)
+    assert_eq!(
+        profiler.functions()["C.sol:C.f"],
+        Stat {
+            count: 1,
+            gas: 3 + 3 + 22100 + 8
+        }
+    );
+    let bb = format!("0x{}", hex::encode(H160::from_low_u64_be(0xbb)));
+    let folded = profiler.folded();
+    assert!(folded.contains(&format!("{};f;SSTORE 22100\n", bb)));
+    // 関数に入るJUMPは呼び出し元、戻った後の命令は関数の外に計上される
+    assert!(folded.contains(&format!("{};JUMP 8\n", bb)));
+    assert!(folded.contains(&format!("{};f;JUMP 8\n", bb)));
+    assert!(folded.contains(&format!("{};STOP 0\n", bb)));
+    assert!(profiler.report(10).contains("by function (top 10):"));
+}
@@ -0,0 +1,523 @@
+//! Execution recording
+//!
+//! 実行した命令ごとにスタック、メモリ、storageの差分を記録し、任意の命令の時点の状態を復元するモジュール
+use super::inspector::{CallInputs, CallOutcome, CreateInputs, Inspector};
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::WorldState;
+use super::vm::VM;
+use ethereum_types::{H160, U256};
+use std::collections::{BTreeMap, HashMap};
+
+/// 呼び出しの状態を丸ごと保存する間隔(命令数)
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// CALL系命令による呼び出し1回分のスタックとメモリ
+#[derive(Debug, Clone, Default)]
+struct Frame {
+    id: usize,
+    stack: Vec<U256>,
+    memory: Vec<u8>,
+    next: Option<u8>, // この状態から次に実行した命令
+}
+
+/// 1命令分の記録<br/>
+/// 差分は同じ呼び出しの直前の記録からの変化で、直前に実行した命令によるもの
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub depth: usize,
+    pub frame: usize, // 呼び出しごとに振られる番号
+    pub address: H160,
+    pub pc: usize,
+    pub opcode: u8,
+    pub gas: usize,
+    pop: usize,                             // 取り除かれたスタックの要素数
+    push: Vec<U256>,                        // その後積まれたスタックの要素
+    memory_size: usize,                     // メモリのサイズ
+    memory: Vec<(usize, Vec<u8>, Vec<u8>)>, // 書き換わった32byteのワード(位置, 変更前, 変更後)
+    storage: Vec<(H160, U256, U256)>,       // 書き換わったstorage(アドレス, slot, 変更後)
+}
+
+/// ある命令を実行する直前の状態
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub step: usize,
+    pub depth: usize,
+    pub frame: usize,
+    pub address: H160,
+    pub pc: usize,
+    pub opcode: u8,
+    pub gas: usize,
+    pub stack: Vec<U256>,
+    pub memory: Vec<u8>,
+    pub storage: HashMap<H160, HashMap<U256, U256>>, // それまでに実行されたアカウントのstorage
+}
+
+impl Snapshot {
+    /// 実行中のコントラクトのstorageのうち、previousの時点から変わったslot<br/>
+    /// (slot, 変更前, 変更後)の組を返す
+    pub fn storage_changes(&self, previous: &Snapshot) -> Vec<(U256, U256, U256)> {
+        let empty = HashMap::new();
+        let before = previous.storage.get(&self.address).unwrap_or(&empty);
+        let after = self.storage.get(&self.address).unwrap_or(&empty);
+        return changes(before, after)
+            .into_iter()
+            .map(|(slot, new)| (slot, before.get(&slot).copied().unwrap_or_default(), new))
+            .collect();
+    }
+}
+
+/// 実行を1命令ずつ記録する<br/>
+/// Inspectorとして実行に渡して使う 他のInspectorのstepから`record`を呼ぶ場合は、
+/// storageの書き込みと呼び出しの通知もRecordingに渡す 最後の命令を実行した結果は記録されない
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    steps: Vec<Step>,
+    checkpoints: Vec<Vec<Frame>>, // CHECKPOINT_INTERVAL命令ごとの呼び出しの状態
+    codes: Vec<Vec<u8>>,          // 呼び出しごとの実行されたコード
+    initial: HashMap<H160, HashMap<U256, U256>>, // 初めて実行されたときのstorage
+    frames: Vec<Frame>,           // 記録中の呼び出しの状態
+    storage: HashMap<H160, HashMap<U256, U256>>, // 記録中のstorage
+    written: Vec<(H160, U256, U256)>, // 前回の記録からのstorageへの書き込み(アドレス, slot, 変更後)
+    writes: Vec<(H160, U256, U256)>, // 失敗した呼び出しで戻すための書き込み(アドレス, slot, 変更前)
+    calls: Vec<usize>,            // 実行中の呼び出しが始まったときのwritesの長さ
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// 記録した命令の数
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn step(&self, step: usize) -> Option<&Step> {
+        self.steps.get(step)
+    }
+
+    /// frame番目の呼び出しで実行されたコード
+    pub fn code(&self, frame: usize) -> &[u8] {
+        &self.codes[frame]
+    }
+
+    /// vmが次に実行する命令を記録する
+    pub fn record(&mut self, vm: &VM, ws: &WorldState) {
+        // 呼び出し元に戻った場合は呼び出し先の状態を捨て、新たに呼び出した場合は空の状態から始める
+        let depth = vm.depth();
+        self.frames.truncate(depth + 1);
+        if self.frames.len() < depth + 1 {
+            self.frames.push(Frame {
+                id: self.codes.len(),
+                ..Default::default()
+            });
+            self.codes.push(vm.code().to_vec());
+        }
+
+        let storage = self.record_storage(vm.address(), ws);
+        let current = self.frames.last().unwrap();
+        let common = current
+            .stack
+            .iter()
+            .zip(vm.stack())
+            .take_while(|(old, new)| old == new)
+            .count();
+        // 直前の命令が書き込んだ範囲だけを比べる 拡張された部分は0なのでmemory_sizeだけで復元できる
+        let mut memory = vec![];
+        if let Some((start, end)) = current
+            .next
+            .and_then(|opcode| written_range(opcode, &current.stack, vm.memory().len()))
+        {
+            for offset in (start / 32 * 32..end).step_by(32) {
+                let new = &vm.memory()[offset..(offset + 32).min(vm.memory().len())];
+                let old = match current.memory.get(offset..offset + new.len()) {
+                    Some(old) => old.to_vec(),
+                    None => vec![0; new.len()],
+                };
+                if old != new {
+                    memory.push((offset, old, new.to_vec()));
+                }
+            }
+        }
+
+        let step = Step {
+            depth,
+            frame: current.id,
+            address: vm.address(),
+            pc: vm.pc(),
+            opcode: vm.opcode().unwrap_or(0),
+            gas: vm.gas(),
+            pop: current.stack.len() - common,
+            push: vm.stack()[common..].to_vec(),
+            memory_size: vm.memory().len(),
+            memory,
+            storage,
+        };
+        apply(&mut self.frames, &step);
+        self.frames.last_mut().unwrap().next = Some(step.opcode);
+        self.steps.push(step);
+        if (self.steps.len() - 1).is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoints.push(self.frames.clone());
+        }
+    }
+
+    /// 前回の記録からstorage_writeで通知された書き込みのうち、値が変わったもの<br/>
+    /// 初めて実行されたアカウントはその時点のstorageを読み込んでおく
+    fn record_storage(&mut self, address: H160, ws: &WorldState) -> Vec<(H160, U256, U256)> {
+        if !self.storage.contains_key(&address) {
+            let storage = storage_of(ws, &address);
+            self.initial.insert(address, storage.clone());
+            self.storage.insert(address, storage);
+        }
+
+        // 同じslotへの書き込みは最後の値だけを見る
+        let last: BTreeMap<(H160, U256), U256> = self
+            .written
+            .drain(..)
+            .map(|(address, slot, value)| ((address, slot), value))
+            .collect();
+        let mut written = vec![];
+        for ((address, slot), value) in last {
+            let known = self.storage.entry(address).or_default();
+            if known.get(&slot).copied().unwrap_or_default() != value {
+                known.insert(slot, value);
+                written.push((address, slot, value));
+            }
+        }
+        return written;
+    }
+
+    /// 呼び出しの開始 失敗したときに戻す書き込みの位置を覚えておく
+    fn enter(&mut self) {
+        self.calls.push(self.writes.len());
+    }
+
+    /// 呼び出しの終了 失敗した呼び出しの書き込みは新しいものから順に元の値に戻す
+    fn exit(&mut self, outcome: &CallOutcome) {
+        let start = self.calls.pop().unwrap_or(0);
+        if outcome.status.is_success() {
+            return;
+        }
+        for (address, slot, old) in self.writes.drain(start..).rev() {
+            self.written.push((address, slot, old));
+        }
+    }
+
+    /// step番目の命令を実行する直前の状態を復元する<br/>
+    /// 直前のチェックポイントから差分を順に適用する
+    pub fn snapshot(&self, step: usize) -> Option<Snapshot> {
+        let target = self.steps.get(step)?;
+        let start = step / CHECKPOINT_INTERVAL * CHECKPOINT_INTERVAL;
+        let mut frames = self.checkpoints[step / CHECKPOINT_INTERVAL].clone();
+        for recorded in &self.steps[start + 1..=step] {
+            apply(&mut frames, recorded);
+        }
+        let frame = frames.pop().unwrap();
+
+        let mut storage = self.initial.clone();
+        for recorded in &self.steps[..=step] {
+            for (address, slot, value) in &recorded.storage {
+                storage.entry(*address).or_default().insert(*slot, *value);
+            }
+        }
+
+        return Some(Snapshot {
+            step,
+            depth: target.depth,
+            frame: target.frame,
+            address: target.address,
+            pc: target.pc,
+            opcode: target.opcode,
+            gas: target.gas,
+            stack: frame.stack,
+            memory: frame.memory,
+            storage,
+        });
+    }
+
+    /// step番目の時点でaddressのslotの値を最後に変えた命令の番号
+    pub fn last_storage_write(&self, step: usize, address: &H160, slot: &U256) -> Option<usize> {
+        let step = step.min(self.steps.len().checked_sub(1)?);
+        return (1..=step)
+            .rev()
+            .find(|&i| {
+                self.steps[i]
+                    .storage
+                    .iter()
+                    .any(|(a, s, _)| a == address && s == slot)
+            })
+            .map(|i| i - 1);
+    }
+
+    /// step番目の時点で、実行中の呼び出しのメモリのoffsetから32byteのいずれかを最後に変えた命令の番号
+    pub fn last_memory_write(&self, step: usize, offset: usize) -> Option<usize> {
+        let frame = self.steps.get(step)?.frame;
+        let mut written = false;
+        for i in (0..=step).rev() {
+            if self.steps[i].frame != frame {
+                continue;
+            }
+            // 同じ呼び出しの1つ前の命令が書き換えた
+            if written {
+                return Some(i);
+            }
+            written = self.steps[i].memory.iter().any(|(word, old, new)| {
+                (0..new.len()).any(|j| {
+                    let position = word + j;
+                    position >= offset && position < offset + 32 && old[j] != new[j]
+                })
+            });
+        }
+        return None;
+    }
+}
+
+impl Inspector for Recording {
+    fn step(&mut self, vm: &VM, ws: &WorldState) {
+        self.record(vm, ws);
+    }
+
+    fn call(&mut self, _inputs: &CallInputs) {
+        self.enter();
+    }
+
+    fn call_end(&mut self, _inputs: &CallInputs, outcome: &CallOutcome) {
+        self.exit(outcome);
+    }
+
+    fn create(&mut self, _inputs: &CreateInputs) {
+        self.enter();
+    }
+
+    fn create_end(&mut self, _inputs: &CreateInputs, outcome: &CallOutcome) {
+        self.exit(outcome);
+    }
+
+    fn storage_write(&mut self, address: &H160, slot: &U256, old: &U256, new: &U256) {
+        self.written.push((*address, *slot, *new));
+        self.writes.push((*address, *slot, *old));
+    }
+}
+
+/// 記録した差分を呼び出しの状態に適用する
+fn apply(frames: &mut Vec<Frame>, step: &Step) {
+    frames.truncate(step.depth + 1);
+    if frames.len() < step.depth + 1 {
+        frames.push(Frame {
+            id: step.frame,
+            ..Default::default()
+        });
+    }
+    let frame = frames.last_mut().unwrap();
+    let keep = frame.stack.len() - step.pop;
+    frame.stack.truncate(keep);
+    frame.stack.extend_from_slice(&step.push);
+    frame.memory.resize(step.memory_size, 0);
+    for (offset, _, word) in &step.memory {
+        frame.memory[*offset..*offset + word.len()].copy_from_slice(word);
+    }
+}
+
+/// opcodeの命令を実行する直前のスタックstackから、命令がメモリに書き込む範囲[start, end)を求める<br/>
+/// 書き込まない命令や長さが0ならNone 範囲は実行後のメモリのサイズsizeまでに切り詰める
+fn written_range(opcode: u8, stack: &[U256], size: usize) -> Option<(usize, usize)> {
+    let arg = |i: usize| -> usize {
+        let value = stack
+            .len()
+            .checked_sub(i + 1)
+            .map_or(U256::zero(), |i| stack[i]);
+        return if value > U256::from(usize::MAX) {
+            usize::MAX
+        } else {
+            value.as_usize()
+        };
+    };
+    let (offset, length) = match opcode {
+        0x52 => (arg(0), 32),                          // MSTORE
+        0x53 => (arg(0), 1),                           // MSTORE8
+        0x37 | 0x39 | 0x3e | 0x5e => (arg(0), arg(2)), // CALLDATACOPY, CODECOPY, RETURNDATACOPY, MCOPY
+        0x3c => (arg(1), arg(3)),                      // EXTCODECOPY
+        0xf1 | 0xf2 => (arg(5), arg(6)),               // CALL, CALLCODE
+        0xf4 | 0xfa => (arg(4), arg(5)),               // DELEGATECALL, STATICCALL
+        _ => return None,
+    };
+    let end = offset.saturating_add(length).min(size);
+    return if offset < end {
+        Some((offset, end))
+    } else {
+        None
+    };
+}
+
+fn storage_of(ws: &WorldState, address: &H160) -> HashMap<U256, U256> {
+    return match ws.account(address) {
+        Some(account) => account.get_storage_entries().into_iter().collect(),
+        None => HashMap::new(),
+    };
+}
+
+/// beforeからafterで値が変わったslotと変更後の値 存在しないslotは0とみなす
+fn changes(before: &HashMap<U256, U256>, after: &HashMap<U256, U256>) -> Vec<(U256, U256)> {
+    let mut slots: Vec<U256> = before.keys().chain(after.keys()).copied().collect();
+    slots.sort();
+    slots.dedup();
+    return slots
+        .into_iter()
+        .filter_map(|slot| {
+            let old = before.get(&slot).copied().unwrap_or_default();
+            let new = after.get(&slot).copied().unwrap_or_default();
+            if old != new {
+                Some((slot, new))
+            } else {
+                None
+            }
+        })
+        .collect();
+}
+
+/// 各命令の直前のスタックとメモリ
+#[cfg(test)]
+type Observed = Vec<(Vec<U256>, Vec<u8>)>;
+
+/// 記録しながら各命令の直前のスタックとメモリを集める
+#[cfg(test)]
+#[derive(Default)]
+struct Observer {
+    recording: Recording,
+    observed: Observed,
+}
+
+#[cfg(test)]
+impl Inspector for Observer {
+    fn step(&mut self, vm: &VM, ws: &WorldState) {
+        self.recording.record(vm, ws);
+        self.observed
+            .push((vm.stack().to_vec(), vm.memory().to_vec()));
+    }
+
+    fn call(&mut self, inputs: &CallInputs) {
+        self.recording.call(inputs);
+    }
+
+    fn call_end(&mut self, inputs: &CallInputs, outcome: &CallOutcome) {
+        self.recording.call_end(inputs, outcome);
+    }
+
+    fn storage_write(&mut self, address: &H160, slot: &U256, old: &U256, new: &U256) {
+        self.recording.storage_write(address, slot, old, new);
+    }
+}
+
+/// 0xbbのコードを実行して記録する 各命令の直前の状態も合わせて返す
+#[cfg(test)]
+fn record_transaction(ws: &mut WorldState) -> (Recording, Observed) {
+    use super::transaction::{self, Transaction};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let observer = Rc::new(RefCell::new(Observer::default()));
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let receipt = transaction::execute_with_inspector(ws, &tx, Some(observer.clone())).unwrap();
+    assert!(receipt.status.is_success());
+    let observer = observer.take();
+    return (observer.recording, observer.observed);
+}
+
+#[test]
+fn test_last_write() {
+    // mem[0] = 0x2a, storage[0] = 1, mem[0x20] = 7, storage[0] = 2
+    // 命令の番号はPUSH1 0x2aが0、STOPが12
+    let mut ws = test_world_state(&[(0xbb, "602a6000526001600055600760205260026000550000")]);
+    let (recording, _) = record_transaction(&mut ws);
+    let address = H160::from_low_u64_be(0xbb);
+    assert_eq!(recording.len(), 13);
+
+    let snapshot = recording.snapshot(5).unwrap();
+    assert_eq!(snapshot.stack, vec![1.into(), 0.into()]);
+    assert_eq!(snapshot.memory[31], 0x2a);
+    assert_eq!(snapshot.storage[&address].get(&0.into()), None);
+    let snapshot = recording.snapshot(12).unwrap();
+    assert_eq!(snapshot.memory.len(), 64);
+    assert_eq!(snapshot.storage[&address][&0.into()], 2.into());
+    let changes = snapshot.storage_changes(&recording.snapshot(6).unwrap());
+    assert_eq!(changes, vec![(0.into(), 1.into(), 2.into())]);
+
+    assert_eq!(
+        recording.last_storage_write(12, &address, &0.into()),
+        Some(11)
+    );
+    assert_eq!(
+        recording.last_storage_write(10, &address, &0.into()),
+        Some(5)
+    );
+    assert_eq!(recording.last_storage_write(5, &address, &0.into()), None);
+    assert_eq!(recording.last_memory_write(12, 0), Some(2));
+    assert_eq!(recording.last_memory_write(12, 0x20), Some(8));
+    // 0x10から32byteのうち値が変わったのは0x1fだけ、0x30から32byteでは0x3f
+    assert_eq!(recording.last_memory_write(12, 0x10), Some(2));
+    assert_eq!(recording.last_memory_write(12, 0x30), Some(8));
+    assert_eq!(recording.last_memory_write(12, 0x40), None);
+}
+
+#[test]
+fn test_snapshot_matches_execution() {
+    // 0xccはカウンタを100から減らしながらmem[0]に書き込むループ
+    // 0xbbは0xccをCALLする チェックポイントと呼び出しをまたいで復元できることを確かめる
+    let mut ws = test_world_state(&[
+        (0xbb, "6000600060006000600060cc61fffff100"),
+        (0xcc, "60645b60019003806000528060025700"),
+    ]);
+    let (recording, states) = record_transaction(&mut ws);
+    assert!(recording.len() > 2 * CHECKPOINT_INTERVAL);
+    for (step, (stack, memory)) in states.iter().enumerate() {
+        let snapshot = recording.snapshot(step).unwrap();
+        assert_eq!(&snapshot.stack, stack, "stack at step {}", step);
+        assert_eq!(&snapshot.memory, memory, "memory at step {}", step);
+    }
+    // 呼び出し先のSTOPの直前のmem[0]への書き込みは最後のMSTORE
+    let last = recording.len() - 2;
+    let writer = recording.last_memory_write(last, 0).unwrap();
+    assert_eq!(recording.step(writer).unwrap().opcode, 0x52);
+    assert_eq!(recording.code(recording.step(last).unwrap().frame)[0], 0x60);
+}
+
+#[test]
+fn test_reverted_call() {
+    // 0xbbはCODECOPYでmem[0x40..0x44]に書き込み、0xccをCALLして返り値の1byteをmem[0x10]に受け取る
+    // 0xccはstorage[0] = 5、mem[0] = 0x77としてREVERTするので、storageは元に戻る
+    let mut ws = test_world_state(&[
+        (0xbb, "600460006040396001601060006000600060cc61fffff15000"),
+        (0xcc, "6005600055607760005360016000fd"),
+    ]);
+    let (recording, states) = record_transaction(&mut ws);
+    for (step, (stack, memory)) in states.iter().enumerate() {
+        let snapshot = recording.snapshot(step).unwrap();
+        assert_eq!(&snapshot.stack, stack, "stack at step {}", step);
+        assert_eq!(&snapshot.memory, memory, "memory at step {}", step);
+    }
+    let last = recording.snapshot(recording.len() - 1).unwrap();
+    assert_eq!(last.memory[0x10], 0x77);
+    assert_eq!(last.memory[0x40..0x44], [0x60, 0x04, 0x60, 0x00]);
+
+    let callee = H160::from_low_u64_be(0xcc);
+    let mut values: Vec<U256> = (0..recording.len())
+        .filter_map(|step| {
+            let storage = recording.snapshot(step).unwrap().storage;
+            storage
+                .get(&callee)
+                .map(|storage| storage.get(&0.into()).copied().unwrap_or_default())
+        })
+        .collect();
+    values.dedup();
+    assert_eq!(values, vec![0.into(), 5.into(), 0.into()]);
+    // 元に戻したのは呼び出し先のREVERT
+    let writer = recording
+        .last_storage_write(recording.len() - 1, &callee, &0.into())
+        .unwrap();
+    assert_eq!(recording.step(writer).unwrap().opcode, 0xfd);
+}
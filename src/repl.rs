@@ -0,0 +1,387 @@
+//! Interactive REPL
+//!
+//! ワールドステートを対話的に操作するREPL 行編集、履歴、アドレスのタブ補完に対応している
+use super::{print_receipt, save_state};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::cell::RefCell;
+use std::env;
+use std::path::PathBuf;
+use std::rc::Rc;
+use toyevm::abi::Abi;
+use toyevm::inspector::{Inspector, InspectorRef};
+use toyevm::vm::VM;
+use toyevm::{disasm, fork, state, transaction, util};
+
+/// REPLのコマンドと説明
+const COMMANDS: [(&str, &str); 16] = [
+    ("accounts", "list accounts"),
+    ("balance", "balance <addr>: show balance"),
+    ("set-balance", "set-balance <addr> <wei>: set balance"),
+    ("storage", "storage <addr> [slot]: show storage"),
+    ("code", "code <addr>: show code"),
+    ("disasm", "disasm <addr>: disassemble code"),
+    (
+        "call",
+        "call <from> <to> [data] [value]: execute transaction",
+    ),
+    (
+        "deploy",
+        "deploy <from> <code|file> [args] [value]: deploy contract",
+    ),
+    ("snapshot", "snapshot: save current state and print its id"),
+    ("revert", "revert [id]: restore state saved by snapshot"),
+    ("trace", "trace on|off: print executed instructions"),
+    ("fork", "fork [name]: show or change hard fork"),
+    ("save", "save [file]: save world state"),
+    ("state", "state: show world state hash"),
+    ("help", "help: print help"),
+    ("exit", "exit: quit EVM"),
+];
+
+/// タブ補完 1語目はコマンド、2語目以降は既知のアドレスを補完する
+struct ReplHelper {
+    addresses: Rc<RefCell<Vec<String>>>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let word = line[start..pos].to_lowercase();
+        let candidates: Vec<String> = if start == 0 {
+            COMMANDS.iter().map(|(name, _)| name.to_string()).collect()
+        } else {
+            self.addresses.borrow().clone()
+        };
+        let pairs = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(&word))
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+        return Ok((start, pairs));
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// コマンドを実行した後の動作
+enum Action {
+    Continue, // ステートは変わっていない
+    Changed,  // ステートが変わったので保存する
+    Exit,
+}
+
+struct Repl {
+    ws: state::WorldState,
+    output: Option<String>, // ステートの保存先
+    fork: fork::Fork,
+    trace: bool,
+    snapshots: Vec<state::WorldState>,
+    decoding: Abi, // ログとrevertデータのデコードに使うABI
+}
+
+/// REPLを起動する
+pub fn run(ws: state::WorldState, output: Option<String>, fork: fork::Fork, decoding: Abi) -> i32 {
+    let addresses = Rc::new(RefCell::new(Vec::new()));
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    editor.set_helper(Some(ReplHelper {
+        addresses: Rc::clone(&addresses),
+    }));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    let mut repl = Repl {
+        ws,
+        output,
+        fork,
+        trace: false,
+        snapshots: Vec::new(),
+        decoding,
+    };
+    repl.ws.update_state();
+    println!("world state: {}", repl.ws.get_hash());
+    println!("type help to list commands");
+
+    loop {
+        *addresses.borrow_mut() = repl
+            .ws
+            .addresses()
+            .iter()
+            .map(|address| format!("0x{}", hex::encode(address)))
+            .collect();
+
+        let line = match editor.readline("toyevm> ") {
+            Ok(line) => line,
+            // Ctrl-Cは入力中の行を破棄するだけ
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                println!("{}", e);
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match repl.execute(&words) {
+            Ok(Action::Continue) => {}
+            Ok(Action::Changed) => {
+                repl.ws.update_state();
+                println!("world state: {}", repl.ws.get_hash());
+                if let Some(path) = &repl.output {
+                    save_state(&repl.ws, path);
+                }
+            }
+            Ok(Action::Exit) => break,
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    return 0;
+}
+
+/// 履歴を保存するファイル ホームディレクトリがわからなければ保存しない
+fn history_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    return Some(PathBuf::from(home).join(".toyevm_history"));
+}
+
+impl Repl {
+    fn execute(&mut self, words: &[&str]) -> Result<Action, String> {
+        let arg = |i: usize| -> Result<&str, String> {
+            words
+                .get(i)
+                .copied()
+                .ok_or_else(|| format!("too few arguments: {}", usage(words[0])))
+        };
+
+        match words[0] {
+            "accounts" => {
+                for address in self.ws.addresses() {
+                    let account = self.ws.get_account_state(&address);
+                    println!(
+                        "0x{}  balance: {}  nonce: {}{}",
+                        hex::encode(address),
+                        account.get_balance(),
+                        account.get_nonce(),
+                        if account.get_code().is_empty() {
+                            ""
+                        } else {
+                            "  (contract)"
+                        }
+                    );
+                }
+            }
+            "balance" => {
+                let account = self.account(arg(1)?)?;
+                println!("{}", account.get_balance());
+            }
+            "set-balance" => {
+                let address = util::parse_h160(arg(1)?)?;
+                let balance = util::parse_u256(arg(2)?)?;
+                self.ws
+                    .get_or_create_account_state(&address)
+                    .set_balance(balance);
+                return Ok(Action::Changed);
+            }
+            "storage" => {
+                let account = self.account(arg(1)?)?;
+                match words.get(2) {
+                    Some(slot) => {
+                        let slot = util::parse_u256(slot)?;
+                        println!("0x{:x}", account.get_storage(&slot));
+                    }
+                    None => {
+                        for (slot, value) in account.get_storage_entries() {
+                            println!("0x{:x}: 0x{:x}", slot, value);
+                        }
+                    }
+                }
+            }
+            "code" => {
+                let account = self.account(arg(1)?)?;
+                println!("0x{}", account.get_code());
+            }
+            "disasm" => {
+                let account = self.account(arg(1)?)?;
+                let code = util::str_to_bytes(&account.get_code());
+                print!("{}", disasm::disassemble(&code));
+            }
+            "call" => {
+                let from = util::parse_h160(arg(1)?)?;
+                let to = util::parse_h160(arg(2)?)?;
+                let mut tx = transaction::Transaction::new(from, to);
+                if let Some(data) = words.get(3) {
+                    tx.data = util::read_bytecode(data)?;
+                }
+                if let Some(value) = words.get(4) {
+                    tx.value = util::parse_u256(value)?;
+                }
+                self.transact(tx);
+                return Ok(Action::Changed);
+            }
+            "deploy" => {
+                let from = util::parse_h160(arg(1)?)?;
+                let mut tx = transaction::Transaction::create(from, util::read_bytecode(arg(2)?)?);
+                if let Some(args) = words.get(3) {
+                    tx.data.extend_from_slice(&util::read_bytecode(args)?);
+                }
+                if let Some(value) = words.get(4) {
+                    tx.value = util::parse_u256(value)?;
+                }
+                self.transact(tx);
+                return Ok(Action::Changed);
+            }
+            "snapshot" => {
+                self.snapshots.push(self.ws.clone());
+                println!("snapshot id: {}", self.snapshots.len() - 1);
+            }
+            "revert" => {
+                // idを省略すると最後のsnapshotに戻す それより後のsnapshotは破棄される
+                let id = match words.get(1) {
+                    Some(id) => id.parse().map_err(|_| format!("{} is invalid id", id))?,
+                    None => self.snapshots.len().checked_sub(1).ok_or("no snapshot")?,
+                };
+                if id >= self.snapshots.len() {
+                    return Err(format!("snapshot {} is not found", id));
+                }
+                self.ws = self.snapshots[id].clone();
+                self.snapshots.truncate(id);
+                return Ok(Action::Changed);
+            }
+            "trace" => {
+                self.trace = match words.get(1).copied() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => return Err(format!("usage: {}", usage("trace"))),
+                };
+                println!("trace: {}", if self.trace { "on" } else { "off" });
+            }
+            "fork" => {
+                if let Some(name) = words.get(1) {
+                    self.fork = name.parse()?;
+                }
+                println!("fork: {}", self.fork);
+            }
+            "save" => {
+                if let Some(path) = words.get(1) {
+                    self.output = Some(path.to_string());
+                }
+                let path = self.output.as_ref().ok_or("please input file to save")?;
+                self.ws.update_state();
+                save_state(&self.ws, path);
+            }
+            "state" => {
+                self.ws.update_state();
+                println!("world state: {}", self.ws.get_hash());
+            }
+            "help" => {
+                for (_, description) in COMMANDS.iter() {
+                    println!("{}", description);
+                }
+            }
+            "exit" | "quit" => return Ok(Action::Exit),
+            command => return Err(format!("{} is invalid command", command)),
+        }
+        return Ok(Action::Continue);
+    }
+
+    /// 存在しないアカウントはエラーにする
+    fn account(&mut self, address: &str) -> Result<&state::AccountState, String> {
+        let address = util::parse_h160(address)?;
+        if !self.ws.exists(&address) {
+            return Err(format!("0x{} is not found", hex::encode(address)));
+        }
+        return Ok(self.ws.get_account_state(&address));
+    }
+
+    /// トランザクションを1回だけ実行して結果を表示する traceがonなら実行中に命令を表示する
+    fn transact(&mut self, mut tx: transaction::Transaction) {
+        tx.fork = self.fork;
+        let inspector: Option<InspectorRef> = if self.trace {
+            Some(Rc::new(RefCell::new(TracePrinter::default())))
+        } else {
+            None
+        };
+        match transaction::execute_with_inspector(&mut self.ws, &tx, inspector) {
+            Ok(receipt) => {
+                print_receipt(&receipt, &self.decoding);
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// 呼び出し元のVM(深さ0)が実行した命令を順に表示する 呼び出し先の命令は表示しない
+#[derive(Default)]
+struct TracePrinter {
+    printed: usize, // 表示した命令の数
+}
+
+impl TracePrinter {
+    fn print(&mut self, vm: &VM) {
+        if vm.depth() != 0 {
+            return;
+        }
+        for mnemonic in &vm.asm()[self.printed..] {
+            println!("{:>5}: {}", self.printed, mnemonic);
+            self.printed += 1;
+        }
+    }
+}
+
+impl Inspector for TracePrinter {
+    fn step_end(&mut self, vm: &VM, _: &state::WorldState) {
+        self.print(vm);
+    }
+
+    fn step_halt(&mut self, vm: &VM, _: &state::WorldState, _: &str) {
+        self.print(vm);
+    }
+}
+
+fn usage(command: &str) -> &'static str {
+    return COMMANDS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, description)| *description)
+        .unwrap_or("");
+}
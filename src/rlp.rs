@@ -0,0 +1,176 @@
+//! RLP
+//!
+//! Recursive Length Prefixのエンコードとデコードを行うモジュール<br/>
+//! 署名済みトランザクションの読み込みと、署名するデータの計算に使う
+use ethereum_types::{H160, U256};
+
+/// RLPの値 バイト列かリスト
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rlp {
+    Bytes(Vec<u8>),
+    List(Vec<Rlp>),
+}
+
+impl Rlp {
+    /// 数値を先頭の0を除いたビッグエンディアンのバイト列にする 0は空のバイト列
+    pub fn uint(value: U256) -> Self {
+        let mut bytes = [0; 32];
+        value.to_big_endian(&mut bytes);
+        let start = bytes.iter().position(|b| *b != 0).unwrap_or(32);
+        return Rlp::Bytes(bytes[start..].to_vec());
+    }
+
+    /// バイト列 リストならErr
+    pub fn as_bytes(&self) -> Result<&[u8], String> {
+        return match self {
+            Rlp::Bytes(bytes) => Ok(bytes),
+            Rlp::List(_) => Err("expected bytes but found list".to_string()),
+        };
+    }
+
+    /// リストの要素 バイト列ならErr
+    pub fn as_list(&self) -> Result<&[Rlp], String> {
+        return match self {
+            Rlp::List(items) => Ok(items),
+            Rlp::Bytes(_) => Err("expected list but found bytes".to_string()),
+        };
+    }
+
+    /// 数値として読む 先頭に0があるものや32byteを超えるものはErr
+    pub fn as_u256(&self) -> Result<U256, String> {
+        let bytes = self.as_bytes()?;
+        if bytes.len() > 32 {
+            return Err("integer is longer than 32 bytes".to_string());
+        }
+        if bytes.first() == Some(&0) {
+            return Err("integer has leading zeros".to_string());
+        }
+        return Ok(U256::from_big_endian(bytes));
+    }
+
+    /// アドレスとして読む 空のバイト列はNone(コントラクトの作成)
+    pub fn as_address(&self) -> Result<Option<H160>, String> {
+        return match self.as_bytes()? {
+            [] => Ok(None),
+            bytes if bytes.len() == 20 => Ok(Some(H160::from_slice(bytes))),
+            bytes => Err(format!(
+                "address must be 20 bytes, but {} bytes",
+                bytes.len()
+            )),
+        };
+    }
+}
+
+/// RLPエンコードする
+pub fn encode(item: &Rlp) -> Vec<u8> {
+    return match item {
+        Rlp::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => bytes.clone(),
+        Rlp::Bytes(bytes) => {
+            let mut encoded = length_prefix(0x80, bytes.len());
+            encoded.extend_from_slice(bytes);
+            encoded
+        }
+        Rlp::List(items) => {
+            let payload: Vec<u8> = items.iter().flat_map(encode).collect();
+            let mut encoded = length_prefix(0xc0, payload.len());
+            encoded.extend(payload);
+            encoded
+        }
+    };
+}
+
+/// 長さの接頭辞 55byteまではoffset+長さ、それより長ければoffset+55+長さのバイト数と長さ
+fn length_prefix(offset: u8, length: usize) -> Vec<u8> {
+    if length <= 55 {
+        return vec![offset + length as u8];
+    }
+    let bytes = (length as u64).to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap();
+    let mut prefix = vec![offset + 55 + (8 - start) as u8];
+    prefix.extend_from_slice(&bytes[start..]);
+    return prefix;
+}
+
+/// 1つの値をデコードする 後ろに余分なデータがあればErr
+pub fn decode(data: &[u8]) -> Result<Rlp, String> {
+    let (item, length) = decode_item(data)?;
+    if length != data.len() {
+        return Err("trailing bytes after RLP item".to_string());
+    }
+    return Ok(item);
+}
+
+/// 先頭の値をデコードし、その値と読んだバイト数を返す
+fn decode_item(data: &[u8]) -> Result<(Rlp, usize), String> {
+    let prefix = *data.first().ok_or("unexpected end of RLP data")?;
+    let (is_list, header, length) = match prefix {
+        0x00..=0x7f => return Ok((Rlp::Bytes(vec![prefix]), 1)),
+        0x80..=0xb7 => (false, 1, (prefix - 0x80) as usize),
+        0xb8..=0xbf => {
+            let (header, length) = long_length(data, (prefix - 0xb7) as usize)?;
+            (false, header, length)
+        }
+        0xc0..=0xf7 => (true, 1, (prefix - 0xc0) as usize),
+        0xf8..=0xff => {
+            let (header, length) = long_length(data, (prefix - 0xf7) as usize)?;
+            (true, header, length)
+        }
+    };
+    let end = header
+        .checked_add(length)
+        .filter(|end| *end <= data.len())
+        .ok_or("unexpected end of RLP data")?;
+    let payload = &data[header..end];
+    if !is_list {
+        if length == 1 && payload[0] < 0x80 {
+            return Err("single byte must be encoded as itself".to_string());
+        }
+        return Ok((Rlp::Bytes(payload.to_vec()), end));
+    }
+    let mut items = vec![];
+    let mut offset = 0;
+    while offset < payload.len() {
+        let (item, length) = decode_item(&payload[offset..])?;
+        items.push(item);
+        offset += length;
+    }
+    return Ok((Rlp::List(items), end));
+}
+
+/// 55byteより長い値の長さ (接頭辞を含むヘッダの長さ, 値の長さ)
+fn long_length(data: &[u8], bytes: usize) -> Result<(usize, usize), String> {
+    let length = data.get(1..1 + bytes).ok_or("unexpected end of RLP data")?;
+    if length[0] == 0 || bytes > 8 {
+        return Err("invalid RLP length".to_string());
+    }
+    let length = length.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64) as usize;
+    if length <= 55 {
+        return Err("short RLP item encoded in long form".to_string());
+    }
+    return Ok((1 + bytes, length));
+}
+
+#[test]
+fn test_rlp() {
+    let dog = Rlp::Bytes(b"dog".to_vec());
+    assert_eq!(encode(&dog), hex::decode("83646f67").unwrap());
+    let list = Rlp::List(vec![Rlp::Bytes(b"cat".to_vec()), dog.clone()]);
+    assert_eq!(encode(&list), hex::decode("c88363617483646f67").unwrap());
+    assert_eq!(encode(&Rlp::uint(U256::zero())), vec![0x80]);
+    assert_eq!(encode(&Rlp::uint(1024.into())), vec![0x82, 0x04, 0x00]);
+
+    let long = Rlp::Bytes(vec![0xaa; 56]);
+    let encoded = encode(&long);
+    assert_eq!(&encoded[..2], &[0xb8, 56]);
+    for item in [dog, list, long, Rlp::List(vec![])] {
+        assert_eq!(decode(&encode(&item)), Ok(item));
+    }
+
+    assert_eq!(
+        decode(&[0x82, 0x00, 0x01]).unwrap().as_u256(),
+        Err("integer has leading zeros".to_string())
+    );
+    assert!(decode(&[0x81, 0x05]).is_err());
+    assert!(decode(&[0x83, 0x64]).is_err());
+    assert!(decode(&[0x05, 0x06]).is_err());
+}
@@ -0,0 +1,492 @@
+//! Scenario script
+//!
+//! deploy, call, assert, snapshot, revertの手順をJSONかYAMLで記述し、ワールドステートに対して順に実行するモジュール
+//!
+//! ```yaml
+//! steps:
+//!   - deploy: { name: counter, from: "0x9c2b...", code: "6005600c..." }
+//!   - call: { name: inc, from: "0x9c2b...", to: $counter, data: "0x" }
+//!   - assert: { storage: { address: $counter, slot: 0, value: 1 } }
+//!   - snapshot: before
+//!   - revert: before
+//! ```
+//!
+//! アドレスを書く場所で`$name`と書くと、nameという名前のdeployで作られたコントラクトのアドレスになる<br/>
+//! data, code, argsの中の`$name`はABIエンコードされたアドレス(32byte)に置き換えられる
+use super::abi::Abi;
+use super::fork::Fork;
+use super::inspector::InspectorRef;
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::WorldState;
+use super::transaction::{self, Receipt, Transaction};
+use super::util;
+use ethereum_types::{H160, U256};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// シナリオ全体
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    pub state: Option<String>, // 初期状態のワールドステートのファイル
+    pub fork: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+/// シナリオの1手順
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Deploy(Deploy),
+    Call(Call),
+    Assert(Assertion),
+    Snapshot(String), // 現在のワールドステートに名前をつけて保存する
+    Revert(String),   // 保存したワールドステートに戻す
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Deploy {
+    pub name: Option<String>,
+    pub from: String,
+    pub code: String,
+    pub args: Option<String>, // ABIエンコード済みのコンストラクタの引数
+    pub value: Option<Number>,
+    pub gas: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Call {
+    pub name: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub data: Option<String>,
+    pub value: Option<Number>,
+    pub gas: Option<usize>,
+}
+
+/// 検証する内容
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Assertion {
+    Storage {
+        address: String,
+        slot: Number,
+        value: Number,
+    },
+    Balance {
+        address: String,
+        value: Number,
+    },
+    Nonce {
+        address: String,
+        value: usize,
+    },
+    Code {
+        address: String,
+        value: String,
+    },
+    // 名前をつけたdeploy, callの結果("success", "revert", "halt")
+    Status {
+        step: String,
+        value: String,
+    },
+    // 名前をつけたdeploy, callの返り値
+    Return {
+        step: String,
+        value: String,
+    },
+}
+
+/// 数値 10進数の整数か、10進数または0xで始まる16進数の文字列で書ける
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Number {
+    Integer(u64),
+    String(String),
+}
+
+impl Number {
+    fn to_u256(&self) -> Result<U256, String> {
+        return match self {
+            Number::Integer(n) => Ok(U256::from(*n)),
+            Number::String(s) => util::parse_u256(s),
+        };
+    }
+}
+
+/// JSON、またはYAMLのシナリオを読む YAMLはJSONを含むので拡張子で区別しない<br/>
+/// serde_yamlは`- deploy: {..}`の形のenumを読めないので、一度JSONの値に変換してから読む
+pub fn parse(text: &str) -> Result<Script, String> {
+    let value: serde_json::Value =
+        serde_yaml::from_str(text).map_err(|e| format!("invalid script: {}", e))?;
+    return serde_json::from_value(value).map_err(|e| format!("invalid script: {}", e));
+}
+
+/// シナリオの実行中の状態
+struct Session<'a> {
+    ws: &'a mut WorldState,
+    fork: Fork,
+    addresses: HashMap<String, H160>, // deployで作られたコントラクトのアドレス
+    receipts: HashMap<String, Receipt>,
+    snapshots: HashMap<String, WorldState>,
+    inspector: Option<InspectorRef>, // すべてのトランザクションの実行に渡すInspector
+    decoding: &'a Abi,               // revertデータのデコードに使うABI
+}
+
+/// シナリオの実行結果
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub steps: Vec<Result<String, String>>, // 実行した手順ごとの結果 Errは失敗したassert
+    pub error: Option<String>, // 手順が不正な場合などに中断した理由 中断した手順の結果はstepsに含めない
+}
+
+impl Report {
+    /// 失敗したassertの数
+    pub fn failures(&self) -> usize {
+        return self.steps.iter().filter(|step| step.is_err()).count();
+    }
+}
+
+/// シナリオを実行し、手順ごとの結果を返す<br/>
+/// 手順が不正な場合やトランザクションを実行できない場合はその時点で中断し、理由をReportのerrorに入れる
+pub fn run(script: &Script, ws: &mut WorldState) -> Report {
+    return run_with_inspector(script, ws, None, &Abi::default());
+}
+
+/// inspectorにすべてのトランザクションの実行を通知しながらシナリオを実行する<br/>
+/// revertデータはdecodingのカスタムエラーでもデコードする
+pub fn run_with_inspector(
+    script: &Script,
+    ws: &mut WorldState,
+    inspector: Option<InspectorRef>,
+    decoding: &Abi,
+) -> Report {
+    let mut report = Report::default();
+    let fork = match script.fork.as_deref().map(str::parse).transpose() {
+        Ok(fork) => fork.unwrap_or_default(),
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    let mut session = Session::new(ws, fork, inspector, decoding);
+
+    for (i, step) in script.steps.iter().enumerate() {
+        match session.step(step) {
+            Ok(result) => report.steps.push(result),
+            Err(e) => {
+                report.error = Some(format!("step {}: {}", i + 1, e));
+                break;
+            }
+        }
+    }
+    return report;
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        ws: &'a mut WorldState,
+        fork: Fork,
+        inspector: Option<InspectorRef>,
+        decoding: &'a Abi,
+    ) -> Self {
+        return Session {
+            ws,
+            fork,
+            addresses: HashMap::new(),
+            receipts: HashMap::new(),
+            snapshots: HashMap::new(),
+            inspector,
+            decoding,
+        };
+    }
+
+    /// revertの返り値をデコードできれば` (reason: ..)`、そうでなければ空文字列
+    fn revert_reason(&self, receipt: &Receipt) -> String {
+        if receipt.status.is_success() {
+            return String::new();
+        }
+        return match self.decoding.decode_revert(&receipt.output) {
+            Some(reason) => format!(" (reason: {})", reason),
+            None => String::new(),
+        };
+    }
+
+    /// 1手順を実行する 外側のErrは中断、内側のErrはassertの失敗
+    fn step(&mut self, step: &Step) -> Result<Result<String, String>, String> {
+        return match step {
+            Step::Deploy(deploy) => {
+                let mut tx = Transaction::create(self.address(&deploy.from)?, vec![]);
+                tx.data = self.bytes(&deploy.code)?;
+                if let Some(args) = &deploy.args {
+                    tx.data.extend_from_slice(&self.bytes(args)?);
+                }
+                self.set_common(&mut tx, &deploy.value, deploy.gas)?;
+                let receipt =
+                    transaction::execute_with_inspector(self.ws, &tx, self.inspector.clone())?;
+                let message = match receipt.contract_address {
+                    Some(address) => format!("deploy: 0x{}", hex::encode(address)),
+                    None => format!("deploy: {}{}", receipt.status, self.revert_reason(&receipt)),
+                };
+                if let (Some(name), Some(address)) = (&deploy.name, receipt.contract_address) {
+                    self.addresses.insert(name.clone(), address);
+                }
+                Ok(Ok(self.record(&deploy.name, receipt, message)))
+            }
+            Step::Call(call) => {
+                let mut tx = Transaction::new(self.address(&call.from)?, self.address(&call.to)?);
+                if let Some(data) = &call.data {
+                    tx.data = self.bytes(data)?;
+                }
+                self.set_common(&mut tx, &call.value, call.gas)?;
+                let receipt =
+                    transaction::execute_with_inspector(self.ws, &tx, self.inspector.clone())?;
+                let message = format!(
+                    "call: {} return 0x{}{}",
+                    receipt.status,
+                    hex::encode(&receipt.output),
+                    self.revert_reason(&receipt)
+                );
+                Ok(Ok(self.record(&call.name, receipt, message)))
+            }
+            Step::Assert(assertion) => self.assert(assertion),
+            Step::Snapshot(name) => {
+                self.snapshots.insert(name.clone(), self.ws.clone());
+                Ok(Ok(format!("snapshot: {}", name)))
+            }
+            Step::Revert(name) => {
+                let snapshot = self
+                    .snapshots
+                    .get(name)
+                    .ok_or(format!("snapshot {} is not found", name))?;
+                *self.ws = snapshot.clone();
+                Ok(Ok(format!("revert: {}", name)))
+            }
+        };
+    }
+
+    fn set_common(
+        &self,
+        tx: &mut Transaction,
+        value: &Option<Number>,
+        gas: Option<usize>,
+    ) -> Result<(), String> {
+        if let Some(value) = value {
+            tx.value = value.to_u256()?;
+        }
+        if let Some(gas) = gas {
+            tx.gas = gas;
+        }
+        tx.fork = self.fork;
+        return Ok(());
+    }
+
+    /// 結果を名前で参照できるように保存し、表示するメッセージに使用gasを加える
+    fn record(&mut self, name: &Option<String>, receipt: Receipt, message: String) -> String {
+        let message = format!("{} (gas used: {})", message, receipt.gas_used);
+        if let Some(name) = name {
+            self.receipts.insert(name.clone(), receipt);
+        }
+        return message;
+    }
+
+    fn assert(&mut self, assertion: &Assertion) -> Result<Result<String, String>, String> {
+        let (label, expected, actual) = match assertion {
+            Assertion::Storage {
+                address,
+                slot,
+                value,
+            } => {
+                let address = self.address(address)?;
+                let slot = slot.to_u256()?;
+                let actual = self.account(&address).get_storage(&slot);
+                (
+                    format!("storage[0x{:x}] of 0x{}", slot, hex::encode(address)),
+                    format!("0x{:x}", value.to_u256()?),
+                    format!("0x{:x}", actual),
+                )
+            }
+            Assertion::Balance { address, value } => {
+                let address = self.address(address)?;
+                (
+                    format!("balance of 0x{}", hex::encode(address)),
+                    value.to_u256()?.to_string(),
+                    self.account(&address).get_balance().to_string(),
+                )
+            }
+            Assertion::Nonce { address, value } => {
+                let address = self.address(address)?;
+                (
+                    format!("nonce of 0x{}", hex::encode(address)),
+                    value.to_string(),
+                    self.account(&address).get_nonce().to_string(),
+                )
+            }
+            Assertion::Code { address, value } => {
+                let address = self.address(address)?;
+                (
+                    format!("code of 0x{}", hex::encode(address)),
+                    format!("0x{}", hex::encode(self.bytes(value)?)),
+                    format!("0x{}", self.account(&address).get_code()),
+                )
+            }
+            Assertion::Status { step, value } => {
+                let status = &self.receipt(step)?.status;
+                // haltは停止の理由を含めずに比較する
+                let actual = status.to_string();
+                let actual = actual.split(' ').next().unwrap().to_string();
+                (format!("status of {}", step), value.clone(), actual)
+            }
+            Assertion::Return { step, value } => (
+                format!("return of {}", step),
+                format!("0x{}", hex::encode(self.bytes(value)?)),
+                format!("0x{}", hex::encode(&self.receipt(step)?.output)),
+            ),
+        };
+
+        if expected == actual {
+            return Ok(Ok(format!("assert {}: {}", label, actual)));
+        }
+        return Ok(Err(format!(
+            "assert {}: expected {}, got {}",
+            label, expected, actual
+        )));
+    }
+
+    /// 存在しないアカウントは空のアカウントとして扱う
+    fn account(&mut self, address: &H160) -> super::state::AccountState {
+        if !self.ws.exists(address) {
+            return Default::default();
+        }
+        return self.ws.get_account_state(address).clone();
+    }
+
+    fn receipt(&self, name: &str) -> Result<&Receipt, String> {
+        return self
+            .receipts
+            .get(name)
+            .ok_or(format!("step {} is not found", name));
+    }
+
+    /// `$name`ならdeployで作られたアドレス、それ以外は16進数のアドレスとして読む
+    fn address(&self, s: &str) -> Result<H160, String> {
+        return match s.strip_prefix('$') {
+            Some(name) => self
+                .addresses
+                .get(name)
+                .copied()
+                .ok_or(format!("contract {} is not deployed", name)),
+            None => util::parse_h160(s),
+        };
+    }
+
+    /// 16進数のバイト列を読む `$name`はABIエンコードされたアドレスに置き換える
+    fn bytes(&self, s: &str) -> Result<Vec<u8>, String> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        let mut hex = String::new();
+        let mut rest = s;
+        while let Some(pos) = rest.find('$') {
+            hex += &rest[..pos];
+            let name_length = rest[pos + 1..]
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len() - pos - 1);
+            let name = &rest[pos..pos + 1 + name_length];
+            hex += &format!("{:0>64}", hex::encode(self.address(name)?));
+            rest = &rest[pos + 1 + name_length..];
+        }
+        hex += rest;
+        return hex::decode(&hex).map_err(|e| format!("invalid hex {}: {}", s, e));
+    }
+}
+
+#[test]
+fn test_run_script() {
+    // calldataの先頭32byteをslot 0に保存するコントラクト
+    // 初期化コードはランタイムコード(600035600055)をそのまま返す
+    let sender = H160::from_low_u64_be(0xaa);
+    let store = util::create_address(&sender, 0);
+    let script = format!(
+        r#"
+fork: cancun
+steps:
+  - deploy:
+      name: store
+      from: "{sender}"
+      code: "0x6006600c60003960066000f3600035600055"
+  - snapshot: empty
+  - call:
+      name: set
+      from: "{sender}"
+      to: $store
+      data: "0x$store"
+  - assert: {{ status: {{ step: set, value: success }} }}
+  - assert: {{ storage: {{ address: $store, slot: 0, value: "0x{store}" }} }}
+  - revert: empty
+  - assert: {{ storage: {{ address: $store, slot: 0, value: 0 }} }}
+  - assert: {{ nonce: {{ address: $store, value: 2 }} }}
+"#,
+        sender = hex::encode(sender),
+        store = hex::encode(store)
+    );
+    let script = parse(&script).unwrap();
+    let mut ws = test_world_state(&[]);
+    // 最後のassertだけが失敗する(nonceは1)
+    let report = run(&script, &mut ws);
+    assert_eq!(report.error, None);
+    assert_eq!(report.steps.len(), 8);
+    assert_eq!(report.failures(), 1);
+    assert_eq!(report.steps[1], Ok("snapshot: empty".to_string()));
+    assert_eq!(
+        report.steps[7],
+        Err(format!(
+            "assert nonce of 0x{}: expected 2, got 1",
+            hex::encode(store)
+        ))
+    );
+}
+
+#[test]
+fn test_run_script_json() {
+    let script = r#"{"steps": [{"snapshot": "a"}, {"revert": "nothing"}, {"revert": "a"}]}"#;
+    let script = parse(script).unwrap();
+    let mut ws = test_world_state(&[]);
+    // 中断した手順より後は実行しない
+    assert_eq!(
+        run(&script, &mut ws),
+        Report {
+            steps: vec![Ok("snapshot: a".to_string())],
+            error: Some("step 2: snapshot nothing is not found".to_string()),
+        }
+    );
+}
+
+#[test]
+fn test_revert_reason() {
+    // REVERT(Unauthorized(7))
+    let mut decoding = Abi::default();
+    decoding
+        .errors
+        .push(super::abi::CustomError::parse("Unauthorized(uint256 id)").unwrap());
+    let selector = hex::encode(decoding.errors[0].selector());
+    let code = format!(
+        "7f{}{}600052600760045260246000fd",
+        selector,
+        "00".repeat(28)
+    );
+    let script = parse(&format!(
+        r#"{{"steps": [{{"call": {{"from": "{}", "to": "{}"}}}}]}}"#,
+        hex::encode(H160::from_low_u64_be(0xaa)),
+        hex::encode(H160::from_low_u64_be(0xbb))
+    ))
+    .unwrap();
+    let mut ws = test_world_state(&[(0xbb, &code)]);
+    let mut session = Session::new(&mut ws, Fork::default(), None, &decoding);
+    let message = session.step(&script.steps[0]).unwrap().unwrap();
+    assert!(message.starts_with(&format!(
+        "call: revert return 0x{}{:0>64} (reason: Unauthorized(id=7))",
+        selector, "7"
+    )));
+}
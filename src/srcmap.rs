@@ -0,0 +1,655 @@
+//! Source map
+//!
+//! solcが出力するsource map(`srcmap`、`srcmap-runtime`)を読み込み、pcをソースファイルの行と列に対応させるモジュール<br/>
+//! solcのstandard JSONの出力、Hardhatのbuild-info、Foundryのartifactから読み込める
+use super::inspector::Inspector;
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::WorldState;
+use super::util;
+use super::vm::VM;
+use ethereum_types::H256;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// 命令がジャンプの場合の種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jump {
+    In,      // 関数に入る
+    Out,     // 関数から戻る
+    Regular, // ジャンプしないか関数内のジャンプ
+}
+
+/// source mapの1命令分
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub offset: usize, // ソースの先頭からのバイト数
+    pub length: usize,
+    pub file: Option<usize>, // ソースファイルのid コンパイラが生成したコードではNone
+    pub jump: Jump,
+    pub modifier_depth: usize,
+}
+
+/// 命令の番号ごとのsource map
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    entries: Vec<Entry>,
+}
+
+impl SourceMap {
+    /// `s:l:f:j:m;...`の形式を読み込む 省略された項目は直前の命令と同じ値になる
+    pub fn parse(map: &str) -> Result<Self, String> {
+        let mut entries = vec![];
+        let mut last = Entry {
+            offset: 0,
+            length: 0,
+            file: None,
+            jump: Jump::Regular,
+            modifier_depth: 0,
+        };
+        if map.is_empty() {
+            return Ok(Self { entries });
+        }
+        for item in map.split(';') {
+            let fields: Vec<&str> = item.split(':').collect();
+            let field = |i: usize| fields.get(i).copied().filter(|field| !field.is_empty());
+            let number = |field: &str| -> Result<i64, String> {
+                return field
+                    .parse()
+                    .map_err(|_| format!("invalid source map entry: {}", item));
+            };
+            if let Some(offset) = field(0) {
+                last.offset = number(offset)?.max(0) as usize;
+            }
+            if let Some(length) = field(1) {
+                last.length = number(length)?.max(0) as usize;
+            }
+            if let Some(file) = field(2) {
+                let file = number(file)?;
+                last.file = if file < 0 { None } else { Some(file as usize) };
+            }
+            if let Some(jump) = field(3) {
+                last.jump = match jump {
+                    "i" => Jump::In,
+                    "o" => Jump::Out,
+                    "-" => Jump::Regular,
+                    _ => return Err(format!("invalid jump type: {}", jump)),
+                };
+            }
+            if let Some(depth) = field(4) {
+                last.modifier_depth = number(depth)?.max(0) as usize;
+            }
+            entries.push(last);
+        }
+        return Ok(Self { entries });
+    }
+}
+
+/// 実行中のコードに対応するコントラクトの名前とsource map<br/>
+/// pcから命令の番号を引く表をコードごとに一度だけ作っておく
+#[derive(Debug)]
+pub struct CodeMap {
+    name: String,
+    map: SourceMap,
+    indices: Vec<Option<usize>>, // pcごとの命令の番号 PUSHの引数の位置はNone
+}
+
+impl CodeMap {
+    fn new(name: &str, map: &SourceMap, code: &[u8]) -> Self {
+        let mut indices = vec![None; code.len()];
+        let mut pc = 0;
+        let mut index = 0;
+        while pc < code.len() {
+            indices[pc] = Some(index);
+            pc += 1;
+            if (0x60..=0x7f).contains(&code[pc - 1]) {
+                pc += (code[pc - 1] - 0x5f) as usize;
+            }
+            index += 1;
+        }
+        return Self {
+            name: name.to_string(),
+            map: map.clone(),
+            indices,
+        };
+    }
+
+    /// コントラクトの名前
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// pcにある命令のsource map
+    pub fn entry(&self, pc: usize) -> Option<&Entry> {
+        return self.map.entries.get((*self.indices.get(pc)?)?);
+    }
+}
+
+/// pcにある命令がコードの何番目の命令か pcが命令の途中(PUSHの引数)ならNone
+pub fn instruction_index(code: &[u8], pc: usize) -> Option<usize> {
+    let mut i = 0;
+    let mut index = 0;
+    while i < pc {
+        let opcode = code.get(i)?;
+        i += 1;
+        if (0x60..=0x7f).contains(opcode) {
+            i += (opcode - 0x5f) as usize;
+        }
+        index += 1;
+    }
+    return if i == pc { Some(index) } else { None };
+}
+
+/// ソースファイル
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: String,
+    pub content: String,
+    line_starts: Vec<usize>, // 各行の先頭のバイト位置
+}
+
+impl SourceFile {
+    pub fn new(path: String, content: String) -> Self {
+        let mut line_starts = vec![0];
+        for (i, byte) in content.bytes().enumerate() {
+            if byte == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        return Self {
+            path,
+            content,
+            line_starts,
+        };
+    }
+
+    /// offsetの行と列(どちらも1から始まる)
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        return (line + 1, offset - self.line_starts[line] + 1);
+    }
+
+    /// line行目(1から始まる)の内容
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.content.len());
+        return self.content[start..end].trim_end();
+    }
+}
+
+/// pcに対応するソースの位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub jump: Jump,
+    pub text: String, // その行の内容
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path, self.line, self.column)
+    }
+}
+
+/// 1つのコントラクトのコードとsource map
+#[derive(Debug, Clone)]
+struct Contract {
+    name: String,
+    creation: Option<(Vec<u8>, SourceMap)>, // 初期化コード
+    runtime: Option<(Vec<u8>, SourceMap)>,  // デプロイされるコード
+    immutables: Vec<(usize, usize)>, // ランタイムコードのimmutableの値の(位置, 長さ) デプロイ時に書き換えられる
+}
+
+impl Contract {
+    /// デプロイされたコードcodeがランタイムコードruntimeとimmutableの値を除いて一致するか
+    fn matches_runtime(&self, runtime: &[u8], code: &[u8]) -> bool {
+        if runtime.len() != code.len() {
+            return false;
+        }
+        let mut mask = vec![false; code.len()];
+        for &(start, length) in &self.immutables {
+            for masked in mask.iter_mut().skip(start).take(length) {
+                *masked = true;
+            }
+        }
+        return runtime
+            .iter()
+            .zip(code)
+            .zip(mask)
+            .all(|((a, b), masked)| masked || a == b);
+    }
+}
+
+/// 読み込んだコントラクトとソースファイル
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    files: BTreeMap<usize, SourceFile>, // idごとのソースファイル
+    contracts: Vec<Contract>,
+    code_maps: RefCell<HashMap<H256, Option<Rc<CodeMap>>>>, // コードのハッシュごとのcode_mapの結果
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// ファイルの形式を判定して読み込む<br/>
+    /// ソースの内容がファイルに含まれていなければ、そのファイルのディレクトリとカレントディレクトリから探す
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+        let base = Path::new(path).parent().unwrap_or(Path::new("."));
+        return self.load_json(&json, base);
+    }
+
+    /// standard JSONの出力、Hardhatのbuild-info、Foundryのartifactを読み込む
+    pub fn load_json(&mut self, json: &Value, base: &Path) -> Result<(), String> {
+        if json["output"].is_object() {
+            // Hardhatのbuild-infoはstandard JSONの入力と出力を持つ
+            return self.load_standard_json(&json["output"], &json["input"], base);
+        }
+        if json["contracts"].is_object() {
+            return self.load_standard_json(json, &Value::Null, base);
+        }
+        if json["deployedBytecode"].is_object() {
+            return self.load_foundry(json, base);
+        }
+        return Err("unknown format: expected standard JSON output or artifact".to_string());
+    }
+
+    /// outputはstandard JSONの出力、inputはあればその入力
+    fn load_standard_json(
+        &mut self,
+        output: &Value,
+        input: &Value,
+        base: &Path,
+    ) -> Result<(), String> {
+        if let Some(sources) = output["sources"].as_object() {
+            for (path, source) in sources {
+                let id = source["id"].as_u64().ok_or("source id is missing")? as usize;
+                let content = input["sources"][path]["content"].as_str();
+                self.add_file(id, path, content, base);
+            }
+        }
+        let contracts = output["contracts"]
+            .as_object()
+            .ok_or("contracts is missing")?;
+        for (path, contracts) in contracts {
+            for (name, contract) in contracts.as_object().into_iter().flatten() {
+                let evm = &contract["evm"];
+                self.contracts.push(Contract {
+                    name: format!("{}:{}", path, name),
+                    creation: code_and_map(&evm["bytecode"])?,
+                    runtime: code_and_map(&evm["deployedBytecode"])?,
+                    immutables: immutable_ranges(&evm["deployedBytecode"]),
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// Foundryのartifact ソースファイルはコントラクトのファイルのみ対応する
+    fn load_foundry(&mut self, json: &Value, base: &Path) -> Result<(), String> {
+        let path = json["ast"]["absolutePath"].as_str();
+        if let (Some(id), Some(path)) = (json["id"].as_u64(), path) {
+            self.add_file(id as usize, path, None, base);
+        }
+        self.contracts.push(Contract {
+            name: path.unwrap_or("").to_string(),
+            creation: code_and_map(&json["bytecode"])?,
+            runtime: code_and_map(&json["deployedBytecode"])?,
+            immutables: immutable_ranges(&json["deployedBytecode"]),
+        });
+        return Ok(());
+    }
+
+    fn add_file(&mut self, id: usize, path: &str, content: Option<&str>, base: &Path) {
+        let content = match content {
+            Some(content) => Some(content.to_string()),
+            None => fs::read_to_string(base.join(path))
+                .or_else(|_| fs::read_to_string(path))
+                .ok(),
+        };
+        if let Some(content) = content {
+            self.files
+                .insert(id, SourceFile::new(path.to_string(), content));
+        }
+    }
+
+    /// 初期化コードとランタイムコードとsource mapを直接追加する
+    pub fn add_contract(
+        &mut self,
+        name: &str,
+        creation: Option<(Vec<u8>, SourceMap)>,
+        runtime: Option<(Vec<u8>, SourceMap)>,
+    ) {
+        self.contracts.push(Contract {
+            name: name.to_string(),
+            creation,
+            runtime,
+            immutables: vec![],
+        });
+    }
+
+    /// idのソースファイルを直接追加する
+    pub fn add_source(&mut self, id: usize, path: &str, content: &str) {
+        self.files
+            .insert(id, SourceFile::new(path.to_string(), content.to_string()));
+    }
+
+    /// 実行中のコードに対応するコントラクトの名前とsource map<br/>
+    /// ランタイムコードはimmutableの値の位置を除いて一致するものを探す<br/>
+    /// 初期化コードはコンストラクタの引数が後ろに連結されるので前方が一致するものを探す<br/>
+    /// どちらも一致しなければNone
+    fn find(&self, code: &[u8]) -> Option<(&str, &SourceMap)> {
+        let runtime = || {
+            self.contracts.iter().find_map(|contract| {
+                let (runtime, map) = contract.runtime.as_ref()?;
+                contract
+                    .matches_runtime(runtime, code)
+                    .then_some((contract.name.as_str(), map))
+            })
+        };
+        let creation = || {
+            self.contracts.iter().find_map(|contract| {
+                let (creation, map) = contract.creation.as_ref()?;
+                (!creation.is_empty() && code.starts_with(creation))
+                    .then_some((contract.name.as_str(), map))
+            })
+        };
+        return runtime().or_else(creation);
+    }
+
+    /// code_hashのコードcodeに対応するコントラクトの名前とsource map<br/>
+    /// 結果はコードのハッシュごとにキャッシュするので、命令ごとに呼んでもコードを探し直さない
+    pub fn code_map(&self, code_hash: H256, code: &[u8]) -> Option<Rc<CodeMap>> {
+        if let Some(code_map) = self.code_maps.borrow().get(&code_hash) {
+            return code_map.clone();
+        }
+        let code_map = self
+            .find(code)
+            .map(|(name, map)| Rc::new(CodeMap::new(name, map, code)));
+        self.code_maps
+            .borrow_mut()
+            .insert(code_hash, code_map.clone());
+        return code_map;
+    }
+
+    /// 実行中のコードに対応するコントラクトの名前
+    pub fn contract_name(&self, code: &[u8]) -> Option<&str> {
+        return self.find(code).map(|(name, _)| name);
+    }
+
+    /// 読み込んだコントラクトのランタイムコード
+    pub fn runtime_codes(&self) -> Vec<&[u8]> {
+        return self
+            .contracts
+            .iter()
+            .filter_map(|contract| contract.runtime.as_ref())
+            .map(|(code, _)| code.as_slice())
+            .filter(|code| !code.is_empty())
+            .collect();
+    }
+
+    /// codeのすべての命令のうちソースの位置がわかるものの(pc, 位置)
+    pub fn locate_all(&self, code: &[u8]) -> Vec<(usize, Location)> {
+        let map = match self.find(code) {
+            Some((_, map)) => map,
+            None => return vec![],
+        };
+        let mut locations = vec![];
+        let mut pc = 0;
+        for entry in &map.entries {
+            if pc >= code.len() {
+                break;
+            }
+            if let Some(location) = self.location(entry) {
+                locations.push((pc, location));
+            }
+            pc += 1;
+            if (0x60..=0x7f).contains(&code[pc - 1]) {
+                pc += (code[pc - 1] - 0x5f) as usize;
+            }
+        }
+        return locations;
+    }
+
+    /// 実行中のコードcodeのpcにある命令のソースの位置
+    pub fn locate(&self, code: &[u8], pc: usize) -> Option<Location> {
+        let code_map = self.code_map(H256::from(util::keccak256(code)), code)?;
+        return self.locate_in(&code_map, pc);
+    }
+
+    /// code_mapのコードのpcにある命令のソースの位置
+    pub fn locate_in(&self, code_map: &CodeMap, pc: usize) -> Option<Location> {
+        return self.location(code_map.entry(pc)?);
+    }
+
+    fn location(&self, entry: &Entry) -> Option<Location> {
+        let file = self.files.get(&entry.file?)?;
+        if entry.offset > file.content.len() {
+            return None;
+        }
+        let (line, column) = file.line_column(entry.offset);
+        return Some(Location {
+            path: file.path.clone(),
+            line,
+            column,
+            jump: entry.jump,
+            text: file.line(line).trim().to_string(),
+        });
+    }
+}
+
+/// 最後に実行した命令を覚えておくInspector<br/>
+/// トランザクションが失敗したとき、最後の命令が失敗の原因となったREVERTや例外停止した命令になる
+#[derive(Debug, Clone, Default)]
+pub struct LastStep {
+    sources: Sources,
+    code_hash: H256,
+    code: Vec<u8>,
+    pc: usize,
+}
+
+impl LastStep {
+    pub fn new(sources: Sources) -> Self {
+        return Self {
+            sources,
+            ..Self::default()
+        };
+    }
+
+    /// 最後に実行した命令のソースの位置
+    pub fn location(&self) -> Option<Location> {
+        let code_map = self.sources.code_map(self.code_hash, &self.code)?;
+        return self.sources.locate_in(&code_map, self.pc);
+    }
+}
+
+impl Inspector for LastStep {
+    /// コードはハッシュが変わったときだけ複製する
+    fn step(&mut self, vm: &VM, _ws: &WorldState) {
+        if self.code_hash != vm.code_hash() {
+            self.code_hash = vm.code_hash();
+            self.code = vm.code().to_vec();
+        }
+        self.pc = vm.pc();
+    }
+}
+
+/// `{"object": "...", "sourceMap": "..."}`からコードとsource mapを読む<br/>
+/// ライブラリのリンクのプレースホルダは0として扱う
+fn code_and_map(bytecode: &Value) -> Result<Option<(Vec<u8>, SourceMap)>, String> {
+    let object = match &bytecode["object"] {
+        Value::String(object) => object.as_str(),
+        // Foundryのartifactはobjectが無いこともある
+        _ => match bytecode.as_str() {
+            Some(object) => object,
+            None => return Ok(None),
+        },
+    };
+    let map = match bytecode["sourceMap"].as_str() {
+        Some(map) => SourceMap::parse(map)?,
+        None => return Ok(None),
+    };
+    return Ok(Some((decode_object(object)?, map)));
+}
+
+/// `{"immutableReferences": {"id": [{"start": .., "length": ..}]}}`からimmutableの値の(位置, 長さ)を読む
+fn immutable_ranges(bytecode: &Value) -> Vec<(usize, usize)> {
+    let references = bytecode["immutableReferences"].as_object();
+    return references
+        .into_iter()
+        .flat_map(|references| references.values())
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(|range| {
+            let start = range["start"].as_u64()? as usize;
+            let length = range["length"].as_u64()? as usize;
+            Some((start, length))
+        })
+        .collect();
+}
+
+/// 16進文字列のバイトコード `__$...$__`のようなリンクのプレースホルダは0に置き換える
+pub fn decode_object(object: &str) -> Result<Vec<u8>, String> {
+    let mut hex = object.strip_prefix("0x").unwrap_or(object).to_string();
+    while let Some(start) = hex.find("__") {
+        let end = (start + 40).min(hex.len());
+        hex.replace_range(start..end, &"0".repeat(end - start));
+    }
+    return hex::decode(&hex).map_err(|e| format!("invalid bytecode: {}", e));
+}
+
+#[test]
+fn test_parse_source_map() {
+    let map = SourceMap::parse("1:2:1;:9;2:1:2:i;;3::-1:o:1").unwrap();
+    let entries = &map.entries;
+    assert_eq!(entries.len(), 5);
+    assert_eq!((entries[1].offset, entries[1].length), (1, 9));
+    assert_eq!(entries[1].file, Some(1));
+    assert_eq!(entries[2].jump, Jump::In);
+    assert_eq!(entries[3], entries[2]);
+    assert_eq!(entries[4].file, None);
+    assert_eq!(entries[4].length, 1);
+    assert_eq!(entries[4].jump, Jump::Out);
+    assert_eq!(entries[4].modifier_depth, 1);
+    assert!(SourceMap::parse("1:2:1:x").is_err());
+}
+
+#[test]
+fn test_locate() {
+    // PUSH1 0x01, PUSH1 0x00, SSTORE, STOP
+    let code = hex::decode("600160005500").unwrap();
+    let source = "contract C {\n    function f() public {\n        x = 1;\n    }\n}\n";
+    let x = source.find("x = 1").unwrap();
+    let map = format!("0:60:0;{}:5;{}:5:0:o;0:60", x, x);
+    let mut sources = Sources::new();
+    sources.add_source(0, "C.sol", source);
+    sources.add_contract(
+        "C.sol:C",
+        None,
+        Some((code.clone(), SourceMap::parse(&map).unwrap())),
+    );
+
+    assert_eq!(instruction_index(&code, 4), Some(2));
+    assert_eq!(instruction_index(&code, 1), None);
+    let location = sources.locate(&code, 4).unwrap();
+    assert_eq!(location.to_string(), "C.sol:3:9");
+    assert_eq!(location.text, "x = 1;");
+    assert_eq!(location.jump, Jump::Out);
+    assert_eq!(sources.locate(&code, 0).unwrap().line, 1);
+    assert_eq!(sources.contract_name(&code), Some("C.sol:C"));
+    assert!(sources.locate(&[0x00], 0).is_none());
+}
+
+#[test]
+fn test_code_map() {
+    // PUSH1 0x01, PUSH32 <immutable>, STOP
+    let runtime = format!("6001{}{}00", "7f", "00".repeat(32));
+    let artifact = serde_json::json!({
+        "bytecode": { "object": "0x00", "sourceMap": "0:1:0" },
+        "deployedBytecode": {
+            "object": runtime,
+            "sourceMap": "0:1:0;2:3:0;5:1:0",
+            "immutableReferences": { "7": [{ "start": 3, "length": 32 }] }
+        },
+        "ast": { "absolutePath": "I.sol" },
+        "id": 0
+    });
+    let mut sources = Sources::new();
+    sources.load_json(&artifact, Path::new(".")).unwrap();
+    sources.add_source(0, "I.sol", "contract I {}");
+
+    // immutableの値だけが異なるコードは同じコントラクト
+    let mut deployed = hex::decode(&runtime).unwrap();
+    deployed[34] = 0x2a;
+    let hash = H256::from(util::keccak256(&deployed));
+    let code_map = sources.code_map(hash, &deployed).unwrap();
+    assert_eq!(code_map.name(), "I.sol");
+    assert_eq!(code_map.entry(2).unwrap().offset, 2);
+    assert_eq!(code_map.entry(35).unwrap().offset, 5);
+    assert!(code_map.entry(3).is_none());
+    // 同じハッシュのコードは作り直さない
+    assert!(Rc::ptr_eq(
+        &code_map,
+        &sources.code_map(hash, &deployed).unwrap()
+    ));
+
+    // 長さが同じでもimmutable以外が異なるコードは対応させない
+    deployed[0] = 0x61;
+    assert!(sources.contract_name(&deployed).is_none());
+    assert!(sources.locate(&deployed, 0).is_none());
+}
+
+#[test]
+fn test_decode_object() {
+    let object = format!("0x73{}3b", "__$0123456789abcdef0123456789abcdef01$__");
+    let code = decode_object(&object).unwrap();
+    assert_eq!(code.len(), 22);
+    assert_eq!(code[21], 0x3b);
+}
+
+#[test]
+fn test_last_step() {
+    use super::transaction::{self, Transaction};
+    use ethereum_types::H160;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // PUSH1 0x00, DUP1, REVERT
+    let code = hex::decode("600080fd").unwrap();
+    let source = "contract C {\n    function f() public {\n        revert();\n    }\n}\n";
+    let revert = source.find("revert()").unwrap();
+    let map = format!("0:60:0;;{}:8", revert);
+    let mut sources = Sources::new();
+    sources.add_source(0, "C.sol", source);
+    sources.add_contract(
+        "C.sol:C",
+        None,
+        Some((code.clone(), SourceMap::parse(&map).unwrap())),
+    );
+
+    let mut ws = test_world_state(&[(0xbb, &hex::encode(&code))]);
+    let last = Rc::new(RefCell::new(LastStep::new(sources)));
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let receipt = transaction::execute_with_inspector(&mut ws, &tx, Some(last.clone())).unwrap();
+    assert!(!receipt.status.is_success());
+    let location = last.borrow().location().unwrap();
+    assert_eq!(location.to_string(), "C.sol:3:9");
+    assert_eq!(location.text, "revert();");
+}
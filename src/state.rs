@@ -3,17 +3,71 @@
 //! Ethereumにおけるステートを表現するモジュール
 use super::util;
 use sha3::{Digest, Sha3_256};
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::io::prelude::*;
 
-use ethereum_types::{H160, U256};
+use ethereum_types::{H160, H256, U256};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct WorldState {
     addresses: HashMap<H160, AccountState>,
     hash: String,
+    #[serde(skip)]
+    substate: Substate, // 実行中のトランザクションの一時的な状態
+    #[serde(skip)]
+    journal: Vec<JournalEntry>, // 実行中のトランザクションで行った変更 失敗した呼び出しの巻き戻しに使う
+}
+
+/// トランザクションの実行中だけ保持される状態(Yellow Paperにおけるaccrued substate)
+///
+/// トランザクションが終了すると`WorldState::finalize_transaction`で破棄される
+#[derive(Debug, Default, Clone)]
+pub struct Substate {
+    pub selfdestructs: HashSet<H160>, // トランザクション終了時に削除されるアカウント
+    pub created: HashSet<H160>,       // このトランザクションで作成されたアカウント
+    pub refund: usize,                // トランザクション終了時に返還されるgas
+    pub transient: HashMap<(H160, U256), U256>, // transient storage(EIP-1153)
+    pub logs: Vec<Log>,               // LOG命令で出力されたログ
+    pub accessed_addresses: HashSet<H160>, // アクセスしたアカウント(EIP-2929)
+    pub accessed_slots: HashSet<(H160, U256)>, // アクセスしたstorageのslot(EIP-2929)
+    pub original_storage: HashMap<(H160, U256), U256>, // トランザクション開始時のstorageの値(EIP-2200)
+}
+
+/// 巻き戻すためにjournalに記録するワールドステートへの変更 値は変更前のもの
+#[derive(Debug, Clone)]
+enum JournalEntry {
+    AccountCreated(H160),
+    Balance(H160, U256),
+    Nonce(H160, usize),
+    Storage(H160, U256, U256),
+    Transient((H160, U256), Option<U256>),
+    Refund(usize),
+    Log,
+    AddressAccessed(H160),
+    SlotAccessed((H160, U256)),
+    Selfdestruct(H160),
+    Created(H160),
+}
+
+/// アカウントの一部を書き換える指定(eth_callのstate override)<br/>
+/// Noneの項目は元のまま stateはstorage全体を置き換え、state_diffは指定したslotだけを書き換える
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<usize>,
+    pub code: Option<Vec<u8>>,
+    pub state: Option<Vec<(U256, U256)>>,
+    pub state_diff: Vec<(U256, U256)>,
+}
+
+/// LOG命令で出力されるログ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    pub address: H160,     // ログを出力したアカウント
+    pub topics: Vec<H256>, // インデックスされるトピック(最大4つ)
+    pub data: Vec<u8>,     // ログのデータ
 }
 
 impl WorldState {
@@ -30,11 +84,35 @@ impl WorldState {
         return ws;
     }
 
+    /// ファイルからワールドステートを読み込む
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        return serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e));
+    }
+
+    /// ワールドステートをファイルに保存する<br/>
+    /// 書き込みの途中で失敗しても元のファイルが壊れないように、一時ファイルに書いてから置き換える
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let tmp = format!("{}.tmp", path);
+        let write = || -> std::io::Result<()> {
+            let mut file = File::create(&tmp)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp, path)
+        };
+        return write().map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("{}: {}", path, e)
+        });
+    }
+
     pub fn update_state(&mut self) {
         // worldstateをアカウントから計算する
+        // HashMapの順序は実行ごとに変わるので、アドレス順に連結する
         let mut account_hashs = Vec::with_capacity(self.addresses.len());
-        for (_, val) in &self.addresses {
-            account_hashs.push(val.calc_hash());
+        for address in self.addresses() {
+            account_hashs.push(self.addresses[&address].calc_hash());
         }
         self.hash = self.calc_hash(account_hashs);
     }
@@ -61,9 +139,239 @@ impl WorldState {
             .expect("key is not found in storage.");
         return account_state;
     }
+
+    /// アカウントを参照する 存在しなければNone
+    pub fn account(&self, address: &H160) -> Option<&AccountState> {
+        self.addresses.get(address)
+    }
+
+    /// アカウントを取得する 存在しなければ空のアカウントを作成する
+    pub fn get_or_create_account_state(&mut self, address: &H160) -> &mut AccountState {
+        return self.addresses.entry(*address).or_default();
+    }
+
+    /// 存在するアカウントのアドレス
+    pub fn addresses(&self) -> Vec<H160> {
+        let mut addresses: Vec<H160> = self.addresses.keys().copied().collect();
+        addresses.sort();
+        return addresses;
+    }
+
+    /// アカウントが存在するか
+    pub fn exists(&self, address: &H160) -> bool {
+        self.addresses.contains_key(address)
+    }
+
+    /// アカウントをoverrideで書き換える 存在しなければ作成する
+    pub fn override_account(&mut self, address: &H160, account_override: &AccountOverride) {
+        let account = self.get_or_create_account_state(address);
+        if let Some(balance) = account_override.balance {
+            account.set_balance(balance);
+        }
+        if let Some(nonce) = account_override.nonce {
+            account.set_nonce(nonce);
+        }
+        if let Some(code) = &account_override.code {
+            account.set_code(code);
+        }
+        if let Some(state) = &account_override.state {
+            account.clear_storage();
+            for (key, value) in state {
+                account.set_storage(*key, *value);
+            }
+        }
+        for (key, value) in &account_override.state_diff {
+            account.set_storage(*key, *value);
+        }
+    }
+
+    /// アカウントを削除する
+    pub fn remove_account_state(&mut self, address: &H160) -> Option<AccountState> {
+        self.addresses.remove(address)
+    }
+
+    /// 巻き戻せるようにfromからtoにvalueを送金する toが存在しなければ作成する
+    pub fn transfer(&mut self, from: &H160, to: &H160, value: U256) {
+        let balance = self.get_account_state(from).get_balance();
+        self.set_balance(from, balance - value);
+        let balance = self.journaled_account(to).get_balance();
+        self.set_balance(to, balance + value);
+    }
+
+    /// getter for substate
+    pub fn substate(&mut self) -> &mut Substate {
+        &mut self.substate
+    }
+
+    /// substateを参照する
+    pub fn get_substate(&self) -> &Substate {
+        &self.substate
+    }
+
+    /// 現在の変更の位置 revert_toに渡すとここまで巻き戻せる
+    pub fn checkpoint(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// checkpointの後に行った変更を新しいものから順に取り消す
+    pub fn revert_to(&mut self, checkpoint: usize) {
+        while self.journal.len() > checkpoint {
+            match self.journal.pop().unwrap() {
+                JournalEntry::AccountCreated(address) => {
+                    self.addresses.remove(&address);
+                }
+                JournalEntry::Balance(address, balance) => {
+                    self.get_account_state(&address).set_balance(balance);
+                }
+                JournalEntry::Nonce(address, nonce) => {
+                    self.get_account_state(&address).set_nonce(nonce);
+                }
+                JournalEntry::Storage(address, key, value) => {
+                    self.get_account_state(&address).set_storage(key, value);
+                }
+                JournalEntry::Transient(slot, value) => match value {
+                    Some(value) => {
+                        self.substate.transient.insert(slot, value);
+                    }
+                    None => {
+                        self.substate.transient.remove(&slot);
+                    }
+                },
+                JournalEntry::Refund(refund) => self.substate.refund = refund,
+                JournalEntry::Log => {
+                    self.substate.logs.pop();
+                }
+                JournalEntry::AddressAccessed(address) => {
+                    self.substate.accessed_addresses.remove(&address);
+                }
+                JournalEntry::SlotAccessed(slot) => {
+                    self.substate.accessed_slots.remove(&slot);
+                }
+                JournalEntry::Selfdestruct(address) => {
+                    self.substate.selfdestructs.remove(&address);
+                }
+                JournalEntry::Created(address) => {
+                    self.substate.created.remove(&address);
+                }
+            }
+        }
+    }
+
+    /// 巻き戻せるように変更を記録してアカウントを取得する 存在しなければ作成する
+    fn journaled_account(&mut self, address: &H160) -> &mut AccountState {
+        if !self.addresses.contains_key(address) {
+            self.journal.push(JournalEntry::AccountCreated(*address));
+        }
+        return self.get_or_create_account_state(address);
+    }
+
+    /// 巻き戻せるようにbalanceを書き換える アカウントが存在しなければ作成する
+    pub fn set_balance(&mut self, address: &H160, balance: U256) {
+        let old = self.journaled_account(address).get_balance();
+        self.journal.push(JournalEntry::Balance(*address, old));
+        self.get_account_state(address).set_balance(balance);
+    }
+
+    /// 巻き戻せるようにnonceを1増やす
+    pub fn increment_nonce(&mut self, address: &H160) {
+        let old = self.journaled_account(address).get_nonce();
+        self.journal.push(JournalEntry::Nonce(*address, old));
+        self.get_account_state(address).increment_nonce();
+    }
+
+    /// 巻き戻せるようにstorageを書き換える
+    pub fn set_storage(&mut self, address: &H160, key: U256, value: U256) {
+        let old = self.journaled_account(address).get_storage(&key);
+        self.journal.push(JournalEntry::Storage(*address, key, old));
+        self.get_account_state(address).set_storage(key, value);
+    }
+
+    /// storageのslotのトランザクション開始時の値(EIP-2200)<br/>
+    /// トランザクションで初めてアクセスしたときの値を覚えておく
+    pub fn original_storage(&mut self, address: &H160, key: &U256) -> U256 {
+        let current = match self.addresses.get(address) {
+            Some(account) => account.get_storage(key),
+            None => U256::zero(),
+        };
+        return *self
+            .substate
+            .original_storage
+            .entry((*address, *key))
+            .or_insert(current);
+    }
+
+    /// 巻き戻せるようにtransient storageを書き換える
+    pub fn set_transient(&mut self, slot: (H160, U256), value: U256) {
+        let old = self.substate.transient.insert(slot, value);
+        self.journal.push(JournalEntry::Transient(slot, old));
+    }
+
+    /// 巻き戻せるように返還されるgasを増やす
+    pub fn add_refund(&mut self, refund: usize) {
+        self.journal
+            .push(JournalEntry::Refund(self.substate.refund));
+        self.substate.refund += refund;
+    }
+
+    /// 巻き戻せるように返還されるgasを減らす
+    pub fn sub_refund(&mut self, refund: usize) {
+        self.journal
+            .push(JournalEntry::Refund(self.substate.refund));
+        self.substate.refund -= refund;
+    }
+
+    /// 巻き戻せるようにログを追加する
+    pub fn push_log(&mut self, log: Log) {
+        self.journal.push(JournalEntry::Log);
+        self.substate.logs.push(log);
+    }
+
+    /// アカウントへのアクセスを記録する 初めてのアクセス(cold)ならtrue(EIP-2929)
+    pub fn access_address(&mut self, address: &H160) -> bool {
+        if !self.substate.accessed_addresses.insert(*address) {
+            return false;
+        }
+        self.journal.push(JournalEntry::AddressAccessed(*address));
+        return true;
+    }
+
+    /// storageのslotへのアクセスを記録する 初めてのアクセス(cold)ならtrue(EIP-2929)
+    pub fn access_slot(&mut self, address: &H160, key: &U256) -> bool {
+        let slot = (*address, *key);
+        if !self.substate.accessed_slots.insert(slot) {
+            return false;
+        }
+        self.journal.push(JournalEntry::SlotAccessed(slot));
+        return true;
+    }
+
+    /// 巻き戻せるようにアカウントをトランザクション終了時の削除対象にする
+    pub fn mark_selfdestruct(&mut self, address: &H160) {
+        if self.substate.selfdestructs.insert(*address) {
+            self.journal.push(JournalEntry::Selfdestruct(*address));
+        }
+    }
+
+    /// 巻き戻せるようにアカウントをこのトランザクションで作成されたものとして記録する
+    pub fn mark_created(&mut self, address: &H160) {
+        if self.substate.created.insert(*address) {
+            self.journal.push(JournalEntry::Created(*address));
+        }
+    }
+
+    /// トランザクションを終了する<br/>
+    /// SELFDESTRUCTされたアカウントを削除し、substateとjournalを破棄する
+    pub fn finalize_transaction(&mut self) -> Substate {
+        self.journal.clear();
+        let substate = std::mem::take(&mut self.substate);
+        for address in &substate.selfdestructs {
+            self.addresses.remove(address);
+        }
+        return substate;
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct AccountState {
     nonce: usize,                 // ナンス
     balance: U256,                // 残高(wei)
@@ -81,10 +389,20 @@ impl AccountState {
         }
     }
 
+    /// getter for nonce
+    pub fn get_nonce(&self) -> usize {
+        self.nonce
+    }
+
     pub fn increment_nonce(&mut self) {
         self.nonce += 1;
     }
 
+    /// setter for nonce
+    pub fn set_nonce(&mut self, nonce: usize) {
+        self.nonce = nonce;
+    }
+
     /// getter for balance
     pub fn get_balance(&self) -> U256 {
         self.balance
@@ -95,39 +413,176 @@ impl AccountState {
         self.balance = balance;
     }
 
-    /// getter for storage
-    pub fn get_storage(&self, key: &U256) -> &U256 {
-        let value = self.storage.get(key).expect("key is not found in storage");
+    /// EIP-161における空のアカウント(nonce, balanceが0でコードを持たない)か
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0 && self.balance.is_zero() && self.code.is_empty()
+    }
+
+    /// getter for storage 書き込まれていないkeyの値は0
+    pub fn get_storage(&self, key: &U256) -> U256 {
+        let value = self.storage.get(key).copied().unwrap_or_default();
         return value;
     }
 
+    /// storageのすべてのkeyと値
+    pub fn get_storage_entries(&self) -> Vec<(U256, U256)> {
+        let mut entries: Vec<(U256, U256)> = self.storage.iter().map(|(k, v)| (*k, *v)).collect();
+        entries.sort();
+        return entries;
+    }
+
     /// setter for storage
     pub fn set_storage(&mut self, key: U256, value: U256) {
         self.storage.insert(key, value);
     }
 
+    /// storageをすべて消す
+    pub fn clear_storage(&mut self) {
+        self.storage.clear();
+    }
+
     /// getter for code
     pub fn get_code(&self) -> String {
         self.code.clone()
     }
 
+    /// setter for code
+    pub fn set_code(&mut self, code: &[u8]) {
+        self.code = hex::encode(code);
+    }
+
     fn calc_hash(&self) -> String {
         let nonce = self.nonce.to_string();
         let balance = self.balance.to_string();
         let mut storage = "".to_string();
-        for (key, val) in &self.storage {
+        for (key, val) in self.get_storage_entries() {
             storage += &(key.to_string() + &val.to_string());
         }
         let code = self.code.clone();
-        let hash = nonce + &balance + &storage + &code;
+        let mut hash = nonce + &balance + &storage + &code;
+        // 10進数の文字列を16進数としてデコードするので、奇数桁なら先頭を0で埋める
+        if hash.len() % 2 == 1 {
+            hash.insert(0, '0');
+        }
         return calc_hash(&util::str_to_bytes(&hash));
     }
 }
 
 fn calc_hash(bytes: &Vec<u8>) -> String {
     let mut hasher = Sha3_256::new();
-    hasher.input(&bytes); // write input message
+    hasher.input(bytes); // write input message
     let result = hasher.result(); // read hash digest
     let hash = result.as_slice();
     return util::bytes_to_str(hash.into());
 }
+
+/// テスト用のワールドステート 0xaaに残高1000の送信者を置き、contractsの(アドレス, コード)にコントラクトを置く
+#[cfg(test)]
+pub fn test_world_state(contracts: &[(u64, &str)]) -> WorldState {
+    let mut ws = WorldState::default();
+    let mut sender = AccountState::new("".to_string());
+    sender.set_balance(1000.into());
+    ws.push_account_state(H160::from_low_u64_be(0xaa), sender);
+    for (address, code) in contracts {
+        ws.push_account_state(
+            H160::from_low_u64_be(*address),
+            AccountState::new(code.to_string()),
+        );
+    }
+    return ws;
+}
+
+#[test]
+fn test_save_and_load() {
+    let path = std::env::temp_dir().join(format!("toyevm_state_{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let mut ws = WorldState::default();
+    let mut account = AccountState::new("6005600401".to_string());
+    account.set_balance(100.into());
+    account.set_storage(1.into(), 2.into());
+    ws.push_account_state(H160::from_low_u64_be(0xaa), account);
+    ws.substate().refund = 10;
+    ws.save(path).unwrap();
+
+    let mut loaded = WorldState::load(path).unwrap();
+    fs::remove_file(path).unwrap();
+    let account = loaded.get_account_state(&H160::from_low_u64_be(0xaa));
+    assert_eq!(account.get_code(), "6005600401");
+    assert_eq!(account.get_balance(), 100.into());
+    assert_eq!(account.get_storage(&1.into()), 2.into());
+    // substateは保存されない
+    assert_eq!(loaded.substate().refund, 0);
+}
+
+#[test]
+fn test_journal() {
+    let address = H160::from_low_u64_be(0xaa);
+    let other = H160::from_low_u64_be(0xbb);
+    let mut ws = test_world_state(&[]);
+    ws.set_storage(&address, 1.into(), 2.into());
+    let checkpoint = ws.checkpoint();
+
+    ws.set_storage(&address, 1.into(), 3.into());
+    ws.transfer(&address, &other, 10.into());
+    ws.add_refund(100);
+    ws.push_log(Log {
+        address,
+        topics: vec![],
+        data: vec![],
+    });
+    assert!(ws.access_address(&other));
+    assert!(!ws.access_address(&other));
+    ws.mark_selfdestruct(&address);
+
+    // checkpointより後の変更だけが取り消される
+    ws.revert_to(checkpoint);
+    let account = ws.account(&address).unwrap();
+    assert_eq!(account.get_storage(&1.into()), 2.into());
+    assert_eq!(account.get_balance(), 1000.into());
+    assert!(!ws.exists(&other));
+    let substate = ws.get_substate();
+    assert_eq!(substate.refund, 0);
+    assert!(substate.logs.is_empty());
+    assert!(substate.accessed_addresses.is_empty());
+    assert!(substate.selfdestructs.is_empty());
+}
+
+#[test]
+fn test_override_account() {
+    let address = H160::from_low_u64_be(0xaa);
+    let mut ws = WorldState::default();
+    let mut account = AccountState::new("".to_string());
+    account.set_storage(1.into(), 2.into());
+    account.set_storage(3.into(), 4.into());
+    ws.push_account_state(address, account);
+
+    let mut account_override = AccountOverride {
+        nonce: Some(7),
+        state_diff: vec![(3.into(), 5.into())],
+        ..AccountOverride::default()
+    };
+    ws.override_account(&address, &account_override);
+    let account = ws.account(&address).unwrap();
+    assert_eq!(account.get_nonce(), 7);
+    assert_eq!(
+        account.get_storage_entries(),
+        vec![(1.into(), 2.into()), (3.into(), 5.into())]
+    );
+
+    // stateはstorage全体を置き換える 存在しないアカウントは作成する
+    account_override.state = Some(vec![(9.into(), 9.into())]);
+    account_override.state_diff = vec![];
+    ws.override_account(&address, &account_override);
+    let account = ws.account(&address).unwrap();
+    assert_eq!(account.get_storage_entries(), vec![(9.into(), 9.into())]);
+    let other = H160::from_low_u64_be(0xbb);
+    ws.override_account(
+        &other,
+        &AccountOverride {
+            balance: Some(1.into()),
+            ..AccountOverride::default()
+        },
+    );
+    assert_eq!(ws.account(&other).unwrap().get_balance(), 1.into());
+}
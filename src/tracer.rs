@@ -0,0 +1,680 @@
+//! Tracer
+//!
+//! 実行した命令をEIP-3155の形式のJSONで出力するモジュール<br/>
+//! gethの`evm --json`などのトレースと行ごとに比較できる<br/>
+//! gethの`callTracer`、`prestateTracer`と同じ形式の呼び出しのツリーと実行前のステートも出力できる
+use super::abi::Abi;
+use super::inspector::{CallInputs, CallKind, CallOutcome, CreateInputs, Inspector};
+use super::opcode;
+use super::srcmap::Sources;
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::{AccountState, Log, WorldState};
+use super::transaction::{self, Receipt, Transaction};
+use super::util;
+use super::vm::{ExitStatus, VM};
+use ethereum_types::{H160, U256};
+use serde::{Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+/// 1命令分のトレース
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StepLog {
+    pc: usize,
+    op: u8,
+    #[serde(serialize_with = "serialize_hex")]
+    gas: usize, // 実行前の残りのgas
+    #[serde(serialize_with = "serialize_hex")]
+    gas_cost: usize, // 命令で消費したgas CALL系命令では呼び出し先で消費した分を含む
+    mem_size: usize,
+    stack: Vec<String>, // 末尾がスタックのトップ
+    depth: usize,       // 1から始まる
+    refund: usize,
+    op_name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>, // source mapがあれば`path:line:column`
+}
+
+/// トランザクションの最後に出力する要約
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Summary {
+    state_root: String,
+    output: String,
+    gas_used: String,
+    pass: bool,
+    fork: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// 命令の実行前と実行後の通知からトレースを集める<br/>
+/// gasCostは命令の実行後にしか決まらないので、出力はトランザクションの終了後にまとめて行う
+#[derive(Debug, Clone, Default)]
+pub struct JsonTracer {
+    steps: Vec<StepLog>,
+    pending: Vec<usize>, // 実行中の命令のインデックス CALL系命令の実行中は呼び出し先の命令が積まれる
+    sources: Sources,
+}
+
+impl JsonTracer {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// 命令ごとにsourcesから求めたソースの位置も出力する
+    pub fn with_sources(sources: Sources) -> Self {
+        return Self {
+            sources,
+            ..Self::default()
+        };
+    }
+
+    /// 命令ごとのJSONと要約の行を返す
+    pub fn finish(self, ws: &mut WorldState, receipt: &Receipt, tx: &Transaction) -> Vec<String> {
+        let error = match &receipt.status {
+            ExitStatus::Success => None,
+            ExitStatus::Revert => Some("execution reverted".to_string()),
+            ExitStatus::Halt(reason) => Some(reason.clone()),
+        };
+
+        ws.update_state();
+        let summary = Summary {
+            state_root: format!("0x{}", ws.get_hash()),
+            output: format!("0x{}", hex::encode(&receipt.output)),
+            gas_used: format!("0x{:x}", receipt.gas_used),
+            pass: receipt.status.is_success(),
+            fork: format!("{:?}", tx.fork),
+            error,
+        };
+        let mut lines: Vec<String> = self
+            .steps
+            .iter()
+            .map(|step| serde_json::to_string(step).unwrap())
+            .collect();
+        lines.push(serde_json::to_string(&summary).unwrap());
+        return lines;
+    }
+}
+
+impl Inspector for JsonTracer {
+    /// 命令の実行前の状態を記録する
+    fn step(&mut self, vm: &VM, ws: &WorldState) {
+        let op = vm.opcode().unwrap_or(0);
+        self.pending.push(self.steps.len());
+        self.steps.push(StepLog {
+            pc: vm.pc(),
+            op,
+            gas: vm.gas(),
+            gas_cost: 0,
+            mem_size: vm.memory().len(),
+            stack: vm.stack().iter().map(|v| format!("0x{:x}", v)).collect(),
+            depth: vm.depth() + 1,
+            refund: ws.get_substate().refund,
+            op_name: opcode::info(op).map(|info| info.name).unwrap_or("INVALID"),
+            error: None,
+            source: self
+                .sources
+                .code_map(vm.code_hash(), vm.code())
+                .and_then(|code_map| self.sources.locate_in(&code_map, vm.pc()))
+                .map(|location| location.to_string()),
+        });
+    }
+
+    /// 命令の実行後の残りのgasから消費したgasを求める
+    fn step_end(&mut self, vm: &VM, _ws: &WorldState) {
+        if let Some(index) = self.pending.pop() {
+            let step = &mut self.steps[index];
+            step.gas_cost = step.gas - vm.gas();
+        }
+    }
+
+    /// 例外停止した命令は残りのgasをすべて消費し、停止の理由を記録する
+    fn step_halt(&mut self, _vm: &VM, _ws: &WorldState, reason: &str) {
+        if let Some(index) = self.pending.pop() {
+            let step = &mut self.steps[index];
+            step.gas_cost = step.gas;
+            step.error = Some(reason.to_string());
+        }
+    }
+}
+
+/// トランザクションを実行し、EIP-3155のトレースを返す sourcesがあれば命令ごとのソースの位置も含める
+pub fn trace(
+    ws: &mut WorldState,
+    tx: &Transaction,
+    sources: Sources,
+) -> Result<(Receipt, Vec<String>), String> {
+    let tracer = Rc::new(RefCell::new(JsonTracer::with_sources(sources)));
+    let receipt = transaction::execute_with_inspector(ws, tx, Some(tracer.clone()))?;
+    let lines = tracer.take().finish(ws, &receipt, tx);
+    return Ok((receipt, lines));
+}
+
+/// callTracerの1つの呼び出し
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    from: String,
+    to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>, // DELEGATECALL、STATICCALLにはない
+    gas: String,
+    gas_used: String,
+    input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revert_reason: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    logs: Vec<CallLog>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    calls: Vec<CallFrame>,
+    #[serde(skip)]
+    revert_data: Option<Vec<u8>>, // 実行後にデコードするrevertデータ
+}
+
+/// callTracerのログ positionはログより前に呼び出した子の数
+#[derive(Debug, Clone, Serialize)]
+struct CallLog {
+    address: String,
+    topics: Vec<String>,
+    data: String,
+    position: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<String>, // ABIでデコードしたイベント
+    #[serde(skip)]
+    raw: Log, // 実行後にデコードするログ
+}
+
+impl CallFrame {
+    fn new(kind: &'static str, from: H160, to: H160, value: Option<U256>, gas: usize) -> Self {
+        return Self {
+            kind,
+            from: hex_address(&from),
+            to: hex_address(&to),
+            value: value.map(|value| format!("0x{:x}", value)),
+            gas: format!("0x{:x}", gas),
+            gas_used: "0x0".to_string(),
+            input: "0x".to_string(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            logs: vec![],
+            calls: vec![],
+            revert_data: None,
+        };
+    }
+
+    /// 呼び出しの結果を設定する 失敗した呼び出しのログは呼び出し先も含めて取り除く
+    fn end(&mut self, outcome: &CallOutcome) {
+        self.gas_used = format!("0x{:x}", outcome.gas_used);
+        if !outcome.output.is_empty() {
+            self.output = Some(format!("0x{}", hex::encode(&outcome.output)));
+        }
+        match &outcome.status {
+            ExitStatus::Success => {}
+            ExitStatus::Revert => {
+                self.error = Some("execution reverted".to_string());
+                self.revert_data = Some(outcome.output.clone());
+            }
+            ExitStatus::Halt(reason) => self.error = Some(reason.clone()),
+        }
+        if !outcome.status.is_success() {
+            self.clear_logs();
+        }
+    }
+
+    /// 実行の後でログとrevertデータをabiでデコードする<br/>
+    /// 実行中にデコードしないので、デコードの結果が実行に影響することはない
+    fn decode(&mut self, abi: &Abi) {
+        if let Some(data) = &self.revert_data {
+            self.revert_reason = abi.decode_revert(data);
+        }
+        for log in self.logs.iter_mut() {
+            log.event = abi
+                .decode_log(&log.raw.topics, &log.raw.data)
+                .map(|decoded| decoded.to_string());
+        }
+        for call in self.calls.iter_mut() {
+            call.decode(abi);
+        }
+    }
+
+    fn clear_logs(&mut self) {
+        self.logs.clear();
+        for call in self.calls.iter_mut() {
+            call.clear_logs();
+        }
+    }
+}
+
+/// gethのcallTracerと同じ形式の呼び出しのツリーを作る<br/>
+/// 失敗した呼び出しの中で出力されたログは含めない
+#[derive(Debug, Clone, Default)]
+pub struct CallTracer {
+    frames: Vec<CallFrame>, // 実行中の呼び出し 末尾が最も深い
+    root: Option<CallFrame>,
+    abi: Abi, // ログとrevertのデコードに使う
+}
+
+impl CallTracer {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// abiのイベントとエラーでログとrevertデータもデコードする
+    pub fn with_abi(abi: Abi) -> Self {
+        return Self {
+            abi,
+            ..Self::default()
+        };
+    }
+
+    fn enter(&mut self, frame: CallFrame) {
+        self.frames.push(frame);
+    }
+
+    fn exit(&mut self, outcome: &CallOutcome) {
+        let mut frame = self.frames.pop().unwrap();
+        frame.end(outcome);
+        match self.frames.last_mut() {
+            Some(parent) => parent.calls.push(frame),
+            None => self.root = Some(frame),
+        }
+    }
+
+    /// トランザクションの呼び出しのツリーを返す<br/>
+    /// 最上位の呼び出しのgasはトランザクションのgas上限、gasUsedはintrinsic gasを含む使用gasになる<br/>
+    /// イベントとrevertReasonはここでデコードする
+    pub fn finish(self, receipt: &Receipt, tx: &Transaction) -> Value {
+        let mut root = self.root.unwrap();
+        root.decode(&self.abi);
+        root.gas = format!("0x{:x}", tx.gas);
+        root.gas_used = format!("0x{:x}", receipt.gas_used);
+        return serde_json::to_value(root).unwrap();
+    }
+}
+
+impl Inspector for CallTracer {
+    fn call(&mut self, inputs: &CallInputs) {
+        let (kind, value) = match inputs.kind {
+            CallKind::Call => ("CALL", Some(inputs.value)),
+            CallKind::CallCode => ("CALLCODE", Some(inputs.value)),
+            CallKind::DelegateCall => ("DELEGATECALL", None),
+            CallKind::StaticCall => ("STATICCALL", None),
+        };
+        let mut frame = CallFrame::new(kind, inputs.caller, inputs.to, value, inputs.gas);
+        frame.input = format!("0x{}", hex::encode(&inputs.input));
+        self.enter(frame);
+    }
+
+    fn call_end(&mut self, _inputs: &CallInputs, outcome: &CallOutcome) {
+        self.exit(outcome);
+    }
+
+    fn create(&mut self, inputs: &CreateInputs) {
+        let mut frame = CallFrame::new(
+            "CREATE",
+            inputs.caller,
+            inputs.address,
+            Some(inputs.value),
+            inputs.gas,
+        );
+        frame.input = format!("0x{}", hex::encode(&inputs.init_code));
+        self.enter(frame);
+    }
+
+    fn create_end(&mut self, _inputs: &CreateInputs, outcome: &CallOutcome) {
+        self.exit(outcome);
+    }
+
+    fn log(&mut self, log: &Log) {
+        let frame = self.frames.last_mut().unwrap();
+        frame.logs.push(CallLog {
+            address: hex_address(&log.address),
+            topics: log
+                .topics
+                .iter()
+                .map(|topic| format!("0x{}", hex::encode(topic)))
+                .collect(),
+            data: format!("0x{}", hex::encode(&log.data)),
+            position: format!("0x{:x}", frame.calls.len()),
+            event: None,
+            raw: log.clone(),
+        });
+    }
+}
+
+/// gethのprestateTracerと同じ形式で、トランザクションが触れたアカウントの実行前の状態を出力する<br/>
+/// diffモードでは変化したアカウントの実行前(pre)と実行後(post)の状態を出力する
+#[derive(Debug, Clone)]
+pub struct PrestateTracer {
+    before: WorldState,
+    touched: BTreeMap<H160, BTreeSet<U256>>, // 触れたアカウントとstorageのslot
+}
+
+impl PrestateTracer {
+    /// beforeは実行前のワールドステート
+    pub fn new(before: WorldState) -> Self {
+        return Self {
+            before,
+            touched: BTreeMap::new(),
+        };
+    }
+
+    fn touch(&mut self, address: &H160) {
+        self.touched.entry(*address).or_default();
+    }
+
+    fn touch_slot(&mut self, address: &H160, slot: &U256) {
+        self.touched.entry(*address).or_default().insert(*slot);
+    }
+
+    /// 実行後のワールドステートafterから出力を作る
+    pub fn finish(self, after: &WorldState, diff: bool) -> Value {
+        if !diff {
+            let mut pre = Map::new();
+            for (address, slots) in &self.touched {
+                if let Some(account) = self.before.account(address) {
+                    pre.insert(hex_address(address), account_json(account, slots));
+                }
+            }
+            return Value::Object(pre);
+        }
+
+        let (mut pre, mut post) = (Map::new(), Map::new());
+        for (address, slots) in &self.touched {
+            let (before, after) = (self.before.account(address), after.account(address));
+            let changed: BTreeSet<U256> = slots
+                .iter()
+                .filter(|slot| storage_of(before, slot) != storage_of(after, slot))
+                .copied()
+                .collect();
+            let fields_changed = match (before, after) {
+                (Some(before), Some(after)) => {
+                    before.get_balance() != after.get_balance()
+                        || before.get_nonce() != after.get_nonce()
+                        || before.get_code() != after.get_code()
+                }
+                (None, None) => false,
+                _ => true,
+            };
+            if !fields_changed && changed.is_empty() {
+                continue;
+            }
+            if let Some(before) = before {
+                pre.insert(hex_address(address), account_json(before, &changed));
+            }
+            // 実行後のstateは変化した項目だけを出力する
+            if let Some(after) = after {
+                let mut fields = account_json(after, &changed);
+                if let (Some(before), Value::Object(fields)) = (before, &mut fields) {
+                    if before.get_balance() == after.get_balance() {
+                        fields.remove("balance");
+                    }
+                    if before.get_nonce() == after.get_nonce() {
+                        fields.remove("nonce");
+                    }
+                    if before.get_code() == after.get_code() {
+                        fields.remove("code");
+                    }
+                }
+                post.insert(hex_address(address), fields);
+            }
+        }
+        return json!({ "pre": pre, "post": post });
+    }
+}
+
+impl Inspector for PrestateTracer {
+    /// 他のアカウントを参照する命令の対象を記録する
+    fn step(&mut self, vm: &VM, _ws: &WorldState) {
+        let stack = vm.stack();
+        let target = match vm.opcode() {
+            // BALANCE, EXTCODESIZE, EXTCODECOPY, EXTCODEHASH
+            Some(0x31 | 0x3b | 0x3c | 0x3f) => stack.last(),
+            _ => None,
+        };
+        if let Some(target) = target {
+            self.touch(&util::u256_to_h160(target));
+        }
+    }
+
+    fn call(&mut self, inputs: &CallInputs) {
+        self.touch(&inputs.caller);
+        self.touch(&inputs.to);
+    }
+
+    fn create(&mut self, inputs: &CreateInputs) {
+        self.touch(&inputs.caller);
+        self.touch(&inputs.address);
+    }
+
+    fn storage_read(&mut self, address: &H160, slot: &U256, _value: &U256) {
+        self.touch_slot(address, slot);
+    }
+
+    fn storage_write(&mut self, address: &H160, slot: &U256, _old: &U256, _new: &U256) {
+        self.touch_slot(address, slot);
+    }
+
+    fn selfdestruct(&mut self, address: &H160, beneficiary: &H160, _balance: &U256) {
+        self.touch(address);
+        self.touch(beneficiary);
+    }
+}
+
+/// prestateTracerのアカウント nonce、code、storageは空なら省略する
+fn account_json(account: &AccountState, slots: &BTreeSet<U256>) -> Value {
+    let mut fields = Map::new();
+    fields.insert(
+        "balance".to_string(),
+        json!(format!("0x{:x}", account.get_balance())),
+    );
+    if account.get_nonce() != 0 {
+        fields.insert("nonce".to_string(), json!(account.get_nonce()));
+    }
+    if !account.get_code().is_empty() {
+        fields.insert(
+            "code".to_string(),
+            json!(format!("0x{}", account.get_code())),
+        );
+    }
+    if !slots.is_empty() {
+        let storage: Map<String, Value> = slots
+            .iter()
+            .map(|slot| {
+                let value = account.get_storage(slot);
+                (hex_word(slot), json!(hex_word(&value)))
+            })
+            .collect();
+        fields.insert("storage".to_string(), Value::Object(storage));
+    }
+    return Value::Object(fields);
+}
+
+fn storage_of(account: Option<&AccountState>, slot: &U256) -> U256 {
+    return account
+        .map(|account| account.get_storage(slot))
+        .unwrap_or_default();
+}
+
+fn hex_address(address: &H160) -> String {
+    return format!("0x{}", hex::encode(address));
+}
+
+/// gasなどの数値を0x付きの16進文字列としてシリアライズする
+fn serialize_hex<S: Serializer>(value: &usize, serializer: S) -> Result<S::Ok, S::Error> {
+    return serializer.serialize_str(&format!("0x{:x}", value));
+}
+
+/// 32byteの0埋めの16進文字列
+fn hex_word(value: &U256) -> String {
+    let bytes: [u8; 32] = (*value).into();
+    return format!("0x{}", hex::encode(bytes));
+}
+
+/// トランザクションを実行し、callTracerの呼び出しのツリーを返す ログとrevertはabiでデコードする
+pub fn call_trace(
+    ws: &mut WorldState,
+    tx: &Transaction,
+    abi: Abi,
+) -> Result<(Receipt, Value), String> {
+    let tracer = Rc::new(RefCell::new(CallTracer::with_abi(abi)));
+    let receipt = transaction::execute_with_inspector(ws, tx, Some(tracer.clone()))?;
+    let trace = tracer.take().finish(&receipt, tx);
+    return Ok((receipt, trace));
+}
+
+/// トランザクションを実行し、prestateTracerの出力を返す
+pub fn prestate_trace(
+    ws: &mut WorldState,
+    tx: &Transaction,
+    diff: bool,
+) -> Result<(Receipt, Value), String> {
+    let tracer = Rc::new(RefCell::new(PrestateTracer::new(ws.clone())));
+    let receipt = transaction::execute_with_inspector(ws, tx, Some(tracer.clone()))?;
+    let tracer = Rc::try_unwrap(tracer).ok().unwrap().into_inner();
+    return Ok((receipt, tracer.finish(ws, diff)));
+}
+
+#[test]
+fn test_trace() {
+    let mut ws = test_world_state(&[(0xbb, "6005600401")]);
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let (_, lines) = trace(&mut ws, &tx, Sources::new()).unwrap();
+    assert_eq!(lines.len(), 4);
+    // 10_000_000 - 21000 = 0x984478
+    assert_eq!(
+        lines[0],
+        r#"{"pc":0,"op":96,"gas":"0x984478","gasCost":"0x3","memSize":0,"stack":[],"depth":1,"refund":0,"opName":"PUSH1"}"#
+    );
+    assert_eq!(
+        lines[2],
+        r#"{"pc":4,"op":1,"gas":"0x984472","gasCost":"0x3","memSize":0,"stack":["0x5","0x4"],"depth":1,"refund":0,"opName":"ADD"}"#
+    );
+    assert!(lines[3].starts_with(r#"{"stateRoot":"0x"#));
+    assert!(lines[3].ends_with(r#""output":"0x","gasUsed":"0x5211","pass":true,"fork":"Cancun"}"#));
+}
+
+#[test]
+fn test_trace_halt_in_call() {
+    // 0xbbは0xccをCALLし、0xccは未定義の命令で例外停止する
+    let mut ws = test_world_state(&[(0xbb, "6000600060006000600060cc61fffff100"), (0xcc, "fe")]);
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let (receipt, lines) = trace(&mut ws, &tx, Sources::new()).unwrap();
+    assert!(receipt.status.is_success());
+    let steps: Vec<serde_json::Value> = lines
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // PUSH x7, CALL, INVALID, STOP, 要約
+    assert_eq!(steps.len(), 11);
+    assert_eq!(steps[8]["depth"], 2);
+    assert_eq!(steps[8]["gasCost"], "0xffff");
+    // 例外停止した命令には理由が記録される
+    assert_eq!(steps[8]["error"], "invalid opcode 0xfe");
+    assert!(steps[7].get("error").is_none());
+    // coldアクセスのCALLのコスト2600と呼び出し先に渡して消費された0xffff
+    assert_eq!(steps[7]["opName"], "CALL");
+    assert_eq!(steps[7]["gasCost"], format!("0x{:x}", 2600 + 0xffff));
+    assert_eq!(steps[9]["opName"], "STOP");
+}
+
+#[test]
+fn test_call_trace() {
+    // 0xcc: REVERT(Error("no"))
+    let callee = "7f08c379a000000000000000000000000000000000000000000000000000000000600052\
+                  60206004526002602452\
+                  7f6e6f000000000000000000000000000000000000000000000000000000000000604452\
+                  60646000fd";
+    // 0xbb: LOG1(topic 1), CALL(0xffff, 0xcc, 0, 0, 0, 0, 0) STOP
+    let caller = "600160006000a16000600060006000600060cc61fffff100";
+    let mut ws = test_world_state(&[(0xbb, caller), (0xcc, callee)]);
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let (receipt, trace) = call_trace(&mut ws, &tx, Abi::default()).unwrap();
+    assert!(receipt.status.is_success());
+    assert_eq!(trace["type"], "CALL");
+    assert_eq!(trace["from"], hex_address(&H160::from_low_u64_be(0xaa)));
+    assert_eq!(trace["value"], "0x0");
+    assert_eq!(trace["gas"], "0x989680");
+    assert_eq!(trace["gasUsed"], format!("0x{:x}", receipt.gas_used));
+    assert_eq!(trace["logs"][0]["position"], "0x0");
+    assert_eq!(trace["logs"][0]["topics"][0], hex_word(&U256::one()));
+
+    let call = &trace["calls"][0];
+    assert_eq!(call["to"], hex_address(&H160::from_low_u64_be(0xcc)));
+    assert_eq!(call["gas"], "0xffff");
+    assert_eq!(call["error"], "execution reverted");
+    assert_eq!(call["revertReason"], "no");
+    assert!(call["output"].as_str().unwrap().starts_with("0x08c379a0"));
+    assert!(call.get("calls").is_none());
+    assert!(trace["logs"][0].get("event").is_none());
+
+    // ABIがあればtopicが1つのanonymousなイベントとしてデコードする
+    let abi = Abi::from_json(&json!([{
+        "type": "event", "name": "Ping", "anonymous": true,
+        "inputs": [{"name": "id", "type": "uint256", "indexed": true}]
+    }]))
+    .unwrap();
+    let mut ws = test_world_state(&[(0xbb, caller), (0xcc, callee)]);
+    let (_, trace) = call_trace(&mut ws, &tx, abi).unwrap();
+    assert_eq!(trace["logs"][0]["event"], "Ping(id=1)");
+
+    // デコードできないログがあっても実行の結果は変わらない
+    let abi = Abi::from_json(&json!([{
+        "type": "event", "name": "E", "anonymous": true,
+        "inputs": [{"name": "flag", "type": "bool", "indexed": true}]
+    }]))
+    .unwrap();
+    // 0xbb: LOG1(topic 0xff..ff)
+    let caller = format!("7f{}60006000a100", "ff".repeat(32));
+    let mut ws = test_world_state(&[(0xbb, &caller)]);
+    let (receipt, trace) = call_trace(&mut ws, &tx, abi).unwrap();
+    assert!(receipt.status.is_success());
+    assert!(trace["logs"][0].get("event").is_none());
+}
+
+#[test]
+fn test_prestate_trace() {
+    // 0xbb: SSTORE(0, 5), CALL(0, 0xdd, 0, 0, 0, 0, 0)
+    let code = "60056000556000600060006000600060dd6000f150";
+    let mut ws = test_world_state(&[(0xbb, code)]);
+    let mut account = AccountState::new("".to_string());
+    account.set_balance(7.into());
+    ws.push_account_state(H160::from_low_u64_be(0xdd), account);
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    let (aa, bb, dd) = (
+        hex_address(&H160::from_low_u64_be(0xaa)),
+        hex_address(&H160::from_low_u64_be(0xbb)),
+        hex_address(&H160::from_low_u64_be(0xdd)),
+    );
+    let slot = hex_word(&U256::zero());
+
+    let (_, trace) = prestate_trace(&mut ws.clone(), &tx, false).unwrap();
+    assert_eq!(trace[&aa], json!({ "balance": "0x3e8" }));
+    assert_eq!(trace[&bb]["code"], format!("0x{}", code));
+    assert_eq!(trace[&bb]["storage"][&slot], hex_word(&U256::zero()));
+    assert_eq!(trace[&dd], json!({ "balance": "0x7" }));
+
+    // 呼び出しただけの0xddは変化していない
+    let (_, trace) = prestate_trace(&mut ws, &tx, true).unwrap();
+    assert_eq!(trace["post"][&aa], json!({ "nonce": 1 }));
+    assert_eq!(
+        trace["post"][&bb],
+        json!({ "storage": { &slot: hex_word(&U256::from(5)) } })
+    );
+    assert!(trace["pre"].get(&dd).is_none());
+    assert!(trace["post"].get(&dd).is_none());
+}
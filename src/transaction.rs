@@ -0,0 +1,463 @@
+//! Transaction
+//!
+//! ワールドステートに対してトランザクションを1つ実行するモジュール
+use super::abi::Abi;
+use super::fork::Fork;
+use super::inspector::{CallInputs, CallKind, CallOutcome, CreateInputs, InspectorRef};
+#[cfg(test)]
+use super::state::test_world_state;
+use super::state::{Log, WorldState};
+use super::util;
+use super::vm::{BlockEnv, Environment, ExitStatus, VM};
+use ethereum_types::{H160, U256};
+
+/// コントラクトを呼び出す、またはデプロイするトランザクション
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub from: H160,       // 送信者
+    pub to: Option<H160>, // 呼び出すアカウント Noneならコントラクトの作成
+    pub value: U256,      // 送金するEth(wei)
+    pub data: Vec<u8>,    // calldata コントラクトの作成では初期化コード
+    pub gas: usize,       // gasの上限
+    pub gas_price: usize, // gasのETHレート(GASPRICEで参照されるだけで、gas代は引かれない)
+    pub fork: Fork,
+    pub block: BlockEnv, // トランザクションを含むブロック
+}
+
+impl Transaction {
+    /// toを呼び出すトランザクション
+    pub fn new(from: H160, to: H160) -> Self {
+        return Self::with_to(from, Some(to));
+    }
+
+    /// 初期化コードを実行してコントラクトを作成するトランザクション
+    pub fn create(from: H160, init_code: Vec<u8>) -> Self {
+        let mut tx = Self::with_to(from, None);
+        tx.data = init_code;
+        return tx;
+    }
+
+    fn with_to(from: H160, to: Option<H160>) -> Self {
+        return Self {
+            from,
+            to,
+            value: U256::zero(),
+            data: vec![],
+            gas: 10_000_000,
+            gas_price: 1,
+            fork: Fork::default(),
+            block: BlockEnv::default(),
+        };
+    }
+}
+
+/// トランザクションの実行結果
+#[derive(Debug, Clone)]
+pub struct Receipt {
+    pub status: ExitStatus,
+    pub output: Vec<u8>,   // RETURN, REVERTで返されたデータ
+    pub gas_used: usize,   // 返還分を差し引いた使用gas
+    pub gas_refund: usize, // 返還されたgas
+    pub logs: Vec<Log>,
+    pub contract_address: Option<H160>, // 作成されたコントラクトのアドレス
+    pub trace: Vec<String>,             // 実行した命令(CALL系命令の呼び出し先は含まない)
+}
+
+/// トランザクションを実行する<br/>
+/// 送信者のnonceは実行結果によらず増え、実行が失敗した場合は送金を含むステートの変更が取り消される<br/>
+/// 残高やgasが足りず実行できない場合はErrを返す
+pub fn execute(ws: &mut WorldState, tx: &Transaction) -> Result<Receipt, String> {
+    return execute_with_inspector(ws, tx, None);
+}
+
+/// 実行をinspectorに通知しながらトランザクションを実行する<br/>
+/// トランザクションによる呼び出しやコントラクトの作成もcall、createとして通知される
+pub fn execute_with_inspector(
+    ws: &mut WorldState,
+    tx: &Transaction,
+    inspector: Option<InspectorRef>,
+) -> Result<Receipt, String> {
+    if !ws.exists(&tx.from) {
+        return Err(format!("sender 0x{} is not found", hex::encode(tx.from)));
+    }
+    if ws.get_account_state(&tx.from).get_balance() < tx.value {
+        return Err("insufficient balance for value".to_string());
+    }
+    let intrinsic = intrinsic_gas(tx);
+    if tx.gas < intrinsic {
+        return Err(format!("intrinsic gas too low: {} < {}", tx.gas, intrinsic));
+    }
+
+    // 作成されるコントラクトのアドレスはインクリメント前のnonceから決まる
+    let nonce = ws.get_account_state(&tx.from).get_nonce();
+    if tx.to.is_none() {
+        let address = util::create_address(&tx.from, nonce);
+        if ws.exists(&address) {
+            let account = ws.get_account_state(&address);
+            if account.get_nonce() != 0 || !account.get_code().is_empty() {
+                return Err(format!(
+                    "contract address 0x{} already exists",
+                    hex::encode(address)
+                ));
+            }
+        }
+    }
+    ws.get_account_state(&tx.from).increment_nonce();
+    let checkpoint = ws.checkpoint();
+
+    let (to, code, input) = match tx.to {
+        Some(to) => {
+            ws.transfer(&tx.from, &to, tx.value);
+            let code = util::str_to_bytes(&ws.get_account_state(&to).get_code());
+            (to, code, tx.data.clone())
+        }
+        None => {
+            let address = util::create_address(&tx.from, nonce);
+            ws.transfer(&tx.from, &address, tx.value);
+            // EIP-161: 作成されたコントラクトのnonceは1から始まる
+            if tx.fork >= Fork::SpuriousDragon {
+                ws.increment_nonce(&address);
+            }
+            ws.mark_created(&address);
+            (address, tx.data.clone(), vec![])
+        }
+    };
+
+    // EIP-2929: 送信者と宛先は最初からアクセス済み
+    ws.access_address(&tx.from);
+    ws.access_address(&to);
+
+    let mut env = Environment::new(to, tx.from, tx.gas_price, 0);
+    env.set_value(tx.value);
+    env.set_code(code);
+    env.set_input(input);
+    env.set_fork(tx.fork);
+    env.set_block(tx.block);
+    let gas = tx.gas - intrinsic;
+    if let Some(inspector) = &inspector {
+        let mut inspector = inspector.borrow_mut();
+        match tx.to {
+            Some(to) => inspector.call(&call_inputs(tx, to, gas)),
+            None => inspector.create(&create_inputs(tx, to, gas)),
+        }
+    }
+    let mut vm = VM::with_gas(env, gas);
+    let mut status = vm.exec_transaction_with(ws, inspector.clone());
+    let mut gas_used = intrinsic + vm.gas_used();
+
+    // 初期化コードの返り値をランタイムコードとして保存する
+    if tx.to.is_none() && status.is_success() {
+        match deposit_code(vm.returns(), tx.gas - gas_used, tx.fork) {
+            Ok(cost) => {
+                gas_used += cost;
+                ws.get_account_state(&to).set_code(vm.returns());
+            }
+            Err(reason) => {
+                gas_used = tx.gas;
+                status = ExitStatus::Halt(reason);
+            }
+        }
+    }
+    if let Some(inspector) = &inspector {
+        let mut inspector = inspector.borrow_mut();
+        let outcome = CallOutcome {
+            status: status.clone(),
+            output: vm.returns().to_vec(),
+            gas_used: gas_used - intrinsic,
+        };
+        match tx.to {
+            Some(to) => inspector.call_end(&call_inputs(tx, to, gas), &outcome),
+            None => inspector.create_end(&create_inputs(tx, to, gas), &outcome),
+        }
+    }
+    if !status.is_success() {
+        ws.revert_to(checkpoint);
+    }
+
+    // SELFDESTRUCTされたアカウントの削除などトランザクションの後処理
+    let substate = ws.finalize_transaction();
+    let gas_refund = if status.is_success() {
+        substate
+            .refund
+            .min(gas_used / tx.fork.max_refund_quotient())
+    } else {
+        0
+    };
+    let contract_address = match (tx.to, status.is_success()) {
+        (None, true) => Some(to),
+        _ => None,
+    };
+    return Ok(Receipt {
+        status,
+        output: vm.returns().to_vec(),
+        gas_used: gas_used - gas_refund,
+        gas_refund,
+        logs: substate.logs,
+        contract_address,
+        trace: vm.asm().to_vec(),
+    });
+}
+
+/// トランザクションによる呼び出しをinspectorに渡す形にする
+fn call_inputs(tx: &Transaction, to: H160, gas: usize) -> CallInputs {
+    return CallInputs {
+        kind: CallKind::Call,
+        caller: tx.from,
+        to,
+        value: tx.value,
+        input: tx.data.clone(),
+        gas,
+        depth: 0,
+    };
+}
+
+/// トランザクションによるコントラクトの作成をinspectorに渡す形にする
+fn create_inputs(tx: &Transaction, address: H160, gas: usize) -> CreateInputs {
+    return CreateInputs {
+        caller: tx.from,
+        address,
+        value: tx.value,
+        init_code: tx.data.clone(),
+        gas,
+        depth: 0,
+    };
+}
+
+/// ランタイムコードを保存するのに必要なgas(1byteあたり200)<br/>
+/// 保存できないコードやgasが足りない場合はErrを返す
+fn deposit_code(code: &[u8], gas_left: usize, fork: Fork) -> Result<usize, String> {
+    // EIP-170: コードサイズの上限
+    if fork >= Fork::SpuriousDragon && code.len() > 0x6000 {
+        return Err("max code size exceeded".to_string());
+    }
+    // EIP-3541: 0xEFで始まるコードは保存できない
+    if fork >= Fork::London && code.first() == Some(&0xef) {
+        return Err("code starting with 0xef is not allowed".to_string());
+    }
+    let cost = 200 * code.len();
+    if cost > gas_left {
+        return Err("out of gas for code deposit".to_string());
+    }
+    return Ok(cost);
+}
+
+/// トランザクションの実行前に消費される基本のgas<br/>
+/// calldataの0でないバイトはEIP-2028で68から16に引き下げられた<br/>
+/// コントラクトの作成にはHomestead以降32000、Shanghai以降は初期化コードの長さに応じたgasが追加でかかる
+pub fn intrinsic_gas(tx: &Transaction) -> usize {
+    let nonzero_cost = if tx.fork >= Fork::Istanbul { 16 } else { 68 };
+    let data_cost: usize = tx
+        .data
+        .iter()
+        .map(|&b| if b == 0 { 4 } else { nonzero_cost })
+        .sum();
+    let mut gas = 21000 + data_cost;
+    if tx.to.is_none() {
+        if tx.fork >= Fork::Homestead {
+            gas += 32000;
+        }
+        // EIP-3860
+        if tx.fork >= Fork::Shanghai {
+            gas += 2 * tx.data.len().div_ceil(32);
+        }
+    }
+    return gas;
+}
+
+/// トランザクションが成功する最小のgasの上限を二分探索で求める<br/>
+/// 試すたびにwsを複製して実行するので、wsは変更されない tx.gasを探索の上限にする<br/>
+/// 呼び出し先に渡せるgasは残りの63/64までで、返還されるgasも実行中は必要なので、使用gasよりも多くなることがある<br/>
+/// 上限でも成功しなければ、revertの理由(decodingのカスタムエラーとError(string), Panic(uint256)はデコードする)か停止の理由をErrで返す
+pub fn estimate_gas(ws: &WorldState, tx: &Transaction, decoding: &Abi) -> Result<usize, String> {
+    let succeeds = |gas: usize| -> Result<Receipt, String> {
+        let mut tx = tx.clone();
+        tx.gas = gas;
+        return execute(&mut ws.clone(), &tx);
+    };
+    let receipt = succeeds(tx.gas)?;
+    match &receipt.status {
+        ExitStatus::Success => {}
+        ExitStatus::Revert => {
+            return Err(match decoding.decode_revert(&receipt.output) {
+                Some(reason) => format!("execution reverted: {}", reason),
+                None if receipt.output.is_empty() => "execution reverted".to_string(),
+                None => format!("execution reverted (0x{})", hex::encode(&receipt.output)),
+            });
+        }
+        ExitStatus::Halt(_) => {
+            return Err(format!("{} with gas limit {}", receipt.status, tx.gas));
+        }
+    }
+    let is_success = |gas: usize| succeeds(gas).is_ok_and(|receipt| receipt.status.is_success());
+
+    // 返還後の使用gasでは足りない 成功する最小のgasは(lower, upper]にある
+    let mut lower = receipt.gas_used.max(intrinsic_gas(tx)) - 1;
+    let mut upper = tx.gas;
+    // 実行中に消費したgasと、それを呼び出し先に渡すための64/63倍で足りることが多いので先に試す
+    let optimistic = (receipt.gas_used + receipt.gas_refund + 2300) * 64 / 63;
+    if optimistic < upper && is_success(optimistic) {
+        upper = optimistic;
+    }
+    while lower + 1 < upper {
+        let middle = lower + (upper - lower) / 2;
+        if is_success(middle) {
+            upper = middle;
+        } else {
+            lower = middle;
+        }
+    }
+    return Ok(upper);
+}
+
+#[test]
+fn test_call() {
+    // calldataの先頭32byteとCALLVALUEを足して返す
+    let mut ws = test_world_state(&[(0xbb, "600035340160005260206000f3")]);
+    let mut tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    tx.value = 10.into();
+    let data: [u8; 32] = U256::from(5).into();
+    tx.data = data.to_vec();
+    let receipt = execute(&mut ws, &tx).unwrap();
+    assert_eq!(receipt.status, ExitStatus::Success);
+    assert_eq!(U256::from_big_endian(&receipt.output), 15.into());
+    // 21000 + calldata(31 * 4 + 16) + 実行(26)
+    assert_eq!(receipt.gas_used, 21166);
+    assert_eq!(
+        ws.get_account_state(&H160::from_low_u64_be(0xbb))
+            .get_balance(),
+        10.into()
+    );
+}
+
+#[test]
+fn test_call_revert() {
+    // SSTOREの後にREVERTするので、ストレージの変更と送金が取り消される
+    let mut ws = test_world_state(&[(0xbb, "600160005560006000fd")]);
+    let mut tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    tx.value = 10.into();
+    let receipt = execute(&mut ws, &tx).unwrap();
+    assert_eq!(receipt.status, ExitStatus::Revert);
+    let callee = ws.get_account_state(&H160::from_low_u64_be(0xbb));
+    assert_eq!(callee.get_storage(&0.into()), 0.into());
+    assert_eq!(callee.get_balance(), 0.into());
+    let sender = ws.get_account_state(&H160::from_low_u64_be(0xaa));
+    assert_eq!(sender.get_balance(), 1000.into());
+}
+
+#[test]
+fn test_call_intrinsic_gas_too_low() {
+    let mut ws = test_world_state(&[(0xbb, "")]);
+    let mut tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    tx.gas = 20000;
+    assert_eq!(
+        execute(&mut ws, &tx).unwrap_err(),
+        "intrinsic gas too low: 20000 < 21000"
+    );
+}
+
+#[test]
+fn test_create() {
+    // 初期化コードはランタイムコード6005600401(5 + 4)をmemoryにコピーして返す
+    let mut ws = test_world_state(&[(0xbb, "")]);
+    let sender = H160::from_low_u64_be(0xaa);
+    let tx = Transaction::create(
+        sender,
+        hex::decode("600580600b6000396000f36005600401").unwrap(),
+    );
+    let receipt = execute(&mut ws, &tx).unwrap();
+    assert_eq!(receipt.status, ExitStatus::Success);
+
+    let address = util::create_address(&sender, 0);
+    assert_eq!(receipt.contract_address, Some(address));
+    // 21000 + 32000 + calldata(16 * 14 + 4 * 2) + EIP-3860(2) + 実行(24) + デポジット(200 * 5)
+    assert_eq!(receipt.gas_used, 54258);
+    let contract = ws.get_account_state(&address);
+    assert_eq!(contract.get_code(), "6005600401");
+    assert_eq!(contract.get_nonce(), 1);
+    assert_eq!(ws.get_account_state(&sender).get_nonce(), 1);
+}
+
+#[test]
+fn test_estimate_gas() {
+    use super::state::AccountState;
+
+    // 0xbb: CALL(0xffffff, 0xcc, 0, 0, 0, 0, 0)が失敗すればREVERTする
+    let caller = "6000600060006000600060cc62fffffff115601657005b60006000fd";
+    let mut ws = test_world_state(&[(0xbb, caller)]);
+    // 0xcc: SSTORE(0, 1), SELFDESTRUCT(0xaa) London以前なのでgasが返還される
+    let callee = AccountState::new("600160005560aaff".to_string());
+    ws.push_account_state(H160::from_low_u64_be(0xcc), callee);
+    let mut tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    tx.fork = Fork::Berlin;
+
+    let gas = estimate_gas(&ws, &tx, &Abi::default()).unwrap();
+    let receipt = execute(&mut ws.clone(), &tx).unwrap();
+    assert!(receipt.gas_refund > 0);
+    // 返還されるgasと、呼び出し先に渡す63/64の分だけ使用gasより多い
+    assert!(gas > receipt.gas_used + receipt.gas_refund);
+    let mut limited = tx.clone();
+    limited.gas = gas;
+    assert!(execute(&mut ws.clone(), &limited)
+        .unwrap()
+        .status
+        .is_success());
+    limited.gas = gas - 1;
+    assert_eq!(
+        execute(&mut ws.clone(), &limited).unwrap().status,
+        ExitStatus::Revert
+    );
+    // wsは変更されない
+    assert_eq!(
+        ws.get_account_state(&H160::from_low_u64_be(0xaa))
+            .get_nonce(),
+        0
+    );
+}
+
+#[test]
+fn test_estimate_gas_revert() {
+    // REVERT(Error("no"))
+    let code = "7f08c379a000000000000000000000000000000000000000000000000000000000600052\
+                60206004526002602452\
+                7f6e6f000000000000000000000000000000000000000000000000000000000000604452\
+                60646000fd";
+    let ws = test_world_state(&[(0xbb, code)]);
+    let tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    assert_eq!(
+        estimate_gas(&ws, &tx, &Abi::default()),
+        Err("execution reverted: no".to_string())
+    );
+
+    // 読み込んだABIのカスタムエラーもデコードする
+    let mut decoding = Abi::default();
+    decoding
+        .errors
+        .push(super::abi::CustomError::parse("Unauthorized(uint256 id)").unwrap());
+    // REVERT(Unauthorized(7))
+    let code = format!(
+        "7f{}{}600052600760045260246000fd",
+        hex::encode(decoding.errors[0].selector()),
+        "00".repeat(28)
+    );
+    let ws = test_world_state(&[(0xbb, &code)]);
+    assert_eq!(
+        estimate_gas(&ws, &tx, &Abi::default()),
+        Err(format!(
+            "execution reverted (0x{}{:0>64})",
+            &code[2..10],
+            "7"
+        ))
+    );
+    assert_eq!(
+        estimate_gas(&ws, &tx, &decoding),
+        Err("execution reverted: Unauthorized(id=7)".to_string())
+    );
+
+    // 上限のgasが足りなければ停止の理由を返す
+    let ws = test_world_state(&[(0xbb, "6001600055")]);
+    let mut tx = Transaction::new(H160::from_low_u64_be(0xaa), H160::from_low_u64_be(0xbb));
+    tx.gas = 22000;
+    assert!(estimate_gas(&ws, &tx, &Abi::default())
+        .unwrap_err()
+        .ends_with("with gas limit 22000"));
+}
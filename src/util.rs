@@ -5,6 +5,9 @@
 //! ユーティリティ関数を集めたモジュール
 use ethereum_types::H160;
 use ethereum_types::U256;
+use sha3::{Digest, Keccak256};
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
 
 pub fn str_to_bytes(src: &str) -> Vec<u8> {
@@ -16,6 +19,18 @@ pub fn bytes_to_str(src: Vec<u8>) -> String {
     hex::encode(src)
 }
 
+/// 16進数の文字列、またはそれが書かれたファイルからバイトコードを読み込む
+pub fn read_bytecode(src: &str) -> Result<Vec<u8>, String> {
+    let text = if Path::new(src).is_file() {
+        fs::read_to_string(src).map_err(|e| format!("{}: {}", src, e))?
+    } else {
+        src.to_string()
+    };
+    let text = text.trim();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    return hex::decode(text).map_err(|e| format!("invalid bytecode: {}", e));
+}
+
 pub fn slice_to_array(s: &[u8]) -> [u8; 32] {
     let mut result = [0; 32];
     if s.len() < 32 {
@@ -23,9 +38,7 @@ pub fn slice_to_array(s: &[u8]) -> [u8; 32] {
             result[i] = *b;
         }
     } else {
-        for i in 0..32 {
-            result[i] = s[i];
-        }
+        result.copy_from_slice(&s[..32]);
     }
     return result;
 }
@@ -38,10 +51,67 @@ pub fn to_h160(s: &str) -> H160 {
     return H160::from_str(s).unwrap();
 }
 
+/// keccak256ハッシュ
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.input(data);
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&hasher.result());
+    return hash;
+}
+
+/// CREATEで作られるコントラクトのアドレス keccak256(rlp([sender, nonce]))の下位20byte
+pub fn create_address(sender: &H160, nonce: usize) -> H160 {
+    // nonceは先頭の0を除いたビッグエンディアンのバイト列としてRLPエンコードする
+    let nonce_bytes = (nonce as u64).to_be_bytes();
+    let nonce_bytes: Vec<u8> = nonce_bytes
+        .iter()
+        .copied()
+        .skip_while(|&b| b == 0)
+        .collect();
+    let mut payload = vec![0x80 + 20];
+    payload.extend_from_slice(sender.as_bytes());
+    match nonce_bytes.len() {
+        1 if nonce_bytes[0] < 0x80 => payload.push(nonce_bytes[0]),
+        length => {
+            payload.push(0x80 + length as u8);
+            payload.extend_from_slice(&nonce_bytes);
+        }
+    }
+    let mut rlp = vec![0xc0 + payload.len() as u8];
+    rlp.extend_from_slice(&payload);
+    return H160::from_slice(&keccak256(&rlp)[12..]);
+}
+
+/// 0xの有無を問わず16進数の文字列をアドレスとして読む
+pub fn parse_h160(s: &str) -> Result<H160, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    return H160::from_str(hex).map_err(|_| format!("{} is invalid address", s));
+}
+
+/// 10進数、または0xで始まる16進数の文字列を数値として読む
+pub fn parse_u256(s: &str) -> Result<U256, String> {
+    let result = match s.strip_prefix("0x") {
+        Some(hex) => U256::from_str(hex).map_err(|_| ()),
+        None => U256::from_dec_str(s).map_err(|_| ()),
+    };
+    return result.map_err(|_| format!("{} is invalid number", s));
+}
+
+/// parse_u256と同じ形式の文字列をusizeとして読む 範囲外ならErr
+pub fn parse_usize(s: &str) -> Result<usize, String> {
+    let value = parse_u256(s)?;
+    if value > U256::from(usize::MAX) {
+        return Err(format!("{} is too large", s));
+    }
+    return Ok(value.as_usize());
+}
+
 pub fn u256_to_h160(u: &U256) -> H160 {
     let mut bytes: [u8; 32] = [0; 32];
     u.to_big_endian(&mut bytes);
-    let result = H160::from_slice(&bytes);
+    // 下位20byteがアドレスになる
+    let result = H160::from_slice(&bytes[12..]);
     return result;
 }
 
@@ -51,3 +121,27 @@ pub fn h160_to_u256(h: &H160) -> U256 {
     let result = U256::from_big_endian(bytes);
     return result;
 }
+
+#[test]
+fn test_create_address() {
+    let sender = to_h160("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0");
+    assert_eq!(
+        hex::encode(create_address(&sender, 0)),
+        "cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d"
+    );
+    assert_eq!(
+        hex::encode(create_address(&sender, 1)),
+        "343c43a37d37dff08ae8c4a11544c718abb4fcf8"
+    );
+}
+
+#[test]
+fn test_parse_usize() {
+    assert_eq!(parse_usize("0x10"), Ok(16));
+    assert_eq!(parse_usize("10"), Ok(10));
+    assert_eq!(
+        parse_usize("0x1000000000000000000"),
+        Err("0x1000000000000000000 is too large".to_string())
+    );
+    assert!(parse_usize("x").is_err());
+}
@@ -5,19 +5,34 @@
 extern crate ethereum_types;
 extern crate hex;
 
+use super::fork::Fork;
+use super::inspector::{CallInputs, CallKind, CallOutcome, Inspector, InspectorRef};
+use super::precompile;
 use super::state;
 use super::util;
-use ethereum_types::{H160, U256};
+use ethereum_types::{H160, H256, U256};
+use std::cell::OnceCell;
 use util::not_implement_panic;
 
 /// トランザクション実行に必要な環境変数
 pub struct Environment {
-    code_owner: H160, // 実行するコントラクトのオーナー
-    sender: H160,     // トランザクションの送信者
-    gas_price: usize, // gasのETHレート
-    value: usize,     // トランザクションに添付されたEth
-    code: Vec<u8>,    // 実行されるEVMバイトコード
-    input: Vec<u8>,   // トランザクションに渡されるデータ(solidityでは引数として渡される)
+    code_owner: H160,       // 実行するコントラクトのオーナー
+    sender: H160,           // トランザクションの送信者
+    gas_price: usize,       // gasのETHレート
+    value: U256,            // トランザクションに添付されたEth(wei)
+    code: Vec<u8>,          // 実行されるEVMバイトコード
+    input: Vec<u8>,         // トランザクションに渡されるデータ(solidityでは引数として渡される)
+    fork: Fork,             // 適用するハードフォーク
+    blob_hashes: Vec<H256>, // トランザクションのblobのversioned hash(EIP-4844)
+    blob_base_fee: U256,    // blobのgasのETHレート(EIP-7516)
+    block: BlockEnv,        // トランザクションを含むブロック
+}
+
+/// トランザクションを含むブロックの情報 NUMBER、TIMESTAMPで参照される
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BlockEnv {
+    pub number: u64,
+    pub timestamp: u64, // UNIX時間(秒)
 }
 
 impl Environment {
@@ -26,9 +41,13 @@ impl Environment {
             code_owner,
             sender,
             gas_price,
-            value,
+            value: value.into(),
             code: Default::default(),
             input: Default::default(),
+            fork: Default::default(),
+            blob_hashes: Default::default(),
+            blob_base_fee: Default::default(),
+            block: Default::default(),
         };
     }
 
@@ -37,38 +56,110 @@ impl Environment {
         self.code = code;
     }
 
+    /// 添付するEth(wei)をセットする
+    pub fn set_value(&mut self, value: U256) {
+        self.value = value;
+    }
+
     /// インプットデータをセットする
     pub fn set_input(&mut self, input: Vec<u8>) {
         self.input = input;
     }
+
+    /// ハードフォークをセットする
+    pub fn set_fork(&mut self, fork: Fork) {
+        self.fork = fork;
+    }
+
+    /// blobのversioned hashをセットする
+    pub fn set_blob_hashes(&mut self, blob_hashes: Vec<H256>) {
+        self.blob_hashes = blob_hashes;
+    }
+
+    /// blobのgasのETHレートをセットする
+    pub fn set_blob_base_fee(&mut self, blob_base_fee: U256) {
+        self.blob_base_fee = blob_base_fee;
+    }
+
+    /// ブロックの情報をセットする
+    pub fn set_block(&mut self, block: BlockEnv) {
+        self.block = block;
+    }
 }
 
 /// EVMインスタンス
 pub struct VM {
-    env: Environment, // 環境変数
-    pc: usize,        // Program Counter
-    gas: usize,       // gas残量
-    sp: usize,        // スタックポインタ
+    env: Environment,                // 環境変数
+    pc: usize,                       // Program Counter
+    gas_limit: usize,                // 実行開始時のgas
+    gas: usize,                      // gas残量
+    sp: usize,                       // スタックポインタ
     stack: Vec<U256>, // トランザクションのライフサイクルの間保持される一時的なスタック領域
     memory: Vec<u8>,  // トランザクションのライフサイクルの間保持される一時的なメモリ領域
     asm: Vec<String>, // 実行した命令を入れておく 逆アセンブルに利用
     returns: Vec<u8>, // アクションの返り値
+    return_data: Vec<u8>, // 直前のCALL系命令で呼び出したコードの返り値
+    depth: usize,     // CALL系命令による呼び出しの深さ
+    is_static: bool,  // STATICCALLで呼び出されたか(ステートの変更が禁止される)
+    reverted: bool,   // REVERTで終了したか
+    inspector: Option<InspectorRef>, // 実行を通知する先 CALL先のVMにも引き継がれる
+    code_hash: OnceCell<H256>, // 実行中のコードのハッシュ 最初に参照したときに求める
+}
+
+/// コードの実行結果
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitStatus {
+    Success,      // STOP, RETURN, SELFDESTRUCTまたはコードの終端で終了した
+    Revert,       // REVERTで終了した 残りのgasは返還される
+    Halt(String), // gas不足や不正なジャンプなどの例外で停止した gasはすべて消費される
+}
+
+impl ExitStatus {
+    pub fn is_success(&self) -> bool {
+        return *self == ExitStatus::Success;
+    }
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExitStatus::Success => write!(f, "success"),
+            ExitStatus::Revert => write!(f, "revert"),
+            ExitStatus::Halt(reason) => write!(f, "halt ({})", reason),
+        }
+    }
 }
 
 /// Opcodeの実行で使われる汎用的な関数を実装している
 impl VM {
     pub fn new(env: Environment) -> Self {
         let gas = env.value / env.gas_price;
+        let gas = if gas > U256::from(usize::MAX) {
+            usize::MAX
+        } else {
+            gas.as_usize()
+        };
+        return Self::with_gas(env, gas);
+    }
 
+    /// gasを指定してVMを作る
+    pub fn with_gas(env: Environment, gas: usize) -> Self {
         Self {
             env,
             pc: 0,
+            gas_limit: gas,
             gas,
             sp: 0,
             stack: Default::default(),
             memory: Default::default(),
             asm: Default::default(),
             returns: Default::default(),
+            return_data: Default::default(),
+            depth: 0,
+            is_static: false,
+            reverted: false,
+            inspector: None,
+            code_hash: OnceCell::new(),
         }
     }
 
@@ -78,15 +169,32 @@ impl VM {
         self.sp += 1;
     }
 
-    /// スタックからのpop
-    fn pop(&mut self) -> U256 {
-        let value = self.stack.pop().unwrap();
+    /// スタックからのpop スタックが空なら例外停止する
+    fn pop(&mut self) -> Result<U256, String> {
+        let value = self.stack.pop().ok_or("stack underflow")?;
         self.sp -= 1;
-        return value;
+        return Ok(value);
+    }
+
+    /// スタックからpopした値をusizeとして返す<br/>
+    /// usizeに収まらない値はusize::MAXにする メモリの拡張ではgas不足、ジャンプでは不正なジャンプ先になる
+    fn pop_usize(&mut self) -> Result<usize, String> {
+        let value = self.pop()?;
+        if value > U256::from(usize::MAX) {
+            return Ok(usize::MAX);
+        }
+        return Ok(value.as_usize());
     }
 
     /// EVMバイトコードを1命令実行する
+    #[cfg(test)]
     fn exec(&mut self, contract: &mut state::AccountState) -> bool {
+        return self.with_contract(contract, |vm, ws| vm.step(ws).unwrap());
+    }
+
+    /// ワールドステート上でEVMバイトコードを1命令実行する<br/>
+    /// 例外停止する場合はその理由を返す
+    fn step(&mut self, ws: &mut state::WorldState) -> Result<bool, String> {
         let opcode = self.env.code[self.pc];
         self.pc += 1;
 
@@ -117,6 +225,9 @@ impl VM {
             0x18 => self.op_xor(),
             0x19 => self.op_not(),
             0x1a => self.op_byte(),
+            0x1b => self.op_shl(),
+            0x1c => self.op_shr(),
+            0x1d => self.op_sar(),
             // 0x20
             0x20 => self.op_sha3(),
             // 0x30
@@ -143,18 +254,25 @@ impl VM {
             0x43 => self.op_number(),
             0x44 => self.op_difficulty(),
             0x45 => self.op_gaslimit(),
+            0x49 => self.op_blobhash(),
+            0x4a => self.op_blobbasefee(),
             // 0x50
             0x50 => self.op_pop(),
             0x51 => self.op_mload(),
             0x52 => self.op_mstore(),
-            0x54 => self.op_sload(contract),
-            0x55 => self.op_sstore(contract),
+            0x53 => self.op_mstore8(),
+            0x54 => self.op_sload(ws),
+            0x55 => self.op_sstore(ws),
             0x56 => self.op_jump(),
             0x57 => self.op_jumpi(),
             0x58 => self.op_pc(),
             0x59 => self.op_msize(),
             0x5a => self.op_gas(),
             0x5b => self.op_jumpdest(),
+            0x5c => self.op_tload(ws),
+            0x5d => self.op_tstore(ws),
+            0x5e => self.op_mcopy(),
+            0x5f => self.op_push0(),
             // 0x60, 0x70
             0x60 => self.op_push(1),
             0x61 => self.op_push(2),
@@ -223,715 +341,1441 @@ impl VM {
             0x9e => self.op_swap(15),
             0x9f => self.op_swap(16),
             // 0xa0
-            0xa0 => self.op_log0(),
-            0xa1 => self.op_log1(),
-            0xa2 => self.op_log2(),
-            0xa3 => self.op_log3(),
-            0xa4 => self.op_log4(),
+            0xa0 => self.op_log0(ws),
+            0xa1 => self.op_log1(ws),
+            0xa2 => self.op_log2(ws),
+            0xa3 => self.op_log3(ws),
+            0xa4 => self.op_log4(ws),
             // 0xf0
             0xf0 => self.op_create(),
-            0xf1 => self.op_call(),
-            0xf2 => self.op_callcode(),
+            0xf1 => self.op_call(ws),
+            0xf2 => self.op_callcode(ws),
             0xf3 => self.op_return(),
-            0xf4 => self.op_delegatecall(),
+            0xf4 => self.op_delegatecall(ws),
             0xf5 => self.op_create2(),
-            0xfa => self.op_staticcall(),
+            0xfa => self.op_staticcall(ws),
             0xfd => self.op_revert(),
-            0xff => self.op_selfdestruct(),
-            _ => not_implement_panic(),
+            0xff => self.op_selfdestruct(ws),
+            // INVALID(0xfe)と未定義のオペコード
+            _ => Err(format!("invalid opcode 0x{:02x}", opcode)),
+        }?;
+
+        // トランザクションを終了させるかのフラグ stop, return, revert, selfdestructのみtrue
+        return Ok(matches!(opcode, 0x00 | 0xf3 | 0xfd | 0xff));
+    }
+
+    /// gasを消費する 足りなければ例外停止する
+    fn consume_gas(&mut self, gas: usize) -> Result<(), String> {
+        if self.gas < gas {
+            return Err("out of gas".to_string());
+        }
+        self.gas -= gas;
+        return Ok(());
+    }
+
+    /// forkより前のハードフォークでは存在しない命令として例外停止する
+    fn require_fork(&self, fork: Fork, mnemonic: &str) -> Result<(), String> {
+        if self.env.fork < fork {
+            return Err(format!("{} is invalid opcode before {}", mnemonic, fork));
         }
+        return Ok(());
+    }
 
-        // トランザクションを終了させるかのフラグ returnのみtrue
-        return match opcode {
-            0xf3 => true,
-            _ => false,
-        };
+    /// STATICCALLの中ではステートを変更する命令は実行できない
+    fn require_not_static(&self, mnemonic: &str) -> Result<(), String> {
+        if self.is_static {
+            return Err(format!("{} is not allowed in static context", mnemonic));
+        }
+        return Ok(());
     }
 
-    fn consume_gas(&mut self, gas: usize) {
-        if self.gas >= gas {
-            self.gas -= gas;
-        } else {
-            panic!("consume_gas: There is a shortage of gas.");
+    /// offsetからsizeバイトのメモリ領域を使えるように32byte単位でメモリを拡張する<br/>
+    /// 拡張にはメモリのサイズに応じたgasがかかる
+    fn extend_memory(&mut self, offset: usize, size: usize) -> Result<(), String> {
+        if size == 0 {
+            return Ok(());
         }
+        let old_words = self.memory.len() / 32;
+        let new_words = offset
+            .checked_add(size)
+            .map(|end| end.div_ceil(32))
+            .ok_or("out of gas")?;
+        if new_words > old_words {
+            let cost = memory_cost(new_words).ok_or("out of gas")?;
+            self.consume_gas(cost - memory_cost(old_words).unwrap_or(0))?;
+            self.memory.resize(new_words * 32, 0);
+        }
+        return Ok(());
     }
 
     /// トランザクションが終了するまでexecを繰り返す
-    pub fn exec_transaction(&mut self, contract: &mut state::AccountState) {
-        loop {
-            if self.pc >= self.env.code.len() {
-                break;
+    pub fn exec_transaction(&mut self, contract: &mut state::AccountState) -> ExitStatus {
+        return self.with_contract(contract, |vm, ws| vm.exec_transaction_with(ws, None));
+    }
+
+    /// ワールドステート上でトランザクションが終了するまでstepを繰り返す<br/>
+    /// コードのオーナーのアカウントはワールドステートに存在しなければならない<br/>
+    /// 例外停止した場合はgasをすべて消費する ワールドステートの巻き戻しは呼び出し側で行う<br/>
+    /// inspectorを渡すと命令ごとや呼び出しごとに通知される
+    pub fn exec_transaction_with(
+        &mut self,
+        ws: &mut state::WorldState,
+        inspector: Option<InspectorRef>,
+    ) -> ExitStatus {
+        self.inspector = inspector;
+        while self.pc < self.env.code.len() {
+            if let Some(inspector) = self.inspector.clone() {
+                inspector.borrow_mut().step(self, ws);
             }
-
-            if self.exec(contract) {
+            let stop = match self.step(ws) {
+                Ok(stop) => stop,
+                Err(reason) => {
+                    self.gas = 0;
+                    self.returns = vec![];
+                    if let Some(inspector) = self.inspector.clone() {
+                        inspector.borrow_mut().step_halt(self, ws, &reason);
+                    }
+                    return ExitStatus::Halt(reason);
+                }
+            };
+            if let Some(inspector) = self.inspector.clone() {
+                inspector.borrow_mut().step_end(self, ws);
+            }
+            if stop {
                 break;
             }
         }
+
+        if self.reverted {
+            return ExitStatus::Revert;
+        }
+        return ExitStatus::Success;
     }
 
-    pub fn disassemble(code: &str) {
-        let mut env = Environment::new(
-            Default::default(),
-            Default::default(),
-            1_000_000_000,
-            100_000_000_000_000_000,
-        );
-        env.set_code(util::str_to_bytes(code));
-        let mut vm = VM::new(env);
-        let mut contract = state::AccountState::new(code.to_string());
-        vm.exec_transaction(&mut contract);
+    /// 単体のアカウントだけを持つワールドステートを作ってfを実行する
+    fn with_contract<T>(
+        &mut self,
+        contract: &mut state::AccountState,
+        f: impl FnOnce(&mut VM, &mut state::WorldState) -> T,
+    ) -> T {
+        let code_owner = self.env.code_owner;
+        let mut ws = state::WorldState::default();
+        ws.push_account_state(code_owner, std::mem::take(contract));
+        let result = f(self, &mut ws);
+        *contract = ws.remove_account_state(&code_owner).unwrap_or_default();
+        return result;
+    }
 
-        for mnemonic in vm.asm {
-            println!("{}", mnemonic);
-        }
+    /// RETURN, REVERTで返されたデータ
+    pub fn returns(&self) -> &[u8] {
+        &self.returns
+    }
+
+    /// 実行した命令のニーモニック
+    pub fn asm(&self) -> &[String] {
+        &self.asm
+    }
+
+    /// getter for pc
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// 実行中のコード
+    pub fn code(&self) -> &[u8] {
+        &self.env.code
+    }
+
+    /// 実行中のコードのkeccak256ハッシュ 同じコードの情報をキャッシュするときのキーに使う
+    pub fn code_hash(&self) -> H256 {
+        return *self
+            .code_hash
+            .get_or_init(|| H256::from(util::keccak256(&self.env.code)));
+    }
+
+    /// 次に実行するopcode コードの終端ではNone
+    pub fn opcode(&self) -> Option<u8> {
+        self.env.code.get(self.pc).copied()
+    }
+
+    /// getter for stack(末尾がスタックのトップ)
+    pub fn stack(&self) -> &[U256] {
+        &self.stack
+    }
+
+    /// getter for memory
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// gas残量
+    pub fn gas(&self) -> usize {
+        self.gas
+    }
+
+    /// CALL系命令による呼び出しの深さ
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// 実行中のコードのオーナー(storageを読み書きするアカウント)
+    pub fn address(&self) -> H160 {
+        self.env.code_owner
+    }
+
+    /// 消費したgas
+    pub fn gas_used(&self) -> usize {
+        self.gas_limit - self.gas
+    }
+
+    /// トランザクション終了時に実際に返還されるgas<br/>
+    /// 返還量は使用したgasに対してフォークごとの上限がある
+    pub fn gas_refund(&self, refund: usize) -> usize {
+        let limit = self.gas_used() / self.env.fork.max_refund_quotient();
+        return refund.min(limit);
     }
 
     fn push_asm(&mut self, mnemonic: &str) {
         self.asm.push(mnemonic.to_string());
     }
+
+    /// inspectorがあればfで通知する
+    fn inspect(&self, f: impl FnOnce(&mut dyn Inspector)) {
+        if let Some(inspector) = &self.inspector {
+            f(&mut *inspector.borrow_mut());
+        }
+    }
+}
+
+/// wordsワード分のメモリを確保するのに必要なgas usizeに収まらなければNone
+fn memory_cost(words: usize) -> Option<usize> {
+    let quadratic = words.checked_mul(words)? / 512;
+    return words.checked_mul(3)?.checked_add(quadratic);
 }
 
 /// 0x00: 算術命令
 impl VM {
     /// 0x00: 何もしない
-    fn op_stop(&mut self) {
+    fn op_stop(&mut self) -> Result<(), String> {
         self.push_asm("STOP");
+        return Ok(());
     }
 
     /// 0x01: operand1(スタック1番目) + operand2(スタック2番目)
-    fn op_add(&mut self) {
-        self.consume_gas(3);
+    fn op_add(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("ADD");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1 + operand2;
         self.push(result);
+        return Ok(());
     }
 
     /// 0x02: operand1(スタック1番目) * operand2(スタック2番目)
-    fn op_mul(&mut self) {
-        self.consume_gas(5);
+    fn op_mul(&mut self) -> Result<(), String> {
+        self.consume_gas(5)?;
         self.push_asm("MUL");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1 * operand2;
         self.push(result);
+        return Ok(());
     }
 
     /// 0x03: operand1(スタック1番目) - operand2(スタック2番目)
-    fn op_sub(&mut self) {
-        self.consume_gas(3);
+    fn op_sub(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("SUB");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1 - operand2;
         self.push(result);
+        return Ok(());
     }
 
     /// 0x04: operand1(スタック1番目) // operand2(スタック2番目)
-    fn op_div(&mut self) {
-        self.consume_gas(5);
+    fn op_div(&mut self) -> Result<(), String> {
+        self.consume_gas(5)?;
         self.push_asm("DIV");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1 / operand2;
         self.push(result);
+        return Ok(());
     }
 
-    fn op_sdiv(&mut self) {
+    fn op_sdiv(&mut self) -> Result<(), String> {
         self.push_asm("SDIV");
         not_implement_panic();
+        return Ok(());
     }
 
-    fn op_mod(&mut self) {
+    fn op_mod(&mut self) -> Result<(), String> {
         self.push_asm("MOD");
         not_implement_panic();
+        return Ok(());
     }
 
-    fn op_smod(&mut self) {
+    fn op_smod(&mut self) -> Result<(), String> {
         self.push_asm("SMOD");
         not_implement_panic();
+        return Ok(());
     }
 
-    fn op_addmod(&mut self) {
+    fn op_addmod(&mut self) -> Result<(), String> {
         self.push_asm("ADDMOD");
         not_implement_panic();
+        return Ok(());
     }
 
-    fn op_mulmod(&mut self) {
+    fn op_mulmod(&mut self) -> Result<(), String> {
         self.push_asm("MULMOD");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x0a: operand1(スタック1番目) ** operand2(スタック2番目)
-    fn op_exp(&mut self) {
-        self.consume_gas(10);
+    fn op_exp(&mut self) -> Result<(), String> {
+        self.consume_gas(10)?;
         self.push_asm("EXP");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1.pow(operand2);
         self.push(result);
+        return Ok(());
     }
 
     /// 0x0b:
-    fn op_sig_next_end(&mut self) {
+    fn op_sig_next_end(&mut self) -> Result<(), String> {
         self.push_asm("SIGNEXTEND");
         not_implement_panic();
+        return Ok(());
     }
 }
 
 /// 0x10: 条件、ビット演算
 impl VM {
     /// 0x10: operand1(スタック1番目) < operand2(スタック2番目)
-    fn op_lt(&mut self) {
-        self.consume_gas(3);
+    fn op_lt(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("LT");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         if operand1 < operand2 {
             self.push(U256::from(1));
         } else {
             self.push(U256::from(0));
         }
+        return Ok(());
     }
 
     /// 0x11: operand1(スタック1番目) > operand2(スタック2番目)
-    fn op_gt(&mut self) {
-        self.consume_gas(3);
+    fn op_gt(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("GT");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         if operand1 > operand2 {
             self.push(U256::from(1));
         } else {
             self.push(U256::from(0));
         }
+        return Ok(());
     }
 
-    fn op_slt(&mut self) {
+    fn op_slt(&mut self) -> Result<(), String> {
         self.push_asm("SLT");
         not_implement_panic();
+        return Ok(());
     }
 
-    fn op_sgt(&mut self) {
+    fn op_sgt(&mut self) -> Result<(), String> {
         self.push_asm("SGT");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x14: operand1(スタック1番目) == operand2(スタック2番目)
-    fn op_eq(&mut self) {
-        self.consume_gas(3);
+    fn op_eq(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("EQ");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         if operand1 == operand2 {
             self.push(U256::from(1));
         } else {
             self.push(U256::from(0));
         }
+        return Ok(());
     }
 
     /// 0x15: operand1(スタック1番目) == 0
-    fn op_is_zero(&mut self) {
-        self.consume_gas(3);
+    fn op_is_zero(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("ISZERO");
-        let operand1 = self.pop();
+        let operand1 = self.pop()?;
         if operand1 == U256::from(0) {
             self.push(U256::from(1));
         } else {
             self.push(U256::from(0));
         }
+        return Ok(());
     }
 
     /// operand1(スタック1番目) & operand2(スタック2番目)
-    fn op_and(&mut self) {
-        self.consume_gas(3);
+    fn op_and(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("AND");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1 & operand2;
         self.push(result);
+        return Ok(());
     }
 
     /// operand1(スタック1番目) | operand2(スタック2番目)
-    fn op_or(&mut self) {
-        self.consume_gas(3);
+    fn op_or(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("OR");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1 | operand2;
         self.push(result);
+        return Ok(());
     }
 
     /// operand1(スタック1番目) ^ operand2(スタック2番目)
-    fn op_xor(&mut self) {
-        self.consume_gas(3);
+    fn op_xor(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("XOR");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
         let result = operand1 ^ operand2;
         self.push(result);
+        return Ok(());
     }
 
     /// not operand1(スタック1番目)
-    fn op_not(&mut self) {
-        self.consume_gas(3);
+    fn op_not(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("NOT");
-        let operand1 = self.pop();
+        let operand1 = self.pop()?;
         let result = !operand1;
         self.push(result);
+        return Ok(());
     }
 
     /// 0x1a: operand2(スタック2番目)のoperand1バイト目を取る
-    fn op_byte(&mut self) {
+    fn op_byte(&mut self) -> Result<(), String> {
         // y = (operand2 >> (248 - operand1 * 8)) & 0xFF
-        self.consume_gas(3);
+        self.consume_gas(3)?;
         self.push_asm("BYTE");
-        let operand1 = self.pop();
-        let operand2 = self.pop();
-        let mask = U256::from(0xff);
-        let index = 248 - (operand1.as_u32() as usize) * 8;
-        let result = (operand2 >> index) & mask;
+        let operand1 = self.pop()?;
+        let operand2 = self.pop()?;
+        // 32バイト目以降は0になる
+        let result = if operand1 < U256::from(32) {
+            let mask = U256::from(0xff);
+            let index = 248 - operand1.as_usize() * 8;
+            (operand2 >> index) & mask
+        } else {
+            U256::zero()
+        };
         self.push(result);
+        return Ok(());
     }
 
-    fn op_shl(&mut self) {
+    /// 0x1b: operand2(スタック2番目)をoperand1(スタック1番目)ビット左シフトする(EIP-145)
+    fn op_shl(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Constantinople, "SHL")?;
+        self.consume_gas(3)?;
         self.push_asm("SHL");
-        not_implement_panic();
+        let shift = self.pop()?;
+        let value = self.pop()?;
+        let result = if shift < U256::from(256) {
+            value << shift.as_usize()
+        } else {
+            U256::zero()
+        };
+        self.push(result);
+        return Ok(());
     }
 
-    fn op_shr(&mut self) {
+    /// 0x1c: operand2(スタック2番目)をoperand1(スタック1番目)ビット論理右シフトする(EIP-145)
+    fn op_shr(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Constantinople, "SHR")?;
+        self.consume_gas(3)?;
         self.push_asm("SHR");
-        not_implement_panic();
+        let shift = self.pop()?;
+        let value = self.pop()?;
+        let result = if shift < U256::from(256) {
+            value >> shift.as_usize()
+        } else {
+            U256::zero()
+        };
+        self.push(result);
+        return Ok(());
     }
 
-    fn op_sar(&mut self) {
+    /// 0x1d: operand2(スタック2番目)をoperand1(スタック1番目)ビット算術右シフトする(EIP-145)
+    fn op_sar(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Constantinople, "SAR")?;
+        self.consume_gas(3)?;
         self.push_asm("SAR");
-        not_implement_panic();
+        let shift = self.pop()?;
+        let value = self.pop()?;
+        // 負の数は反転して論理右シフトしてから戻すことで符号ビットを埋める
+        let negative = value.bit(255);
+        let result = match (shift < U256::from(256), negative) {
+            (true, false) => value >> shift.as_usize(),
+            (true, true) => !(!value >> shift.as_usize()),
+            (false, false) => U256::zero(),
+            (false, true) => !U256::zero(),
+        };
+        self.push(result);
+        return Ok(());
     }
 }
 
 /// 0x20: 暗号操作
 impl VM {
-    fn op_sha3(&mut self) {
+    /// 0x20: スタックからoffset, sizeをpopし、メモリのoffsetからsize分のkeccak256ハッシュをpush
+    fn op_sha3(&mut self) -> Result<(), String> {
         self.push_asm("SHA3");
-        not_implement_panic();
+        let offset = self.pop_usize()?;
+        let size = self.pop_usize()?;
+        // メモリを拡張できたならsizeは十分小さいので、ワードごとのgasを計算してもオーバーフローしない
+        self.extend_memory(offset, size)?;
+        self.consume_gas(30 + 6 * size.div_ceil(32))?;
+        let data = if size == 0 {
+            &[][..]
+        } else {
+            &self.memory[offset..offset + size]
+        };
+        let hash = util::keccak256(data);
+        self.push(U256::from_big_endian(&hash));
+        return Ok(());
     }
 }
 
 /// 0x30: 実行環境に関する操作 その1
 impl VM {
     /// 0x30: address of the executing contract
-    fn op_address(&mut self) {
-        self.consume_gas(2);
+    fn op_address(&mut self) -> Result<(), String> {
+        self.consume_gas(2)?;
         self.push_asm("ADDRESS");
         let address = util::h160_to_u256(&self.env.code_owner);
         self.push(address);
+        return Ok(());
     }
 
     /// 0x31: Get balance of the given account.
-    fn op_balance(&mut self) {
-        self.consume_gas(400);
+    fn op_balance(&mut self) -> Result<(), String> {
+        self.consume_gas(400)?;
         self.push_asm("BALANCE");
-        let address = util::u256_to_h160(&self.pop());
+        let _address = util::u256_to_h160(&self.pop()?);
         // TODO: balanceを取得できるようにVMの状態を修正する
         not_implement_panic();
+        return Ok(());
     }
 
-    fn op_origin(&mut self) {
+    fn op_origin(&mut self) -> Result<(), String> {
         self.push_asm("ORIGIN");
         not_implement_panic();
+        return Ok(());
     }
 
-    fn op_caller(&mut self) {
-        self.consume_gas(2);
+    fn op_caller(&mut self) -> Result<(), String> {
+        self.consume_gas(2)?;
         self.push_asm("CALLER");
         self.push(util::h160_to_u256(&self.env.sender));
+        return Ok(());
     }
 
-    fn op_callvalue(&mut self) {
+    /// 0x34: トランザクションに添付されたEthをstackにpush
+    fn op_callvalue(&mut self) -> Result<(), String> {
+        self.consume_gas(2)?;
         self.push_asm("CALLVALUE");
-        not_implement_panic();
+        self.push(self.env.value);
+        return Ok(());
     }
 
     /// 0x35: スタックからpopした値をstartとしてinputのstartの位置からstart+32の位置までの32byteのデータをstackにpush
-    fn op_calldataload(&mut self) {
-        self.consume_gas(3);
+    fn op_calldataload(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("CALLDATALOAD");
-        let start = self.pop().as_u32() as usize;
-        let bytes: [u8; 32] = util::slice_to_array(&self.env.input[start..]);
+        let start = self.pop_usize()?;
+        // inputの範囲外は0で埋める
+        let bytes: [u8; 32] = util::slice_to_array(self.env.input.get(start..).unwrap_or(&[]));
         self.push(bytes.into());
+        return Ok(());
     }
 
     /// 0x36: inputに格納されたデータサイズをstackにpush
-    fn op_calldatasize(&mut self) {
-        self.consume_gas(2);
+    fn op_calldatasize(&mut self) -> Result<(), String> {
+        self.consume_gas(2)?;
         self.push_asm("CALLDATASIZE");
         let size = self.env.input.len();
         self.push(size.into());
+        return Ok(());
     }
 
     /// 0x37:
-    fn op_calldatacopy(&mut self) {
+    fn op_calldatacopy(&mut self) -> Result<(), String> {
         self.push_asm("CALLDATACOPY");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x38:
-    fn op_codesize(&mut self) {
+    fn op_codesize(&mut self) -> Result<(), String> {
         self.push_asm("CODESIZE");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x39: コントラクトにデプロイされたコードをコピーする
-    fn op_codecopy(&mut self) {
-        self.consume_gas(9); // ???
+    fn op_codecopy(&mut self) -> Result<(), String> {
         self.push_asm("CODECOPY");
-        let dest_offset = self.pop().as_u32() as usize;
-        let offset = self.pop().as_u32() as usize;
-        let length = self.pop().as_u32() as usize;
+        let dest_offset = self.pop_usize()?;
+        let offset = self.pop_usize()?;
+        let length = self.pop_usize()?;
+        self.extend_memory(dest_offset, length)?;
+        self.consume_gas(3 + 3 * length.div_ceil(32))?;
 
+        // コードの範囲外は0で埋める
         for i in 0..length {
-            let b = self.env.code[offset + i];
-            self.memory.insert(dest_offset + i, b);
+            let b = offset
+                .checked_add(i)
+                .and_then(|index| self.env.code.get(index))
+                .copied()
+                .unwrap_or(0);
+            self.memory[dest_offset + i] = b;
         }
+        return Ok(());
     }
 
     /// 0x3a:
-    fn op_gasprice(&mut self) {
+    fn op_gasprice(&mut self) -> Result<(), String> {
         self.push_asm("GASPRICE");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x3b:
-    fn op_extcodesize(&mut self) {
+    fn op_extcodesize(&mut self) -> Result<(), String> {
         self.push_asm("EXTCODESIZE");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x3c:
-    fn op_extcodecopy(&mut self) {
+    fn op_extcodecopy(&mut self) -> Result<(), String> {
         self.push_asm("EXTCODECOPY");
         not_implement_panic();
+        return Ok(());
     }
 
-    /// 0x3d:
-    fn op_returndatasize(&mut self) {
+    /// 0x3d: 直前のCALL系命令の返り値のサイズをstackにpush
+    fn op_returndatasize(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Byzantium, "RETURNDATASIZE")?;
+        self.consume_gas(2)?;
         self.push_asm("RETURNDATASIZE");
-        not_implement_panic();
+        self.push(self.return_data.len().into());
+        return Ok(());
     }
 
-    /// 0x3e:
-    fn op_returndatacopy(&mut self) {
+    /// 0x3e: 直前のCALL系命令の返り値のoffsetからlength分をメモリのdest_offsetにコピーする
+    fn op_returndatacopy(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Byzantium, "RETURNDATACOPY")?;
         self.push_asm("RETURNDATACOPY");
-        not_implement_panic();
+        let dest_offset = self.pop_usize()?;
+        let offset = self.pop_usize()?;
+        let length = self.pop_usize()?;
+        // 返り値の範囲外を読もうとした場合は例外になる
+        match offset.checked_add(length) {
+            Some(end) if end <= self.return_data.len() => {}
+            _ => return Err("return data out of bounds".to_string()),
+        }
+        if length == 0 {
+            self.consume_gas(3)?;
+            return Ok(());
+        }
+        self.extend_memory(dest_offset, length)?;
+        self.consume_gas(3 + 3 * length.div_ceil(32))?;
+        self.memory[dest_offset..dest_offset + length]
+            .copy_from_slice(&self.return_data[offset..offset + length]);
+        return Ok(());
     }
 
     /// 0x3f:
-    fn op_extcodehash(&mut self) {
+    fn op_extcodehash(&mut self) -> Result<(), String> {
         self.push_asm("EXTCODEHASH");
         not_implement_panic();
+        return Ok(());
     }
 }
 
 /// 0x40: 実行環境に関する操作 その2
 impl VM {
     /// 0x40:
-    fn op_blockhash(&mut self) {
+    fn op_blockhash(&mut self) -> Result<(), String> {
         self.push_asm("BLOCKHASH");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x41:
-    fn op_coinbase(&mut self) {
+    fn op_coinbase(&mut self) -> Result<(), String> {
         self.push_asm("COINBASE");
         not_implement_panic();
+        return Ok(());
     }
 
-    /// 0x42:
-    fn op_timestamp(&mut self) {
+    /// 0x42: ブロックのタイムスタンプ
+    fn op_timestamp(&mut self) -> Result<(), String> {
+        self.consume_gas(2)?;
         self.push_asm("TIMESTAMP");
-        not_implement_panic();
+        self.push(self.env.block.timestamp.into());
+        return Ok(());
     }
 
-    /// 0x43:
-    fn op_number(&mut self) {
+    /// 0x43: ブロックの番号
+    fn op_number(&mut self) -> Result<(), String> {
+        self.consume_gas(2)?;
         self.push_asm("NUMBER");
-        not_implement_panic();
+        self.push(self.env.block.number.into());
+        return Ok(());
     }
 
     /// 0x44:
-    fn op_difficulty(&mut self) {
+    fn op_difficulty(&mut self) -> Result<(), String> {
         self.push_asm("DIFFICULTY");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x45:
-    fn op_gaslimit(&mut self) {
+    fn op_gaslimit(&mut self) -> Result<(), String> {
         self.push_asm("GASLIMIT");
         not_implement_panic();
+        return Ok(());
+    }
+
+    /// 0x49: トランザクションのindex番目(スタック1番目)のblobのversioned hash
+    fn op_blobhash(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Cancun, "BLOBHASH")?;
+        self.consume_gas(3)?;
+        self.push_asm("BLOBHASH");
+        let index = self.pop()?;
+        // 範囲外のindexは0になる
+        let hash = if index < U256::from(self.env.blob_hashes.len()) {
+            U256::from_big_endian(self.env.blob_hashes[index.as_usize()].as_bytes())
+        } else {
+            U256::zero()
+        };
+        self.push(hash);
+        return Ok(());
+    }
+
+    /// 0x4a: 現在のブロックのblobのgasのETHレート
+    fn op_blobbasefee(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Cancun, "BLOBBASEFEE")?;
+        self.consume_gas(2)?;
+        self.push_asm("BLOBBASEFEE");
+        self.push(self.env.blob_base_fee);
+        return Ok(());
     }
 }
 
 /// 0x50: EVM内のステート操作
 impl VM {
-    /// 0x50:
-    fn op_pop(&mut self) {
+    /// 0x50: スタックから値を1つ取り除く
+    fn op_pop(&mut self) -> Result<(), String> {
+        self.consume_gas(2)?;
         self.push_asm("POP");
-        not_implement_panic();
+        self.pop()?;
+        return Ok(());
     }
 
     /// 0x51: スタックからpopしたstartを先頭アドレスしてstart+32までの32byteの値をメモリからロード
-    fn op_mload(&mut self) {
-        self.consume_gas(3);
+    fn op_mload(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("MLOAD");
-        let start = self.pop().as_u32() as usize;
+        let start = self.pop_usize()?;
+        self.extend_memory(start, 32)?;
         let mut bytes: [u8; 32] = [0; 32];
-        for i in 0..32 {
-            let b = self.memory[start + i];
-            bytes[i] = b;
-        }
+        bytes.copy_from_slice(&self.memory[start..start + 32]);
         self.push(bytes.into());
+        return Ok(());
     }
 
     /// 0x52: スタックからstart, valueをpopし、startを先頭アドレスしてstart+32までの32byteのメモリ領域にvalueを格納する
-    fn op_mstore(&mut self) {
-        self.consume_gas(6);
+    fn op_mstore(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("MSTORE");
-        let address = self.pop().as_u32() as usize;
-        let value = self.pop();
+        let address = self.pop_usize()?;
+        let value = self.pop()?;
+        self.extend_memory(address, 32)?;
         let bytes: [u8; 32] = value.into();
-        for (i, b) in bytes.iter().enumerate() {
-            self.memory.insert(address + i, *b);
-        }
+        self.memory[address..address + 32].copy_from_slice(&bytes);
+        return Ok(());
     }
 
-    /// 0x53:
-    fn op_mstore8(&mut self) {
+    /// 0x53: スタックからaddress, valueをpopし、valueの下位1byteをメモリのaddressに格納する
+    fn op_mstore8(&mut self) -> Result<(), String> {
+        self.consume_gas(3)?;
         self.push_asm("MSTORE8");
-        not_implement_panic();
+        let address = self.pop_usize()?;
+        let value = self.pop()?;
+        self.extend_memory(address, 1)?;
+        self.memory[address] = value.byte(0);
+        return Ok(());
     }
 
     /// 0x54: スタックからpopした値をkeyとしてstorageから対応する値をロード
-    fn op_sload(&mut self, contract: &mut state::AccountState) {
-        self.consume_gas(200);
+    fn op_sload(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
+        let key = self.pop()?;
+        // EIP-2929: Berlin以降は初めてアクセスするslotかどうかでgasが変わる
+        let cost = if self.env.fork >= Fork::Berlin {
+            if ws.access_slot(&self.env.code_owner, &key) {
+                COLD_SLOAD_COST
+            } else {
+                WARM_ACCESS_COST
+            }
+        } else {
+            200
+        };
+        self.consume_gas(cost)?;
         self.push_asm("SLOAD");
-        let key = self.pop();
+        let contract = ws.get_account_state(&self.env.code_owner);
         let value = contract.get_storage(&key);
-        self.push(*value);
+        self.inspect(|inspector| inspector.storage_read(&self.env.code_owner, &key, &value));
+        self.push(value);
+        return Ok(());
     }
 
     /// 0x55: storageに書き込みを行う storage[operand1(スタック1番目)] = operand2(スタック2番目)
-    fn op_sstore(&mut self, contract: &mut state::AccountState) {
-        let key = self.pop();
-        let value = self.pop();
+    fn op_sstore(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
+        self.require_not_static("SSTORE")?;
+        let key = self.pop()?;
+        let value = self.pop()?;
+        let owner = self.env.code_owner;
+        let old = ws.get_account_state(&owner).get_storage(&key);
 
         // ストレージへの書き込みは書き込み先と書き込むデータによってgasが変動する
-        if (key == U256::from(0)) && (value != U256::from(0)) {
-            self.consume_gas(20000);
+        if self.env.fork >= Fork::Berlin {
+            self.sstore_gas(ws, &key, &old, &value)?;
+        } else if (key == U256::from(0)) && (value != U256::from(0)) {
+            self.consume_gas(20000)?;
         } else {
-            self.consume_gas(5000);
+            self.consume_gas(5000)?;
         }
         self.push_asm("SSTORE");
 
-        contract.set_storage(key, value);
+        ws.set_storage(&owner, key, value);
+        self.inspect(|inspector| inspector.storage_write(&owner, &key, &old, &value));
+        return Ok(());
+    }
+
+    /// Berlin以降のSSTOREのgasを消費し、返還されるgasを増減する(EIP-2200, EIP-2929, EIP-3529)<br/>
+    /// gasはトランザクション開始時の値(original)、現在の値(current)、書き込む値(value)で決まる
+    fn sstore_gas(
+        &mut self,
+        ws: &mut state::WorldState,
+        key: &U256,
+        current: &U256,
+        value: &U256,
+    ) -> Result<(), String> {
+        // EIP-2200: 残りのgasが2300以下では書き込めない
+        if self.gas <= 2300 {
+            return Err("out of gas".to_string());
+        }
+        let owner = self.env.code_owner;
+        let original = ws.original_storage(&owner, key);
+        let mut cost = if current == value || original != *current {
+            WARM_ACCESS_COST
+        } else if original.is_zero() {
+            20000
+        } else {
+            5000 - COLD_SLOAD_COST
+        };
+        if ws.access_slot(&owner, key) {
+            cost += COLD_SLOAD_COST;
+        }
+        self.consume_gas(cost)?;
+
+        // EIP-3529: London以降はslotを0にしたときの返還が減った
+        let clear_refund = if self.env.fork >= Fork::London {
+            4800
+        } else {
+            15000
+        };
+        if current == value {
+            return Ok(());
+        }
+        if original == *current {
+            if !original.is_zero() && value.is_zero() {
+                ws.add_refund(clear_refund);
+            }
+            return Ok(());
+        }
+        if !original.is_zero() {
+            if current.is_zero() {
+                ws.sub_refund(clear_refund);
+            } else if value.is_zero() {
+                ws.add_refund(clear_refund);
+            }
+        }
+        // 元の値に戻した場合は最初の書き込みとの差額を返還する
+        if original == *value {
+            if original.is_zero() {
+                ws.add_refund(20000 - WARM_ACCESS_COST);
+            } else {
+                ws.add_refund(5000 - COLD_SLOAD_COST - WARM_ACCESS_COST);
+            }
+        }
+        return Ok(());
     }
 
     /// 0x56: スタックからdestinationをpopしてジャンプ
-    fn op_jump(&mut self) {
-        self.consume_gas(8);
+    fn op_jump(&mut self) -> Result<(), String> {
+        self.consume_gas(8)?;
         self.push_asm("JUMP");
-        let destination = self.pop().as_u32() as usize;
+        let destination = self.pop_usize()?;
         // ジャンプ先のアドレスのオペコードはJUMPDESTでなければならない
-        if self.env.code[destination] != 0x5b {
-            panic!("op_jump: destination must be JUMPDEST");
+        if self.env.code.get(destination) != Some(&0x5b) {
+            return Err("invalid jump destination".to_string());
         }
 
         self.pc = destination + 1; // TODO: +1が必要か調査する
+        return Ok(());
     }
 
     /// 0x57: スタックからdestination, conditionをpop<br/>
     /// conditionが0以外ならdestinationにジャンプ
-    fn op_jumpi(&mut self) {
-        self.consume_gas(10);
+    fn op_jumpi(&mut self) -> Result<(), String> {
+        self.consume_gas(10)?;
         self.push_asm("JUMPI");
-        let destination = self.pop().as_u32() as usize;
-        let condition = self.pop().as_u32() as usize;
+        let destination = self.pop_usize()?;
+        let condition = self.pop()?;
         // ジャンプ先のアドレスのオペコードはJUMPDESTでなければならない
-        if self.env.code[destination] != 0x5b {
-            panic!("op_jumpi: destination must be JUMPDEST");
+        if self.env.code.get(destination) != Some(&0x5b) {
+            return Err("invalid jump destination".to_string());
         }
 
         // conditionか0ならジャンプする
-        if condition != 0 {
+        if !condition.is_zero() {
             self.pc = destination + 1; // TODO: +1が必要か調査する
         }
+        return Ok(());
     }
 
     /// 0x58:
-    fn op_pc(&mut self) {
+    fn op_pc(&mut self) -> Result<(), String> {
         self.push_asm("PC");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x59
-    fn op_msize(&mut self) {
+    fn op_msize(&mut self) -> Result<(), String> {
         self.push_asm("MSIZE");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x5a:
-    fn op_gas(&mut self) {
+    fn op_gas(&mut self) -> Result<(), String> {
         self.push_asm("GAS");
         not_implement_panic();
+        return Ok(());
     }
 
     /// 0x5b: 動的ジャンプを行う際にスタックからpopした値が示すアドレスにジャンプするが、そのアドレスではこのop_jumpdestがオペコードでなければならない<br/>
     /// このオペコードはそのマーカーとなるだけで単体では意味を持たない
-    fn op_jumpdest(&mut self) {
-        self.consume_gas(1);
+    fn op_jumpdest(&mut self) -> Result<(), String> {
+        self.consume_gas(1)?;
         self.push_asm("JUMPDEST");
+        return Ok(());
+    }
+
+    /// 0x5c: スタックからpopした値をkeyとしてtransient storageから対応する値をロード(EIP-1153)<br/>
+    /// transient storageはトランザクションの終了時に破棄される
+    fn op_tload(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
+        self.require_fork(Fork::Cancun, "TLOAD")?;
+        self.consume_gas(100)?;
+        self.push_asm("TLOAD");
+        let key = self.pop()?;
+        let slot = (self.env.code_owner, key);
+        let value = ws.substate().transient.get(&slot).copied();
+        self.push(value.unwrap_or_default());
+        return Ok(());
+    }
+
+    /// 0x5d: transient storageに書き込みを行う transient[operand1(スタック1番目)] = operand2(スタック2番目)
+    fn op_tstore(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
+        self.require_fork(Fork::Cancun, "TSTORE")?;
+        self.require_not_static("TSTORE")?;
+        self.consume_gas(100)?;
+        self.push_asm("TSTORE");
+        let key = self.pop()?;
+        let value = self.pop()?;
+        let slot = (self.env.code_owner, key);
+        ws.set_transient(slot, value);
+        return Ok(());
+    }
+
+    /// 0x5e: メモリのsrc(スタック2番目)からlength(スタック3番目)バイトをdest(スタック1番目)にコピーする(EIP-5656)
+    fn op_mcopy(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Cancun, "MCOPY")?;
+        self.push_asm("MCOPY");
+        let dest = self.pop_usize()?;
+        let src = self.pop_usize()?;
+        let length = self.pop_usize()?;
+        if length == 0 {
+            self.consume_gas(3)?;
+            return Ok(());
+        }
+        self.extend_memory(dest.max(src), length)?;
+        self.consume_gas(3 + 3 * length.div_ceil(32))?;
+        // 領域が重なっていてもコピー元を一時領域に取ったかのように動作する
+        self.memory.copy_within(src..src + length, dest);
+        return Ok(());
+    }
+
+    /// 0x5f: 0をpushする(EIP-3855)
+    fn op_push0(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Shanghai, "PUSH0")?;
+        self.consume_gas(2)?;
+        self.push_asm("PUSH0");
+        self.push(U256::zero());
+        return Ok(());
     }
 }
 
 /// 0x60-0x7f: PUSH命令
 impl VM {
     /// lengthバイトpushする
-    fn op_push(&mut self, length: usize) {
+    fn op_push(&mut self, length: usize) -> Result<(), String> {
         let mut operand = [0; 32];
         let mut operand_str = "".to_string();
+        // コードの終端を越える部分は0として読む
         for i in 0..length {
-            operand[32 - length + i] = self.env.code[self.pc];
-            operand_str += &hex::encode(vec![self.env.code[self.pc]]);
+            let b = self.env.code.get(self.pc).copied().unwrap_or(0);
+            operand[32 - length + i] = b;
+            operand_str += &hex::encode(vec![b]);
             self.pc += 1;
         }
-        self.consume_gas(3);
+        self.consume_gas(3)?;
         let asm = "PUSH".to_string() + " " + &operand_str;
         self.push_asm(&asm);
         self.push(operand.into());
+        return Ok(());
     }
 }
 
 /// 0x80: DUP命令
 impl VM {
     /// スタックの先頭をスタックのindex+1番目にコピーする
-    fn op_dup(&mut self, index: usize) {
-        self.consume_gas(3);
+    fn op_dup(&mut self, index: usize) -> Result<(), String> {
+        self.consume_gas(3)?;
+        if self.sp < index {
+            return Err("stack underflow".to_string());
+        }
         let operand = self.stack[self.sp - 1];
         self.push_asm("DUP");
-        if self.sp > 1 {
+        if self.sp > index {
             self.stack[self.sp - index - 1] = operand;
         } else {
             self.push(operand);
         }
+        return Ok(());
     }
 }
 
 /// 0x90: SWAP命令
 impl VM {
     /// スタックの先頭をスタックのindex+1番目と交換する
-    fn op_swap(&mut self, index: usize) {
-        self.consume_gas(3);
+    fn op_swap(&mut self, index: usize) -> Result<(), String> {
+        self.consume_gas(3)?;
+        if self.sp <= index {
+            return Err("stack underflow".to_string());
+        }
         self.push_asm("SWAP");
         let operand1 = self.stack[self.sp - 1];
         let operand2 = self.stack[self.sp - index - 1];
         self.stack[self.sp - 1] = operand2;
         self.stack[self.sp - index - 1] = operand1;
+        return Ok(());
     }
 }
 
 /// 0xa0: ログ
 impl VM {
-    /// 0xa0:
-    fn op_log0(&mut self) {
+    /// 0xa0: トピックなしのログを出力する
+    fn op_log0(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("LOG0");
-        not_implement_panic();
+        return self.log(ws, 0);
     }
 
-    /// 0xa1:
-    fn op_log1(&mut self) {
+    /// 0xa1: トピックを1つ持つログを出力する
+    fn op_log1(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("LOG1");
-        not_implement_panic();
+        return self.log(ws, 1);
     }
 
-    /// 0xa2:
-    fn op_log2(&mut self) {
+    /// 0xa2: トピックを2つ持つログを出力する
+    fn op_log2(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("LOG2");
-        not_implement_panic();
+        return self.log(ws, 2);
     }
 
-    /// 0xa3:
-    fn op_log3(&mut self) {
+    /// 0xa3: トピックを3つ持つログを出力する
+    fn op_log3(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("LOG3");
-        not_implement_panic();
+        return self.log(ws, 3);
     }
 
-    /// 0xa4:
-    fn op_log4(&mut self) {
+    /// 0xa4: トピックを4つ持つログを出力する
+    fn op_log4(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("LOG4");
-        not_implement_panic();
+        return self.log(ws, 4);
+    }
+
+    /// スタックからoffset, size, topicsをpopし、メモリのoffsetからsize分をデータとするログをsubstateに追加する
+    fn log(&mut self, ws: &mut state::WorldState, topic_count: usize) -> Result<(), String> {
+        self.require_not_static("LOG")?;
+        let offset = self.pop_usize()?;
+        let size = self.pop_usize()?;
+        let mut topics: Vec<H256> = Vec::new();
+        for _ in 0..topic_count {
+            let bytes: [u8; 32] = self.pop()?.into();
+            topics.push(H256::from(bytes));
+        }
+        self.extend_memory(offset, size)?;
+        self.consume_gas(375 + 375 * topic_count + 8 * size)?;
+
+        let data = if size == 0 {
+            vec![]
+        } else {
+            self.memory[offset..offset + size].to_vec()
+        };
+        let log = state::Log {
+            address: self.env.code_owner,
+            topics,
+            data,
+        };
+        self.inspect(|inspector| inspector.log(&log));
+        ws.push_log(log);
+        return Ok(());
     }
 }
 
 /// 0xf0:
 impl VM {
     /// 0xf0:
-    fn op_create(&mut self) {
+    fn op_create(&mut self) -> Result<(), String> {
         self.push_asm("CREATE");
         not_implement_panic();
+        return Ok(());
     }
 
-    /// 0xf1:
-    fn op_call(&mut self) {
+    /// 0xf1: 他のアカウントのコードをそのアカウントのコンテキストで実行する
+    fn op_call(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("CALL");
-        not_implement_panic();
+        return self.message_call(ws, CallKind::Call);
     }
 
-    /// 0xf2:
-    fn op_callcode(&mut self) {
+    /// 0xf2: 他のアカウントのコードを自身のコンテキストで実行する
+    fn op_callcode(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("CALLCODE");
-        not_implement_panic();
+        return self.message_call(ws, CallKind::CallCode);
     }
 
     /// 0xf3: スタックのoffsetからlength分のバイトデータを返り値として返す<br/>
     /// この命令を実行するとトランザクションは終了する？
-    fn op_return(&mut self) {
+    fn op_return(&mut self) -> Result<(), String> {
         self.push_asm("RETURN");
-        let offset = self.pop().as_u32() as usize;
-        let length = self.pop().as_u32() as usize;
+        let offset = self.pop_usize()?;
+        let length = self.pop_usize()?;
+        self.extend_memory(offset, length)?;
 
-        let return_value = &self.memory[offset..offset + length];
-        self.returns = Vec::from(return_value);
+        self.returns = if length == 0 {
+            vec![]
+        } else {
+            self.memory[offset..offset + length].to_vec()
+        };
+        return Ok(());
     }
 
-    /// 0xf4:
-    fn op_delegatecall(&mut self) {
+    /// 0xf4: 他のアカウントのコードを自身のコンテキストで、呼び出し元とvalueを引き継いで実行する
+    fn op_delegatecall(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
+        self.require_fork(Fork::Homestead, "DELEGATECALL")?;
         self.push_asm("DELEGATECALL");
-        not_implement_panic();
+        return self.message_call(ws, CallKind::DelegateCall);
     }
 
     /// 0xf5:
-    fn op_create2(&mut self) {
+    fn op_create2(&mut self) -> Result<(), String> {
         self.push_asm("CREATE2");
         not_implement_panic();
+        return Ok(());
     }
 
-    /// 0xfa:
-    fn op_staticcall(&mut self) {
+    /// 0xfa: ステートの変更を禁止して他のアカウントのコードを実行する
+    fn op_staticcall(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
+        self.require_fork(Fork::Byzantium, "STATICCALL")?;
         self.push_asm("STATICCALL");
-        not_implement_panic();
+        return self.message_call(ws, CallKind::StaticCall);
     }
 
-    /// 0xfd:
-    fn op_revert(&mut self) {
+    /// 0xfd: スタックのoffsetからlength分のバイトデータを返り値として実行を取り消す<br/>
+    /// 残りのgasは呼び出し元に返還される
+    fn op_revert(&mut self) -> Result<(), String> {
+        self.require_fork(Fork::Byzantium, "REVERT")?;
         self.push_asm("REVERT");
-        not_implement_panic();
+        let offset = self.pop_usize()?;
+        let length = self.pop_usize()?;
+        self.extend_memory(offset, length)?;
+
+        self.returns = if length == 0 {
+            vec![]
+        } else {
+            self.memory[offset..offset + length].to_vec()
+        };
+        self.reverted = true;
+        return Ok(());
     }
 
-    /// 0xff:
-    fn op_selfdestruct(&mut self) {
+    /// 0xff: コントラクトの残高をすべて受取人(スタック1番目)に送金し、アカウントを削除対象にする<br/>
+    /// 削除はトランザクション終了時に行われる
+    fn op_selfdestruct(&mut self, ws: &mut state::WorldState) -> Result<(), String> {
         self.push_asm("SELFDESTRUCT");
-        not_implement_panic();
+        self.require_not_static("SELFDESTRUCT")?;
+        let fork = self.env.fork;
+        let owner = self.env.code_owner;
+        let beneficiary = util::u256_to_h160(&self.pop()?);
+        let balance = ws.get_account_state(&owner).get_balance();
+
+        // EIP-150以降はgasを消費し、受取人のアカウントを新たに作る場合は追加でgasがかかる
+        if fork >= Fork::TangerineWhistle {
+            let new_account = if fork >= Fork::SpuriousDragon {
+                // EIP-161: 空のアカウントに送金する場合のみ
+                let empty =
+                    !ws.exists(&beneficiary) || ws.get_account_state(&beneficiary).is_empty();
+                empty && !balance.is_zero()
+            } else {
+                !ws.exists(&beneficiary)
+            };
+            // EIP-2929: 受取人に初めてアクセスする場合は追加でgasがかかる
+            let cold = fork >= Fork::Berlin
+                && self.access_account(ws, &beneficiary) == COLD_ACCOUNT_ACCESS_COST;
+            let access = if cold { COLD_ACCOUNT_ACCESS_COST } else { 0 };
+            if new_account {
+                self.consume_gas(5000 + 25000 + access)?;
+            } else {
+                self.consume_gas(5000 + access)?;
+            }
+        }
+
+        // EIP-6780: Cancun以降は同じトランザクションで作成されたコントラクトのみ削除される
+        let destroy = fork < Fork::Cancun || ws.substate().created.contains(&owner);
+
+        // EIP-3529: London以降はgasの返還がない
+        if fork < Fork::London && !ws.substate().selfdestructs.contains(&owner) {
+            ws.add_refund(24000);
+        }
+
+        // 自身を受取人にして削除される場合は残高が消滅する
+        ws.set_balance(&owner, U256::zero());
+        let burn = destroy && beneficiary == owner;
+        if !balance.is_zero() && !burn {
+            let beneficiary_balance = ws
+                .account(&beneficiary)
+                .map_or(U256::zero(), |account| account.get_balance());
+            ws.set_balance(&beneficiary, beneficiary_balance + balance);
+        }
+
+        if destroy {
+            ws.mark_selfdestruct(&owner);
+        }
+        self.inspect(|inspector| inspector.selfdestruct(&owner, &beneficiary, &balance));
+        return Ok(());
+    }
+}
+
+/// アクセス済みのアカウントにアクセスするgas(EIP-2929)
+const WARM_ACCESS_COST: usize = 100;
+/// 初めてアカウントにアクセスするgas(EIP-2929)
+const COLD_ACCOUNT_ACCESS_COST: usize = 2600;
+/// 初めてstorageのslotにアクセスするgas(EIP-2929)
+const COLD_SLOAD_COST: usize = 2100;
+
+/// CALL系命令の共通処理
+impl VM {
+    /// addressへのアクセスを記録し、アクセスのgasを返す(EIP-2929)<br/>
+    /// プリコンパイル済みコントラクトとアクセス済みのアカウントはwarm、それ以外はcold
+    fn access_account(&self, ws: &mut state::WorldState, address: &H160) -> usize {
+        if precompile::is_precompile(address, self.env.fork)
+            || *address == self.env.code_owner
+            || !ws.access_address(address)
+        {
+            return WARM_ACCESS_COST;
+        }
+        return COLD_ACCOUNT_ACCESS_COST;
+    }
+
+    /// スタックからgas, address, value(CALL, CALLCODEのみ), args_offset, args_size, ret_offset, ret_sizeをpopし、
+    /// addressのコードを実行する<br/>
+    /// 成功した場合は1、失敗した場合は0をpushする
+    fn message_call(&mut self, ws: &mut state::WorldState, kind: CallKind) -> Result<(), String> {
+        let fork = self.env.fork;
+        let gas = self.pop()?;
+        let address = util::u256_to_h160(&self.pop()?);
+        let value = match kind {
+            CallKind::Call | CallKind::CallCode => self.pop()?,
+            _ => U256::zero(),
+        };
+        let args_offset = self.pop_usize()?;
+        let args_size = self.pop_usize()?;
+        let ret_offset = self.pop_usize()?;
+        let ret_size = self.pop_usize()?;
+        if kind == CallKind::Call && !value.is_zero() {
+            self.require_not_static("CALL with value")?;
+        }
+
+        self.extend_memory(args_offset, args_size)?;
+        self.extend_memory(ret_offset, ret_size)?;
+
+        // EIP-150で呼び出しのコストが引き上げられ、EIP-2929でアクセス済みかどうかで変わるようになった
+        let mut cost = if fork >= Fork::Berlin {
+            self.access_account(ws, &address)
+        } else if fork >= Fork::TangerineWhistle {
+            700
+        } else {
+            40
+        };
+        if !value.is_zero() {
+            cost += 9000;
+        }
+        if kind == CallKind::Call {
+            let new_account = if fork >= Fork::SpuriousDragon {
+                let empty = !ws.exists(&address) || ws.get_account_state(&address).is_empty();
+                empty && !value.is_zero()
+            } else {
+                !ws.exists(&address)
+            };
+            if new_account {
+                cost += 25000;
+            }
+        }
+        self.consume_gas(cost)?;
+
+        // EIP-150: 呼び出し先に渡せるgasは残りの63/64まで
+        let requested = if gas > U256::from(usize::MAX) {
+            usize::MAX
+        } else {
+            gas.as_usize()
+        };
+        let mut callee_gas = if fork >= Fork::TangerineWhistle {
+            requested.min(self.gas - self.gas / 64)
+        } else {
+            requested
+        };
+        self.consume_gas(callee_gas)?;
+        // valueを送る場合は呼び出し先にgasが上乗せされる
+        if !value.is_zero() {
+            callee_gas += 2300;
+        }
+
+        let input = if args_size == 0 {
+            vec![]
+        } else {
+            self.memory[args_offset..args_offset + args_size].to_vec()
+        };
+        self.return_data = vec![];
+
+        // 呼び出しの深さの上限を超えるか残高が足りなければ呼び出さずに失敗する
+        let balance = ws.get_account_state(&self.env.code_owner).get_balance();
+        if self.depth >= 1024 || value > balance {
+            self.gas += callee_gas;
+            self.push(U256::zero());
+            return Ok(());
+        }
+
+        let inputs = CallInputs {
+            kind,
+            caller: self.env.code_owner,
+            to: address,
+            value,
+            input: input.clone(),
+            gas: callee_gas,
+            depth: self.depth + 1,
+        };
+        self.inspect(|inspector| inspector.call(&inputs));
+
+        let checkpoint = ws.checkpoint();
+        // EIP-161: valueが0ならアカウントを作らない
+        if kind == CallKind::Call && (!value.is_zero() || fork < Fork::SpuriousDragon) {
+            ws.transfer(&self.env.code_owner, &address, value);
+        }
+
+        let (status, output, gas_left) = if precompile::is_precompile(&address, fork) {
+            match precompile::execute(&address, &input, callee_gas, fork) {
+                Ok((output, used)) => (ExitStatus::Success, output, callee_gas - used),
+                Err(e) => (ExitStatus::Halt(e), vec![], 0),
+            }
+        } else {
+            // DELEGATECALL, CALLCODEは自身のコンテキストでコードを実行する
+            let (code_owner, sender, value) = match kind {
+                CallKind::Call | CallKind::StaticCall => (address, self.env.code_owner, value),
+                CallKind::CallCode => (self.env.code_owner, self.env.code_owner, value),
+                CallKind::DelegateCall => (self.env.code_owner, self.env.sender, self.env.value),
+            };
+            let mut env = Environment::new(code_owner, sender, self.env.gas_price, 0);
+            env.set_value(value);
+            if ws.exists(&address) {
+                env.set_code(util::str_to_bytes(
+                    &ws.get_account_state(&address).get_code(),
+                ));
+            }
+            env.set_input(input);
+            env.set_fork(fork);
+            env.set_blob_hashes(self.env.blob_hashes.clone());
+            env.set_blob_base_fee(self.env.blob_base_fee);
+            env.set_block(self.env.block);
+
+            let mut vm = VM::with_gas(env, callee_gas);
+            vm.depth = self.depth + 1;
+            vm.is_static = self.is_static || kind == CallKind::StaticCall;
+            let status = vm.exec_transaction_with(ws, self.inspector.clone());
+            (status, vm.returns, vm.gas)
+        };
+        let success = status.is_success();
+        self.inspect(|inspector| {
+            let outcome = CallOutcome {
+                status,
+                output: output.clone(),
+                gas_used: callee_gas - gas_left,
+            };
+            inspector.call_end(&inputs, &outcome)
+        });
+
+        // REVERTの場合も返り値はコピーされる
+        self.gas += gas_left;
+        let length = ret_size.min(output.len());
+        if length > 0 {
+            self.memory[ret_offset..ret_offset + length].copy_from_slice(&output[..length]);
+        }
+        self.return_data = output;
+        if success {
+            self.push(U256::one());
+        } else {
+            ws.revert_to(checkpoint);
+            self.push(U256::zero());
+        }
+        return Ok(());
     }
 }
 
@@ -1214,6 +2058,40 @@ fn test_swap1() {
     assert_eq!(vm.stack, vec![0x04.into(), 0x05.into()]);
 }
 
+/// 十分なgasを持つ環境でcodeを実行するEnvironment
+#[cfg(test)]
+fn test_env(code: &str) -> Environment {
+    let mut env = Environment::new(
+        Default::default(),
+        Default::default(),
+        10_000_000,
+        100_000_000_000_000_000,
+    );
+    env.set_code(util::str_to_bytes(code));
+    return env;
+}
+
+#[test]
+fn test_shift() {
+    // (コード, 結果) SHL(4, 1), SHR(4, 0x10), SHL(256, 1), SAR(4, -16), SAR(4, 0x10)
+    let cases = [
+        ("600160041b", U256::from(16)),
+        ("601060041c", U256::from(1)),
+        ("60016101001b", U256::zero()),
+        (
+            "7ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff060041d",
+            !U256::zero(),
+        ),
+        ("601060041d", U256::from(1)),
+    ];
+    for (code, result) in cases.iter() {
+        let mut vm = VM::new(test_env(code));
+        let mut contract = state::AccountState::new("".to_string());
+        assert_eq!(vm.exec_transaction(&mut contract), ExitStatus::Success);
+        assert_eq!(vm.stack, vec![*result]);
+    }
+}
+
 #[test]
 fn test_loop() {
     let mut env = Environment::new(
@@ -1296,3 +2174,520 @@ fn test_deploy() {
     assert_eq!(vm.gas, 9999999976);
     assert_eq!(vm.sp, 0);
 }
+
+/// SELFDESTRUCTのテスト用にオーナーとワールドステートを用意する
+#[cfg(test)]
+fn selfdestruct_world_state(fork: Fork, code: &str) -> (VM, state::WorldState) {
+    let owner = H160::from_low_u64_be(0xaa);
+    let mut env = Environment::new(
+        owner,
+        Default::default(),
+        10_000_000,
+        100_000_000_000_000_000,
+    );
+    env.set_code(util::str_to_bytes(code));
+    env.set_fork(fork);
+    let mut ws = state::WorldState::default();
+    let mut contract = state::AccountState::new(code.to_string());
+    contract.set_balance(100.into());
+    ws.push_account_state(owner, contract);
+    return (VM::new(env), ws);
+}
+
+#[test]
+fn test_selfdestruct() {
+    // PUSH1 0xbb, SELFDESTRUCT
+    let (mut vm, mut ws) = selfdestruct_world_state(Fork::Berlin, "60bbff");
+    let owner = H160::from_low_u64_be(0xaa);
+    let beneficiary = H160::from_low_u64_be(0xbb);
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.pc, 3);
+    // 受取人は初めてのアクセスなので2600が加算される
+    assert_eq!(vm.gas, 9999967397);
+    assert_eq!(ws.get_account_state(&beneficiary).get_balance(), 100.into());
+
+    // 削除はトランザクション終了時に行われる
+    assert!(ws.exists(&owner));
+    let substate = ws.finalize_transaction();
+    assert_eq!(substate.refund, 24000);
+    assert!(!ws.exists(&owner));
+    assert_eq!(vm.gas_refund(substate.refund), 16301);
+}
+
+#[test]
+fn test_selfdestruct_london() {
+    let (mut vm, mut ws) = selfdestruct_world_state(Fork::London, "60bbff");
+    let owner = H160::from_low_u64_be(0xaa);
+    vm.exec_transaction_with(&mut ws, None);
+    let substate = ws.finalize_transaction();
+    assert_eq!(substate.refund, 0);
+    assert!(!ws.exists(&owner));
+}
+
+#[test]
+fn test_selfdestruct_to_self() {
+    // 自身を受取人にすると残高は消滅する PUSH1 0xaa, SELFDESTRUCT
+    let (mut vm, mut ws) = selfdestruct_world_state(Fork::London, "60aaff");
+    let owner = H160::from_low_u64_be(0xaa);
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.gas, 9999994997);
+    assert_eq!(ws.get_account_state(&owner).get_balance(), 0.into());
+    ws.finalize_transaction();
+    assert!(!ws.exists(&owner));
+}
+
+#[test]
+fn test_selfdestruct_cancun() {
+    // EIP-6780: 作成済みのコントラクトは残高の送金のみ行われる
+    let (mut vm, mut ws) = selfdestruct_world_state(Fork::Cancun, "60bbff");
+    let owner = H160::from_low_u64_be(0xaa);
+    let beneficiary = H160::from_low_u64_be(0xbb);
+    vm.exec_transaction_with(&mut ws, None);
+    ws.finalize_transaction();
+    assert!(ws.exists(&owner));
+    assert_eq!(ws.get_account_state(&owner).get_balance(), 0.into());
+    assert_eq!(ws.get_account_state(&beneficiary).get_balance(), 100.into());
+
+    // 自身を受取人にしても残高は変わらない
+    let (mut vm, mut ws) = selfdestruct_world_state(Fork::Cancun, "60aaff");
+    vm.exec_transaction_with(&mut ws, None);
+    ws.finalize_transaction();
+    assert_eq!(ws.get_account_state(&owner).get_balance(), 100.into());
+}
+
+#[test]
+fn test_selfdestruct_cancun_created() {
+    // 同じトランザクションで作成されたコントラクトは削除される
+    let (mut vm, mut ws) = selfdestruct_world_state(Fork::Cancun, "60bbff");
+    let owner = H160::from_low_u64_be(0xaa);
+    ws.substate().created.insert(owner);
+    vm.exec_transaction_with(&mut ws, None);
+    ws.finalize_transaction();
+    assert!(!ws.exists(&owner));
+}
+
+#[test]
+fn test_push0() {
+    let mut vm = VM::new(test_env("5f"));
+    let mut contract = state::AccountState::new("".to_string());
+    vm.exec_transaction(&mut contract);
+    assert_eq!(vm.pc, 1);
+    assert_eq!(vm.gas, 9999999998);
+    assert_eq!(vm.stack, vec![0.into()]);
+}
+
+#[test]
+fn test_push0_before_shanghai() {
+    let mut env = test_env("5f");
+    env.set_fork(Fork::London);
+    let mut vm = VM::new(env);
+    let mut contract = state::AccountState::new("".to_string());
+    assert_eq!(
+        vm.exec_transaction(&mut contract),
+        ExitStatus::Halt("PUSH0 is invalid opcode before shanghai".to_string())
+    );
+    assert_eq!(vm.gas, 0);
+}
+
+#[test]
+fn test_opcode_before_fork() {
+    // フォークより前の命令は未定義のオペコードと同じく例外停止し、gasをすべて消費する
+    let cases = [
+        (
+            "5f5f5f5e",
+            Fork::Shanghai,
+            "MCOPY is invalid opcode before cancun",
+        ),
+        (
+            "5f5c",
+            Fork::Shanghai,
+            "TLOAD is invalid opcode before cancun",
+        ),
+        (
+            "3d",
+            Fork::Homestead,
+            "RETURNDATASIZE is invalid opcode before byzantium",
+        ),
+        (
+            "600160011b",
+            Fork::Byzantium,
+            "SHL is invalid opcode before constantinople",
+        ),
+    ];
+    for (code, fork, reason) in cases.iter() {
+        let mut env = test_env(code);
+        env.set_fork(*fork);
+        let mut vm = VM::new(env);
+        let mut contract = state::AccountState::new("".to_string());
+        assert_eq!(
+            vm.exec_transaction(&mut contract),
+            ExitStatus::Halt(reason.to_string())
+        );
+        assert_eq!(vm.gas, 0);
+        assert_eq!(vm.gas_used(), 10000000000);
+    }
+}
+
+#[test]
+fn test_transient_storage() {
+    // TSTORE(1, 5), TLOAD(1)
+    let code = "600560015d60015c";
+    let mut vm = VM::new(test_env(code));
+    let mut ws = state::WorldState::default();
+    ws.push_account_state(
+        Default::default(),
+        state::AccountState::new(code.to_string()),
+    );
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.gas, 9999999791);
+    assert_eq!(vm.stack, vec![5.into()]);
+
+    // トランザクションが終了すると破棄される
+    assert_eq!(ws.substate().transient.len(), 1);
+    ws.finalize_transaction();
+    assert!(ws.substate().transient.is_empty());
+}
+
+#[test]
+fn test_mcopy() {
+    // MSTORE(0, 0x0102...20), MCOPY(1, 0, 32)
+    let mut vm = VM::new(test_env(
+        "7f0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f206000526020600060015e",
+    ));
+    let mut contract = state::AccountState::new("".to_string());
+    vm.exec_transaction(&mut contract);
+    // メモリは64byteに拡張され、重なった領域も正しくコピーされる
+    assert_eq!(vm.memory.len(), 64);
+    assert_eq!(vm.memory[0], 0x01);
+    assert_eq!(vm.memory[1], 0x01);
+    assert_eq!(vm.memory[2], 0x02);
+    assert_eq!(vm.memory[32], 0x20);
+    assert_eq!(vm.gas, 9999999970);
+}
+
+#[test]
+fn test_zero_length_copy() {
+    // MCOPY(0x100, 0x100, 0), CODECOPY(0x100, 0, 0) 長さが0ならメモリの範囲外でもよい
+    for code in ["60006101006101005e00", "600060006101003900"] {
+        let mut vm = VM::new(test_env(code));
+        let mut contract = state::AccountState::new("".to_string());
+        assert_eq!(vm.exec_transaction(&mut contract), ExitStatus::Success);
+        assert!(vm.memory.is_empty());
+    }
+}
+
+#[test]
+fn test_zero_length_memory_ops() {
+    // RETURN, REVERT, SHA3, LOG0をoffset 0x100、長さ0で実行してもメモリは拡張されない
+    let cases = [
+        ("6000610100f3", ExitStatus::Success),
+        ("6000610100fd", ExitStatus::Revert),
+        ("60006101002000", ExitStatus::Success),
+        ("6000610100a000", ExitStatus::Success),
+    ];
+    for (code, status) in cases {
+        let mut vm = VM::new(test_env(code));
+        let mut contract = state::AccountState::new("".to_string());
+        assert_eq!(vm.exec_transaction(&mut contract), status, "{}", code);
+        assert!(vm.memory.is_empty());
+        assert!(vm.returns.is_empty());
+    }
+}
+
+#[test]
+fn test_codecopy_out_of_range() {
+    // CODECOPY(0, 4, 0x20) 8byteのコードの範囲外は0で埋める
+    let mut vm = VM::new(test_env("6020600460003900"));
+    let mut contract = state::AccountState::new("".to_string());
+    assert_eq!(vm.exec_transaction(&mut contract), ExitStatus::Success);
+    assert_eq!(vm.memory[..4], [0x60, 0x00, 0x39, 0x00]);
+    assert!(vm.memory[4..].iter().all(|b| *b == 0));
+    assert_eq!(vm.memory.len(), 32);
+}
+
+#[test]
+fn test_blobhash() {
+    let mut env = test_env("5f496001494a");
+    // BLOBHASH(0), BLOBHASH(1), BLOBBASEFEE
+    env.set_blob_hashes(vec![H256::from_low_u64_be(0x0100)]);
+    env.set_blob_base_fee(7.into());
+    let mut vm = VM::new(env);
+    let mut contract = state::AccountState::new("".to_string());
+    vm.exec_transaction(&mut contract);
+    assert_eq!(vm.stack, vec![0x0100.into(), 0.into(), 7.into()]);
+    assert_eq!(vm.gas, 9999999987);
+}
+
+#[test]
+fn test_block() {
+    let mut env = Environment::new(Default::default(), Default::default(), 1, 0);
+    // TIMESTAMP, NUMBER
+    env.set_code(util::str_to_bytes("4243"));
+    env.set_block(BlockEnv {
+        number: 5,
+        timestamp: 1_700_000_000,
+    });
+    let mut vm = VM::with_gas(env, 100);
+    let mut contract = state::AccountState::new("".to_string());
+    vm.exec_transaction(&mut contract);
+    assert_eq!(vm.stack, vec![1_700_000_000.into(), 5.into()]);
+    assert_eq!(vm.gas, 96);
+}
+
+/// CALL系命令のテスト用に呼び出し元(0xaa)と呼び出し先(0xbb)を用意する
+#[cfg(test)]
+fn call_world_state(caller_code: &str, callee_code: &str) -> (VM, state::WorldState) {
+    let caller = H160::from_low_u64_be(0xaa);
+    let mut env = Environment::new(
+        caller,
+        Default::default(),
+        10_000_000,
+        100_000_000_000_000_000,
+    );
+    env.set_code(util::str_to_bytes(caller_code));
+    let mut ws = state::WorldState::default();
+    ws.push_account_state(caller, state::AccountState::new(caller_code.to_string()));
+    ws.push_account_state(
+        H160::from_low_u64_be(0xbb),
+        state::AccountState::new(callee_code.to_string()),
+    );
+    return (VM::new(env), ws);
+}
+
+#[test]
+fn test_staticcall_precompile() {
+    // STATICCALL(0xffff, 0x02, 0, 0, 0, 0x20) sha256("")を呼び出す
+    let (mut vm, mut ws) = call_world_state("6020600060006000600261fffffa", "");
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.stack, vec![1.into()]);
+    assert_eq!(
+        hex::encode(&vm.memory[0..32]),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(vm.return_data.len(), 32);
+    // プリコンパイル済みコントラクトはwarmなので100
+    assert_eq!(vm.gas, 9999999819);
+}
+
+#[test]
+fn test_call() {
+    // CALL(0xffff, 0xbb, 0, 0, 0, 0, 0x20) 呼び出し先はmemory[0..32]に5を格納して返す
+    let (mut vm, mut ws) =
+        call_world_state("6020600060006000600060bb61fffff1", "600560005260206000f3");
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.stack, vec![1.into()]);
+    assert_eq!(vm.memory[31], 5);
+    assert_eq!(vm.return_data.len(), 32);
+    // 呼び出し先で消費したgasとcoldアクセスの2600だけが差し引かれる
+    assert_eq!(vm.gas, 9999997358);
+}
+
+#[test]
+fn test_call_zero_length_return() {
+    // CALL(0xffff, 0xbb, 0, 0, 0, 0x100, 0), RETURNDATACOPY(0x100, 0, 0)
+    // 長さが0ならメモリの範囲外でもコピーしない
+    let (mut vm, mut ws) =
+        call_world_state("600061010060006000600060bb61fffff1600060006101003e", "00");
+    assert_eq!(vm.exec_transaction_with(&mut ws, None), ExitStatus::Success);
+    assert_eq!(vm.stack, vec![1.into()]);
+    assert!(vm.memory.is_empty());
+}
+
+#[test]
+fn test_delegatecall() {
+    // DELEGATECALL(0xffff, 0xbb, 0, 0, 0, 0) 呼び出し先のコードで呼び出し元のstorageに書き込む
+    let (mut vm, mut ws) = call_world_state("600060006000600060bb61fffff4", "6001600055");
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.stack, vec![1.into()]);
+    let caller = H160::from_low_u64_be(0xaa);
+    assert_eq!(
+        ws.get_account_state(&caller).get_storage(&0.into()),
+        1.into()
+    );
+}
+
+#[test]
+fn test_staticcall_sstore() {
+    // 呼び出し先はSSTOREで例外停止するので、STATICCALLは0をpushする
+    let (mut vm, mut ws) = call_world_state("600060006000600060bb61fffffa", "6001600055");
+    assert_eq!(vm.exec_transaction_with(&mut ws, None), ExitStatus::Success);
+    assert_eq!(vm.stack, vec![0.into()]);
+    let callee = H160::from_low_u64_be(0xbb);
+    assert_eq!(
+        ws.get_account_state(&callee).get_storage(&0.into()),
+        0.into()
+    );
+}
+
+#[test]
+fn test_call_access_cost() {
+    // CALL(0, 0xbb, 0, 0, 0, 0, 0)を2回 1回目はcold、2回目はwarm
+    let call = "6000600060006000600060bb6000f1";
+    let (mut vm, mut ws) = call_world_state(&call.repeat(2), "");
+    let gas = vm.gas;
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.stack, vec![1.into(), 1.into()]);
+    assert_eq!(gas - vm.gas, 7 * 3 * 2 + 2600 + 100);
+
+    // Berlin以前はアクセス済みかどうかに関係なく700
+    let (mut vm, mut ws) = call_world_state(&call.repeat(2), "");
+    vm.env.fork = Fork::Istanbul;
+    let gas = vm.gas;
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(gas - vm.gas, 7 * 3 * 2 + 700 * 2);
+}
+
+#[test]
+fn test_call_zero_value_to_missing_account() {
+    // CALL(0, 0xcc, 0, 0, 0, 0, 0) 存在しないアカウントへのvalueが0のCALL
+    let callee = H160::from_low_u64_be(0xcc);
+    let (mut vm, mut ws) = call_world_state("6000600060006000600060cc6000f1", "");
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.stack, vec![1.into()]);
+    // EIP-161: 空のアカウントは作られない
+    assert!(!ws.exists(&callee));
+
+    // EIP-161以前は空のアカウントが作られる
+    let (mut vm, mut ws) = call_world_state("6000600060006000600060cc6000f1", "");
+    vm.env.fork = Fork::Homestead;
+    vm.exec_transaction_with(&mut ws, None);
+    assert!(ws.exists(&callee));
+}
+
+#[test]
+fn test_call_insufficient_balance() {
+    // CALL(0xffff, 0xbb, 1, 0, 0, 0, 0) 残高が足りないので呼び出さずに0をpushする
+    let (mut vm, mut ws) = call_world_state("6000600060006000600160bb61fffff1", "6001600055");
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(vm.stack, vec![0.into()]);
+    // 呼び出し先に渡すはずだったgasは上乗せ分も含めて返される
+    assert_eq!(vm.gas, 9999990679);
+}
+
+#[test]
+fn test_revert() {
+    // 呼び出し先はSSTOREの後にmemory[0..32]の5を返り値としてREVERTする
+    let (mut vm, mut ws) = call_world_state(
+        "6020600060006000600060bb61fffff1",
+        "6001600055600560005260206000fd",
+    );
+    assert_eq!(vm.exec_transaction_with(&mut ws, None), ExitStatus::Success);
+    assert_eq!(vm.stack, vec![0.into()]);
+    assert_eq!(vm.memory[31], 5);
+    assert_eq!(vm.return_data.len(), 32);
+    // 呼び出し先のストレージの変更とslotへのアクセスは取り消される
+    let callee = H160::from_low_u64_be(0xbb);
+    assert_eq!(
+        ws.get_account_state(&callee).get_storage(&0.into()),
+        0.into()
+    );
+    assert!(ws.get_substate().accessed_slots.is_empty());
+}
+
+#[test]
+fn test_sload_access_cost() {
+    // SLOAD(0)を2回 Berlin以降は1回目がcoldで2100、2回目がwarmで100
+    let (mut vm, mut ws) = call_world_state("6000545060005450", "");
+    let gas = vm.gas;
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(gas - vm.gas, (3 + 2) * 2 + 2100 + 100);
+
+    let (mut vm, mut ws) = call_world_state("6000545060005450", "");
+    vm.env.fork = Fork::Istanbul;
+    let gas = vm.gas;
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(gas - vm.gas, (3 + 2) * 2 + 200 * 2);
+}
+
+#[test]
+fn test_sstore_gas() {
+    // storage[0]は元々1 SSTORE(0, 0)で消してから、SSTORE(0, 1)で元に戻す
+    let caller = H160::from_low_u64_be(0xaa);
+    let (mut vm, mut ws) = call_world_state("60006000556001600055", "");
+    ws.get_account_state(&caller)
+        .set_storage(0.into(), 1.into());
+    let gas = vm.gas;
+    vm.exec_transaction_with(&mut ws, None);
+    // 1回目はcoldなslotの書き換えで2100 + 2900、2回目は書き換え済みのslotなので100
+    assert_eq!(gas - vm.gas, 3 * 4 + 2100 + 2900 + 100);
+    // 消したときの返還4800は取り消され、元に戻した分の2800が返還される
+    assert_eq!(ws.get_substate().refund, 2800);
+
+    // 0のslotに書き込むと20000 + 2100、同じ値の書き込みは100
+    let (mut vm, mut ws) = call_world_state("60016000556001600055", "");
+    let gas = vm.gas;
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(gas - vm.gas, 3 * 4 + 20000 + 2100 + 100);
+    assert_eq!(ws.get_substate().refund, 0);
+
+    // Berlinでは消したときに15000が返還される
+    let (mut vm, mut ws) = call_world_state("6000600055", "");
+    ws.get_account_state(&caller)
+        .set_storage(0.into(), 1.into());
+    vm.env.fork = Fork::Berlin;
+    vm.exec_transaction_with(&mut ws, None);
+    assert_eq!(ws.get_substate().refund, 15000);
+}
+
+#[test]
+fn test_log() {
+    // memory[0..32]に5を格納し、トピック0xaaでLOG1
+    let (mut vm, mut ws) = call_world_state("600560005260aa60206000a1", "");
+    assert_eq!(vm.exec_transaction_with(&mut ws, None), ExitStatus::Success);
+    let logs = &ws.substate().logs;
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].address, H160::from_low_u64_be(0xaa));
+    assert_eq!(logs[0].topics, vec![H256::from_low_u64_be(0xaa)]);
+    assert_eq!(logs[0].data[31], 5);
+}
+
+#[test]
+fn test_out_of_gas() {
+    // gasが足りなければ例外停止してgasをすべて消費する
+    let env = {
+        let mut env = Environment::new(Default::default(), Default::default(), 1, 0);
+        env.set_code(util::str_to_bytes("6005600401"));
+        env
+    };
+    let mut vm = VM::with_gas(env, 5);
+    let mut contract = state::AccountState::new("".to_string());
+    assert_eq!(
+        vm.exec_transaction(&mut contract),
+        ExitStatus::Halt("out of gas".to_string())
+    );
+    assert_eq!(vm.gas_used(), 5);
+}
+
+#[test]
+fn test_exceptional_halt() {
+    // (コード, 理由) ADD(スタック不足), INVALID, 未定義のオペコード, JUMP(JUMPDESTでない), SWAP1(スタック不足)
+    let cases = [
+        ("600101", "stack underflow"),
+        ("fe", "invalid opcode 0xfe"),
+        ("0c", "invalid opcode 0x0c"),
+        ("600056", "invalid jump destination"),
+        ("600190", "stack underflow"),
+    ];
+    for (code, reason) in cases.iter() {
+        let mut env = Environment::new(Default::default(), Default::default(), 1, 0);
+        env.set_code(util::str_to_bytes(code));
+        let mut vm = VM::with_gas(env, 100);
+        let mut contract = state::AccountState::new("".to_string());
+        assert_eq!(
+            vm.exec_transaction(&mut contract),
+            ExitStatus::Halt(reason.to_string())
+        );
+        assert_eq!(vm.gas, 0);
+    }
+}
+
+#[test]
+#[should_panic(expected = "not implement")]
+fn test_not_implemented_opcode() {
+    // 未実装の命令は例外停止にせずにパニックする
+    let mut env = Environment::new(Default::default(), Default::default(), 1, 0);
+    env.set_code(util::str_to_bytes("6001600105"));
+    let mut vm = VM::with_gas(env, 100);
+    let mut contract = state::AccountState::new("".to_string());
+    vm.exec_transaction(&mut contract);
+}